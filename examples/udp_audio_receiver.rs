@@ -0,0 +1,90 @@
+//! Minimal receiver for `clap-osc-host --output udp://host:port`.
+//!
+//! Plays incoming frames on the default output device and reports lost packets.
+//!
+//! ```bash
+//! cargo run --example udp_audio_receiver -- 0.0.0.0:9100
+//! ```
+
+use anyhow::{anyhow, Context, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use rtrb::RingBuffer;
+use std::net::UdpSocket;
+
+const UDP_MAGIC: &[u8; 4] = b"OCAF";
+const UDP_HEADER_LEN: usize = 16;
+
+fn main() -> Result<()> {
+    let bind = std::env::args().nth(1).unwrap_or_else(|| "0.0.0.0:9100".to_string());
+    let socket = UdpSocket::bind(&bind).with_context(|| format!("Failed to bind {}", bind))?;
+    println!("Listening for audio on {}", socket.local_addr()?);
+
+    let mut buf = [0u8; 65536];
+
+    // Wait for the first packet to learn the stream format.
+    let (size, _) = socket.recv_from(&mut buf)?;
+    let (_, sample_rate, channels, _) = parse_header(&buf[..size])?;
+    println!("Stream format: {}Hz, {} channels", sample_rate, channels);
+
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or_else(|| anyhow!("No default output device"))?;
+    let config = cpal::StreamConfig {
+        channels,
+        sample_rate: cpal::SampleRate(sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+    };
+
+    // Half a second of buffering absorbs network jitter.
+    let (mut producer, mut consumer) = RingBuffer::<f32>::new(sample_rate as usize * channels as usize / 2);
+
+    let stream = device.build_output_stream(
+        &config,
+        move |data: &mut [f32], _| {
+            for sample in data.iter_mut() {
+                *sample = consumer.pop().unwrap_or(0.0);
+            }
+        },
+        |e| eprintln!("Output error: {}", e),
+        None,
+    )?;
+    stream.play()?;
+
+    let mut expected_sequence: Option<u32> = None;
+    let mut lost: u64 = 0;
+
+    loop {
+        let (size, _) = socket.recv_from(&mut buf)?;
+        let Ok((sequence, _, _, frames)) = parse_header(&buf[..size]) else {
+            continue;
+        };
+
+        if let Some(expected) = expected_sequence {
+            if sequence != expected {
+                lost += sequence.wrapping_sub(expected) as u64;
+                println!("Lost packets: {} total", lost);
+            }
+        }
+        expected_sequence = Some(sequence.wrapping_add(1));
+
+        let sample_count = frames as usize * channels as usize;
+        let payload = &buf[UDP_HEADER_LEN..size];
+        for bytes in payload.chunks_exact(4).take(sample_count) {
+            let sample = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            if producer.push(sample).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+fn parse_header(packet: &[u8]) -> Result<(u32, u32, u16, u16)> {
+    if packet.len() < UDP_HEADER_LEN || &packet[..4] != UDP_MAGIC {
+        return Err(anyhow!("Not an OCAF audio packet"));
+    }
+    let sequence = u32::from_le_bytes(packet[4..8].try_into()?);
+    let sample_rate = u32::from_le_bytes(packet[8..12].try_into()?);
+    let channels = u16::from_le_bytes(packet[12..14].try_into()?);
+    let frames = u16::from_le_bytes(packet[14..16].try_into()?);
+    Ok((sequence, sample_rate, channels, frames))
+}
@@ -0,0 +1,203 @@
+use crate::osc::{get_f64, split_plugin_address, ParseError, BUILTIN_ADDRESSES};
+use anyhow::{anyhow, Context, Result};
+use rosc::{OscMessage, OscType};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How an aliased address wants its single argument interpreted.
+#[derive(Debug, Clone)]
+pub enum AliasValue {
+    /// `/alias value` — plugin units, like `/param/set`.
+    Plain(f64),
+    /// `/alias/norm value` — 0..1 mapped onto the parameter range.
+    Normalized(f64),
+    /// `/alias/text "value"` — parsed by the plugin's `text_to_value`.
+    Text(String),
+}
+
+/// An alias from the file that shadowed a built-in address and was renamed.
+pub struct AddressCollision {
+    pub original: String,
+    pub rewritten: String,
+    pub builtin: &'static str,
+}
+
+/// User-defined OSC addresses that map straight to a parameter id.
+/// Persisted to the alias file (one `/address param_id` per line) when one is set.
+#[derive(Default)]
+pub struct AliasTable {
+    aliases: BTreeMap<String, u32>,
+    path: Option<PathBuf>,
+    collisions: Vec<AddressCollision>,
+}
+
+impl AliasTable {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut table = Self {
+            aliases: BTreeMap::new(),
+            path: Some(path.to_path_buf()),
+            collisions: Vec::new(),
+        };
+
+        if !path.exists() {
+            return Ok(table);
+        }
+
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read alias file {}", path.display()))?;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let (Some(address), Some(id), None) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(anyhow!("{}:{}: expected '/address param_id'", path.display(), i + 1));
+            };
+            let param_id = id
+                .parse::<u32>()
+                .with_context(|| format!("{}:{}: invalid param id '{}'", path.display(), i + 1, id))?;
+            validate_syntax(address).map_err(|e| anyhow!("{}:{}: {}", path.display(), i + 1, e))?;
+
+            // Built-ins added after the file was written may now shadow an
+            // alias. The built-in wins and the alias is renamed, not dropped.
+            let address = match builtin_conflict(address) {
+                Some(builtin) => {
+                    let rewritten = table.rename_shadowed(address);
+                    table.collisions.push(AddressCollision {
+                        original: address.to_string(),
+                        rewritten: rewritten.clone(),
+                        builtin,
+                    });
+                    rewritten
+                }
+                None => address.to_string(),
+            };
+            table.aliases.insert(address, param_id);
+        }
+
+        Ok(table)
+    }
+
+    pub fn add(&mut self, address: &str, param_id: u32) -> Result<()> {
+        validate_alias(address)?;
+        self.aliases.insert(address.to_string(), param_id);
+        self.save()
+    }
+
+    pub fn remove(&mut self, address: &str) -> Result<bool> {
+        let removed = self.aliases.remove(address).is_some();
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.aliases.iter().map(|(address, id)| (address.as_str(), *id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.aliases.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// The alias file this table was loaded from and saves to.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Aliases renamed at load time because they collided with a built-in address.
+    pub fn collisions(&self) -> &[AddressCollision] {
+        &self.collisions
+    }
+
+    /// Appends `_1`, `_2`, ... to the first path segment until the address is
+    /// free, so `/param/set` becomes `/param_1/set`.
+    fn rename_shadowed(&self, address: &str) -> String {
+        let (first, rest) = match address[1..].find('/') {
+            Some(i) => address.split_at(i + 1),
+            None => (address, ""),
+        };
+        (1..)
+            .map(|n| format!("{}_{}{}", first, n, rest))
+            .find(|candidate| builtin_conflict(candidate).is_none() && !self.aliases.contains_key(candidate))
+            .expect("unbounded search always finds a free address")
+    }
+
+    /// Returns the target param and value if `msg` is addressed to an alias.
+    pub fn resolve(&self, msg: &OscMessage) -> Option<Result<(u32, AliasValue), ParseError>> {
+        if let Some(&param_id) = self.aliases.get(&msg.addr) {
+            return Some(alias_arg(msg, get_f64).map(|v| (param_id, AliasValue::Plain(v))));
+        }
+        if let Some(&param_id) = msg.addr.strip_suffix("/norm").and_then(|a| self.aliases.get(a)) {
+            return Some(alias_arg(msg, get_f64).map(|v| (param_id, AliasValue::Normalized(v))));
+        }
+        if let Some(&param_id) = msg.addr.strip_suffix("/text").and_then(|a| self.aliases.get(a)) {
+            return Some(alias_arg(msg, get_string).map(|v| (param_id, AliasValue::Text(v))));
+        }
+        None
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut text = String::from("# OSC address aliases: /address param_id\n");
+        for (address, id) in &self.aliases {
+            text.push_str(&format!("{} {}\n", address, id));
+        }
+        fs::write(path, text).with_context(|| format!("Failed to write alias file {}", path.display()))
+    }
+}
+
+fn validate_alias(address: &str) -> Result<()> {
+    validate_syntax(address)?;
+    if let Some(builtin) = builtin_conflict(address) {
+        return Err(anyhow!("alias '{}' conflicts with built-in address {}", address, builtin));
+    }
+    Ok(())
+}
+
+fn validate_syntax(address: &str) -> Result<()> {
+    if !address.starts_with('/') || address.len() < 2 {
+        return Err(anyhow!("alias '{}' must be an OSC address starting with '/'", address));
+    }
+    if address.ends_with("/norm") || address.ends_with("/text") {
+        return Err(anyhow!("alias '{}' must not end in /norm or /text", address));
+    }
+    Ok(())
+}
+
+/// The built-in address `address` would shadow or be shadowed by, if any.
+fn builtin_conflict(address: &str) -> Option<&'static str> {
+    let conflicts = |builtin: &str| {
+        builtin == address
+            || builtin.starts_with(&format!("{}/", address))
+            || address.starts_with(&format!("{}/", builtin))
+    };
+    if split_plugin_address(address).is_some() {
+        return Some("/plugin");
+    }
+    BUILTIN_ADDRESSES.iter().copied().find(|b| conflicts(b))
+}
+
+fn alias_arg<T>(msg: &OscMessage, get: fn(&OscType) -> Option<T>) -> Result<T, ParseError> {
+    msg.args
+        .first()
+        .and_then(get)
+        .ok_or_else(|| ParseError::Invalid(format!("{} requires 1 arg: value", msg.addr)))
+}
+
+fn get_string(arg: &OscType) -> Option<String> {
+    match arg {
+        OscType::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
@@ -1,14 +1,31 @@
-use clap::Parser;
+use crate::config_file;
+use crate::device::DeviceSelector;
+use crate::features;
+use crate::osc_tcp::TcpFraming;
+use crate::test_tone::{TestSignal, TestToneTarget};
+use crate::voices::RetriggerMode;
+use clap::builder::BoolishValueParser;
+use clap::error::ErrorKind;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser};
+use cpal::SampleFormat;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
-#[command(name = "clap-osc-host")]
+#[command(name = "clap-osc-host", version)]
 #[command(about = "A CLI CLAP host that receives OSC messages for note and parameter control")]
 pub struct Args {
     /// Path to the .clap plugin bundle
-    #[arg(required_unless_present_any = ["list_devices"])]
+    #[arg(required_unless_present_any = [
+        "list_devices", "list_backends", "list_configs", "bench_latency", "list_midi_ports", "config"
+    ])]
     pub plugin_path: Option<PathBuf>,
 
+    /// Plugins to run after the main one, in order (repeatable or comma-separated).
+    /// Use `path#plugin-id` to pick one plugin from a multi-plugin bundle.
+    #[arg(long = "chain", value_delimiter = ',')]
+    pub chain: Vec<String>,
+
     /// Select plugin by CLAP descriptor id (if bundle contains multiple plugins)
     #[arg(long = "plugin-id")]
     pub plugin_id: Option<String>,
@@ -21,21 +38,82 @@ pub struct Args {
     #[arg(long = "list-plugins")]
     pub list_plugins: bool,
 
+    /// Print the bundle's factory presets (location URI, load key, name) and exit
+    #[arg(long = "list-presets")]
+    pub list_presets: bool,
+
     /// OSC UDP port to listen on (default: 9000)
     #[arg(long = "osc-port", default_value = "9000")]
     pub osc_port: u16,
 
+    /// Address to listen for OSC on; 0.0.0.0 accepts messages from other machines
+    #[arg(long = "osc-bind", visible_alias = "osc-host", default_value = "127.0.0.1")]
+    pub osc_bind: IpAddr,
+
+    /// Also accept OSC over TCP on the OSC port
+    #[arg(long = "osc-tcp")]
+    pub osc_tcp: bool,
+
+    /// Packet framing for --osc-tcp: slip (OSC 1.1) or length (OSC 1.0 size prefix)
+    #[arg(long = "osc-tcp-framing", value_enum, default_value = "slip", requires = "osc_tcp")]
+    pub osc_tcp_framing: TcpFraming,
+
+    /// Port for --osc-tcp, when it shouldn't be the UDP one
+    #[arg(long = "osc-tcp-port", requires = "osc_tcp")]
+    pub osc_tcp_port: Option<u16>,
+
+    /// Largest UDP packet accepted, in bytes; larger ones are dropped with a warning
+    #[arg(
+        long = "osc-buffer-size",
+        default_value = "65536",
+        value_parser = clap::value_parser!(u32).range(512..=65536)
+    )]
+    pub osc_buffer_size: u32,
+
+    /// Open the plugin's editor window (requires the `gui` feature)
+    #[arg(long = "show-gui")]
+    pub show_gui: bool,
+
+    /// Play the plugin from this MIDI input port (index from --list-midi-ports or part of its name)
+    #[arg(long = "midi-port", visible_alias = "midi-in")]
+    pub midi_port: Option<String>,
+
+    /// Print available MIDI input ports and exit
+    #[arg(long = "list-midi-ports", visible_alias = "list-midi")]
+    pub list_midi_ports: bool,
+
+    /// Map a MIDI CC to a parameter, e.g. 74:1234 or 74:Cutoff (repeatable or comma-separated)
+    #[arg(long = "cc-map", value_delimiter = ',', requires = "midi_port")]
+    pub cc_map: Vec<String>,
+
     /// Print the OSC API and parameter table, then exit
     #[arg(short = 'p', long = "print-osc")]
     pub print_osc: bool,
 
-    /// Print available audio output devices and exit
+    /// Print available audio output and input devices and exit
     #[arg(long = "list-devices")]
     pub list_devices: bool,
 
-    /// Audio output device index (default: system default)
-    #[arg(long = "device")]
-    pub device: Option<u32>,
+    /// Print --print-osc, --list-plugins, --list-presets, --list-devices and --list-backends output as JSON
+    #[arg(long = "json")]
+    pub json: bool,
+
+    /// Audio backend to use, e.g. jack, alsa, asio or wasapi (default: the platform's default)
+    #[arg(long = "audio-backend")]
+    pub audio_backend: Option<String>,
+
+    /// Print the audio backends available on this system and exit
+    #[arg(long = "list-backends")]
+    pub list_backends: bool,
+
+    /// Audio output device: index, part of its name, or `default` (default: system default)
+    #[arg(long = "device", value_parser = crate::device::parse_device_selector)]
+    pub device: Option<DeviceSelector>,
+
+    /// Audio input device fed to the plugin's inputs: index, part of its name, or `default`
+    /// (default: none, inputs are silent)
+    #[arg(long = "input-device", value_parser = crate::device::parse_device_selector)]
+    pub input_device: Option<DeviceSelector>,
 
     /// Sample rate (default: device's preferred rate)
     #[arg(long = "sample-rate")]
@@ -45,11 +123,329 @@ pub struct Args {
     #[arg(long = "buffer-size")]
     pub buffer_size: Option<u32>,
 
-    /// Number of output channels (default: match plugin output, usually 2)
+    /// Fail instead of falling back to the nearest supported sample rate or buffer size
+    #[arg(long = "strict-config")]
+    pub strict_config: bool,
+
+    /// Reopen the audio stream when the device drops out (on/off); the host exits with an error if it can't
+    #[arg(
+        long = "device-reconnect",
+        default_value = "on",
+        value_parser = BoolishValueParser::new(),
+        action = ArgAction::Set
+    )]
+    pub device_reconnect: bool,
+
+    /// Host output gain in dB, up to +12 (also /host/gain)
+    #[arg(long = "master-gain", default_value = "0", allow_negative_numbers = true)]
+    pub master_gain: f32,
+
+    /// Soft-clip the host output so it never reaches full scale
+    #[arg(long = "limiter")]
+    pub limiter: bool,
+
+    /// Output sample format: f32, f64, i8, i16, i32, u8, u16 or u32 (default: float if the device offers it)
+    #[arg(long = "sample-format", value_parser = crate::device::parse_sample_format)]
+    pub sample_format: Option<SampleFormat>,
+
+    /// Print the selected device's supported configs and sample formats, then exit
+    #[arg(long = "list-configs")]
+    pub list_configs: bool,
+
+    /// Number of output channels (default: the plugin's main output, if the device can play that many)
     #[arg(long = "channels")]
     pub channels: Option<u16>,
 
+    /// Device channels (1-based) that get the plugin's main output, e.g. 3,4; the others stay silent
+    #[arg(long = "output-channels", value_delimiter = ',')]
+    pub output_channels: Vec<u16>,
+
+    /// Slots in the command queue between the OSC/MIDI threads and the audio thread
+    #[arg(long = "queue-capacity", default_value = "1024", value_parser = clap::value_parser!(u32).range(16..=1_048_576))]
+    pub queue_capacity: u32,
+
+    /// Pass every /param/set on instead of only the latest per param each millisecond
+    #[arg(long = "no-param-coalescing")]
+    pub no_param_coalescing: bool,
+
+    /// Log command traffic and queue pressure every this many seconds (also /host/stats)
+    #[arg(long = "stats-interval")]
+    pub stats_interval: Option<f64>,
+
+    /// Send audio somewhere other than a local device, e.g. udp://host:port
+    #[arg(long = "output", conflicts_with = "no_audio")]
+    pub output: Option<String>,
+
+    /// Run the plugin on a wall-clock timer without opening any audio output
+    #[arg(long = "no-audio")]
+    pub no_audio: bool,
+
+    /// Render a timed OSC script (`sample_offset /address args...` per line) offline to --out, then exit
+    #[arg(long = "render", requires = "out", conflicts_with_all = ["output", "no_audio", "input_device", "show_gui"])]
+    pub render: Option<PathBuf>,
+
+    /// Also record whatever the host plays to this WAV file (32-bit float)
+    #[arg(long = "record", conflicts_with = "render")]
+    pub record: Option<PathBuf>,
+
+    /// WAV file written by --render (32-bit float)
+    #[arg(long = "out", requires = "render")]
+    pub out: Option<PathBuf>,
+
+    /// Seconds rendered after the last message of a --render script
+    #[arg(long = "render-tail", default_value = "2.0")]
+    pub render_tail: f64,
+
+    /// Render exactly this many seconds instead of stopping --render-tail after the last message
+    #[arg(long = "render-duration", requires = "render")]
+    pub render_duration: Option<f64>,
+
+    /// Make --render bit-exact: flush denormals, pass every /param/set on and print the output's SHA-256
+    #[arg(long = "deterministic", requires = "render")]
+    pub deterministic: bool,
+
+    /// File of user OSC address aliases (`/address param_id` per line); updated by /alias/add and /alias/remove
+    #[arg(long = "alias-file")]
+    pub alias_file: Option<PathBuf>,
+
+    /// JSON file mapping custom OSC addresses and argument layouts to host commands
+    #[arg(long = "osc-map")]
+    pub osc_map: Option<PathBuf>,
+
+    /// Release velocity (0..1) for /note/off messages that send 0 or omit it
+    #[arg(long = "default-release-velocity", value_parser = parse_release_velocity)]
+    pub default_release_velocity: Option<f32>,
+
+    /// Semitones a full MIDI pitch bend reaches either way; /channel/bend is clamped to it too
+    #[arg(long = "bend-range", default_value = "2.0", value_parser = parse_bend_range)]
+    pub bend_range: f64,
+
+    /// Scala scale (.scl) that retunes /note/on keys, with key 60 as its 1/1
+    #[arg(long = "tuning", conflicts_with = "edo")]
+    pub tuning: Option<PathBuf>,
+
+    /// Retune /note/on keys to this many equal divisions of the octave, with key 60 unchanged
+    #[arg(long = "edo", value_parser = clap::value_parser!(u32).range(1..=1200))]
+    pub edo: Option<u32>,
+
+    /// Ramp plain /param/set changes over this many milliseconds instead of jumping (stepped params still jump)
+    #[arg(long = "ramp-default-ms")]
+    pub ramp_default_ms: Option<f64>,
+
+    /// What /note/play does to a key that is already held: choke the old voice or stack a new one
+    /// (default: stack if the plugin supports overlapping notes, else choke)
+    #[arg(long = "retrigger-mode", value_enum)]
+    pub retrigger_mode: Option<RetriggerMode>,
+
+    /// Treat /param/set values as 0..1 and map them onto each parameter's range, like /param/setn
+    #[arg(long = "normalized-params")]
+    pub normalized_params: bool,
+
+    /// Reload the alias file whenever it changes on disk
+    #[arg(long = "watch-config", requires = "alias_file")]
+    pub watch_config: bool,
+
+    /// Play a test signal at this frequency in Hz from startup (also /host/testTone)
+    #[arg(long = "test-tone")]
+    pub test_tone: Option<f32>,
+
+    /// Test signal waveform
+    #[arg(long = "test-signal", value_enum, default_value = "sine")]
+    pub test_signal: TestSignal,
+
+    /// Test signal gain (linear)
+    #[arg(long = "test-tone-gain", default_value = "0.1", value_parser = parse_tone_gain)]
+    pub test_tone_gain: f32,
+
+    /// Send the test signal to the device output (bypassing the plugin) or into the plugin's input
+    #[arg(long = "test-tone-target", value_enum, default_value = "output")]
+    pub test_tone_target: TestToneTarget,
+
+    /// Stop the test signal automatically after this many seconds
+    #[arg(long = "test-tone-duration")]
+    pub test_tone_duration: Option<f64>,
+
+    /// OSC script run at startup, one `/address args...` message per line
+    #[arg(long = "init-script")]
+    pub init_script: Option<PathBuf>,
+
+    /// Validate the plugin, audio config and init script without producing sound, then exit
+    #[arg(long = "check")]
+    pub check: bool,
+
+    /// Play a short test sequence through the plugin, then exit 0 if it made sound and 1 if not
+    #[arg(long = "self-test", conflicts_with_all = ["check", "render"])]
+    pub self_test: bool,
+
+    /// Refuse to start if the startup probe finds a setup problem (e.g. an effect with no input)
+    #[arg(long = "strict-setup")]
+    pub strict_setup: bool,
+
+    /// Send LED feedback (/led/param, /led/note) to this controller, e.g. 192.168.1.30:8000
+    #[arg(long = "led-feedback")]
+    pub led_feedback: Option<SocketAddr>,
+
+    /// Maximum rate of LED feedback updates per second
+    #[arg(long = "led-rate", default_value = "30")]
+    pub led_rate: f64,
+
+    /// Map param ids and note keys to LED indices (`param <id> <led>` / `note <key> <led>` per line)
+    #[arg(long = "led-map", requires = "led_feedback")]
+    pub led_map: Option<PathBuf>,
+
+    /// Forward parameter changes made by the plugin itself (/param/changed, /param/gesture) and its note output (/note/end, ...) to this UDP port
+    #[arg(long = "osc-send-port")]
+    pub osc_send_port: Option<u16>,
+
+    /// Forward parameter values the plugin reports as /param/value param_id:i32 value:f64 to this UDP port
+    #[arg(long = "feedback-port")]
+    pub feedback_port: Option<u16>,
+
+    /// Host for --osc-send-port and --feedback-port
+    #[arg(long = "osc-send-host", default_value = "127.0.0.1")]
+    pub osc_send_host: IpAddr,
+
+    /// Send /host/panic and similar host notifications to this address (repeatable)
+    #[arg(long = "notify")]
+    pub notify: Vec<SocketAddr>,
+
+    /// Drop the packet and keep going when the OSC thread panics, instead of shutting down
+    #[arg(long = "keep-running-on-panic")]
+    pub keep_running_on_panic: bool,
+
+    /// Print per-block event statistics (events, bytes, params, notes) on exit
+    #[arg(long = "event-stats")]
+    pub event_stats: bool,
+
+    /// Measure OSC-to-block latency under synthetic load (no plugin needed), then exit non-zero if over budget
+    #[arg(long = "bench-latency")]
+    pub bench_latency: bool,
+
+    /// p99 latency budget for --bench-latency in milliseconds (default: one buffer)
+    #[arg(long = "latency-budget-ms", value_parser = parse_latency_budget)]
+    pub latency_budget_ms: Option<f64>,
+
+    /// Also write the session summary printed at exit to this JSON file
+    #[arg(long = "session-report")]
+    pub session_report: Option<PathBuf>,
+
+    /// Read options from a session file (TOML); options on the command line take precedence
+    #[arg(long = "config")]
+    pub config: Option<PathBuf>,
+
+    /// Write this run's options (command line and --config) to a session file
+    #[arg(long = "save-config")]
+    pub save_config: Option<PathBuf>,
+
+    /// Set a parameter before the plugin starts, e.g. 12=0.5 or Cutoff=0.5 (repeatable or comma-separated)
+    #[arg(long = "param", value_delimiter = ',')]
+    pub param: Vec<String>,
+
+    /// Load a plugin state file before the plugin starts
+    #[arg(long = "load-state")]
+    pub load_state: Option<PathBuf>,
+
+    /// Load a factory preset before the plugin starts, by location URI from --list-presets
+    #[arg(long = "preset")]
+    pub preset: Option<String>,
+
+    /// Load key picking the preset inside --preset's location, from --list-presets
+    #[arg(long = "preset-load-key", requires = "preset")]
+    pub preset_load_key: Option<String>,
+
     /// Enable verbose event logging (OSC receive, queue, plugin ingestion)
     #[arg(short = 'v', long = "verbose")]
     pub verbose: bool,
 }
+
+impl Args {
+    /// Parses the command line with `--version` reporting the compiled-in
+    /// features, merging in `--config` and writing `--save-config`.
+    pub fn parse_with_features() -> Self {
+        let version: &'static str = Box::leak(features::version_string().into_boxed_str());
+        let mut command = Args::command().version(version);
+        let argv: Vec<_> = std::env::args_os().collect();
+        let mut matches = command.clone().get_matches_from(argv.clone());
+
+        if let Some(path) = matches.get_one::<PathBuf>("config").cloned() {
+            let merged = config_file::merge_config(&path, &command, &matches, argv)
+                .unwrap_or_else(|e| command.error(ErrorKind::InvalidValue, e).exit());
+            matches = command.clone().get_matches_from(merged);
+        }
+        if let Some(path) = matches.get_one::<PathBuf>("save_config") {
+            if let Err(e) = config_file::save_config(path, &command, &matches) {
+                command.error(ErrorKind::Io, e).exit();
+            }
+        }
+        Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+    }
+}
+
+/// MPE allows bend ranges of up to 96 semitones.
+fn parse_release_velocity(value: &str) -> Result<f32, String> {
+    let velocity: f32 = value.parse().map_err(|e| format!("{}", e))?;
+    if !(0.0..=1.0).contains(&velocity) {
+        return Err(format!("{} is outside 0-1", velocity));
+    }
+    Ok(velocity)
+}
+
+fn parse_latency_budget(value: &str) -> Result<f64, String> {
+    let ms: f64 = value.parse().map_err(|e| format!("{}", e))?;
+    if !ms.is_finite() || ms <= 0.0 {
+        return Err(format!("{} is not a positive number of milliseconds", ms));
+    }
+    Ok(ms)
+}
+
+fn parse_tone_gain(value: &str) -> Result<f32, String> {
+    let gain: f32 = value.parse().map_err(|e| format!("{}", e))?;
+    if !gain.is_finite() || gain < 0.0 {
+        return Err(format!("{} is not a gain of 0 or more", gain));
+    }
+    Ok(gain)
+}
+
+fn parse_bend_range(value: &str) -> Result<f64, String> {
+    let range: f64 = value.parse().map_err(|e| format!("{}", e))?;
+    if !(0.0..=96.0).contains(&range) {
+        return Err(format!("{} is outside 0-96 semitones", range));
+    }
+    Ok(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_velocity_is_between_zero_and_one() {
+        assert_eq!(parse_release_velocity("0"), Ok(0.0));
+        assert_eq!(parse_release_velocity("0.5"), Ok(0.5));
+        assert_eq!(parse_release_velocity("1"), Ok(1.0));
+        assert!(parse_release_velocity("1.01").is_err());
+        assert!(parse_release_velocity("-0.1").is_err());
+        assert!(parse_release_velocity("NaN").is_err());
+        assert!(parse_release_velocity("inf").is_err());
+        assert!(parse_release_velocity("loud").is_err());
+    }
+
+    #[test]
+    fn latency_budget_is_positive_and_finite() {
+        assert_eq!(parse_latency_budget("2.5"), Ok(2.5));
+        assert!(parse_latency_budget("0").is_err());
+        assert!(parse_latency_budget("-1").is_err());
+        assert!(parse_latency_budget("inf").is_err());
+        assert!(parse_latency_budget("NaN").is_err());
+    }
+
+    #[test]
+    fn preset_load_key_goes_with_a_preset() {
+        let args = ["clap-osc-host", "synth.clap", "--preset", "plugin:", "--preset-load-key", "bass"];
+        let args = Args::try_parse_from(args).unwrap();
+        assert_eq!(args.preset.as_deref(), Some("plugin:"));
+        assert_eq!(args.preset_load_key.as_deref(), Some("bass"));
+
+        assert!(Args::try_parse_from(["clap-osc-host", "synth.clap", "--preset-load-key", "bass"]).is_err());
+    }
+}
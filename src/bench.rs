@@ -0,0 +1,141 @@
+use crate::args::Args;
+use crate::device::{get_headless_config, AudioConfig};
+use crate::osc::{
+    bind_udp, create_command_queue, run_receiver, OscReceiverState, ScheduledCommand, DEFAULT_RECV_BUFFER_SIZE,
+};
+use crate::queue::SheddingQueue;
+use crate::testing::{automated_params, command_tag, LatencyStats, LoadProfile, LoopbackSender, VirtualClock};
+use anyhow::{Context, Result};
+use crossbeam_channel::unbounded;
+use rtrb::Consumer;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PROFILE_DURATION: Duration = Duration::from_secs(2);
+/// Blocks to keep draining after the last message is sent.
+const GRACE_BLOCKS: u32 = 20;
+
+/// Measures how long commands take from leaving the loopback sender to the
+/// start of the block of the no-audio processing loop that picks them up, for each load
+/// profile. Returns false if any profile's p99 exceeds the budget, which
+/// defaults to one buffer.
+pub fn run_latency_bench(args: &Args) -> Result<bool> {
+    let config = get_headless_config(args.sample_rate, args.channels, None, args.buffer_size);
+    let block_duration = VirtualClock::new(config.sample_rate, config.buffer_size).block_duration();
+    let budget = args
+        .latency_budget_ms
+        .map(|ms| Duration::from_secs_f64(ms / 1000.0))
+        .unwrap_or(block_duration);
+
+    log::info!(
+        "Latency benchmark: {}Hz, buffer size {} ({:.2}ms), p99 budget {:.2}ms",
+        config.sample_rate,
+        config.buffer_size,
+        block_duration.as_secs_f64() * 1000.0,
+        budget.as_secs_f64() * 1000.0
+    );
+
+    let (command_producer, mut command_consumer) = create_command_queue(1024);
+    let (main_sender, _main_receiver) = unbounded();
+
+    let state = OscReceiverState {
+        params: automated_params(),
+        bend_range: args.bend_range,
+        ..OscReceiverState::detached(SheddingQueue::new(command_producer, !args.no_param_coalescing), main_sender)
+    };
+
+    let socket = bind_udp("127.0.0.1:0").context("Failed to bind benchmark OSC socket")?;
+    let target = socket.local_addr()?;
+    thread::spawn(move || {
+        run_receiver(socket, move || bind_udp(&target.to_string()), None, DEFAULT_RECV_BUFFER_SIZE, state);
+    });
+
+    let mut passed = true;
+    for profile in LoadProfile::ALL {
+        let (stats, sent) = run_profile(profile, target, &mut command_consumer, &config)?;
+
+        let Some(stats) = stats else {
+            log::error!("{}: no commands reached the processing loop", profile.name());
+            passed = false;
+            continue;
+        };
+
+        let ok = stats.p99 <= budget;
+        passed &= ok;
+        println!(
+            "{:<18} sent {:>5}  received {:>5}  p50 {:>7.3}ms  p90 {:>7.3}ms  p99 {:>7.3}ms  max {:>7.3}ms  {}",
+            profile.name(),
+            sent,
+            stats.count,
+            ms(stats.p50),
+            ms(stats.p90),
+            ms(stats.p99),
+            ms(stats.max),
+            if ok { "ok" } else { "OVER BUDGET" }
+        );
+    }
+
+    Ok(passed)
+}
+
+/// Returns the latency stats and how many messages were sent.
+fn run_profile(
+    profile: LoadProfile,
+    target: SocketAddr,
+    command_consumer: &mut Consumer<ScheduledCommand>,
+    config: &AudioConfig,
+) -> Result<(Option<LatencyStats>, usize)> {
+    let schedule = profile.schedule(PROFILE_DURATION);
+    let total = schedule.len();
+    let sent_at: Arc<Mutex<Vec<Option<Instant>>>> = Arc::new(Mutex::new(vec![None; total]));
+
+    let sender = LoopbackSender::new(target)?;
+    let sender_sent_at = sent_at.clone();
+    let sender_thread = thread::spawn(move || -> Result<()> {
+        let start = Instant::now();
+        for scheduled in schedule {
+            let due = start + scheduled.at;
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+            sender_sent_at.lock().unwrap()[scheduled.tag as usize] = Some(Instant::now());
+            sender.send(scheduled.msg)?;
+        }
+        Ok(())
+    });
+
+    let mut clock = VirtualClock::new(config.sample_rate, config.buffer_size);
+    let mut latencies = Vec::with_capacity(total);
+    let mut grace = GRACE_BLOCKS;
+
+    while grace > 0 {
+        clock.wait();
+        // Events are scheduled at the block's sample time, so measure against
+        // when the block was due rather than when this thread woke up.
+        let scheduled_at = clock.block_start();
+        while let Ok(scheduled) = command_consumer.pop() {
+            let sent = command_tag(&scheduled.command)
+                .and_then(|tag| sent_at.lock().unwrap().get(tag as usize).copied().flatten());
+            if let Some(sent) = sent {
+                latencies.push(scheduled_at.saturating_duration_since(sent));
+            }
+        }
+        clock.advance();
+        if sender_thread.is_finished() {
+            grace -= 1;
+        }
+    }
+
+    sender_thread
+        .join()
+        .map_err(|_| anyhow::anyhow!("Benchmark sender thread panicked"))??;
+
+    Ok((LatencyStats::from_samples(latencies), total))
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
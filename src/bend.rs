@@ -0,0 +1,108 @@
+//! Channel pitch bend (`/channel/bend`, MIDI pitch bend). CLAP has no
+//! channel-wide bend, only per-note tuning, so the audio thread keeps each
+//! channel's bend and the notes sounding on it. When a channel's bend moves,
+//! every one of its notes gets a tuning expression, and a note started on a
+//! bent channel gets one right after its note-on. The bend adds to the
+//! tuning a note started with (`--tuning`, fractional keys).
+
+use crate::osc::Command;
+
+/// Notes tracked at once across all channels; further ones aren't bent
+/// rather than allocate.
+const MAX_ACTIVE: usize = 512;
+const CHANNELS: usize = 16;
+
+/// `NOTE_EXPRESSIONS` id of the tuning expression, in semitones.
+pub const EXPRESSION_TUNING: i32 = 2;
+
+/// A note sounding on a chain stage, for retuning.
+#[derive(Debug, Clone, Copy)]
+pub struct ActiveNote {
+    pub stage: usize,
+    pub note_id: i32,
+    pub key: i32,
+    pub channel: i32,
+    pub port: i32,
+    /// Semitones the note was tuned by at its note-on.
+    pub tuning: f64,
+}
+
+impl ActiveNote {
+    /// The expression that tunes this note `semitones` away from its key.
+    pub fn tuning(&self, semitones: f64) -> Command {
+        Command::NoteExpression {
+            expression_id: EXPRESSION_TUNING,
+            note_id: self.note_id,
+            key: self.key,
+            channel: self.channel,
+            port: self.port,
+            value: semitones,
+        }
+    }
+}
+
+/// Bend per channel and the notes it applies to, owned by the audio thread.
+pub struct ChannelBend {
+    bend: [f64; CHANNELS],
+    /// Never grows past the capacity it was created with.
+    active: Vec<ActiveNote>,
+}
+
+impl ChannelBend {
+    pub fn new() -> Self {
+        Self {
+            bend: [0.0; CHANNELS],
+            active: Vec::with_capacity(MAX_ACTIVE),
+        }
+    }
+
+    /// Sets `channel`'s bend and passes each note sounding on it to `retune`.
+    pub fn set(&mut self, channel: i32, semitones: f64, mut retune: impl FnMut(&ActiveNote)) {
+        let Some(index) = channel_index(channel) else {
+            return;
+        };
+        self.bend[index] = semitones;
+        for note in self.active.iter().filter(|note| note.channel == channel) {
+            retune(note);
+        }
+    }
+
+    /// Starts tracking a note. Returns its own tuning plus its channel's
+    /// bend when that isn't zero, so the new note can be tuned to match.
+    pub fn note_on(&mut self, note: ActiveNote) -> Option<f64> {
+        let bend = match channel_index(note.channel) {
+            Some(index) => {
+                if self.active.len() < MAX_ACTIVE {
+                    self.active.push(note);
+                }
+                self.bend[index]
+            }
+            None => 0.0,
+        };
+        let tuning = note.tuning + bend;
+        (tuning != 0.0).then_some(tuning)
+    }
+
+    /// Stops tracking the notes a note-off or choke ends. Negative fields
+    /// match anything, as they do in the event.
+    pub fn note_off(&mut self, stage: usize, note_id: i32, key: i32, channel: i32) {
+        // A note started without an id or key matches on the other fields.
+        let matches = |field: i32, value: i32| field < 0 || value < 0 || field == value;
+        self.active.retain(|note| {
+            !(note.stage == stage
+                && matches(note_id, note.note_id)
+                && matches(key, note.key)
+                && matches(channel, note.channel))
+        });
+    }
+
+    /// Stops tracking every note on `channel` (every channel when negative)
+    /// after `/notes/off` or a panic. The bends themselves stay.
+    pub fn forget(&mut self, channel: i32) {
+        self.active.retain(|note| channel >= 0 && note.channel != channel);
+    }
+}
+
+fn channel_index(channel: i32) -> Option<usize> {
+    usize::try_from(channel).ok().filter(|&channel| channel < CHANNELS)
+}
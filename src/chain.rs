@@ -0,0 +1,88 @@
+//! Plugins loaded after the main one with `--chain`. The engine runs them in
+//! order, each one's main output feeding the next one's main input. State,
+//! the editor window and `/alias` handling stay with the main plugin.
+//! `/plugin/load` also loads its replacement for the main plugin this way.
+
+use crate::engine::{MainThreadMessage, OscClapHost, OscClapHostMainThread, OscClapHostShared};
+use crate::plugin::{
+    enumerate_params, find_descriptor, load_bundle, query_audio_ports, select_plugin_id, AudioPortLayout, ParamInfo,
+    PluginDescriptorInfo,
+};
+use anyhow::{Context, Result};
+use clack_host::prelude::*;
+use crossbeam_channel::Sender;
+use std::path::Path;
+
+pub struct ChainedPlugin {
+    pub descriptor: PluginDescriptorInfo,
+    pub params: Vec<ParamInfo>,
+    pub audio_ports: AudioPortLayout,
+    pub instance: PluginInstance<OscClapHost>,
+    /// Declared after `instance` so the plugin is destroyed before its
+    /// library is unloaded.
+    _bundle: PluginBundle,
+}
+
+impl ChainedPlugin {
+    /// Loads `path`, or `path#plugin-id` to pick from a bundle with several
+    /// plugins, as plugin `index` of the chain.
+    pub fn load(
+        spec: &str,
+        index: usize,
+        main_sender: &Sender<MainThreadMessage>,
+        host_info: &HostInfo,
+    ) -> Result<Self> {
+        let (path, plugin_id) = match spec.rsplit_once('#') {
+            Some((path, id)) => (path, Some(id)),
+            None => (spec, None),
+        };
+        Self::open(Path::new(path), plugin_id, index, main_sender, host_info)
+            .with_context(|| format!("Failed to load chained plugin {}", path))
+    }
+
+    /// Loads `plugin_id`, or the bundle's only plugin, from the bundle at
+    /// `path` as plugin `index` of the chain.
+    pub fn open(
+        path: &Path,
+        plugin_id: Option<&str>,
+        index: usize,
+        main_sender: &Sender<MainThreadMessage>,
+        host_info: &HostInfo,
+    ) -> Result<Self> {
+        let bundle = load_bundle(path)?;
+        let plugin_id = select_plugin_id(&bundle, plugin_id, None)?;
+
+        let sender = main_sender.clone();
+        let mut instance = PluginInstance::<OscClapHost>::new(
+            |_| OscClapHostShared::new(sender, index),
+            |shared| OscClapHostMainThread::new(shared),
+            &bundle,
+            &plugin_id,
+            host_info,
+        )?;
+
+        Ok(Self {
+            descriptor: find_descriptor(&bundle, &plugin_id)?,
+            params: enumerate_params(&mut instance),
+            audio_ports: query_audio_ports(&mut instance),
+            instance,
+            _bundle: bundle,
+        })
+    }
+
+    /// Trades places with the main plugin, for `/plugin/load`. Afterwards
+    /// `self` holds the old plugin, which is destroyed before its library is
+    /// unloaded when `self` is dropped.
+    pub fn swap_with_main(
+        &mut self,
+        instance: &mut PluginInstance<OscClapHost>,
+        bundle: &mut PluginBundle,
+        descriptor: &mut PluginDescriptorInfo,
+        params: &mut Vec<ParamInfo>,
+    ) {
+        std::mem::swap(&mut self.instance, instance);
+        std::mem::swap(&mut self._bundle, bundle);
+        std::mem::swap(&mut self.descriptor, descriptor);
+        std::mem::swap(&mut self.params, params);
+    }
+}
@@ -0,0 +1,311 @@
+use crate::alias::{AliasTable, AliasValue};
+use crate::args::Args;
+use crate::device::{get_device_config, output_routes, requested_channels, select_device, ConfigRequest};
+use crate::osc_map::OscMap;
+use crate::osc::{denormalize, is_normalized_set, parse_message, Command, ParseError};
+use crate::param_names::ParamNames;
+use crate::plugin::{resolve_param_assignments, ParamInfo};
+use crate::script::{load_script, load_timed_script};
+use cpal::traits::DeviceTrait;
+use cpal::Host;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Errors and warnings collected by `--check`. Nothing here opens a stream or
+/// touches plugin state; it only resolves what a real run would use.
+#[derive(Default)]
+pub struct CheckReport {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl CheckReport {
+    pub fn error(&mut self, message: impl Into<String>) {
+        self.errors.push(message.into());
+    }
+
+    pub fn warning(&mut self, message: impl Into<String>) {
+        self.warnings.push(message.into());
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    pub fn print(&self) {
+        println!("=== Check Report ===\n");
+        for error in &self.errors {
+            println!("error: {}", error);
+        }
+        for warning in &self.warnings {
+            println!("warning: {}", warning);
+        }
+        if !self.errors.is_empty() || !self.warnings.is_empty() {
+            println!();
+        }
+        println!(
+            "{} error(s), {} warning(s)",
+            self.errors.len(),
+            self.warnings.len()
+        );
+    }
+}
+
+pub fn run_check(
+    args: &Args,
+    cpal_host: &Host,
+    plugin_channels: Option<u16>,
+    params: &[ParamInfo],
+    per_note_mod_params: &HashSet<u32>,
+    setup_warnings: &[String],
+) -> CheckReport {
+    let mut report = CheckReport::default();
+
+    for warning in setup_warnings {
+        if args.strict_setup {
+            report.error(format!("setup: {}", warning));
+        } else {
+            report.warning(format!("setup: {}", warning));
+        }
+    }
+
+    check_audio_config(args, cpal_host, plugin_channels, &mut report);
+
+    let aliases = match &args.alias_file {
+        Some(path) => match AliasTable::load(path) {
+            Ok(aliases) => aliases,
+            Err(e) => {
+                report.error(format!("{:#}", e));
+                AliasTable::default()
+            }
+        },
+        None => AliasTable::default(),
+    };
+
+    let osc_map = match &args.osc_map {
+        Some(path) => match OscMap::load(path) {
+            Ok(osc_map) => osc_map,
+            Err(e) => {
+                report.error(format!("{:#}", e));
+                OscMap::default()
+            }
+        },
+        None => OscMap::default(),
+    };
+    for address in osc_map.overrides() {
+        report.warning(format!("custom OSC address {} replaces the built-in one", address));
+    }
+
+    let params_by_id: HashMap<u32, &ParamInfo> = params.iter().map(|p| (p.id, p)).collect();
+
+    for collision in aliases.collisions() {
+        report.warning(format!(
+            "alias {} shadows built-in {}; renamed to {}",
+            collision.original, collision.builtin, collision.rewritten
+        ));
+    }
+
+    for (address, param_id) in aliases.iter() {
+        if !params_by_id.contains_key(&param_id) {
+            report.error(format!("alias {}: unknown parameter id {}", address, param_id));
+        }
+    }
+
+    match resolve_param_assignments(&args.param, params) {
+        Ok(values) => {
+            for (param_id, value) in values {
+                check_command(&Command::ParamSet { param_id, value }, &params_by_id, "--param", &mut report);
+            }
+        }
+        Err(e) => report.error(format!("{:#}", e)),
+    }
+
+    let lookup = ScriptLookup {
+        params_by_id: &params_by_id,
+        per_note_mod_params,
+        aliases: &aliases,
+        osc_map: &osc_map,
+        normalized_params: args.normalized_params,
+    };
+
+    if let Some(path) = &args.init_script {
+        check_script(path, false, &lookup, &mut report);
+    }
+
+    if let Some(path) = &args.render {
+        check_script(path, true, &lookup, &mut report);
+    }
+
+    report
+}
+
+fn check_audio_config(args: &Args, cpal_host: &Host, plugin_channels: Option<u16>, report: &mut CheckReport) {
+    if args.output.is_some() || args.no_audio || args.render.is_some() {
+        return;
+    }
+
+    let device = match select_device(cpal_host, args.device.as_ref()) {
+        Ok(device) => device,
+        Err(e) => {
+            report.error(format!("audio device: {}", e));
+            return;
+        }
+    };
+
+    let channels = match requested_channels(&device, args.channels, &args.output_channels) {
+        Ok(channels) => channels,
+        Err(e) => {
+            report.error(format!("audio config: {}", e));
+            return;
+        }
+    };
+    let request = ConfigRequest {
+        sample_rate: args.sample_rate,
+        channels,
+        plugin_channels,
+        buffer_size: args.buffer_size,
+        sample_format: args.sample_format,
+        strict: args.strict_config,
+    };
+    let config = match get_device_config(&device, request) {
+        Ok(config) => config,
+        Err(e) => {
+            report.error(format!("audio config: {}", e));
+            return;
+        }
+    };
+
+    let max_channels = device
+        .supported_output_configs()
+        .map(|configs| configs.map(|c| c.channels()).max().unwrap_or(0))
+        .unwrap_or(0);
+
+    if config.channels > max_channels {
+        report.error(format!(
+            "audio config: {} channels requested but device offers at most {}",
+            config.channels, max_channels
+        ));
+    }
+    if let Err(e) = output_routes(&args.output_channels, config.channels) {
+        report.error(format!("audio config: {}", e));
+    }
+}
+
+/// What a script's messages are resolved against, as a real run would.
+pub struct ScriptLookup<'a> {
+    pub params_by_id: &'a HashMap<u32, &'a ParamInfo>,
+    pub per_note_mod_params: &'a HashSet<u32>,
+    pub aliases: &'a AliasTable,
+    pub osc_map: &'a OscMap,
+    pub normalized_params: bool,
+}
+
+/// Validates an init script or, with `timed`, a `--render` script.
+pub fn check_script(path: &Path, timed: bool, lookup: &ScriptLookup, report: &mut CheckReport) {
+    let ScriptLookup { params_by_id, per_note_mod_params, aliases, osc_map, normalized_params } = *lookup;
+    let loaded = if timed { load_timed_script(path) } else { load_script(path) };
+    let entries = match loaded {
+        Ok(entries) => entries,
+        Err(e) => {
+            report.error(format!("{:#}", e));
+            return;
+        }
+    };
+
+    let param_names = ParamNames::new(params_by_id.values().copied());
+
+    for entry in entries {
+        let location = format!("{}:{}", path.display(), entry.line);
+
+        let msg = match entry.message.and_then(|msg| Ok(osc_map.rewrite(&msg)?.unwrap_or(msg))) {
+            Ok(msg) => msg,
+            Err(e) => {
+                report.error(format!("{}: {}", location, e));
+                continue;
+            }
+        };
+
+        // Host-side addresses don't produce commands to validate, and plugins
+        // after the first in the chain aren't loaded by --check.
+        const HOST_SIDE: &[&str] = &[
+            "/alias/", "/cue/", "/patchState", "/state/", "/loadState", "/preset/", "/plugin/", "/param/text", "/param/get",
+            "/params/list", "/mix/",
+        ];
+        if HOST_SIDE.iter().any(|prefix| msg.addr.starts_with(prefix)) {
+            continue;
+        }
+
+        if let Some(resolved) = aliases.resolve(&msg) {
+            match resolved {
+                Ok((param_id, AliasValue::Plain(value))) => {
+                    check_command(&Command::ParamSet { param_id, value }, params_by_id, &location, report);
+                }
+                Ok((param_id, _)) => {
+                    if !params_by_id.contains_key(&param_id) {
+                        report.error(format!("{}: unknown parameter id {}", location, param_id));
+                    }
+                }
+                Err(e) => report.error(format!("{}: {}", location, e)),
+            }
+            continue;
+        }
+
+        let named = match param_names.rewrite(&msg) {
+            Ok(named) => named,
+            Err(e) => {
+                report.error(format!("{}: {}", location, e));
+                continue;
+            }
+        };
+
+        let msg = named.as_ref().unwrap_or(&msg);
+        let parsed = parse_message(msg, per_note_mod_params).and_then(|mut cmd| {
+            if is_normalized_set(&msg.addr, normalized_params) {
+                denormalize(&mut cmd, &msg.addr, |id| params_by_id.get(&id).copied())?;
+            }
+            Ok(cmd)
+        });
+        match parsed {
+            Ok(cmd) => check_command(&cmd, params_by_id, &location, report),
+            Err(ParseError::UnknownAddress(addr)) => {
+                report.error(format!("{}: unknown OSC address {}", location, addr));
+            }
+            Err(e) => report.error(format!("{}: {}", location, e)),
+        }
+    }
+}
+
+fn check_command(
+    cmd: &Command,
+    params_by_id: &HashMap<u32, &ParamInfo>,
+    location: &str,
+    report: &mut CheckReport,
+) {
+    let (param_id, value) = match cmd {
+        Command::ParamSetMany { pairs } => {
+            for &(param_id, value) in pairs {
+                check_command(&Command::ParamSet { param_id, value }, params_by_id, location, report);
+            }
+            return;
+        }
+        Command::ParamSet { param_id, value } | Command::ParamRamp { param_id, target: value, .. } => {
+            (*param_id, Some(*value))
+        }
+        Command::ParamMod { param_id, .. } | Command::ParamInc { param_id, .. } => (*param_id, None),
+        _ => return,
+    };
+
+    let Some(param) = params_by_id.get(&param_id) else {
+        report.error(format!("{}: unknown parameter id {}", location, param_id));
+        return;
+    };
+
+    if let Some(value) = value {
+        if value < param.min_value || value > param.max_value {
+            report.warning(format!(
+                "{}: value {} for '{}' ({}) is outside [{}, {}]",
+                location, value, param.name, param_id, param.min_value, param.max_value
+            ));
+        }
+    }
+}
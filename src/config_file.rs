@@ -0,0 +1,175 @@
+//! Session files (`--config session.toml`, `--save-config`). Top-level keys
+//! are long option names (`sample_rate = 48000`, `osc-port = 9001`, and
+//! `plugin_path` for the positional argument); `[params]` holds `id = value`
+//! startup values. A file is turned into command-line arguments and parsed by
+//! clap together with the real ones, so it gets the same validation, and any
+//! option given on the command line wins over the file.
+
+use clap::parser::ValueSource;
+use clap::{Arg, ArgMatches, Command};
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use toml::{Table, Value};
+
+const PARAMS_SECTION: &str = "params";
+
+/// Options that make the host do something other than run; saving them would
+/// make the session file useless for launching.
+const NOT_SAVED: &[&str] = &[
+    "config",
+    "save_config",
+    "list_devices",
+    "list_backends",
+    "list_configs",
+    "list_plugins",
+    "list_presets",
+    "list_midi_ports",
+    "print_osc",
+    "json",
+    "check",
+    "bench_latency",
+];
+
+/// Builds the argument list for a run with `--config`: the file's options
+/// that the command line doesn't set, then the command line itself.
+/// `[params]` entries are always kept and go before the command line's, so
+/// a `--param` on the command line overrides the same parameter from the file.
+pub fn merge_config(
+    path: &Path,
+    command: &Command,
+    matches: &ArgMatches,
+    argv: Vec<OsString>,
+) -> Result<Vec<OsString>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+    let table: Table = text
+        .parse()
+        .map_err(|e| format!("Failed to parse config {}: {}", path.display(), e))?;
+
+    let mut argv = argv.into_iter();
+    let mut merged: Vec<OsString> = argv.next().into_iter().collect();
+
+    for (key, value) in &table {
+        if key == PARAMS_SECTION {
+            let Value::Table(params) = value else {
+                return Err(format!("{}: [{}] must be a table of id = value", path.display(), PARAMS_SECTION));
+            };
+            for (param, value) in params {
+                let value = scalar_text(value)
+                    .ok_or_else(|| format!("{}: [params] {} must be a number", path.display(), param))?;
+                merged.push("--param".into());
+                merged.push(format!("{}={}", param, value).into());
+            }
+            continue;
+        }
+
+        let id = key.replace('-', "_");
+        let arg = find_arg(command, &id)
+            .filter(|_| !NOT_SAVED.contains(&id.as_str()))
+            .ok_or_else(|| format!("{}: unknown option '{}'", path.display(), key))?;
+        if matches.value_source(&id) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let values = match value {
+            Value::Array(items) => items.iter().map(scalar_text).collect::<Option<Vec<_>>>(),
+            value => scalar_text(value).map(|v| vec![v]),
+        }
+        .ok_or_else(|| format!("{}: '{}' must be a string, number, boolean or array of those", path.display(), key))?;
+
+        if arg.is_positional() {
+            merged.extend(values.into_iter().map(OsString::from));
+            continue;
+        }
+        let flag = format!("--{}", arg.get_long().unwrap_or(&id));
+        if !arg.get_action().takes_values() {
+            if values.iter().any(|v| v == "true") {
+                merged.push(flag.into());
+            }
+            continue;
+        }
+        for value in values {
+            merged.push(flag.clone().into());
+            merged.push(value.into());
+        }
+    }
+
+    merged.extend(argv);
+    Ok(merged)
+}
+
+/// Writes the options of this run (from the command line and any `--config`)
+/// as a session file.
+pub fn save_config(path: &Path, command: &Command, matches: &ArgMatches) -> Result<(), String> {
+    let mut table = Table::new();
+    let mut params = Table::new();
+
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        if NOT_SAVED.contains(&id) || matches.value_source(id) != Some(ValueSource::CommandLine) {
+            continue;
+        }
+        if id == "param" {
+            for entry in matches.get_raw(id).into_iter().flatten() {
+                let entry = entry.to_string_lossy();
+                let (param, value) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("--param entry '{}' must be id=value", entry))?;
+                params.insert(param.trim().to_string(), toml_scalar(value.trim()));
+            }
+            continue;
+        }
+        if !arg.get_action().takes_values() {
+            table.insert(id.to_string(), Value::Boolean(true));
+            continue;
+        }
+        let values: Vec<Value> = matches
+            .get_raw(id)
+            .into_iter()
+            .flatten()
+            .map(|v| toml_scalar(&v.to_string_lossy()))
+            .collect();
+        let value = match values.len() {
+            1 if !is_list(arg) => values.into_iter().next().expect("one value"),
+            _ => Value::Array(values),
+        };
+        table.insert(id.to_string(), value);
+    }
+    if !params.is_empty() {
+        table.insert(PARAMS_SECTION.to_string(), Value::Table(params));
+    }
+
+    let text = toml::to_string_pretty(&table).map_err(|e| format!("Failed to encode config: {}", e))?;
+    fs::write(path, text).map_err(|e| format!("Failed to write config {}: {}", path.display(), e))?;
+    log::info!("Saved configuration to {}", path.display());
+    Ok(())
+}
+
+fn find_arg<'a>(command: &'a Command, id: &str) -> Option<&'a Arg> {
+    command.get_arguments().find(|arg| arg.get_id() == id)
+}
+
+fn is_list(arg: &Arg) -> bool {
+    matches!(arg.get_action(), clap::ArgAction::Append)
+}
+
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(v) => Some(v.to_string()),
+        Value::Float(v) => Some(v.to_string()),
+        Value::Boolean(v) => Some(v.to_string()),
+        _ => None,
+    }
+}
+
+/// Numbers are written as TOML numbers so the file reads naturally; they
+/// become the same text again when loaded.
+fn toml_scalar(text: &str) -> Value {
+    if let Ok(v) = text.parse::<i64>() {
+        return Value::Integer(v);
+    }
+    match text.parse::<f64>() {
+        Ok(v) if v.is_finite() => Value::Float(v),
+        _ => Value::String(text.to_string()),
+    }
+}
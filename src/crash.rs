@@ -0,0 +1,74 @@
+use crate::engine::MainThreadMessage;
+use crate::session::SessionReporter;
+use crossbeam_channel::Sender;
+use rosc::OscType;
+use std::backtrace::Backtrace;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+
+/// Exit code after a panic-triggered shutdown, distinct from errors (1) and
+/// an unhandled main-thread panic (101).
+pub const PANIC_EXIT_CODE: i32 = 70;
+
+/// Exit code when the audio device is lost and the stream can't be reopened
+/// (`EX_IOERR`).
+pub const DEVICE_LOST_EXIT_CODE: i32 = 74;
+
+/// Exit code when a second Ctrl+C cuts a hanging shutdown short (128 + SIGINT).
+pub const FORCED_EXIT_CODE: i32 = 130;
+
+/// Name of the OSC receive thread, which `--keep-running-on-panic` exempts.
+pub const OSC_THREAD_NAME: &str = "osc-receiver";
+
+/// Replaces the default panic hook. Every panic is logged with a backtrace
+/// and announced to `notify` as `/host/panic message`. Panics on other
+/// threads ask the main thread to shut down gracefully; a panic on the main
+/// thread prints the session summary and exits straight away since nothing is
+/// left to run the shutdown.
+pub fn install_panic_hook(
+    main_sender: Sender<MainThreadMessage>,
+    notify: Vec<SocketAddr>,
+    keep_running_osc: bool,
+    session: Arc<SessionReporter>,
+) {
+    std::panic::set_hook(Box::new(move |info| {
+        let thread_name = thread::current().name().unwrap_or("<unnamed>").to_string();
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(s) => s.to_string(),
+            None => match info.payload().downcast_ref::<String>() {
+                Some(s) => s.clone(),
+                None => "Box<dyn Any>".to_string(),
+            },
+        };
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}", l.file(), l.line()))
+            .unwrap_or_default();
+
+        log::error!(
+            "Thread '{}' panicked at {}: {}\n{}",
+            thread_name,
+            location,
+            message,
+            Backtrace::force_capture()
+        );
+
+        if keep_running_osc && thread_name == OSC_THREAD_NAME {
+            log::warn!("Continuing after OSC thread panic (--keep-running-on-panic)");
+            return;
+        }
+
+        crate::osc::notify(
+            &notify,
+            "/host/panic",
+            vec![OscType::String(format!("{} ({}): {}", thread_name, location, message))],
+        );
+
+        if thread_name == "main" {
+            session.finish("panic in thread 'main'");
+            std::process::exit(PANIC_EXIT_CODE);
+        }
+        let _ = main_sender.send(MainThreadMessage::Panic { thread: thread_name, message });
+    }));
+}
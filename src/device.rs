@@ -1,6 +1,8 @@
 use anyhow::{Context, Result, anyhow};
 use cpal::traits::{DeviceTrait, HostTrait};
-use cpal::{Device, Host, SampleFormat, SupportedStreamConfig};
+use cpal::{
+    Device, Host, HostId, SampleFormat, SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange,
+};
 
 pub struct DeviceInfo {
     pub index: u32,
@@ -8,8 +10,62 @@ pub struct DeviceInfo {
     pub is_default: bool,
 }
 
-pub fn get_cpal_host() -> Host {
-    cpal::default_host()
+/// The audio backend named by `--audio-backend` (case-insensitive, e.g.
+/// `jack`, `alsa`, `asio`, `wasapi`), or the platform default. Devices are
+/// listed, selected and probed through the host returned here.
+pub fn get_cpal_host(backend: Option<&str>) -> Result<Host> {
+    let Some(name) = backend else {
+        return Ok(cpal::default_host());
+    };
+    let available = cpal::available_hosts();
+    let Some(id) = available.iter().copied().find(|id| id.name().eq_ignore_ascii_case(name)) else {
+        let reason = if cpal::ALL_HOSTS.iter().any(|id| id.name().eq_ignore_ascii_case(name)) {
+            "is compiled in but not available (is its server or driver running?)"
+        } else {
+            "is not compiled into this build (JACK and ASIO need the `jack` and `asio` features)"
+        };
+        return Err(anyhow!(
+            "Audio backend '{}' {}. Available backends: {}",
+            name,
+            reason,
+            backend_names(&available)
+        ));
+    };
+    let host = cpal::host_from_id(id).with_context(|| format!("Failed to open audio backend {}", id.name()))?;
+    let has_devices = host.output_devices().is_ok_and(|mut devices| devices.next().is_some())
+        || host.input_devices().is_ok_and(|mut devices| devices.next().is_some());
+    if !has_devices {
+        return Err(anyhow!(
+            "Audio backend {} has no devices. Available backends: {}",
+            id.name(),
+            backend_names(&available)
+        ));
+    }
+    Ok(host)
+}
+
+fn backend_names(ids: &[HostId]) -> String {
+    if ids.is_empty() {
+        return "none".to_string();
+    }
+    ids.iter().map(|id| id.name()).collect::<Vec<_>>().join(", ")
+}
+
+/// The backends `--audio-backend` accepts on this system, and whether each
+/// is the default.
+pub fn list_backends() -> Vec<(&'static str, bool)> {
+    let default = cpal::default_host().id();
+    cpal::available_hosts()
+        .into_iter()
+        .map(|id| (id.name(), id == default))
+        .collect()
+}
+
+pub fn print_backends() {
+    println!("Audio backends (select with --audio-backend):");
+    for (name, is_default) in list_backends() {
+        println!("  {}{}", name, if is_default { " (default)" } else { "" });
+    }
 }
 
 pub fn list_output_devices(host: &Host) -> Result<Vec<DeviceInfo>> {
@@ -36,91 +92,659 @@ pub fn list_output_devices(host: &Host) -> Result<Vec<DeviceInfo>> {
     Ok(devices)
 }
 
+pub fn list_input_devices(host: &Host) -> Result<Vec<DeviceInfo>> {
+    let default_device = host.default_input_device();
+    let default_name = default_device
+        .as_ref()
+        .and_then(|d| d.name().ok());
+
+    let devices: Vec<_> = host
+        .input_devices()
+        .context("Failed to enumerate input devices")?
+        .enumerate()
+        .filter_map(|(i, device)| {
+            let name = device.name().ok()?;
+            let is_default = default_name.as_ref().map(|dn| dn == &name).unwrap_or(false);
+            Some(DeviceInfo {
+                index: i as u32,
+                name,
+                is_default,
+            })
+        })
+        .collect();
+
+    Ok(devices)
+}
+
 pub fn print_devices(host: &Host) -> Result<()> {
     let devices = list_output_devices(host)?;
     
     if devices.is_empty() {
         println!("No output devices found.");
-        return Ok(());
+    } else {
+        println!("Available audio output devices:");
+        for device in &devices {
+            let default_marker = if device.is_default { " (default)" } else { "" };
+            println!("  [{}] {}{}", device.index, device.name, default_marker);
+        }
     }
 
-    println!("Available audio output devices:");
-    for device in &devices {
-        let default_marker = if device.is_default { " (default)" } else { "" };
-        println!("  [{}] {}{}", device.index, device.name, default_marker);
+    let inputs = list_input_devices(host)?;
+    if inputs.is_empty() {
+        println!("No input devices found.");
+    } else {
+        println!("Available audio input devices (--input-device):");
+        for device in &inputs {
+            let default_marker = if device.is_default { " (default)" } else { "" };
+            println!("  [{}] {}{}", device.index, device.name, default_marker);
+        }
     }
 
     Ok(())
 }
 
-pub fn select_device(host: &Host, device_index: Option<u32>) -> Result<Device> {
-    match device_index {
-        Some(index) => {
-            let devices: Vec<_> = host
+/// How `--device` and `--input-device` name a device. Indices change when
+/// devices are plugged in, names don't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// The system default, even when a session file names a device.
+    Default,
+    /// Position in `--list-devices`.
+    Index(u32),
+    /// All or part of the device's name, ignoring case.
+    Name(String),
+}
+
+pub fn parse_device_selector(s: &str) -> Result<DeviceSelector, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("expected a device index, part of its name, or 'default'".to_string());
+    }
+    if s.eq_ignore_ascii_case("default") {
+        return Ok(DeviceSelector::Default);
+    }
+    Ok(s.parse().map_or_else(|_| DeviceSelector::Name(s.to_string()), DeviceSelector::Index))
+}
+
+/// `None` and `Default` both pick the system default.
+pub fn select_device(host: &Host, selector: Option<&DeviceSelector>) -> Result<Device> {
+    let query = match selector {
+        None | Some(DeviceSelector::Default) => {
+            return host
+                .default_output_device()
+                .ok_or_else(|| anyhow!("No default output device available"));
+        }
+        Some(DeviceSelector::Index(index)) => {
+            return host
                 .output_devices()
                 .context("Failed to enumerate output devices")?
-                .collect();
-            
-            devices
-                .into_iter()
-                .nth(index as usize)
-                .ok_or_else(|| anyhow!("Device index {} not found", index))
+                .nth(*index as usize)
+                .ok_or_else(|| anyhow!("Device index {} not found", index));
         }
-        None => host
-            .default_output_device()
-            .ok_or_else(|| anyhow!("No default output device available")),
+        Some(DeviceSelector::Name(query)) => query,
+    };
+    let devices: Vec<_> = host
+        .output_devices()
+        .context("Failed to enumerate output devices")?
+        .collect();
+    let names: Vec<String> = devices.iter().map(|d| d.name().unwrap_or_default()).collect();
+    let index = match_device_name(&names, query, "output")?;
+    devices.into_iter().nth(index).context("Output device disappeared")
+}
+
+/// Like `select_device`, among the capture devices.
+pub fn select_input_device(host: &Host, selector: Option<&DeviceSelector>) -> Result<Device> {
+    let query = match selector {
+        None | Some(DeviceSelector::Default) => {
+            return host
+                .default_input_device()
+                .ok_or_else(|| anyhow!("No default input device available"));
+        }
+        Some(DeviceSelector::Index(index)) => {
+            return host
+                .input_devices()
+                .context("Failed to enumerate input devices")?
+                .nth(*index as usize)
+                .ok_or_else(|| anyhow!("Input device index {} not found", index));
+        }
+        Some(DeviceSelector::Name(query)) => query,
+    };
+    let devices: Vec<_> = host
+        .input_devices()
+        .context("Failed to enumerate input devices")?
+        .collect();
+    let names: Vec<String> = devices.iter().map(|d| d.name().unwrap_or_default()).collect();
+    let index = match_device_name(&names, query, "input")?;
+    devices.into_iter().nth(index).context("Input device disappeared")
+}
+
+/// Position of the device `query` names: the one whose whole name matches,
+/// ignoring case, or else the only one whose name contains it. Several
+/// matches are an error listing them, so the wrong interface is never
+/// picked silently.
+fn match_device_name(names: &[String], query: &str, kind: &str) -> Result<usize> {
+    let query_lower = query.to_lowercase();
+    let listed = |indices: &[usize]| {
+        indices
+            .iter()
+            .map(|&i| format!("[{}] {}", i, names[i]))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let exact: Vec<usize> = (0..names.len()).filter(|&i| names[i].to_lowercase() == query_lower).collect();
+    let candidates = if exact.is_empty() {
+        (0..names.len()).filter(|&i| names[i].to_lowercase().contains(&query_lower)).collect()
+    } else {
+        exact
+    };
+    match candidates.as_slice() {
+        [index] => Ok(*index),
+        [] => {
+            let all: Vec<usize> = (0..names.len()).collect();
+            Err(anyhow!("No {} device matches '{}'; devices: {}", kind, query, listed(&all)))
+        }
+        _ => Err(anyhow!(
+            "'{}' matches several {} devices: {}; use more of the name or an index",
+            query,
+            kind,
+            listed(&candidates)
+        )),
     }
 }
 
 pub struct AudioConfig {
     pub sample_rate: u32,
     pub channels: u16,
+    /// Nominal block size. With `fixed_buffer_size` it is exactly what the
+    /// stream is opened with; otherwise the backend picks.
     pub buffer_size: u32,
     pub sample_format: SampleFormat,
+    /// False when the device doesn't report a buffer range and the stream has
+    /// to be opened with `BufferSize::Default`.
+    pub fixed_buffer_size: bool,
+    /// Largest callback the plugin may be handed; it is activated with this.
+    pub max_callback_frames: u32,
 }
 
-pub fn get_device_config(
-    device: &Device,
-    preferred_sample_rate: Option<u32>,
-    preferred_channels: Option<u16>,
-    preferred_buffer_size: Option<u32>,
-) -> Result<AudioConfig> {
+/// Activation size when the backend picks the buffer size and won't say how
+/// large it can get.
+const UNKNOWN_MAX_CALLBACK_FRAMES: u32 = 8192;
+
+/// What the user asked the stream to be; `None` leaves it to the device.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConfigRequest {
+    pub sample_rate: Option<u32>,
+    /// `--channels`, or every channel `--output-channels` needs.
+    pub channels: Option<u16>,
+    /// The last plugin's main output, used when `channels` is absent; see
+    /// `negotiate_channels`.
+    pub plugin_channels: Option<u16>,
+    pub buffer_size: Option<u32>,
+    pub sample_format: Option<SampleFormat>,
+    /// `--strict-config`: fail rather than fall back to what the device offers.
+    pub strict: bool,
+}
+
+pub fn get_device_config(device: &Device, request: ConfigRequest) -> Result<AudioConfig> {
     let default_config = device
         .default_output_config()
         .context("Failed to get default output config")?;
+    let ranges: Vec<SupportedStreamConfigRange> = device
+        .supported_output_configs()
+        .context("Failed to get supported configs")?
+        .collect();
+    negotiate_config(&ranges, &default_config, request)
+}
 
-    let sample_rate = preferred_sample_rate.unwrap_or(default_config.sample_rate().0);
-    let channels = preferred_channels.unwrap_or(default_config.channels());
-    let buffer_size = preferred_buffer_size.unwrap_or(512);
-    let sample_format = default_config.sample_format();
+/// `get_device_config` over the device's supported `ranges` and its
+/// `default_config`.
+fn negotiate_config(
+    ranges: &[SupportedStreamConfigRange],
+    default_config: &SupportedStreamConfig,
+    request: ConfigRequest,
+) -> Result<AudioConfig> {
+    let ConfigRequest {
+        sample_rate: preferred_sample_rate,
+        channels: preferred_channels,
+        plugin_channels,
+        buffer_size: preferred_buffer_size,
+        sample_format: preferred_sample_format,
+        strict,
+    } = request;
+    let mut sample_rate = preferred_sample_rate.unwrap_or(default_config.sample_rate().0);
+    let channels = match preferred_channels {
+        Some(channels) => channels,
+        None => {
+            let max_channels = ranges
+                .iter()
+                .map(|cfg| cfg.channels())
+                .max()
+                .unwrap_or(default_config.channels());
+            negotiate_channels(plugin_channels, default_config.channels(), max_channels)
+        }
+    };
+    let mut configs = matching_configs(ranges, sample_rate, channels);
+    if configs.is_empty() {
+        let unsupported = format!(
+            "Device does not support {}Hz with {} channels (supported: {}; see --list-configs)",
+            sample_rate,
+            channels,
+            describe_ranges(ranges)
+        );
+        let nearest = if strict { None } else { nearest_sample_rate(ranges, sample_rate, channels) };
+        let Some(nearest) = nearest else {
+            return Err(anyhow!("{}", unsupported));
+        };
+        log::warn!("{}; using {}Hz instead (--strict-config makes this an error)", unsupported, nearest);
+        sample_rate = nearest;
+        configs = matching_configs(ranges, sample_rate, channels);
+    }
+    let mut formats: Vec<SampleFormat> = Vec::new();
+    for cfg in &configs {
+        if !formats.contains(&cfg.sample_format()) {
+            formats.push(cfg.sample_format());
+        }
+    }
+
+    let sample_format = match preferred_sample_format {
+        Some(format) if formats.contains(&format) => format,
+        Some(format) => {
+            let offered: Vec<String> = formats.iter().map(|f| f.to_string()).collect();
+            return Err(anyhow!(
+                "Sample format {} is not supported at {}Hz with {} channels (device offers: {})",
+                format,
+                sample_rate,
+                channels,
+                if offered.is_empty() { "none".to_string() } else { offered.join(", ") }
+            ));
+        }
+        None => preferred_default_format(&formats).unwrap_or(default_config.sample_format()),
+    };
+
+    let buffer_range = configs
+        .iter()
+        .find(|cfg| cfg.sample_format() == sample_format)
+        .map(|cfg| *cfg.buffer_size())
+        .unwrap_or(*default_config.buffer_size());
+
+    let requested = preferred_buffer_size.unwrap_or(512);
+    let (buffer_size, fixed_buffer_size, max_callback_frames) = match buffer_range {
+        SupportedBufferSize::Range { min, max } => {
+            let size = requested.clamp(min, max);
+            if size != requested {
+                if strict && preferred_buffer_size.is_some() {
+                    return Err(anyhow!(
+                        "Buffer size {} is outside the device's supported range {}-{} (--strict-config)",
+                        requested,
+                        min,
+                        max
+                    ));
+                }
+                let message = format!(
+                    "Buffer size {} is outside the device's supported range {}-{}; using {}",
+                    requested, min, max, size
+                );
+                if preferred_buffer_size.is_some() {
+                    log::warn!("{}", message);
+                } else {
+                    log::info!("{}", message);
+                }
+            }
+            (size, true, size)
+        }
+        SupportedBufferSize::Unknown => {
+            log::warn!(
+                "Device does not report a supported buffer range; letting the backend choose \
+                 and activating the plugin for up to {} frames",
+                UNKNOWN_MAX_CALLBACK_FRAMES
+            );
+            (requested, false, UNKNOWN_MAX_CALLBACK_FRAMES.max(requested))
+        }
+    };
 
     Ok(AudioConfig {
         sample_rate,
         channels,
         buffer_size,
         sample_format,
+        fixed_buffer_size,
+        max_callback_frames,
     })
 }
 
-pub fn find_supported_config(
-    device: &Device,
-    config: &AudioConfig,
-) -> Result<SupportedStreamConfig> {
-    let supported_configs: Vec<_> = device
+/// The channel count to ask the device for. With `--output-channels` and no
+/// `--channels`, the stream opens with every channel the device has so any of
+/// them can be routed to.
+pub fn requested_channels(device: &Device, channels: Option<u16>, output_channels: &[u16]) -> Result<Option<u16>> {
+    if channels.is_some() || output_channels.is_empty() {
+        return Ok(channels);
+    }
+    Ok(device
         .supported_output_configs()
         .context("Failed to get supported configs")?
-        .collect();
+        .map(|cfg| cfg.channels())
+        .max())
+}
+
+/// `--output-channels` (1-based) as 0-based device channels, or every
+/// channel when none were given.
+pub fn output_routes(output_channels: &[u16], channels: u16) -> Result<Vec<usize>> {
+    if output_channels.is_empty() {
+        return Ok((0..channels as usize).collect());
+    }
+    output_channels
+        .iter()
+        .map(|&channel| {
+            if channel == 0 || channel > channels {
+                Err(anyhow!(
+                    "Output channel {} is not one of the stream's channels 1-{} (see --channels)",
+                    channel,
+                    channels
+                ))
+            } else {
+                Ok(channel as usize - 1)
+            }
+        })
+        .collect()
+}
 
-    for cfg in &supported_configs {
-        if cfg.channels() >= config.channels
-            && cfg.min_sample_rate().0 <= config.sample_rate
-            && cfg.max_sample_rate().0 >= config.sample_rate
-        {
-            return Ok(cfg.clone().with_sample_rate(cpal::SampleRate(config.sample_rate)));
+/// Channel count when `--channels` is absent: the plugin's main output if
+/// the device can play that many, otherwise the most the device offers, with
+/// the plugin's output mixed down onto them. Mono plugins and plugins without
+/// an output keep the device default; mono output is copied to every channel.
+pub fn negotiate_channels(plugin_channels: Option<u16>, device_default: u16, device_max: u16) -> u16 {
+    match plugin_channels {
+        Some(plugin) if plugin > 1 && plugin <= device_max => {
+            if plugin != device_default {
+                log::info!(
+                    "Using the plugin's {} output channels instead of the device default of {}",
+                    plugin,
+                    device_default
+                );
+            }
+            plugin
         }
+        Some(plugin) if plugin > device_max => {
+            log::warn!(
+                "The plugin has {} output channels but the device offers at most {}; mixing down (see --channels)",
+                plugin,
+                device_max
+            );
+            device_max
+        }
+        _ => device_default,
+    }
+}
+
+/// Config ranges covering this rate and channel count, in the order the
+/// device reports them.
+fn matching_configs(
+    ranges: &[SupportedStreamConfigRange],
+    sample_rate: u32,
+    channels: u16,
+) -> Vec<&SupportedStreamConfigRange> {
+    ranges
+        .iter()
+        .filter(|cfg| {
+            cfg.channels() >= channels
+                && cfg.min_sample_rate().0 <= sample_rate
+                && cfg.max_sample_rate().0 >= sample_rate
+        })
+        .collect()
+}
+
+/// The supported rate closest to `sample_rate` among the ranges that have
+/// enough channels; `None` if no range does.
+fn nearest_sample_rate(ranges: &[SupportedStreamConfigRange], sample_rate: u32, channels: u16) -> Option<u32> {
+    ranges
+        .iter()
+        .filter(|cfg| cfg.channels() >= channels)
+        .map(|cfg| sample_rate.clamp(cfg.min_sample_rate().0, cfg.max_sample_rate().0))
+        .min_by_key(|rate| rate.abs_diff(sample_rate))
+}
+
+/// One `channels ch min-max Hz` entry per distinct supported range.
+fn describe_ranges(ranges: &[SupportedStreamConfigRange]) -> String {
+    let mut described: Vec<String> = Vec::new();
+    for cfg in ranges {
+        let range = format!("{} ch {}-{} Hz", cfg.channels(), cfg.min_sample_rate().0, cfg.max_sample_rate().0);
+        if !described.contains(&range) {
+            described.push(range);
+        }
+    }
+    if described.is_empty() {
+        "none".to_string()
+    } else {
+        described.join(", ")
+    }
+}
+
+/// Float formats avoid quantizing quiet plugin output, so take them over
+/// whatever the device's default config happens to use.
+fn preferred_default_format(formats: &[SampleFormat]) -> Option<SampleFormat> {
+    [SampleFormat::F32, SampleFormat::F64]
+        .into_iter()
+        .find(|f| formats.contains(f))
+}
+
+pub fn parse_sample_format(s: &str) -> Result<SampleFormat, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "f32" => Ok(SampleFormat::F32),
+        "f64" => Ok(SampleFormat::F64),
+        "i8" => Ok(SampleFormat::I8),
+        "i16" => Ok(SampleFormat::I16),
+        "i32" => Ok(SampleFormat::I32),
+        "u8" => Ok(SampleFormat::U8),
+        "u16" => Ok(SampleFormat::U16),
+        "u32" => Ok(SampleFormat::U32),
+        _ => Err(format!("unknown sample format '{}' (expected f32, f64, i8, i16, i32, u8, u16 or u32)", s)),
     }
+}
 
-    device
+/// Prints each supported config range of the selected device with the sample
+/// formats it offers. `*` marks the format used when `--sample-format` is not given.
+pub fn print_configs(host: &Host, selector: Option<&DeviceSelector>) -> Result<()> {
+    let device = select_device(host, selector)?;
+    let default_config = device
         .default_output_config()
-        .context("No suitable output config found")
+        .context("Failed to get default output config")?;
+
+    // Ranges that differ only in sample format are listed on one line.
+    let mut ranges: Vec<((u16, u32, u32), Vec<SampleFormat>)> = Vec::new();
+    for cfg in device
+        .supported_output_configs()
+        .context("Failed to get supported configs")?
+    {
+        let key = (cfg.channels(), cfg.min_sample_rate().0, cfg.max_sample_rate().0);
+        match ranges.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, formats)) => formats.push(cfg.sample_format()),
+            None => ranges.push((key, vec![cfg.sample_format()])),
+        }
+    }
+
+    println!("Supported output configs for {}:", device.name().unwrap_or_default());
+    for ((channels, min_rate, max_rate), formats) in &ranges {
+        let chosen = preferred_default_format(formats).unwrap_or(default_config.sample_format());
+        let formats: Vec<String> = formats
+            .iter()
+            .map(|f| if *f == chosen { format!("{}*", f) } else { f.to_string() })
+            .collect();
+        println!("  {} ch, {}-{} Hz: {}", channels, min_rate, max_rate, formats.join(", "));
+    }
+    println!(
+        "Default: {} ch, {} Hz, {}. * = format chosen when --sample-format is not given",
+        default_config.channels(),
+        default_config.sample_rate().0,
+        default_config.sample_format()
+    );
+
+    Ok(())
+}
+
+/// Config used when no device is opened (`--output`, `--no-audio`).
+pub fn get_headless_config(
+    preferred_sample_rate: Option<u32>,
+    preferred_channels: Option<u16>,
+    plugin_channels: Option<u16>,
+    preferred_buffer_size: Option<u32>,
+) -> AudioConfig {
+    let buffer_size = preferred_buffer_size.unwrap_or(512);
+    AudioConfig {
+        sample_rate: preferred_sample_rate.unwrap_or(48000),
+        channels: preferred_channels.unwrap_or_else(|| negotiate_channels(plugin_channels, 2, u16::MAX)),
+        buffer_size,
+        sample_format: SampleFormat::F32,
+        fixed_buffer_size: true,
+        max_callback_frames: buffer_size,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::SampleRate;
+
+    fn range(
+        channels: u16,
+        rates: (u32, u32),
+        buffer: SupportedBufferSize,
+        format: SampleFormat,
+    ) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(channels, SampleRate(rates.0), SampleRate(rates.1), buffer, format)
+    }
+
+    /// A stereo interface at 44.1 or 48kHz, in 16-bit and float, with
+    /// buffers of 64 to 1024 frames.
+    fn interface() -> (Vec<SupportedStreamConfigRange>, SupportedStreamConfig) {
+        let buffer = SupportedBufferSize::Range { min: 64, max: 1024 };
+        let ranges = vec![
+            range(2, (44100, 48000), buffer, SampleFormat::I16),
+            range(2, (44100, 48000), buffer, SampleFormat::F32),
+        ];
+        let default_config = SupportedStreamConfig::new(2, SampleRate(48000), buffer, SampleFormat::I16);
+        (ranges, default_config)
+    }
+
+    #[test]
+    fn float_format_and_requested_size_are_used() {
+        let (ranges, default_config) = interface();
+        let request = ConfigRequest { sample_rate: Some(44100), buffer_size: Some(256), ..Default::default() };
+        let config = negotiate_config(&ranges, &default_config, request).unwrap();
+        assert_eq!((config.sample_rate, config.channels), (44100, 2));
+        assert_eq!(config.sample_format, SampleFormat::F32);
+        assert_eq!((config.buffer_size, config.fixed_buffer_size, config.max_callback_frames), (256, true, 256));
+    }
+
+    #[test]
+    fn buffer_size_is_clamped_to_the_range_unless_strict() {
+        let (ranges, default_config) = interface();
+        let request = ConfigRequest { buffer_size: Some(4096), ..Default::default() };
+        let config = negotiate_config(&ranges, &default_config, request).unwrap();
+        assert_eq!(config.buffer_size, 1024);
+        let request = ConfigRequest { buffer_size: Some(4096), strict: true, ..Default::default() };
+        assert!(negotiate_config(&ranges, &default_config, request).is_err());
+    }
+
+    #[test]
+    fn unsupported_rate_falls_back_to_the_nearest_unless_strict() {
+        let (ranges, default_config) = interface();
+        let request = ConfigRequest { sample_rate: Some(96000), ..Default::default() };
+        let config = negotiate_config(&ranges, &default_config, request).unwrap();
+        assert_eq!(config.sample_rate, 48000);
+
+        let request = ConfigRequest { sample_rate: Some(96000), strict: true, ..Default::default() };
+        let error = negotiate_config(&ranges, &default_config, request).err().unwrap().to_string();
+        assert!(error.contains("96000Hz with 2 channels"), "{}", error);
+        assert!(error.contains("supported: 2 ch 44100-48000 Hz;"), "{}", error);
+    }
+
+    #[test]
+    fn too_many_channels_is_an_error() {
+        let (ranges, default_config) = interface();
+        let request = ConfigRequest { channels: Some(8), ..Default::default() };
+        let error = negotiate_config(&ranges, &default_config, request).err().unwrap();
+        assert!(error.to_string().contains("48000Hz with 8 channels"), "{}", error);
+    }
+
+    #[test]
+    fn unoffered_sample_format_is_an_error() {
+        let (ranges, default_config) = interface();
+        let request = ConfigRequest { sample_format: Some(SampleFormat::I32), ..Default::default() };
+        let error = negotiate_config(&ranges, &default_config, request).err().unwrap();
+        assert!(error.to_string().contains("device offers: i16, f32"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_buffer_range_lets_the_backend_choose() {
+        let ranges = vec![range(2, (48000, 48000), SupportedBufferSize::Unknown, SampleFormat::F32)];
+        let default_config =
+            SupportedStreamConfig::new(2, SampleRate(48000), SupportedBufferSize::Unknown, SampleFormat::F32);
+        let request = ConfigRequest { buffer_size: Some(256), ..Default::default() };
+        let config = negotiate_config(&ranges, &default_config, request).unwrap();
+        assert!(!config.fixed_buffer_size);
+        assert_eq!(config.max_callback_frames, UNKNOWN_MAX_CALLBACK_FRAMES);
+    }
+
+    #[test]
+    fn plugin_channels_pick_the_stream_width() {
+        let buffer = SupportedBufferSize::Range { min: 64, max: 1024 };
+        let ranges = vec![range(8, (48000, 48000), buffer, SampleFormat::F32)];
+        let default_config = SupportedStreamConfig::new(2, SampleRate(48000), buffer, SampleFormat::F32);
+        let request = ConfigRequest { plugin_channels: Some(6), ..Default::default() };
+        let config = negotiate_config(&ranges, &default_config, request).unwrap();
+        assert_eq!(config.channels, 6);
+        let request = ConfigRequest { plugin_channels: Some(16), ..Default::default() };
+        let config = negotiate_config(&ranges, &default_config, request).unwrap();
+        assert_eq!(config.channels, 8);
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn device_selectors() {
+        assert_eq!(parse_device_selector("2"), Ok(DeviceSelector::Index(2)));
+        assert_eq!(parse_device_selector(" Default "), Ok(DeviceSelector::Default));
+        assert_eq!(parse_device_selector("Scarlett"), Ok(DeviceSelector::Name("Scarlett".to_string())));
+        assert!(parse_device_selector("  ").is_err());
+    }
+
+    #[test]
+    fn device_names_match_exactly_then_by_unique_substring() {
+        let devices = names(&["MacBook Pro Speakers", "Scarlett 2i2 USB", "Scarlett 2i2", "BlackHole 16ch"]);
+        assert_eq!(match_device_name(&devices, "scarlett 2i2", "output").unwrap(), 2);
+        assert_eq!(match_device_name(&devices, "USB", "output").unwrap(), 1);
+        assert_eq!(match_device_name(&devices, "blackhole", "output").unwrap(), 3);
+    }
+
+    #[test]
+    fn ambiguous_or_missing_device_names_list_the_candidates() {
+        let devices = names(&["Scarlett 2i2 USB", "Scarlett 18i20 USB", "Speakers"]);
+        let error = match_device_name(&devices, "scarlett", "output").unwrap_err().to_string();
+        assert!(error.contains("[0] Scarlett 2i2 USB, [1] Scarlett 18i20 USB;"), "{}", error);
+        assert!(!error.contains("Speakers"), "{}", error);
+
+        let error = match_device_name(&devices, "motu", "input").unwrap_err().to_string();
+        assert!(error.starts_with("No input device matches 'motu'"), "{}", error);
+        assert!(error.contains("[2] Speakers"), "{}", error);
+    }
+
+    #[test]
+    fn backends_list_the_default_once() {
+        let backends = list_backends();
+        let defaults: Vec<_> = backends.iter().filter(|(_, is_default)| *is_default).collect();
+        assert_eq!(defaults.len(), 1, "{:?}", backends);
+        assert_eq!(defaults[0].0, cpal::default_host().id().name());
+    }
+
+    #[test]
+    fn unknown_backends_are_rejected_with_the_available_ones() {
+        let error = get_cpal_host(Some("no-such-backend")).err().unwrap().to_string();
+        assert!(error.starts_with("Audio backend 'no-such-backend' is not compiled into this build"), "{}", error);
+        for (name, _) in list_backends() {
+            assert!(error.contains(name), "{}", error);
+        }
+    }
 }
@@ -1,18 +1,53 @@
-use crate::osc::Command;
+use crate::event_stats::BlockEventStats;
+use crate::features::GuiRequest;
+use crate::feedback::{Notification, NotificationProducer};
+use crate::input::InputSource;
+use crate::osc::{Command, ScheduledCommand};
+use crate::param_out::{PluginOutputEvent, PluginOutputProducer};
+use crate::plugin::AudioPortLayout;
+use crate::presets::{lossy, PresetLocation};
+use crate::ramp::Ramps;
+use crate::record::RecordProducer;
+use crate::session::SharedSessionCounters;
+use crate::mix::Mixer;
+use crate::schedule::{Schedule, MAX_SCHEDULED};
+use crate::sustain::{HeldNote, Sustain};
+use crate::bend::{ActiveNote, ChannelBend};
+use crate::test_tone::{TestTone, TestToneTarget};
+use crate::transport::Transport;
+use crate::output::AudioSink;
 use anyhow::{Context, Result};
 use clack_extensions::audio_ports::{HostAudioPortsImpl, RescanType};
+#[cfg(feature = "gui")]
+use clack_extensions::gui::{GuiSize, HostGui, HostGuiImpl};
+use clack_extensions::latency::{HostLatency, HostLatencyImpl};
 use clack_extensions::log::{HostLog, HostLogImpl, LogSeverity};
 use clack_extensions::note_ports::{HostNotePortsImpl, NoteDialects, NotePortRescanFlags};
 use clack_extensions::params::{
     HostParams, HostParamsImplMainThread, HostParamsImplShared, ParamClearFlags, ParamRescanFlags,
 };
+use clack_extensions::preset_discovery::Location;
+use clack_extensions::preset_load::{HostPresetLoad, HostPresetLoadImpl};
+use clack_extensions::state::{HostState, HostStateImpl};
+use clack_extensions::voice_info::{HostVoiceInfo, HostVoiceInfoImpl};
 use clack_host::prelude::*;
 use clack_host::process::StartedPluginAudioProcessor;
 use cpal::traits::{DeviceTrait, StreamTrait};
-use cpal::{BuildStreamError, Device, FromSample, OutputCallbackInfo, Sample, SampleFormat, Stream, StreamConfig};
-use crossbeam_channel::{Receiver, Sender, unbounded};
-use rtrb::Consumer;
-use std::sync::OnceLock;
+use cpal::{
+    BuildStreamError, Device, FromSample, OutputCallbackInfo, Sample, SampleFormat, Stream, StreamConfig, StreamError,
+    StreamInstant,
+};
+use crossbeam_channel::{Receiver, Sender};
+use rtrb::{Consumer, Producer};
+use std::ffi::CStr;
+use std::fmt;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 pub struct OscClapHost;
 
@@ -24,24 +59,102 @@ impl HostHandlers for OscClapHost {
     fn declare_extensions(builder: &mut HostExtensions<Self>, _shared: &Self::Shared<'_>) {
         builder
             .register::<HostLog>()
-            .register::<HostParams>();
+            .register::<HostLatency>()
+            .register::<HostParams>()
+            .register::<HostPresetLoad>()
+            .register::<HostState>()
+            .register::<HostVoiceInfo>();
+        #[cfg(feature = "gui")]
+        builder.register::<HostGui>();
     }
 }
 
 pub enum MainThreadMessage {
     RunOnMainThread,
-    DumpPatchState,
+    /// Serialize the plugin state to `path`, or base64-encode it back to
+    /// `reply_to` as `/patchState`. With neither, a timestamped file is written.
+    SaveState {
+        path: Option<PathBuf>,
+        reply_to: Option<SocketAddr>,
+    },
+    /// Replace the plugin state with the contents of a state file.
+    LoadState { path: PathBuf },
+    /// Another thread panicked; shut down gracefully and exit.
+    Panic { thread: String, message: String },
+    /// `--self-test` finished; leave the main loop and exit 0 if it heard sound.
+    SelfTestDone { passed: bool },
+    /// Ctrl+C: leave the main loop and deactivate the plugins.
+    Shutdown,
+    /// The audio stream opened as `generation` reported an error.
+    /// `device_lost` means the device is gone and the stream won't recover
+    /// by itself.
+    StreamError {
+        generation: u64,
+        device_lost: bool,
+        message: String,
+    },
+    /// `/plugin/load`: replace the main plugin with `plugin_id` (or the
+    /// bundle's only plugin) from the bundle at `path`, replying to `reply_to`.
+    LoadPlugin {
+        path: PathBuf,
+        plugin_id: Option<String>,
+        reply_to: Option<SocketAddr>,
+    },
+    SetParamFromText { param_id: u32, text: String },
+    /// Plugin `plugin` (chain index) asked to be deactivated and activated
+    /// again, e.g. because its latency changed.
+    RestartPlugin { plugin: usize },
+    /// A plugin's latency changed; re-query and announce the chain's total.
+    LatencyChanged,
+    /// The main plugin's voice count or capacity changed; re-query it.
+    VoiceInfoChanged,
+    /// `/preset/load`: have the main plugin load the preset at
+    /// `location_uri`, picked by `load_key` if the location holds several.
+    LoadPreset {
+        location_uri: String,
+        load_key: Option<String>,
+        reply_to: Option<SocketAddr>,
+    },
+    /// Plugin `plugin` (chain index) loaded a preset, from `/preset/load` or
+    /// its own editor, so its parameters may have changed.
+    PresetLoaded { plugin: usize },
+    /// Plugin `plugin` (chain index) changed its parameter list, names,
+    /// ranges, flags or texts; re-read them. `all` is a full rescan, which
+    /// CLAP only allows while the plugin is deactivated.
+    ParamsChanged { plugin: usize, all: bool },
+    /// Plugin `plugin` (chain index) asked the host to drop what it holds
+    /// for `param_id`, usually because the parameter is going away.
+    ParamCleared { plugin: usize, param_id: u32 },
+    /// Plugin `plugin` (chain index) asked for a parameter flush, or the OSC
+    /// thread handed over parameter values for it while it is stopped. While
+    /// it is active, the next block takes care of it.
+    FlushRequested { plugin: usize },
+    /// `/param/text` or `/param/get`: reply to `reply_to` on `address` with
+    /// the live value of a param of `plugin` (chain index) and the plugin's
+    /// text for it.
+    ParamText {
+        plugin: usize,
+        param_id: u32,
+        reply_to: SocketAddr,
+        address: &'static str,
+    },
+    /// Only sent when the `gui` feature is on.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    Gui(GuiRequest),
 }
 
 pub struct OscClapHostShared {
     sender: Sender<MainThreadMessage>,
+    /// Position of this plugin in the chain; 0 is the main plugin.
+    plugin: usize,
     callbacks: OnceLock<()>,
 }
 
 impl OscClapHostShared {
-    pub fn new(sender: Sender<MainThreadMessage>) -> Self {
+    pub fn new(sender: Sender<MainThreadMessage>, plugin: usize) -> Self {
         Self {
             sender,
+            plugin,
             callbacks: OnceLock::new(),
         }
     }
@@ -52,7 +165,9 @@ impl<'a> SharedHandler<'a> for OscClapHostShared {
         let _ = self.callbacks.set(());
     }
 
-    fn request_restart(&self) {}
+    fn request_restart(&self) {
+        let _ = self.sender.send(MainThreadMessage::RestartPlugin { plugin: self.plugin });
+    }
 
     fn request_process(&self) {}
 
@@ -62,14 +177,14 @@ impl<'a> SharedHandler<'a> for OscClapHostShared {
 }
 
 pub struct OscClapHostMainThread<'a> {
-    _shared: &'a OscClapHostShared,
+    shared: &'a OscClapHostShared,
     _plugin: Option<InitializedPluginHandle<'a>>,
 }
 
 impl<'a> OscClapHostMainThread<'a> {
     pub fn new(shared: &'a OscClapHostShared) -> Self {
         Self {
-            _shared: shared,
+            shared,
             _plugin: None,
         }
     }
@@ -81,6 +196,37 @@ impl<'a> MainThreadHandler<'a> for OscClapHostMainThread<'a> {
     }
 }
 
+impl HostLatencyImpl for OscClapHostMainThread<'_> {
+    fn changed(&mut self) {
+        let _ = self.shared.sender.send(MainThreadMessage::LatencyChanged);
+    }
+}
+
+impl HostVoiceInfoImpl for OscClapHostMainThread<'_> {
+    fn changed(&mut self) {
+        if self.shared.plugin == 0 {
+            let _ = self.shared.sender.send(MainThreadMessage::VoiceInfoChanged);
+        }
+    }
+}
+
+impl HostPresetLoadImpl for OscClapHostMainThread<'_> {
+    fn on_error(&mut self, location: Location, load_key: Option<&CStr>, os_error: i32, message: Option<&CStr>) {
+        log::error!(
+            "Plugin {} failed to load preset {}{}: {} (OS error {})",
+            self.shared.plugin,
+            PresetLocation::from_clap(location).uri(),
+            load_key.map_or_else(String::new, |key| format!(" ({})", lossy(key))),
+            message.map_or_else(|| "no message".to_string(), lossy),
+            os_error
+        );
+    }
+
+    fn loaded(&mut self, _location: Location, _load_key: Option<&CStr>) {
+        let _ = self.shared.sender.send(MainThreadMessage::PresetLoaded { plugin: self.shared.plugin });
+    }
+}
+
 impl HostLogImpl for OscClapHostShared {
     fn log(&self, severity: LogSeverity, message: &str) {
         match severity {
@@ -111,227 +257,1570 @@ impl HostNotePortsImpl for OscClapHostMainThread<'_> {
     fn rescan(&mut self, _flags: NotePortRescanFlags) {}
 }
 
+// Plugins call these from inside other main-thread calls (a preset load,
+// activation), so the work is left to the main loop rather than done here.
 impl HostParamsImplMainThread for OscClapHostMainThread<'_> {
-    fn rescan(&mut self, _flags: ParamRescanFlags) {}
-    fn clear(&mut self, _param_id: ClapId, _flags: ParamClearFlags) {}
+    fn rescan(&mut self, flags: ParamRescanFlags) {
+        // Current values aren't part of the parameter table, so a
+        // values-only rescan leaves nothing to refresh.
+        if flags.intersects(ParamRescanFlags::ALL | ParamRescanFlags::INFO | ParamRescanFlags::TEXT) {
+            let _ = self.shared.sender.send(MainThreadMessage::ParamsChanged {
+                plugin: self.shared.plugin,
+                all: flags.contains(ParamRescanFlags::ALL),
+            });
+        }
+    }
+
+    fn clear(&mut self, param_id: ClapId, _flags: ParamClearFlags) {
+        let _ = self.shared.sender.send(MainThreadMessage::ParamCleared {
+            plugin: self.shared.plugin,
+            param_id: param_id.get(),
+        });
+    }
 }
 
 impl HostParamsImplShared for OscClapHostShared {
-    fn request_flush(&self) {}
+    fn request_flush(&self) {
+        let _ = self.sender.send(MainThreadMessage::FlushRequested { plugin: self.plugin });
+    }
+}
+
+#[cfg(feature = "gui")]
+impl HostGuiImpl for OscClapHostShared {
+    fn resize_hints_changed(&self) {
+        let _ = self.sender.send(MainThreadMessage::Gui(GuiRequest::ResizeHintsChanged));
+    }
+
+    fn request_resize(&self, new_size: GuiSize) -> Result<(), HostError> {
+        let _ = self.sender.send(MainThreadMessage::Gui(GuiRequest::Resize {
+            width: new_size.width,
+            height: new_size.height,
+        }));
+        Ok(())
+    }
+
+    fn request_show(&self) -> Result<(), HostError> {
+        Ok(())
+    }
+
+    fn request_hide(&self) -> Result<(), HostError> {
+        Ok(())
+    }
+
+    fn closed(&self, was_destroyed: bool) {
+        let _ = self.sender.send(MainThreadMessage::Gui(GuiRequest::Closed { was_destroyed }));
+    }
+}
+
+impl HostStateImpl for OscClapHostMainThread<'_> {
+    fn mark_dirty(&mut self) {}
+}
+
+/// A started plugin handed to the engine. The engine runs them in the order
+/// given, feeding each one's main output into the next one's main input.
+pub struct ChainPlugin {
+    pub processor: StartedPluginAudioProcessor<OscClapHost>,
+    pub ports: AudioPortLayout,
+    /// Notes that don't name a plugin go to the first instrument in the chain.
+    pub is_instrument: bool,
+    /// `(param_id, value)` at activation; parameter ramps start from here
+    /// until the host or the plugin changes a value.
+    pub param_values: Vec<(u32, f64)>,
+    /// `(param_id, min, max)`, which `/param/inc` clamps to.
+    pub param_ranges: Vec<(u32, f64, f64)>,
+}
+
+/// The plugin chain and everything around it that the audio thread takes
+/// over, whichever backend drives it.
+pub struct EngineOptions {
+    pub plugins: Vec<ChainPlugin>,
+    pub command_consumer: Consumer<ScheduledCommand>,
+    /// Published after each block so the OSC thread can resolve cue positions.
+    pub sample_position: Arc<AtomicU64>,
+    pub event_stats: Producer<BlockEventStats>,
+    /// Param and note activity for controller feedback, if enabled.
+    pub notifications: Option<NotificationProducer>,
+    /// Parameter and note events the plugins emit, forwarded over OSC if enabled.
+    pub param_output: Option<PluginOutputProducer>,
+    /// Copy of the output for `--record`.
+    pub recorder: Option<RecordProducer>,
+    /// Captured audio for the first plugin's inputs; silence when `None`.
+    pub input: Option<InputSource>,
+    pub test_tone: TestTone,
+    pub mixer: Mixer,
+    /// Once set, output fades to zero over one block and stays silent.
+    pub silence: Arc<AtomicBool>,
+    /// Raised by an immediate `/panic`.
+    pub panic: Arc<AtomicBool>,
+    pub session: SharedSessionCounters,
+    pub channel_count: usize,
+    /// Device channels the last plugin's main output goes to.
+    pub output_channels: Vec<usize>,
+    pub verbose: bool,
 }
 
 pub struct AudioEngine {
-    stream: Stream,
-    _receiver: Receiver<MainThreadMessage>,
-    _sender: Sender<MainThreadMessage>,  // Keep sender alive to prevent receiver from disconnecting
+    backend: EngineBackend,
+    swapper: PluginSwapper,
+}
+
+enum EngineBackend {
+    Stream {
+        /// `None` after the stream failed and couldn't be reopened.
+        stream: Option<Stream>,
+        /// Counts the streams opened, so errors from a replaced one are ignored.
+        generation: u64,
+        config: StreamConfig,
+        sample_format: SampleFormat,
+        /// Asks the audio callback to give the plugins back.
+        release: Arc<AtomicBool>,
+        released: Receiver<Vec<Option<StartedPluginAudioProcessor<OscClapHost>>>>,
+        /// The other end of the handoff, for each new stream.
+        released_sender: Sender<Vec<Option<StartedPluginAudioProcessor<OscClapHost>>>>,
+        /// The processor comes back here when cpal drops a stream's callback.
+        returned: Receiver<StreamAudioProcessor>,
+        returned_sender: Sender<StreamAudioProcessor>,
+        /// Where stream errors are reported.
+        errors: Sender<MainThreadMessage>,
+    },
+    Clocked {
+        running: Arc<AtomicBool>,
+        thread: Option<JoinHandle<Vec<Option<StartedPluginAudioProcessor<OscClapHost>>>>>,
+    },
+}
+
+/// Audio-callback side of `EngineBackend::Stream`'s release handshake.
+struct PluginHandoff {
+    release: Arc<AtomicBool>,
+    released: Sender<Vec<Option<StartedPluginAudioProcessor<OscClapHost>>>>,
+}
+
+/// Owns the processor inside a stream callback and sends it back when cpal
+/// drops the callback, whether the stream was closed or never got built, so
+/// the plugins and any held commands outlive a lost device.
+struct ProcessorGuard {
+    processor: Option<StreamAudioProcessor>,
+    returned: Sender<StreamAudioProcessor>,
+}
+
+impl Drop for ProcessorGuard {
+    fn drop(&mut self) {
+        if let Some(processor) = self.processor.take() {
+            let _ = self.returned.send(processor);
+        }
+    }
+}
+
+/// What the main thread asks of the audio thread to restart or replace one
+/// plugin while the rest play on: take it out of the chain, put it back, or
+/// put a different plugin in its place.
+enum PluginSwap {
+    Take(usize),
+    Return(usize, StartedPluginAudioProcessor<OscClapHost>),
+    Replace(usize, Box<StageReplacement>),
+    Params(usize, Box<StageParams>),
+}
+
+/// A stage built on the main thread for `PluginSwap::Replace`. The audio
+/// thread swaps it with the stage it replaces and sends the box back, so the
+/// old stage's buffers are freed on the main thread.
+struct StageReplacement {
+    stage: ChainStage,
+    /// `(param_id, value)` sorted by id, for the ramps.
+    param_values: Vec<(u32, f64)>,
+}
+
+/// Parameter values and ranges re-read after a plugin loaded a state or
+/// preset, for `PluginSwap::Params`. The audio thread swaps them with the
+/// stage's and sends the box back with the old ones.
+struct StageParams {
+    /// `(param_id, value)` sorted by id, for the ramps and `/param/inc`.
+    values: Vec<(u32, f64)>,
+    /// `(param_id, min, max)` sorted by id.
+    ranges: Vec<(u32, f64, f64)>,
+}
+
+/// Main-thread end of the plugin swap.
+#[derive(Clone)]
+struct PluginSwapper {
+    requests: Sender<PluginSwap>,
+    /// Plugins the audio thread took out, with their chain index.
+    taken: Receiver<(usize, Option<StartedPluginAudioProcessor<OscClapHost>>)>,
+    /// Stages the audio thread swapped out for a replacement.
+    retired: Receiver<Box<StageReplacement>>,
+    /// Parameter tables the audio thread swapped out for fresh ones.
+    rescanned: Receiver<Box<StageParams>>,
+    /// What replacement stages are sized for.
+    channel_count: usize,
+    max_buffer_size: usize,
 }
 
 impl AudioEngine {
+    /// `max_buffer_size` is the `max_frames_count` the plugins were activated
+    /// with; longer device callbacks are split into blocks of that size.
+    /// Stream errors are sent to `errors` as `MainThreadMessage::StreamError`.
     pub fn new(
         device: &Device,
         config: StreamConfig,
         sample_format: SampleFormat,
-        audio_processor: StartedPluginAudioProcessor<OscClapHost>,
-        command_consumer: Consumer<Command>,
-        channel_count: usize,
+        errors: Sender<MainThreadMessage>,
         max_buffer_size: usize,
-        verbose: bool,
-    ) -> Result<(Self, Receiver<MainThreadMessage>)> {
-        let (sender, receiver) = unbounded();
+        options: EngineOptions,
+    ) -> Result<Self> {
+        let processor = StreamAudioProcessor::new(options, config.sample_rate.0, max_buffer_size);
 
-        let processor = StreamAudioProcessor::new(
-            audio_processor,
-            command_consumer,
-            sender.clone(),
-            channel_count,
-            max_buffer_size,
-            verbose,
-        );
+        let swapper = processor.swapper();
+        let release = Arc::new(AtomicBool::new(false));
+        let (released_sender, released) = crossbeam_channel::bounded(1);
+        let (returned_sender, returned) = crossbeam_channel::bounded(1);
+        let handoff = PluginHandoff {
+            release: release.clone(),
+            released: released_sender.clone(),
+        };
+        let guard = ProcessorGuard {
+            processor: Some(processor),
+            returned: returned_sender.clone(),
+        };
 
-        let stream = build_output_stream_for_sample_format(device, processor, &config, sample_format)?;
+        let stream =
+            build_output_stream_for_sample_format(device, guard, handoff, &config, sample_format, errors.clone(), 0)?;
         stream.play().context("Failed to start audio stream")?;
 
-        Ok((
-            Self {
-                stream,
-                _receiver: receiver.clone(),
-                _sender: sender,  // Store sender to keep it alive
+        Ok(Self {
+            backend: EngineBackend::Stream {
+                stream: Some(stream),
+                generation: 0,
+                config,
+                sample_format,
+                release,
+                released,
+                released_sender,
+                returned,
+                returned_sender,
+                errors,
             },
-            receiver,
-        ))
+            swapper,
+        })
+    }
+
+    /// True if a `StreamError` with this generation came from the stream
+    /// that is running now, rather than one already replaced.
+    pub fn is_current_stream(&self, error_generation: u64) -> bool {
+        matches!(&self.backend, EngineBackend::Stream { generation, .. } if *generation == error_generation)
+    }
+
+    /// Closes the current stream and opens a new one on `device` with the
+    /// same config, taking the plugins and held commands along. Commands
+    /// queued meanwhile play in the first block. On failure no stream runs
+    /// and the processor waits for the next attempt.
+    pub fn reopen_stream(&mut self, device: &Device) -> Result<()> {
+        let EngineBackend::Stream {
+            stream,
+            generation,
+            config,
+            sample_format,
+            release,
+            released_sender,
+            returned,
+            returned_sender,
+            errors,
+            ..
+        } = &mut self.backend
+        else {
+            anyhow::bail!("the clocked engine has no audio stream");
+        };
+        if let Some(old) = stream.take() {
+            let _ = old.pause();
+            // Dropping the stream drops its callback, which sends the processor back.
+            drop(old);
+        }
+        let processor = returned
+            .recv_timeout(Duration::from_secs(2))
+            .context("The audio callback did not hand back the plugins")?;
+
+        *generation += 1;
+        let handoff = PluginHandoff {
+            release: release.clone(),
+            released: released_sender.clone(),
+        };
+        let guard = ProcessorGuard {
+            processor: Some(processor),
+            returned: returned_sender.clone(),
+        };
+        // On failure cpal drops the callback, and with it the guard, so the
+        // processor is back in `returned` for the next attempt.
+        let new_stream = build_output_stream_for_sample_format(
+            device,
+            guard,
+            handoff,
+            config,
+            *sample_format,
+            errors.clone(),
+            *generation,
+        )
+        .context("Failed to build audio stream")?;
+        new_stream.play().context("Failed to start audio stream")?;
+        *stream = Some(new_stream);
+        Ok(())
+    }
+
+    /// Runs the plugin on a dedicated thread paced by the wall clock instead of
+    /// an audio device, handing each block to `sink`.
+    pub fn new_clocked(
+        mut sink: Box<dyn AudioSink>,
+        sample_rate: u32,
+        buffer_size: usize,
+        options: EngineOptions,
+    ) -> Result<Self> {
+        let clock_session = options.session.clone();
+        let channel_count = options.channel_count;
+        let mut processor = StreamAudioProcessor::new(options, sample_rate, buffer_size);
+
+        let swapper = processor.swapper();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let block_duration = Duration::from_secs_f64(buffer_size as f64 / sample_rate as f64);
+
+        let thread = thread::Builder::new()
+            .name("clocked-audio".into())
+            .spawn(move || {
+                let mut block = vec![0.0f32; buffer_size * channel_count];
+                let mut deadline = Instant::now();
+
+                while thread_running.load(Ordering::Relaxed) {
+                    processor.process(&mut block);
+                    sink.write(&block, channel_count);
+
+                    deadline += block_duration;
+                    let now = Instant::now();
+                    if deadline > now {
+                        thread::sleep(deadline - now);
+                    } else if now - deadline > block_duration * 4 {
+                        // Fell far behind (suspended machine, debugger); resync instead of bursting.
+                        log::warn!("Clocked audio loop fell behind, resyncing");
+                        clock_session.record_xrun();
+                        deadline = now;
+                    }
+                }
+                processor.release_plugins()
+            })
+            .context("Failed to spawn clocked audio thread")?;
+
+        Ok(Self {
+            backend: EngineBackend::Clocked {
+                running,
+                thread: Some(thread),
+            },
+            swapper,
+        })
+    }
+
+    pub fn stream(&self) -> Option<&Stream> {
+        match &self.backend {
+            EngineBackend::Stream { stream, .. } => stream.as_ref(),
+            EngineBackend::Clocked { .. } => None,
+        }
+    }
+
+    /// Takes plugin `index` out of the chain so the main thread can restart
+    /// it. Its stage is silent until `return_plugin`; the others keep
+    /// playing. `None` if the plugin is already out or the audio thread
+    /// didn't respond within `timeout`.
+    pub fn take_plugin(&self, index: usize, timeout: Duration) -> Option<StartedPluginAudioProcessor<OscClapHost>> {
+        // A plugin that came out after an earlier take timed out goes back.
+        while let Ok((late, processor)) = self.swapper.taken.try_recv() {
+            if let Some(processor) = processor {
+                self.return_plugin(late, processor);
+            }
+        }
+        self.swapper.requests.send(PluginSwap::Take(index)).ok()?;
+        match self.swapper.taken.recv_timeout(timeout) {
+            Ok((_, processor)) => processor,
+            Err(_) => {
+                log::warn!("Audio thread did not hand over plugin {} within {:?}", index, timeout);
+                None
+            }
+        }
+    }
+
+    /// Puts a started plugin back at `index` of the chain.
+    pub fn return_plugin(&self, index: usize, processor: StartedPluginAudioProcessor<OscClapHost>) {
+        let _ = self.swapper.requests.send(PluginSwap::Return(index, processor));
+    }
+
+    /// Puts a different plugin at `index` of the chain, after `take_plugin`
+    /// emptied it. Ramps on the old plugin's parameters are dropped.
+    pub fn replace_plugin(&self, index: usize, mut plugin: ChainPlugin) {
+        let mut param_values = std::mem::take(&mut plugin.param_values);
+        param_values.sort_by_key(|(id, _)| *id);
+        let replacement = StageReplacement {
+            stage: ChainStage::new(plugin, self.swapper.channel_count, self.swapper.max_buffer_size),
+            param_values,
+        };
+        if self.swapper.requests.send(PluginSwap::Replace(index, Box::new(replacement))).is_err() {
+            return;
+        }
+        // Wait for the old stage so it is freed here rather than left in the channel.
+        if self.swapper.retired.recv_timeout(Duration::from_secs(1)).is_err() {
+            log::warn!("Audio thread did not confirm the new plugin {}; it will start when audio resumes", index);
+        }
+    }
+
+    /// Replaces the parameter values and ranges the engine keeps for plugin
+    /// `index`, which ramps and `/param/inc` start from, after the plugin
+    /// loaded a state or preset. Ramps on its parameters stop.
+    pub fn update_params(&self, index: usize, mut values: Vec<(u32, f64)>, mut ranges: Vec<(u32, f64, f64)>) {
+        values.sort_by_key(|(id, _)| *id);
+        ranges.sort_by_key(|(id, _, _)| *id);
+        let params = Box::new(StageParams { values, ranges });
+        if self.swapper.requests.send(PluginSwap::Params(index, params)).is_err() {
+            return;
+        }
+        // Wait for the old tables so they are freed here.
+        if self.swapper.rescanned.recv_timeout(Duration::from_secs(1)).is_err() {
+            log::warn!("Audio thread did not take plugin {}'s new parameter values; they apply when it resumes", index);
+        }
+    }
+
+    /// Stops audio and hands back the started plugins, in chain order, so
+    /// the main thread can stop and deactivate them. `None` marks a plugin
+    /// that is out of the chain after a failed restart. Empty if the audio
+    /// thread didn't respond within `timeout`.
+    pub fn shutdown(mut self, timeout: Duration) -> Vec<Option<StartedPluginAudioProcessor<OscClapHost>>> {
+        let mut plugins = match &mut self.backend {
+            EngineBackend::Stream {
+                stream,
+                release,
+                released,
+                returned,
+                ..
+            } => {
+                let handed_back = stream.as_ref().and_then(|stream| {
+                    release.store(true, Ordering::Relaxed);
+                    let plugins = released.recv_timeout(timeout).ok();
+                    let _ = stream.pause();
+                    plugins
+                });
+                // A stream whose device is gone never calls back; dropping it
+                // returns the processor instead. After a failed reconnect
+                // it is already back.
+                handed_back.unwrap_or_else(|| {
+                    drop(stream.take());
+                    returned
+                        .recv_timeout(timeout)
+                        .map(|mut processor| processor.release_plugins())
+                        .unwrap_or_else(|_| {
+                            log::warn!("Audio callback did not hand back the plugins within {:?}", timeout);
+                            Vec::new()
+                        })
+                })
+            }
+            EngineBackend::Clocked { running, thread } => {
+                running.store(false, Ordering::Relaxed);
+                thread.take().and_then(|thread| thread.join().ok()).unwrap_or_default()
+            }
+        };
+        // A plugin taken out after its restart gave up waiting still needs deactivating.
+        while let Ok((index, processor)) = self.swapper.taken.try_recv() {
+            if let (Some(slot), Some(processor)) = (plugins.get_mut(index), processor) {
+                slot.get_or_insert(processor);
+            }
+        }
+        plugins
     }
+}
 
-    pub fn stream(&self) -> &Stream {
-        &self.stream
+impl Drop for AudioEngine {
+    fn drop(&mut self) {
+        if let EngineBackend::Clocked { running, thread } = &mut self.backend {
+            running.store(false, Ordering::Relaxed);
+            if let Some(thread) = thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// Runs the chain block by block as fast as it can, for `--render`. The
+/// caller queues each block's commands before rendering it, so the output
+/// depends only on the script.
+pub struct OfflineRenderer {
+    processor: StreamAudioProcessor,
+    block: Vec<f32>,
+}
+
+impl OfflineRenderer {
+    /// Renders blocks of `buffer_size` frames.
+    pub fn new(options: EngineOptions, sample_rate: u32, buffer_size: usize) -> Self {
+        let channel_count = options.channel_count;
+        let processor = StreamAudioProcessor::new(options, sample_rate, buffer_size);
+        Self {
+            processor,
+            block: vec![0.0; buffer_size * channel_count],
+        }
+    }
+
+    /// Sample position of the next block.
+    pub fn position(&self) -> u64 {
+        self.processor.steady_counter
+    }
+
+    pub fn block_frames(&self) -> usize {
+        self.block.len() / self.processor.channel_count
+    }
+
+    /// Processes the next block and returns it interleaved.
+    pub fn render_block(&mut self) -> &[f32] {
+        self.processor.process(&mut self.block);
+        &self.block
+    }
+
+    /// Hands back the started plugins, in chain order.
+    pub fn finish(mut self) -> Vec<Option<StartedPluginAudioProcessor<OscClapHost>>> {
+        self.processor.release_plugins()
     }
 }
 
 fn build_output_stream_for_sample_format(
     device: &Device,
-    processor: StreamAudioProcessor,
+    processor: ProcessorGuard,
+    handoff: PluginHandoff,
     config: &StreamConfig,
     sample_format: SampleFormat,
+    errors: Sender<MainThreadMessage>,
+    generation: u64,
 ) -> Result<Stream, BuildStreamError> {
-    let err = |e| log::error!("Audio stream error: {}", e);
+    let err = move |e: StreamError| {
+        log::error!("Audio stream error: {}", e);
+        let _ = errors.send(MainThreadMessage::StreamError {
+            generation,
+            device_lost: matches!(e, StreamError::DeviceNotAvailable),
+            message: e.to_string(),
+        });
+    };
 
     match sample_format {
-        SampleFormat::I8 => device.build_output_stream(config, make_stream_runner::<i8>(processor), err, None),
-        SampleFormat::I16 => device.build_output_stream(config, make_stream_runner::<i16>(processor), err, None),
-        SampleFormat::I32 => device.build_output_stream(config, make_stream_runner::<i32>(processor), err, None),
-        SampleFormat::U8 => device.build_output_stream(config, make_stream_runner::<u8>(processor), err, None),
-        SampleFormat::U16 => device.build_output_stream(config, make_stream_runner::<u16>(processor), err, None),
-        SampleFormat::U32 => device.build_output_stream(config, make_stream_runner::<u32>(processor), err, None),
-        SampleFormat::F32 => device.build_output_stream(config, make_stream_runner::<f32>(processor), err, None),
-        SampleFormat::F64 => device.build_output_stream(config, make_stream_runner::<f64>(processor), err, None),
-        _ => device.build_output_stream(config, make_stream_runner::<f32>(processor), err, None),
+        SampleFormat::I8 => device.build_output_stream(config, make_stream_runner::<i8>(processor, handoff), err, None),
+        SampleFormat::I16 => device.build_output_stream(config, make_stream_runner::<i16>(processor, handoff), err, None),
+        SampleFormat::I32 => device.build_output_stream(config, make_stream_runner::<i32>(processor, handoff), err, None),
+        SampleFormat::U8 => device.build_output_stream(config, make_stream_runner::<u8>(processor, handoff), err, None),
+        SampleFormat::U16 => device.build_output_stream(config, make_stream_runner::<u16>(processor, handoff), err, None),
+        SampleFormat::U32 => device.build_output_stream(config, make_stream_runner::<u32>(processor, handoff), err, None),
+        SampleFormat::F32 => device.build_output_stream(config, make_stream_runner::<f32>(processor, handoff), err, None),
+        SampleFormat::F64 => device.build_output_stream(config, make_stream_runner::<f64>(processor, handoff), err, None),
+        _ => device.build_output_stream(config, make_stream_runner::<f32>(processor, handoff), err, None),
     }
 }
 
 fn make_stream_runner<S: FromSample<f32> + Sample>(
-    mut audio_processor: StreamAudioProcessor,
+    mut guard: ProcessorGuard,
+    handoff: PluginHandoff,
 ) -> impl FnMut(&mut [S], &OutputCallbackInfo) {
-    move |data, _info| audio_processor.process(data)
+    // A reopened stream's timestamps don't continue the old one's.
+    if let Some(audio_processor) = guard.processor.as_mut() {
+        audio_processor.last_playback = None;
+    }
+    move |data, info| {
+        let Some(audio_processor) = guard.processor.as_mut() else {
+            return;
+        };
+        if handoff.release.load(Ordering::Relaxed) && !audio_processor.stages.is_empty() {
+            let _ = handoff.released.try_send(audio_processor.release_plugins());
+        }
+        audio_processor.check_callback_gap(info.timestamp().playback, data.len());
+        audio_processor.process(data)
+    }
 }
 
+/// Events each stage's input and output lists hold before they have to grow:
+/// a full command queue plus everything held back.
+const EVENT_CAPACITY: usize = 2 * MAX_SCHEDULED;
+
 struct StreamAudioProcessor {
-    audio_processor: StartedPluginAudioProcessor<OscClapHost>,
-    command_consumer: Consumer<Command>,
-    main_thread_sender: Sender<MainThreadMessage>,
-    input_ports: AudioPorts,
-    output_ports: AudioPorts,
-    input_buffers: Vec<f32>,
+    /// The plugins in processing order; each one's main output feeds the next
+    /// one's main input.
+    stages: Vec<ChainStage>,
+    /// Stage that receives notes not addressed to a specific plugin.
+    instrument: usize,
+    command_consumer: Consumer<ScheduledCommand>,
+    /// Published after each block so the OSC thread can resolve cue positions.
+    sample_position: Arc<AtomicU64>,
+    /// Commands held for later blocks, and the ones due in the current one.
+    schedule: Schedule,
+    /// Per-block counters, aggregated on the main thread.
+    event_stats: Producer<BlockEventStats>,
+    /// Distinct (stage, param) pairs touched in the current block, for `event_stats`.
+    touched_params: Vec<(usize, u32)>,
+    /// Param and note activity for controller feedback, if enabled.
+    notifications: Option<NotificationProducer>,
+    /// Parameter and note events the plugins emit, forwarded over OSC if enabled.
+    param_output: Option<PluginOutputProducer>,
+    /// Copy of the output for `--record`.
+    recorder: Option<RecordProducer>,
+    /// Captured audio for the first plugin's inputs; silence when `None`.
+    input: Option<InputSource>,
+    /// Test signal that can replace the first plugin's input or the output.
+    test_tone: TestTone,
+    /// `/mix/master` and `/mix/channel` gains on the device channels, then
+    /// the master bus.
+    mixer: Mixer,
+    /// Once set, output fades to zero over one block and stays silent.
+    silence: Arc<AtomicBool>,
+    /// Raised by an immediate `/panic`; checked at the start of each block.
+    panic: Arc<AtomicBool>,
+    /// Parameters moving towards a `/param/ramp` target.
+    ramps: Ramps,
+    /// Plugin restarts: the main thread asks for a plugin and returns it here.
+    swap_requests: Receiver<PluginSwap>,
+    taken: Sender<(usize, Option<StartedPluginAudioProcessor<OscClapHost>>)>,
+    /// `/plugin/load` replacements go back here once swapped in.
+    retired: Sender<Box<StageReplacement>>,
+    /// Parameter tables replaced after a state or preset load go back here.
+    rescanned: Sender<Box<StageParams>>,
+    /// The other end of all four, for the engine.
+    swapper: PluginSwapper,
+    /// Host clock reported to every plugin each block.
+    transport: Transport,
+    /// `/pedal/sustain` state and the note-offs it is holding.
+    sustain: Sustain,
+    /// `/channel/bend` per channel and the notes it retunes.
+    bend: ChannelBend,
+    /// Block timing, xruns and plugin errors for the session summary.
+    session: SharedSessionCounters,
+    /// Playback time and frame count of the previous device callback.
+    last_playback: Option<(StreamInstant, usize)>,
+    sample_rate: u32,
+    output_gain: f32,
+    /// The last stage's main output mapped onto the device's channels.
     output_buffers: Vec<f32>,
     channel_count: usize,
+    /// Device channels the last stage's main output goes to, from
+    /// `--output-channels`; every channel when not given. The rest stay silent.
+    output_channels: Vec<usize>,
+    /// The plugins' `max_frames_count`; longer callbacks are processed in
+    /// blocks of at most this many frames.
+    max_block_frames: usize,
     steady_counter: u64,
     verbose: bool,
 }
 
 impl StreamAudioProcessor {
-    fn new(
-        audio_processor: StartedPluginAudioProcessor<OscClapHost>,
-        command_consumer: Consumer<Command>,
-        main_thread_sender: Sender<MainThreadMessage>,
-        channel_count: usize,
-        max_buffer_size: usize,
-        verbose: bool,
-    ) -> Self {
+    fn new(options: EngineOptions, sample_rate: u32, max_buffer_size: usize) -> Self {
+        let EngineOptions {
+            plugins,
+            command_consumer,
+            sample_position,
+            event_stats,
+            notifications,
+            param_output,
+            recorder,
+            input,
+            test_tone,
+            mixer,
+            silence,
+            panic,
+            session,
+            channel_count,
+            output_channels,
+            verbose,
+        } = options;
+        let max_buffer_size = max_buffer_size.max(1);
+        // Plugins see only the routed channels, as if they were the device.
+        let routed_count = output_channels.len();
+        let instrument = plugins.iter().position(|p| p.is_instrument).unwrap_or(0);
+        let ramps = Ramps::new(plugins.iter().map(|p| p.param_values.clone()).collect());
+        let stages: Vec<ChainStage> = plugins
+            .into_iter()
+            .map(|plugin| ChainStage::new(plugin, routed_count, max_buffer_size))
+            .collect();
+
+        for (index, pair) in stages.windows(2).enumerate() {
+            let (from, to) = (pair[0].main_output_channels(), pair[1].main_input_channels());
+            if to == 0 {
+                log::info!("Plugin {} has no audio input; plugin {}'s output is discarded", index + 1, index);
+            } else if from != to {
+                log::info!(
+                    "Mapping plugin {}'s {}-channel output onto plugin {}'s {}-channel input",
+                    index,
+                    from,
+                    index + 1,
+                    to
+                );
+            }
+        }
+        for (index, stage) in stages.iter().enumerate() {
+            if stage.output_port_channels.len() > 1 {
+                log::info!(
+                    "Plugin {} has {} output ports; only the main one is passed on",
+                    index,
+                    stage.output_port_channels.len()
+                );
+            }
+        }
+        let last = stages.last().expect("the chain has at least one plugin");
+        if last.main_output_channels() != routed_count {
+            log::info!(
+                "Mapping the plugin's {}-channel main output onto {} device channel(s)",
+                last.main_output_channels(),
+                routed_count
+            );
+        }
+        if routed_count != channel_count {
+            log::info!(
+                "Routing the output to device channel(s) {} of {}",
+                output_channels.iter().map(|c| (c + 1).to_string()).collect::<Vec<_>>().join(","),
+                channel_count
+            );
+        }
+
+        let (requests, swap_requests) = crossbeam_channel::unbounded();
+        let (taken, taken_receiver) = crossbeam_channel::unbounded();
+        let (retired, retired_receiver) = crossbeam_channel::unbounded();
+        let (rescanned, rescanned_receiver) = crossbeam_channel::unbounded();
+
         Self {
-            audio_processor,
+            stages,
+            instrument,
             command_consumer,
-            main_thread_sender,
-            input_ports: AudioPorts::with_capacity(channel_count, 1),
-            output_ports: AudioPorts::with_capacity(channel_count, 1),
-            input_buffers: vec![0.0; channel_count * max_buffer_size],
+            sample_position,
+            schedule: Schedule::new(EVENT_CAPACITY),
+            event_stats,
+            touched_params: Vec::with_capacity(MAX_SCHEDULED),
+            notifications,
+            param_output,
+            recorder,
+            input,
+            test_tone,
+            mixer,
+            silence,
+            panic,
+            ramps,
+            swap_requests,
+            taken,
+            retired,
+            rescanned,
+            swapper: PluginSwapper {
+                requests,
+                taken: taken_receiver,
+                retired: retired_receiver,
+                rescanned: rescanned_receiver,
+                channel_count: routed_count,
+                max_buffer_size,
+            },
+            transport: Transport::new(),
+            sustain: Sustain::new(),
+            bend: ChannelBend::new(),
+            session,
+            last_playback: None,
+            sample_rate,
+            output_gain: 1.0,
             output_buffers: vec![0.0; channel_count * max_buffer_size],
             channel_count,
+            output_channels,
+            max_block_frames: max_buffer_size,
             steady_counter: 0,
             verbose,
         }
     }
 
+    /// Gives up the plugins; later blocks are silent.
+    fn release_plugins(&mut self) -> Vec<Option<StartedPluginAudioProcessor<OscClapHost>>> {
+        self.stages.drain(..).map(|stage| stage.processor).collect()
+    }
+
+    fn swapper(&self) -> PluginSwapper {
+        self.swapper.clone()
+    }
+
+    /// Takes out, puts back or replaces the plugins the main thread is
+    /// restarting or loading, and swaps in parameter tables it re-read.
+    fn swap_plugins(&mut self) {
+        while let Ok(swap) = self.swap_requests.try_recv() {
+            match swap {
+                PluginSwap::Take(index) => {
+                    let processor = self.stages.get_mut(index).and_then(|stage| stage.processor.take());
+                    let _ = self.taken.send((index, processor));
+                }
+                PluginSwap::Return(index, processor) => {
+                    if let Some(stage) = self.stages.get_mut(index) {
+                        stage.processor = Some(processor);
+                    }
+                }
+                PluginSwap::Replace(index, mut replacement) => {
+                    if let Some(stage) = self.stages.get_mut(index) {
+                        std::mem::swap(stage, &mut replacement.stage);
+                        self.ramps.replace_stage(index, &mut replacement.param_values);
+                        self.instrument = self.stages.iter().position(|s| s.is_instrument).unwrap_or(0);
+                    }
+                    let _ = self.retired.send(replacement);
+                }
+                PluginSwap::Params(index, mut params) => {
+                    if let Some(stage) = self.stages.get_mut(index) {
+                        std::mem::swap(&mut stage.param_ranges, &mut params.ranges);
+                        self.ramps.replace_stage(index, &mut params.values);
+                    }
+                    let _ = self.rescanned.send(params);
+                }
+            }
+        }
+    }
+
+    /// Counts a late callback, see `is_late_callback`. Allocation-free.
+    fn check_callback_gap(&mut self, playback: StreamInstant, samples: usize) {
+        let frames = samples / self.channel_count.max(1);
+        if let Some((last, last_frames)) = self.last_playback {
+            if is_late_callback(last, last_frames, playback, self.sample_rate) {
+                self.session.record_late_callback();
+            }
+        }
+        self.last_playback = Some((playback, frames));
+    }
+
+    /// Fills an interleaved callback buffer, in blocks no longer than the
+    /// plugins were activated for. Samples past the last whole frame are
+    /// silenced rather than left as they were.
     fn process<S: FromSample<f32> + Sample>(&mut self, data: &mut [S]) {
+        // Swaps pass boxed stages between threads, so they stay outside the
+        // allocation check.
+        self.swap_plugins();
+        // Verbose logging formats strings on this thread.
+        #[cfg(feature = "alloc-check")]
+        if !self.verbose {
+            return assert_no_alloc::assert_no_alloc(|| self.process_blocks(data));
+        }
+        self.process_blocks(data);
+    }
+
+    fn process_blocks<S: FromSample<f32> + Sample>(&mut self, data: &mut [S]) {
+        if self.stages.is_empty() {
+            data.fill(S::EQUILIBRIUM);
+            return;
+        }
+        let (channel_count, max_block_frames) = (self.channel_count, self.max_block_frames);
+        for_each_block(data, channel_count, max_block_frames, |block| self.process_block(block));
+    }
+
+    /// `data` holds at least one and at most `max_block_frames` whole frames.
+    /// Every buffer is sized for that at construction, so nothing here
+    /// allocates.
+    fn process_block<S: FromSample<f32> + Sample>(&mut self, data: &mut [S]) {
+        let started = Instant::now();
         let frame_count = data.len() / self.channel_count;
+        debug_assert!(frame_count <= self.max_block_frames);
         let needed_size = self.channel_count * frame_count;
 
-        if self.output_buffers.len() < needed_size {
-            self.input_buffers.resize(needed_size, 0.0);
-            self.output_buffers.resize(needed_size, 0.0);
+        self.output_buffers[..needed_size].fill(0.0);
+        for stage in &mut self.stages {
+            stage.prepare(frame_count);
         }
 
-        self.input_buffers[..needed_size].fill(0.0);
-        self.output_buffers[..needed_size].fill(0.0);
+        let first = &mut self.stages[0];
+        let main_input = first.main_input_range(frame_count);
+        let main_input_channels = first.main_input_channels();
+
+        let has_input = match &mut self.input {
+            Some(input) if main_input_channels > 0 => {
+                input.fill(&mut first.input_buffers[main_input.clone()], frame_count, main_input_channels);
+                true
+            }
+            _ => false,
+        };
+        let has_input = self.test_tone.render(
+            TestToneTarget::Plugin,
+            &mut first.input_buffers[main_input],
+            frame_count,
+            main_input_channels,
+        ) || has_input;
+
+        let (instrument, last_stage) = (self.instrument, self.stages.len() - 1);
+        let stage_for = |plugin, cmd: &Command| route_command(plugin, cmd, instrument, last_stage);
+
+        let capacity = self.command_consumer.buffer().capacity();
+        self.session
+            .record_queue_occupancy(self.command_consumer.slots() as f32 / capacity.max(1) as f32);
+        let panic = self.panic.swap(false, Ordering::Relaxed);
+        let due = self.schedule.collect(
+            &mut self.command_consumer,
+            self.steady_counter,
+            frame_count,
+            panic,
+            stage_for,
+            self.verbose,
+        );
 
-        let mut input_event_buffer = EventBuffer::new();
         let mut event_count = 0;
-        while let Ok(cmd) = self.command_consumer.pop() {
-            // Handle main-thread commands separately
-            if matches!(cmd, Command::DumpPatchState) {
-                let _ = self.main_thread_sender.send(MainThreadMessage::DumpPatchState);
+        let mut block_stats = BlockEventStats::default();
+        self.touched_params.clear();
+
+        // Ramp values go first, at the start of the block, ahead of anything due.
+        let (stages, touched_params, notifications) =
+            (&mut self.stages, &mut self.touched_params, &mut self.notifications);
+        self.ramps.next_block(frame_count, |stage, param_id, value| {
+            let Some(clap_id) = ClapId::from_raw(param_id) else {
+                return;
+            };
+            let pckn = Pckn::new(Match::All, Match::All, Match::All, Match::All);
+            let event = ParamValueEvent::new(0, clap_id, pckn, value, Cookie::empty());
+            stages[stage].input_events.push(&event);
+            touch_param(touched_params, stage, Some(param_id));
+            block_stats.events += 1;
+            block_stats.bytes += std::mem::size_of_val(&event) as u32;
+            event_count += 1;
+            if let (Some(notifications), 0) = (notifications.as_mut(), stage) {
+                let _ = notifications.push(Notification::ParamChanged { param_id, value });
+            }
+        });
+        let mut panicked = false;
+        for (time, _, stage, cmd) in due.drain(..) {
+            if let Command::Panic = cmd {
+                // Every voice in every plugin, with a note-off for plugins
+                // that ignore chokes. Notes and per-voice expression or
+                // modulation held for later blocks are dropped after the block.
+                // The OSC thread logs it.
+                panicked = true;
+                let all = Pckn::new(Match::All, Match::All, Match::All, Match::All);
+                for plugin in &mut self.stages {
+                    plugin.input_events.push(&NoteChokeEvent::new(time, all));
+                    plugin.input_events.push(&NoteOffEvent::new(time, all, 0.0));
+                }
+                block_stats.events += 2 * self.stages.len() as u32;
+                self.sustain.forget(-1);
+                self.bend.forget(-1);
+                continue;
+            }
+            // Increments apply to the value the parameter has now, even mid-ramp.
+            let cmd = if let Command::ParamInc { param_id, delta } = cmd {
+                let Some(value) = self.ramps.value(stage, param_id) else {
+                    if self.verbose {
+                        log::info!("[AUDIO-INC] Plugin {} param {} has no known value; dropped", stage, param_id);
+                    }
+                    continue;
+                };
+                let value = self.stages[stage].clamp_param(param_id, value + delta);
+                if self.verbose {
+                    log::info!("[AUDIO-INC] Plugin {} param {} by {} to {}", stage, param_id, delta, value);
+                }
+                Command::ParamSet { param_id, value }
+            } else {
+                cmd
+            };
+            let cmd = if let Command::ParamRamp {
+                param_id,
+                target,
+                duration_ms,
+            } = cmd
+            {
+                let samples = (duration_ms.max(0.0) * self.sample_rate as f64 / 1000.0).round() as u64;
+                let elapsed = frame_count.saturating_sub(time as usize) as u64;
+                if self.verbose {
+                    log::info!(
+                        "[AUDIO-RAMP] Plugin {} param {} to {} over {} samples",
+                        stage,
+                        param_id,
+                        target,
+                        samples
+                    );
+                }
+                // A ramp that can't run is sent as a plain set.
+                match self.ramps.start(stage, param_id, target, samples, elapsed) {
+                    Some(value) => Command::ParamSet { param_id, value },
+                    None => continue,
+                }
+            } else {
+                cmd
+            };
+            if let Command::ForgetParam { param_id } = cmd {
                 if self.verbose {
-                    log::info!("[AUDIO-FORWARD] Forwarding DumpPatchState to main thread");
+                    log::info!("[AUDIO-CLEAR] Plugin {} param {} forgotten", stage, param_id);
                 }
+                self.ramps.forget(stage, param_id);
                 continue;
             }
-
-            if self.verbose {
-                log::info!("[AUDIO-DEQUEUE] Processing command: {:?}", cmd);
+            // Transport changes apply from the start of the block.
+            if let Command::Transport(change) = &cmd {
+                if self.verbose {
+                    log::info!("[AUDIO-TRANSPORT] {:?}", change);
+                }
+                self.transport.apply(change);
+                continue;
             }
-            if let Some(event) = command_to_event(cmd.clone()) {
-                event_count += 1;
+            // So do master gain and mute, fading in from there.
+            if let Command::Master(change) = cmd {
                 if self.verbose {
-                    log::info!("[AUDIO-EVENT] Sending to plugin: {:?}", format_event(&event));
+                    log::info!("[AUDIO-MASTER] {:?}", change);
+                }
+                let master = self.mixer.master_mut();
+                master.apply(change);
+                if let Some(notifications) = &mut self.notifications {
+                    let _ = notifications.push(Notification::MasterGain {
+                        gain: master.gain(),
+                        muted: master.muted(),
+                    });
                 }
-                match event {
-                    EventUnion::NoteOn(e) => { input_event_buffer.push(&e); }
-                    EventUnion::NoteOff(e) => { input_event_buffer.push(&e); }
-                    EventUnion::NoteChoke(e) => { input_event_buffer.push(&e); }
-                    EventUnion::ParamValue(e) => { input_event_buffer.push(&e); }
-                    EventUnion::ParamMod(e) => { input_event_buffer.push(&e); }
+                continue;
+            }
+            // Note-offs wait for the pedal to lift; a held key pressed again
+            // is released first so it retriggers. A note with its own tuning
+            // or started on a bent channel is tuned right after its note-on.
+            let mut retune = None;
+            match cmd {
+                Command::Sustain { channel, down } => {
+                    if self.verbose {
+                        log::info!("[AUDIO-SUSTAIN] Channel {} pedal {}", channel, if down { "down" } else { "up" });
+                    }
+                    let (stages, notifications, bend) = (&mut self.stages, &mut self.notifications, &mut self.bend);
+                    self.sustain.set(channel, down, |held| {
+                        release_held(stages, notifications, bend, &mut block_stats, held, time);
+                    });
+                    continue;
+                }
+                Command::ChannelBend { channel, semitones } => {
+                    if self.verbose {
+                        log::info!("[AUDIO-BEND] Plugin {} channel {} by {} semitones", stage, channel, semitones);
+                    }
+                    let stages = &mut self.stages;
+                    self.bend.set(channel, semitones, |note| {
+                        send_tuning(stages, &mut block_stats, note, note.tuning + semitones, time);
+                    });
+                    continue;
+                }
+                Command::NoteOff {
+                    note_id,
+                    key,
+                    velocity,
+                    channel,
+                    port,
+                } => {
+                    let held = HeldNote {
+                        stage,
+                        note_id,
+                        key,
+                        velocity,
+                        channel,
+                        port,
+                    };
+                    if self.sustain.hold(held) {
+                        continue;
+                    }
+                    self.bend.note_off(stage, note_id, key, channel);
                 }
+                Command::NoteOn {
+                    note_id,
+                    key,
+                    channel,
+                    port,
+                    tuning,
+                    ..
+                } => {
+                    if let Some(held) = self.sustain.take_retrigger(stage, key, channel) {
+                        release_held(
+                            &mut self.stages,
+                            &mut self.notifications,
+                            &mut self.bend,
+                            &mut block_stats,
+                            held,
+                            time,
+                        );
+                    }
+                    let note = ActiveNote {
+                        stage,
+                        note_id,
+                        key,
+                        channel,
+                        port,
+                        tuning,
+                    };
+                    retune = self.bend.note_on(note).map(|semitones| (note, semitones));
+                }
+                Command::NoteChoke {
+                    note_id, key, channel, ..
+                } => self.bend.note_off(stage, note_id, key, channel),
+                Command::AllNotesOff { channel, .. } => {
+                    self.sustain.forget(channel);
+                    self.bend.forget(channel);
+                }
+                _ => {}
             }
+            let param_id = match &cmd {
+                Command::ParamSet { param_id, .. } | Command::ParamMod { param_id, .. } => Some(*param_id),
+                _ => None,
+            };
+            if let Command::ParamSet { param_id, value } = cmd {
+                self.ramps.set(stage, param_id, value);
+            }
+            // Controller feedback follows the first plugin's params.
+            if let Some(notifications) = &mut self.notifications {
+                if stage == 0 || param_id.is_none() {
+                    if let Some(notification) = command_notification(&cmd) {
+                        let _ = notifications.push(notification);
+                    }
+                }
+            }
+            if let Some(event) = command_to_event(cmd, time) {
+                event_count += 1;
+                if self.verbose {
+                    log::info!(
+                        "[AUDIO-EVENT] Sending to plugin {} at +{}: {}",
+                        stage,
+                        time,
+                        event
+                    );
+                }
+                let input_event_buffer = &mut self.stages[stage].input_events;
+                let size = match event {
+                    EventUnion::NoteOn(e) => {
+                        block_stats.notes_started += 1;
+                        input_event_buffer.push(&e);
+                        std::mem::size_of_val(&e)
+                    }
+                    EventUnion::NoteOff(e) => {
+                        block_stats.notes_ended += 1;
+                        input_event_buffer.push(&e);
+                        std::mem::size_of_val(&e)
+                    }
+                    EventUnion::NoteChoke(e) => {
+                        block_stats.notes_ended += 1;
+                        input_event_buffer.push(&e);
+                        std::mem::size_of_val(&e)
+                    }
+                    EventUnion::ParamValue(e) => {
+                        touch_param(&mut self.touched_params, stage, param_id);
+                        input_event_buffer.push(&e);
+                        std::mem::size_of_val(&e)
+                    }
+                    EventUnion::ParamMod(e) => {
+                        touch_param(&mut self.touched_params, stage, param_id);
+                        input_event_buffer.push(&e);
+                        std::mem::size_of_val(&e)
+                    }
+                    EventUnion::NoteExpression(e) => {
+                        input_event_buffer.push(&e);
+                        std::mem::size_of_val(&e)
+                    }
+                };
+                block_stats.events += 1;
+                block_stats.bytes += size as u32;
+            }
+            if let Some((note, semitones)) = retune {
+                send_tuning(&mut self.stages, &mut block_stats, &note, semitones, time);
+            }
+        }
+        if panicked {
+            self.schedule.drop_held_notes();
         }
+        block_stats.params = self.touched_params.len() as u32;
+        self.session.record_events_delivered(block_stats.events);
+        // Dropped if the main thread falls behind; the stats are diagnostic only.
+        let _ = self.event_stats.push(block_stats);
         if self.verbose && event_count > 0 {
             log::info!("[AUDIO-PROCESS] Processing {} events, {} frames", event_count, frame_count);
         }
 
-        let input_events_ref = InputEvents::from_buffer(&input_event_buffer);
+        let transport = self.transport.event();
+        for index in 0..self.stages.len() {
+            let (done, rest) = self.stages.split_at_mut(index);
+            let stage = &mut rest[0];
 
-        let mut output_events = EventBuffer::new();
-        let mut output_events_ref = OutputEvents::from_buffer(&mut output_events);
+            let live_input = match done.last() {
+                None => has_input,
+                Some(previous) => {
+                    let main_input = stage.main_input_range(frame_count);
+                    map_channels(
+                        &previous.output_buffers[previous.main_output_range(frame_count)],
+                        previous.main_output_channels(),
+                        &mut stage.input_buffers[main_input],
+                        stage.main_input_channels(),
+                        frame_count,
+                    );
+                    true
+                }
+            };
 
-        let channel_frame_count = frame_count;
-        let mut input_channels: Vec<&mut [f32]> = self.input_buffers[..needed_size]
-            .chunks_exact_mut(channel_frame_count)
-            .take(self.channel_count)
-            .collect();
-        let mut output_channels: Vec<&mut [f32]> = self.output_buffers[..needed_size]
-            .chunks_exact_mut(channel_frame_count)
-            .take(self.channel_count)
-            .collect();
+            match stage.process(frame_count, live_input, self.steady_counter, &transport) {
+                Ok(status) => {
+                    if self.verbose && event_count > 0 {
+                        log::info!("[AUDIO-STATUS] Plugin {} returned: {:?}", index, status);
+                    }
+                }
+                Err(e) => {
+                    // Later stages still run (a reverb tail keeps ringing) on silence.
+                    log::error!("Plugin {} process error: {:?}", index, e);
+                    self.session.record_plugin_error();
+                    stage.output_buffers.fill(0.0);
+                }
+            }
 
-        let inputs = self.input_ports.with_input_buffers([AudioPortBuffer {
-            latency: 0,
-            channels: AudioPortBufferType::f32_input_only(
-                input_channels.iter_mut().map(|ch| InputChannel {
-                    buffer: *ch,
-                    is_constant: true,
-                }),
-            ),
-        }]);
-
-        let mut outputs = self.output_ports.with_output_buffers([AudioPortBuffer {
-            latency: 0,
-            channels: AudioPortBufferType::f32_output_only(
-                output_channels.iter_mut().map(|ch| &mut **ch),
-            ),
-        }]);
-
-        match self.audio_processor.process(
-            &inputs,
-            &mut outputs,
-            &input_events_ref,
-            &mut output_events_ref,
-            Some(self.steady_counter),
-            None,
-        ) {
-            Ok(status) => {
-                if self.verbose && event_count > 0 {
-                    log::info!("[AUDIO-STATUS] Plugin returned: {:?}", status);
-                    // Log first few samples of output to check if audio is being generated
-                    let sample_preview: Vec<f32> = self.output_buffers.iter().take(8).cloned().collect();
-                    log::info!("[AUDIO-OUTPUT] First 8 samples: {:?}", sample_preview);
+            // A plugin moving a parameter itself stops any ramp on it.
+            for event in &stage.output_events {
+                if let Some(e) = event.as_event::<ParamValueEvent>() {
+                    if let Some(param_id) = e.param_id() {
+                        self.ramps.set(index, param_id.get(), e.value());
+                    }
                 }
-                interleave_to_output(data, &self.output_buffers, self.channel_count, frame_count);
             }
-            Err(e) => {
-                log::error!("Plugin process error: {:?}", e);
-                for sample in data.iter_mut() {
-                    *sample = S::EQUILIBRIUM;
+
+            // Finished voices and played notes are forwarded from every plugin.
+            if let Some(param_output) = &mut self.param_output {
+                forward_note_events(index, &stage.output_events, param_output);
+            }
+        }
+
+        let last = self.stages.last().expect("the chain has at least one plugin");
+        route_channels(
+            &last.output_buffers[last.main_output_range(frame_count)],
+            last.main_output_channels(),
+            &mut self.output_buffers[..needed_size],
+            &self.output_channels,
+            frame_count,
+        );
+        if self.verbose && event_count > 0 {
+            // Log first few samples of output to check if audio is being generated
+            let sample_preview: Vec<f32> = self.output_buffers.iter().take(8).cloned().collect();
+            log::info!("[AUDIO-OUTPUT] First 8 samples: {:?}", sample_preview);
+        }
+        self.test_tone.render(
+            TestToneTarget::Output,
+            &mut self.output_buffers[..needed_size],
+            frame_count,
+            self.channel_count,
+        );
+        self.mixer.apply(&mut self.output_buffers[..needed_size], frame_count);
+        if self.output_gain > 0.0 && self.silence.load(Ordering::Relaxed) {
+            fade_out(&mut self.output_buffers[..needed_size], frame_count, self.output_gain);
+            self.output_gain = 0.0;
+        } else if self.output_gain == 0.0 {
+            self.output_buffers[..needed_size].fill(0.0);
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.push_planar(&self.output_buffers[..needed_size], self.channel_count, frame_count);
+        }
+        self.session.output_meter().record(&self.output_buffers[..needed_size]);
+        interleave_to_output(data, &self.output_buffers, self.channel_count, frame_count);
+
+        // Changes the plugin made itself (e.g. a preset load or its own GUI)
+        // reach controllers too. Only the first plugin's are forwarded.
+        for event in &self.stages[0].output_events {
+            if let Some(e) = event.as_event::<ParamValueEvent>() {
+                let Some(param_id) = e.param_id() else { continue };
+                if let Some(notifications) = &mut self.notifications {
+                    let _ = notifications.push(Notification::ParamChanged {
+                        param_id: param_id.get(),
+                        value: e.value(),
+                    });
+                }
+                if let Some(param_output) = &mut self.param_output {
+                    let _ = param_output.push(PluginOutputEvent::Value {
+                        param_id: param_id.get(),
+                        value: e.value(),
+                    });
+                }
+            } else if let Some(param_output) = &mut self.param_output {
+                let gesture = if let Some(e) = event.as_event::<ParamGestureBeginEvent>() {
+                    e.param_id().map(|id| (id.get(), true))
+                } else {
+                    event
+                        .as_event::<ParamGestureEndEvent>()
+                        .and_then(|e| e.param_id())
+                        .map(|id| (id.get(), false))
+                };
+                if let Some((param_id, begin)) = gesture {
+                    let _ = param_output.push(PluginOutputEvent::Gesture { param_id, begin });
                 }
             }
         }
 
+        self.transport.advance(frame_count, self.sample_rate);
         self.steady_counter += frame_count as u64;
+        self.sample_position.store(self.steady_counter, Ordering::Relaxed);
+        self.session.record_block(
+            started.elapsed(),
+            Duration::from_secs_f64(frame_count as f64 / self.sample_rate as f64),
+        );
+    }
+}
+
+/// One plugin of the chain with its port buffers.
+struct ChainStage {
+    /// `None` while the plugin is being restarted, or for good if that failed.
+    processor: Option<StartedPluginAudioProcessor<OscClapHost>>,
+    input_ports: AudioPorts,
+    output_ports: AudioPorts,
+    /// Planar channels of every input port, in port order.
+    input_buffers: Vec<f32>,
+    /// Planar channels of every output port, in port order.
+    output_buffers: Vec<f32>,
+    /// Channels per input port. The main one gets captured audio (first
+    /// plugin) or the previous plugin's output; the rest (e.g. sidechains)
+    /// get silence.
+    input_port_channels: Vec<usize>,
+    main_input: Option<usize>,
+    /// Channels per output port. Only the main one is passed on; the rest
+    /// are processed and discarded.
+    output_port_channels: Vec<usize>,
+    main_output: usize,
+    /// `(param_id, min, max)` sorted by id.
+    param_ranges: Vec<(u32, f64, f64)>,
+    is_instrument: bool,
+    /// Events for the plugin this block, and the ones it sent back.
+    input_events: EventBuffer,
+    output_events: EventBuffer,
+}
+
+impl ChainStage {
+    fn new(plugin: ChainPlugin, channel_count: usize, max_buffer_size: usize) -> Self {
+        let ports = &plugin.ports;
+        let input_port_channels: Vec<usize> = ports.inputs.iter().map(|p| p.channel_count as usize).collect();
+        let mut output_port_channels: Vec<usize> = ports.outputs.iter().map(|p| p.channel_count as usize).collect();
+        if output_port_channels.is_empty() {
+            // No audio-ports extension: offer one port matching the device.
+            output_port_channels.push(channel_count);
+        }
+        let mut param_ranges = plugin.param_ranges;
+        param_ranges.sort_by_key(|(id, _, _)| *id);
+        let input_channel_total: usize = input_port_channels.iter().sum();
+        let output_channel_total: usize = output_port_channels.iter().sum();
+
+        Self {
+            input_ports: AudioPorts::with_capacity(input_channel_total, input_port_channels.len()),
+            output_ports: AudioPorts::with_capacity(output_channel_total, output_port_channels.len()),
+            input_buffers: vec![0.0; input_channel_total * max_buffer_size],
+            output_buffers: vec![0.0; output_channel_total * max_buffer_size],
+            main_input: ports.main_input(),
+            main_output: ports.main_output().unwrap_or(0),
+            input_port_channels,
+            output_port_channels,
+            param_ranges,
+            is_instrument: plugin.is_instrument,
+            processor: Some(plugin.processor),
+            input_events: EventBuffer::with_capacity(EVENT_CAPACITY),
+            output_events: EventBuffer::with_capacity(EVENT_CAPACITY),
+        }
+    }
+
+    fn main_input_channels(&self) -> usize {
+        self.main_input.map_or(0, |port| self.input_port_channels[port])
+    }
+
+    fn main_input_range(&self, frame_count: usize) -> Range<usize> {
+        self.main_input
+            .map_or(0..0, |port| port_range(&self.input_port_channels, port, frame_count))
+    }
+
+    fn main_output_channels(&self) -> usize {
+        self.output_port_channels[self.main_output]
+    }
+
+    fn main_output_range(&self, frame_count: usize) -> Range<usize> {
+        port_range(&self.output_port_channels, self.main_output, frame_count)
+    }
+
+    fn clamp_param(&self, param_id: u32, value: f64) -> f64 {
+        clamp_param(&self.param_ranges, param_id, value)
+    }
+
+    /// Clears the port buffers and event lists for a block of `frame_count`.
+    /// The buffers are sized for the largest block up front and only the
+    /// part this block uses is touched.
+    fn prepare(&mut self, frame_count: usize) {
+        let input_size = self.input_port_channels.iter().sum::<usize>() * frame_count;
+        let output_size = self.output_port_channels.iter().sum::<usize>() * frame_count;
+        self.input_buffers[..input_size].fill(0.0);
+        self.output_buffers[..output_size].fill(0.0);
+        self.input_events.clear();
+        self.output_events.clear();
+    }
+
+    /// Runs the plugin over the prepared buffers and events. `live_input`
+    /// marks the main input as carrying signal; every other input port is
+    /// constant silence.
+    fn process(
+        &mut self,
+        frame_count: usize,
+        live_input: bool,
+        steady_time: u64,
+        transport: &TransportEvent,
+    ) -> Result<ProcessStatus, PluginInstanceError> {
+        // A plugin out of the chain outputs the silence `prepare` left.
+        let Some(processor) = &mut self.processor else {
+            return Ok(ProcessStatus::Sleep);
+        };
+        let input_events = InputEvents::from_buffer(&self.input_events);
+        let mut output_events = OutputEvents::from_buffer(&mut self.output_events);
+
+        // One buffer per declared port, its planes cut off the front of the
+        // port buffers in order; plugins without audio inputs get none.
+        let live_input = self.main_input.filter(|_| live_input);
+        let mut input_planes = &mut self.input_buffers[..];
+        let inputs = self.input_ports.with_input_buffers(self.input_port_channels.iter().enumerate().map(
+            |(port, &count)| {
+                let (planes, rest) = std::mem::take(&mut input_planes).split_at_mut(count * frame_count);
+                input_planes = rest;
+                let is_constant = live_input != Some(port);
+                AudioPortBuffer {
+                    latency: 0,
+                    channels: AudioPortBufferType::f32_input_only(
+                        planes
+                            .chunks_exact_mut(frame_count)
+                            .map(move |buffer| InputChannel { buffer, is_constant }),
+                    ),
+                }
+            },
+        ));
+
+        let mut output_planes = &mut self.output_buffers[..];
+        let mut outputs = self.output_ports.with_output_buffers(self.output_port_channels.iter().map(|&count| {
+            let (planes, rest) = std::mem::take(&mut output_planes).split_at_mut(count * frame_count);
+            output_planes = rest;
+            AudioPortBuffer {
+                latency: 0,
+                channels: AudioPortBufferType::f32_output_only(planes.chunks_exact_mut(frame_count)),
+            }
+        }));
+
+        processor.process(
+            &inputs,
+            &mut outputs,
+            &input_events,
+            &mut output_events,
+            Some(steady_time),
+            Some(transport),
+        )
+    }
+}
+
+/// Sends a note-off the sustain pedal held back, at `time`.
+fn release_held(
+    stages: &mut [ChainStage],
+    notifications: &mut Option<NotificationProducer>,
+    bend: &mut ChannelBend,
+    block_stats: &mut BlockEventStats,
+    held: HeldNote,
+    time: u32,
+) {
+    bend.note_off(held.stage, held.note_id, held.key, held.channel);
+    let Some(EventUnion::NoteOff(event)) = command_to_event(held.note_off(), time) else {
+        return;
+    };
+    let Some(stage) = stages.get_mut(held.stage) else {
+        return;
+    };
+    stage.input_events.push(&event);
+    block_stats.events += 1;
+    block_stats.notes_ended += 1;
+    block_stats.bytes += std::mem::size_of_val(&event) as u32;
+    if let (Some(notifications), Some(notification)) = (notifications, command_notification(&held.note_off())) {
+        let _ = notifications.push(notification);
+    }
+}
+
+/// Sends the tuning expression that bends `note` by `semitones`.
+fn send_tuning(
+    stages: &mut [ChainStage],
+    block_stats: &mut BlockEventStats,
+    note: &ActiveNote,
+    semitones: f64,
+    time: u32,
+) {
+    let Some(EventUnion::NoteExpression(event)) = command_to_event(note.tuning(semitones), time) else {
+        return;
+    };
+    let Some(stage) = stages.get_mut(note.stage) else {
+        return;
+    };
+    stage.input_events.push(&event);
+    block_stats.events += 1;
+    block_stats.bytes += std::mem::size_of_val(&event) as u32;
+}
+
+fn command_notification(cmd: &Command) -> Option<Notification> {
+    match cmd {
+        Command::ParamSet { param_id, value } => Some(Notification::ParamChanged {
+            param_id: *param_id,
+            value: *value,
+        }),
+        Command::NoteOn { key, .. } => Some(Notification::Note { key: *key, on: true }),
+        Command::NoteOff { key, .. } | Command::NoteChoke { key, .. } if *key >= 0 => {
+            Some(Notification::Note { key: *key, on: false })
+        }
+        _ => None,
+    }
+}
+
+/// Forwards the voices `plugin` finished and the notes it played this block.
+/// A full queue drops the rest rather than block the audio thread.
+fn forward_note_events(plugin: usize, events: &EventBuffer, output: &mut PluginOutputProducer) {
+    for event in events {
+        if let Some(note) = note_output_event(plugin, event) {
+            let _ = output.push(note);
+        }
+    }
+}
+
+/// The note event a plugin emitted, in the form the OSC sender forwards.
+fn note_output_event(plugin: usize, event: &UnknownEvent) -> Option<PluginOutputEvent> {
+    let value = |m: Match<u16>| match m {
+        Match::Specific(v) => v as i32,
+        Match::All => -1,
+    };
+    let note_id = |m: Match<u32>| match m {
+        Match::Specific(v) => v as i32,
+        Match::All => -1,
+    };
+    if let Some(e) = event.as_event::<NoteEndEvent>() {
+        let pckn = e.pckn();
+        return Some(PluginOutputEvent::NoteEnd {
+            plugin,
+            note_id: note_id(pckn.note_id),
+            key: value(pckn.key),
+            channel: value(pckn.channel),
+        });
+    }
+    let (on, pckn, velocity) = if let Some(e) = event.as_event::<NoteOnEvent>() {
+        (true, e.pckn(), e.velocity())
+    } else if let Some(e) = event.as_event::<NoteOffEvent>() {
+        (false, e.pckn(), e.velocity())
+    } else {
+        let e = event.as_event::<MidiEvent>()?;
+        return Some(PluginOutputEvent::Midi {
+            plugin,
+            data: e.data(),
+            port: e.port_index() as i32,
+        });
+    };
+    Some(PluginOutputEvent::NoteOut {
+        plugin,
+        on,
+        note_id: note_id(pckn.note_id),
+        key: value(pckn.key),
+        velocity,
+        channel: value(pckn.channel),
+        port: value(pckn.port_index),
+    })
+}
+
+/// Notes go to the first instrument and everything else to the first plugin,
+/// unless the command names a plugin.
+fn route_command(plugin: Option<usize>, cmd: &Command, instrument: usize, last_stage: usize) -> usize {
+    match (plugin, cmd) {
+        (Some(plugin), _) => plugin.min(last_stage),
+        (
+            None,
+            Command::ParamSet { .. }
+            | Command::ParamMod { .. }
+            | Command::ParamRamp { .. }
+            | Command::ParamInc { .. }
+            | Command::ParamSetMany { .. }
+            | Command::ForgetParam { .. },
+        ) => 0,
+        (None, _) => instrument,
+    }
+}
+
+fn touch_param(touched: &mut Vec<(usize, u32)>, stage: usize, param_id: Option<u32>) {
+    if let Some(id) = param_id {
+        if !touched.contains(&(stage, id)) {
+            touched.push((stage, id));
+        }
     }
 }
 
@@ -341,15 +1830,20 @@ enum EventUnion {
     NoteChoke(NoteChokeEvent),
     ParamValue(ParamValueEvent),
     ParamMod(ParamModEvent),
+    NoteExpression(NoteExpressionEvent),
 }
 
-fn format_event(event: &EventUnion) -> String {
-    match event {
-        EventUnion::NoteOn(_) => "NoteOn".to_string(),
-        EventUnion::NoteOff(_) => "NoteOff".to_string(),
-        EventUnion::NoteChoke(_) => "NoteChoke".to_string(),
-        EventUnion::ParamValue(_) => "ParamValue".to_string(),
-        EventUnion::ParamMod(_) => "ParamMod".to_string(),
+/// Written straight into the log line, so `-v` doesn't build a string per event.
+impl fmt::Display for EventUnion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventUnion::NoteOn(e) => write!(f, "NoteOn vel={:.3}", e.velocity()),
+            EventUnion::NoteOff(e) => write!(f, "NoteOff release_vel={:.3}", e.velocity()),
+            EventUnion::NoteChoke(_) => f.write_str("NoteChoke"),
+            EventUnion::ParamValue(_) => f.write_str("ParamValue"),
+            EventUnion::ParamMod(_) => f.write_str("ParamMod"),
+            EventUnion::NoteExpression(_) => f.write_str("NoteExpression"),
+        }
     }
 }
 
@@ -361,11 +1855,20 @@ impl AsRef<UnknownEvent> for EventUnion {
             EventUnion::NoteChoke(e) => e.as_ref(),
             EventUnion::ParamValue(e) => e.as_ref(),
             EventUnion::ParamMod(e) => e.as_ref(),
+            EventUnion::NoteExpression(e) => e.as_ref(),
         }
     }
 }
 
-fn command_to_event(cmd: Command) -> Option<EventUnion> {
+/// Whether the backend's playback timestamp moved more than a block past
+/// where the previous callback's audio, `last_frames` frames played from
+/// `last`, ended; i.e. the device ran out of audio in between.
+fn is_late_callback(last: StreamInstant, last_frames: usize, playback: StreamInstant, sample_rate: u32) -> bool {
+    let expected = Duration::from_secs_f64(last_frames as f64 / sample_rate.max(1) as f64);
+    playback.duration_since(&last).is_some_and(|gap| gap > expected * 2)
+}
+
+fn command_to_event(cmd: Command, time: u32) -> Option<EventUnion> {
     match cmd {
         Command::NoteOn {
             note_id,
@@ -373,9 +1876,10 @@ fn command_to_event(cmd: Command) -> Option<EventUnion> {
             velocity,
             channel,
             port,
+            ..
         } => {
             let pckn = Pckn::new(port as u16, channel as u16, key as u16, note_id as u32);
-            Some(EventUnion::NoteOn(NoteOnEvent::new(0, pckn, velocity as f64)))
+            Some(EventUnion::NoteOn(NoteOnEvent::new(time, pckn, velocity as f64)))
         }
         Command::NoteOff {
             note_id,
@@ -385,7 +1889,7 @@ fn command_to_event(cmd: Command) -> Option<EventUnion> {
             port,
         } => {
             let pckn = Pckn::new(port as u16, channel as u16, key as u16, note_id as u32);
-            Some(EventUnion::NoteOff(NoteOffEvent::new(0, pckn, velocity as f64)))
+            Some(EventUnion::NoteOff(NoteOffEvent::new(time, pckn, velocity as f64)))
         }
         Command::NoteChoke {
             note_id,
@@ -394,13 +1898,21 @@ fn command_to_event(cmd: Command) -> Option<EventUnion> {
             port,
         } => {
             let pckn = Pckn::new(port as u16, channel as u16, key as u16, note_id as u32);
-            Some(EventUnion::NoteChoke(NoteChokeEvent::new(0, pckn)))
+            Some(EventUnion::NoteChoke(NoteChokeEvent::new(time, pckn)))
+        }
+        Command::AllNotesOff { channel, velocity } => {
+            let channel = match u16::try_from(channel) {
+                Ok(channel) => Match::Specific(channel),
+                Err(_) => Match::All,
+            };
+            let pckn = Pckn::new(Match::All, channel, Match::All, Match::All);
+            Some(EventUnion::NoteOff(NoteOffEvent::new(time, pckn, velocity as f64)))
         }
         Command::ParamSet { param_id, value } => {
             let param_id = ClapId::from_raw(param_id)?;
             let pckn = Pckn::new(Match::All, Match::All, Match::All, Match::All);
             Some(EventUnion::ParamValue(ParamValueEvent::new(
-                0,
+                time,
                 param_id,
                 pckn,
                 value,
@@ -425,17 +1937,147 @@ fn command_to_event(cmd: Command) -> Option<EventUnion> {
                 Pckn::new(port_match, chan_match, key_match, Match::Specific(note_id as u32))
             };
             Some(EventUnion::ParamMod(ParamModEvent::new(
-                0,
+                time,
                 param_id,
                 pckn,
                 amount,
                 Cookie::empty(),
             )))
         }
-        Command::DumpPatchState => {
-            // Handled separately in the audio callback, not converted to CLAP event
-            None
+        Command::NoteExpression {
+            expression_id,
+            note_id,
+            key,
+            channel,
+            port,
+            value,
+        } => {
+            let expression = match expression_id {
+                0 => NoteExpressionType::Volume,
+                1 => NoteExpressionType::Pan,
+                2 => NoteExpressionType::Tuning,
+                3 => NoteExpressionType::Vibrato,
+                4 => NoteExpressionType::Expression,
+                5 => NoteExpressionType::Brightness,
+                6 => NoteExpressionType::Pressure,
+                _ => return None,
+            };
+            // Negative fields are wildcards, so MPE controllers can address a
+            // voice by channel and key without knowing its note id.
+            let specific = |v: i32| if v < 0 { Match::All } else { Match::Specific(v as u16) };
+            let note_match = if note_id < 0 { Match::All } else { Match::Specific(note_id as u32) };
+            let pckn = Pckn::new(specific(port), specific(channel), specific(key), note_match);
+            Some(EventUnion::NoteExpression(NoteExpressionEvent::new(time, pckn, expression, value)))
+        }
+        // Handled by the audio thread itself, not sent as a single event.
+        Command::Transport(_)
+        | Command::Master(_)
+        | Command::Sustain { .. }
+        | Command::ChannelBend { .. }
+        | Command::Panic
+        | Command::ParamRamp { .. }
+        | Command::ParamInc { .. }
+        | Command::ForgetParam { .. } => None,
+        // Split into plain sets by `push_due`.
+        Command::ParamSetMany { .. } => None,
+    }
+}
+
+/// Sample range of `port`'s planes within a buffer holding every port's
+/// channels back to back.
+fn port_range(port_channels: &[usize], port: usize, frame_count: usize) -> Range<usize> {
+    let start = port_channels[..port].iter().sum::<usize>() * frame_count;
+    start..start + port_channels[port] * frame_count
+}
+
+/// Maps a port's planes onto the device's, or onto the next plugin's input.
+/// Fewer port channels repeat across the device (a mono plugin feeds both
+/// speakers); more are folded down by averaging the port channels that land
+/// on each device channel.
+fn map_channels(port: &[f32], port_channels: usize, device: &mut [f32], device_channels: usize, frame_count: usize) {
+    if port_channels == 0 {
+        return;
+    }
+    for (channel, out) in device.chunks_exact_mut(frame_count).take(device_channels).enumerate() {
+        map_plane(port, port_channels, out, channel, device_channels, frame_count);
+    }
+}
+
+/// Like `map_channels`, onto the listed device channels only. The others
+/// keep the silence the block started with.
+fn route_channels(port: &[f32], port_channels: usize, device: &mut [f32], routes: &[usize], frame_count: usize) {
+    if port_channels == 0 {
+        return;
+    }
+    for (channel, &target) in routes.iter().enumerate() {
+        let out = &mut device[target * frame_count..(target + 1) * frame_count];
+        map_plane(port, port_channels, out, channel, routes.len(), frame_count);
+    }
+}
+
+/// Fills one of `channel_total` target channels from a port: repeating
+/// narrower ports, averaging wider ones.
+fn map_plane(
+    port: &[f32],
+    port_channels: usize,
+    out: &mut [f32],
+    channel: usize,
+    channel_total: usize,
+    frame_count: usize,
+) {
+    if port_channels <= channel_total {
+        let source = channel % port_channels;
+        out.copy_from_slice(&port[source * frame_count..(source + 1) * frame_count]);
+    } else {
+        let sources = (channel..port_channels).step_by(channel_total);
+        let scale = 1.0 / sources.len() as f32;
+        for source in sources {
+            let plane = &port[source * frame_count..(source + 1) * frame_count];
+            for (o, s) in out.iter_mut().zip(plane) {
+                *o += s * scale;
+            }
+        }
+    }
+}
+
+/// Limits `value` to the parameter's range in `ranges`, sorted by id;
+/// unknown parameters pass as is.
+fn clamp_param(ranges: &[(u32, f64, f64)], param_id: u32, value: f64) -> f64 {
+    match ranges.binary_search_by_key(&param_id, |(id, _, _)| *id) {
+        Ok(i) => {
+            let (_, min, max) = ranges[i];
+            value.max(min).min(max)
         }
+        Err(_) => value,
+    }
+}
+
+/// Ramps each channel of a planar block linearly from `gain` to zero.
+fn fade_out(channel_buffers: &mut [f32], frame_count: usize, gain: f32) {
+    if frame_count == 0 {
+        return;
+    }
+    for channel in channel_buffers.chunks_exact_mut(frame_count) {
+        for (i, sample) in channel.iter_mut().enumerate() {
+            *sample *= gain * (1.0 - (i + 1) as f32 / frame_count as f32);
+        }
+    }
+}
+
+/// Hands an interleaved callback buffer to `process` in order, in blocks of
+/// at most `max_block_frames` whole frames. Samples past the last whole
+/// frame are silenced.
+fn for_each_block<S: Sample>(
+    data: &mut [S],
+    channel_count: usize,
+    max_block_frames: usize,
+    mut process: impl FnMut(&mut [S]),
+) {
+    let whole_frames = data.len() / channel_count * channel_count;
+    let (data, partial) = data.split_at_mut(whole_frames);
+    partial.fill(S::EQUILIBRIUM);
+    for block in data.chunks_mut(max_block_frames * channel_count) {
+        process(block);
     }
 }
 
@@ -457,9 +2099,260 @@ fn interleave_to_output<S: FromSample<f32> + Sample>(
 }
 
 use clack_host::events::event_types::{
-    NoteChokeEvent, NoteOffEvent, NoteOnEvent, ParamModEvent, ParamValueEvent,
+    NoteChokeEvent, NoteExpressionEvent, NoteExpressionType, NoteOffEvent, NoteOnEvent, ParamGestureBeginEvent,
+    ParamGestureEndEvent, ParamModEvent, ParamValueEvent, TransportEvent,
 };
 use clack_host::events::io::EventBuffer;
 use clack_host::events::{Match, Pckn, UnknownEvent};
 use clack_host::prelude::{AudioPortBuffer, AudioPortBufferType, AudioPorts, InputChannel};
 use clack_host::utils::{ClapId, Cookie};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc::parse_message;
+    use crate::param_out::{start_param_sender, ParamSenderTargets};
+    use crate::testing::detached_receiver;
+    use rosc::{OscMessage, OscPacket, OscType};
+    use std::net::UdpSocket;
+
+    /// What a polyphonic synth leaves in its output events over a block:
+    /// a parameter it moved, and two voices that finished their release.
+    fn synth_output() -> EventBuffer {
+        let mut events = EventBuffer::new();
+        let all = Pckn::new(Match::All, Match::All, Match::All, Match::All);
+        events.push(&ParamValueEvent::new(0, ClapId::from_raw(3).unwrap(), all, 0.5, Cookie::empty()));
+        events.push(&NoteEndEvent::new(10, Pckn::new(0u16, 0u16, 60u16, 7u32)));
+        events.push(&NoteEndEvent::new(20, Pckn::new(0u16, 2u16, 64u16, Match::All)));
+        events
+    }
+
+    fn receive(socket: &UdpSocket) -> (String, Vec<OscType>) {
+        let mut buf = [0u8; 1024];
+        let len = socket.recv(&mut buf).expect("a forwarded message");
+        match rosc::decoder::decode_udp(&buf[..len]).unwrap().1 {
+            OscPacket::Message(msg) => (msg.addr, msg.args),
+            OscPacket::Bundle(bundle) => panic!("unexpected bundle {:?}", bundle),
+        }
+    }
+
+    #[test]
+    fn note_ends_are_forwarded_over_osc() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut output = start_param_sender(ParamSenderTargets {
+            changed: Some(socket.local_addr().unwrap()),
+            value: None,
+        })
+        .unwrap();
+
+        let events = synth_output();
+        forward_note_events(0, &events, &mut output);
+        forward_note_events(1, &events, &mut output);
+
+        let int = OscType::Int;
+        assert_eq!(receive(&socket), ("/note/end".to_string(), vec![int(7), int(60), int(0)]));
+        assert_eq!(receive(&socket), ("/note/end".to_string(), vec![int(-1), int(64), int(2)]));
+        assert_eq!(receive(&socket), ("/plugin/1/note/end".to_string(), vec![int(7), int(60), int(0)]));
+        assert_eq!(receive(&socket), ("/plugin/1/note/end".to_string(), vec![int(-1), int(64), int(2)]));
+    }
+
+    #[test]
+    fn repeated_param_values_are_forwarded_once() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut output = start_param_sender(ParamSenderTargets {
+            changed: None,
+            value: Some(socket.local_addr().unwrap()),
+        })
+        .unwrap();
+
+        // A plugin's LFO reporting where it stopped, block after block.
+        for (param_id, value) in [(3, 0.5), (3, 0.5), (4, 0.5), (3, 0.5), (3, 0.25), (3, 0.25), (3, 0.5)] {
+            output.push(PluginOutputEvent::Value { param_id, value }).unwrap();
+        }
+
+        let value = |id, value| ("/param/value".to_string(), vec![OscType::Int(id), OscType::Double(value)]);
+        assert_eq!(receive(&socket), value(3, 0.5));
+        assert_eq!(receive(&socket), value(4, 0.5));
+        assert_eq!(receive(&socket), value(3, 0.25));
+        assert_eq!(receive(&socket), value(3, 0.5));
+        socket.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        assert!(socket.recv(&mut [0u8; 1024]).is_err(), "a repeated value was sent again");
+    }
+
+    #[test]
+    fn parameter_changes_are_not_note_output() {
+        let events = synth_output();
+        let notes: Vec<_> = (&events).into_iter().filter_map(|event| note_output_event(0, event)).collect();
+        assert_eq!(notes.len(), 2);
+        assert!(notes.iter().all(|note| matches!(note, PluginOutputEvent::NoteEnd { plugin: 0, .. })));
+    }
+
+    #[test]
+    fn increments_clamp_to_the_current_range() {
+        let mut ranges = vec![(1, 0.0, 1.0), (4, -12.0, 12.0)];
+        assert_eq!(clamp_param(&ranges, 1, 0.75 + 0.5), 1.0);
+        assert_eq!(clamp_param(&ranges, 4, -11.5 - 1.0), -12.0);
+        assert_eq!(clamp_param(&ranges, 4, 3.0), 3.0);
+        assert_eq!(clamp_param(&ranges, 9, 42.0), 42.0);
+
+        // A state load widened param 1.
+        ranges[0] = (1, 0.0, 2.0);
+        assert_eq!(clamp_param(&ranges, 1, 0.75 + 0.5), 1.25);
+    }
+
+    #[test]
+    fn oversized_callbacks_are_split_into_continuous_blocks() {
+        const MAX_BLOCK_FRAMES: usize = 64;
+        // Each sample holds its frame's position in the callback and its channel.
+        let sample = |frame: usize, channel: usize| (frame * 10 + channel) as f32;
+        for channel_count in [1, 2, 3, 6] {
+            for frames in [1, 7, 63, 64, 65, 128, 129, 1000] {
+                // One stray sample past the last whole frame, as some backends send.
+                let mut data = vec![-1.0f32; frames * channel_count + usize::from(channel_count > 1)];
+                let mut position = 0;
+                let mut blocks = 0;
+                for_each_block(&mut data, channel_count, MAX_BLOCK_FRAMES, |block| {
+                    let frame_count = block.len() / channel_count;
+                    assert_eq!(block.len(), frame_count * channel_count);
+                    assert!((1..=MAX_BLOCK_FRAMES).contains(&frame_count));
+                    let planes: Vec<f32> = (0..channel_count)
+                        .flat_map(|channel| (position..position + frame_count).map(move |frame| sample(frame, channel)))
+                        .collect();
+                    interleave_to_output(block, &planes, channel_count, frame_count);
+                    position += frame_count;
+                    blocks += 1;
+                });
+
+                let what = format!("{} frames of {} channels", frames, channel_count);
+                assert_eq!(position, frames, "{}", what);
+                assert_eq!(blocks, frames.div_ceil(MAX_BLOCK_FRAMES), "{}", what);
+                let (whole, partial) = data.split_at(frames * channel_count);
+                for (i, value) in whole.iter().enumerate() {
+                    assert_eq!(*value, sample(i / channel_count, i % channel_count), "{} at {}", what, i);
+                }
+                assert!(partial.iter().all(|value| *value == 0.0), "{}", what);
+            }
+        }
+    }
+
+    #[cfg(feature = "alloc-check")]
+    #[test]
+    fn routing_a_callback_does_not_allocate() {
+        const MAX_BLOCK_FRAMES: usize = 64;
+        let port = vec![0.25f32; 6 * MAX_BLOCK_FRAMES];
+        let mut device = vec![0.0f32; 2 * MAX_BLOCK_FRAMES];
+        let mut data = vec![0i16; 2 * 1000 + 1];
+
+        assert_no_alloc::reset_violation_count();
+        assert_no_alloc::assert_no_alloc(|| {
+            for_each_block(&mut data, 2, MAX_BLOCK_FRAMES, |block| {
+                let frame_count = block.len() / 2;
+                let device = &mut device[..2 * frame_count];
+                device.fill(0.0);
+                route_channels(&port[..6 * frame_count], 6, device, &[0, 1], frame_count);
+                interleave_to_output(block, device, 2, frame_count);
+            });
+        });
+        assert_eq!(assert_no_alloc::violation_count(), 0);
+        assert!(data[..2000].iter().all(|&sample| sample == 0.25f32.to_sample::<i16>()));
+    }
+
+    /// Maps a planar port of `port_channels` onto `channel_total` device
+    /// channels, returning each device channel's plane.
+    fn map_port(port: &[f32], port_channels: usize, channel_total: usize) -> Vec<Vec<f32>> {
+        let frame_count = port.len() / port_channels;
+        let mut device = vec![0.0; frame_count * channel_total];
+        map_channels(port, port_channels, &mut device, channel_total, frame_count);
+        device.chunks(frame_count).map(<[f32]>::to_vec).collect()
+    }
+
+    #[test]
+    fn mono_is_copied_to_both_stereo_channels() {
+        let mono = [0.1, 0.2, 0.3];
+        assert_eq!(map_port(&mono, 1, 2), vec![mono.to_vec(), mono.to_vec()]);
+    }
+
+    #[test]
+    fn stereo_is_averaged_into_mono() {
+        let stereo = [1.0, 0.5, 0.0, 0.5, -1.0, 0.0];
+        assert_eq!(map_port(&stereo, 2, 1), vec![vec![0.75, -0.25, 0.0]]);
+    }
+
+    #[test]
+    fn surround_folds_alternate_channels_into_stereo() {
+        // Six planes of two frames; plane n holds n + 1. Left takes planes
+        // 0, 2 and 4 and right 1, 3 and 5, each at a third.
+        let surround: Vec<f32> = (0..6).flat_map(|plane| [plane as f32 + 1.0; 2]).collect();
+        let stereo = map_port(&surround, 6, 2);
+        let expect = |sum: f32| sum / 3.0;
+        assert_eq!(stereo, vec![vec![expect(1.0 + 3.0 + 5.0); 2], vec![expect(2.0 + 4.0 + 6.0); 2]]);
+    }
+
+    fn pitchbend_event(args: Vec<OscType>) -> NoteExpressionEvent {
+        let msg = OscMessage {
+            addr: "/note/pitchbend".to_string(),
+            args,
+        };
+        let cmd = parse_message(&msg, &Default::default()).unwrap();
+        match command_to_event(cmd, 12) {
+            Some(EventUnion::NoteExpression(event)) => event,
+            other => panic!("expected a note expression, got {:?}", other.map(|event| event.to_string())),
+        }
+    }
+
+    #[test]
+    fn note_pitchbend_becomes_a_tuning_expression_in_semitones() {
+        let event = pitchbend_event(vec![OscType::Int(7), OscType::Double(-2.5)]);
+        assert_eq!(event.expression_type(), Some(NoteExpressionType::Tuning));
+        assert_eq!(event.value(), -2.5);
+        assert_eq!(event.pckn(), Pckn::new(Match::All, Match::All, Match::All, 7u32));
+    }
+
+    #[test]
+    fn note_pitchbend_without_a_note_id_matches_on_key_and_channel() {
+        let event = pitchbend_event(vec![OscType::Int(-1), OscType::Float(12.0), OscType::Int(60), OscType::Int(1)]);
+        assert_eq!(event.expression_type(), Some(NoteExpressionType::Tuning));
+        assert_eq!(event.value(), 12.0);
+        assert_eq!(event.pckn(), Pckn::new(Match::All, 1u16, 60u16, Match::All));
+    }
+
+    #[test]
+    fn callbacks_more_than_a_block_apart_are_late() {
+        let last = StreamInstant::new(1, 0);
+        let block = Duration::from_secs_f64(256.0 / 48000.0);
+        let at = |gap: Duration| last.add(gap).unwrap();
+        assert!(!is_late_callback(last, 256, at(block), 48000));
+        assert!(!is_late_callback(last, 256, at(block * 2), 48000));
+        assert!(is_late_callback(last, 256, at(block * 3), 48000));
+    }
+
+    /// The event `/notes/off channel` queues with `--default-release-velocity 0.4`.
+    fn notes_off_event(channel: i32) -> NoteOffEvent {
+        let (mut state, mut consumer) = detached_receiver(16);
+        state.default_release_velocity = Some(0.4);
+        let msg = OscMessage {
+            addr: "/notes/off".to_string(),
+            args: vec![OscType::Int(channel)],
+        };
+        state.handle_message(&msg, None).unwrap();
+        state.queue.flush();
+        let scheduled = consumer.pop().expect("the note-off is queued");
+        match command_to_event(scheduled.command, 0) {
+            Some(EventUnion::NoteOff(event)) => event,
+            other => panic!("expected a note-off, got {:?}", other.map(|event| event.to_string())),
+        }
+    }
+
+    #[test]
+    fn notes_off_releases_one_channel_or_all_at_the_default_velocity() {
+        let event = notes_off_event(3);
+        assert_eq!(event.pckn(), Pckn::new(Match::All, 3u16, Match::All, Match::All));
+        assert_eq!(event.velocity(), 0.4f32 as f64);
+
+        let event = notes_off_event(-1);
+        assert_eq!(event.pckn(), Pckn::new(Match::All, Match::All, Match::All, Match::All));
+        assert_eq!(event.velocity(), 0.4f32 as f64);
+    }
+}
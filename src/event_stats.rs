@@ -0,0 +1,158 @@
+use rosc::OscType;
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::sync::{Arc, Mutex};
+
+/// What the audio thread put into one block's input event buffer. Filled in
+/// with plain integer updates while events are pushed, then handed off.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlockEventStats {
+    pub events: u32,
+    pub bytes: u32,
+    pub params: u32,
+    pub notes_started: u32,
+    pub notes_ended: u32,
+}
+
+/// Power-of-two buckets: 0, 1, 2-3, 4-7, ... with the last one open-ended.
+const BUCKETS: usize = 16;
+
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    pub counts: [u64; BUCKETS],
+    pub total: u64,
+    pub max: u32,
+}
+
+impl Histogram {
+    fn record(&mut self, value: u32) {
+        let bucket = if value == 0 { 0 } else { (32 - value.leading_zeros()) as usize };
+        self.counts[bucket.min(BUCKETS - 1)] += 1;
+        self.total += value as u64;
+        self.max = self.max.max(value);
+    }
+
+    /// Bucket counts up to the last non-empty one.
+    fn used_counts(&self) -> &[u64] {
+        let len = self.counts.iter().rposition(|&c| c > 0).map_or(0, |i| i + 1);
+        &self.counts[..len]
+    }
+}
+
+fn bucket_label(i: usize) -> String {
+    match i {
+        0 => "0".to_string(),
+        1 => "1".to_string(),
+        _ if i == BUCKETS - 1 => format!("{}+", 1u32 << (i - 1)),
+        _ => format!("{}-{}", 1u32 << (i - 1), (1u32 << i) - 1),
+    }
+}
+
+/// Per-block event statistics aggregated over the whole run.
+#[derive(Debug, Clone, Default)]
+pub struct EventStats {
+    pub blocks: u64,
+    pub events: Histogram,
+    pub bytes: Histogram,
+    pub params: Histogram,
+    pub notes_started: Histogram,
+    pub notes_ended: Histogram,
+}
+
+pub type SharedEventStats = Arc<Mutex<EventStats>>;
+
+impl EventStats {
+    fn record(&mut self, block: &BlockEventStats) {
+        self.blocks += 1;
+        self.events.record(block.events);
+        self.bytes.record(block.bytes);
+        self.params.record(block.params);
+        self.notes_started.record(block.notes_started);
+        self.notes_ended.record(block.notes_ended);
+    }
+
+    pub fn metrics(&self) -> [(&'static str, &Histogram); 5] {
+        [
+            ("events", &self.events),
+            ("bytes", &self.bytes),
+            ("params", &self.params),
+            ("notes_started", &self.notes_started),
+            ("notes_ended", &self.notes_ended),
+        ]
+    }
+
+    /// One `/host/eventStats` reply per metric:
+    /// `name:s total:i64 max:i32 mean:f32 bucket_counts:i64...`.
+    pub fn osc_replies(&self) -> Vec<Vec<OscType>> {
+        self.metrics()
+            .iter()
+            .map(|(name, histogram)| {
+                let mut args = vec![
+                    OscType::String(name.to_string()),
+                    OscType::Long(histogram.total as i64),
+                    OscType::Int(histogram.max as i32),
+                    OscType::Float(self.mean(histogram) as f32),
+                ];
+                args.extend(histogram.used_counts().iter().map(|&c| OscType::Long(c as i64)));
+                args
+            })
+            .collect()
+    }
+
+    pub fn print(&self) {
+        println!("=== Per-block event statistics ({} blocks) ===", self.blocks);
+        for (name, histogram) in self.metrics() {
+            println!(
+                "{:<14} total {:>10}  max {:>6}  mean {:>8.3}",
+                name,
+                histogram.total,
+                histogram.max,
+                self.mean(histogram)
+            );
+            for (i, count) in histogram.used_counts().iter().enumerate() {
+                if *count > 0 {
+                    println!("    {:>11}: {}", bucket_label(i), count);
+                }
+            }
+        }
+    }
+
+    fn mean(&self, histogram: &Histogram) -> f64 {
+        if self.blocks == 0 {
+            0.0
+        } else {
+            histogram.total as f64 / self.blocks as f64
+        }
+    }
+}
+
+/// Enough for a few seconds of small blocks between collections.
+const STATS_QUEUE_CAPACITY: usize = 4096;
+
+/// Main-thread end of the audio thread's stats queue.
+pub struct EventStatsCollector {
+    consumer: Consumer<BlockEventStats>,
+    stats: SharedEventStats,
+}
+
+pub fn create_event_stats() -> (Producer<BlockEventStats>, EventStatsCollector) {
+    let (producer, consumer) = RingBuffer::new(STATS_QUEUE_CAPACITY);
+    let collector = EventStatsCollector {
+        consumer,
+        stats: Arc::new(Mutex::new(EventStats::default())),
+    };
+    (producer, collector)
+}
+
+impl EventStatsCollector {
+    pub fn shared(&self) -> SharedEventStats {
+        self.stats.clone()
+    }
+
+    /// Folds every block reported since the last call into the histograms.
+    pub fn collect(&mut self) {
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        while let Ok(block) = self.consumer.pop() {
+            stats.record(&block);
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use crate::args::Args;
+use crate::engine::OscClapHost;
+use crate::osc::CommandProducer;
+use crate::plugin::ParamInfo;
+use crate::session::SharedSessionCounters;
+use crate::tuning::Scale;
+use anyhow::{anyhow, Result};
+use clack_host::prelude::PluginInstance;
+use std::time::Duration;
+
+/// Optional subsystems and the CLI flags that belong to them. The core OSC
+/// and audio path is always built; everything listed here can be left out
+/// with `--no-default-features` plus the features that are wanted.
+pub struct Feature {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub description: &'static str,
+    /// Flags from this feature that are set on the command line.
+    pub flags_used: fn(&Args) -> Vec<&'static str>,
+}
+
+pub const METRICS: bool = cfg!(feature = "metrics");
+
+pub const FEATURES: &[Feature] = &[
+    Feature {
+        name: "metrics",
+        enabled: METRICS,
+        description: "per-block event statistics and the latency benchmark",
+        flags_used: metrics_flags,
+    },
+    Feature {
+        name: "midi",
+        enabled: cfg!(feature = "midi"),
+        description: "MIDI input",
+        flags_used: midi_flags,
+    },
+    Feature {
+        name: "gui",
+        enabled: cfg!(feature = "gui"),
+        description: "plugin GUI windows",
+        flags_used: gui_flags,
+    },
+    Feature {
+        name: "jack",
+        enabled: cfg!(feature = "jack"),
+        description: "the JACK audio backend",
+        flags_used: no_flags,
+    },
+    Feature {
+        name: "asio",
+        enabled: cfg!(feature = "asio"),
+        description: "the ASIO audio backend",
+        flags_used: no_flags,
+    },
+    Feature {
+        name: "alloc-check",
+        enabled: cfg!(feature = "alloc-check"),
+        description: "allocation reports from the audio callback",
+        flags_used: no_flags,
+    },
+];
+
+fn metrics_flags(args: &Args) -> Vec<&'static str> {
+    let mut flags = Vec::new();
+    if args.event_stats {
+        flags.push("--event-stats");
+    }
+    if args.bench_latency {
+        flags.push("--bench-latency");
+    }
+    if args.latency_budget_ms.is_some() {
+        flags.push("--latency-budget-ms");
+    }
+    flags
+}
+
+fn midi_flags(args: &Args) -> Vec<&'static str> {
+    let mut flags = Vec::new();
+    if args.midi_port.is_some() {
+        flags.push("--midi-port");
+    }
+    if args.list_midi_ports {
+        flags.push("--list-midi-ports");
+    }
+    if !args.cc_map.is_empty() {
+        flags.push("--cc-map");
+    }
+    flags
+}
+
+fn gui_flags(args: &Args) -> Vec<&'static str> {
+    if args.show_gui {
+        vec!["--show-gui"]
+    } else {
+        Vec::new()
+    }
+}
+
+fn no_flags(_args: &Args) -> Vec<&'static str> {
+    Vec::new()
+}
+
+/// Version line for `--version`, listing what this binary was built with.
+pub fn version_string() -> String {
+    let names = |enabled: bool| {
+        let names: Vec<&str> = FEATURES.iter().filter(|f| f.enabled == enabled).map(|f| f.name).collect();
+        if names.is_empty() { "none".to_string() } else { names.join(", ") }
+    };
+    format!(
+        "{} (features: {}; not compiled in: {})",
+        env!("CARGO_PKG_VERSION"),
+        names(true),
+        names(false)
+    )
+}
+
+/// Fails on the first flag whose feature was not compiled in.
+pub fn reject_disabled_flags(args: &Args) -> Result<()> {
+    for feature in FEATURES.iter().filter(|f| !f.enabled) {
+        if let Some(flag) = (feature.flags_used)(args).first() {
+            return Err(anyhow!(
+                "{} requires the '{}' feature ({}); rebuild with `--features {}`",
+                flag,
+                feature.name,
+                feature.description,
+                feature.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Runs a feature's standalone mode (one that replaces running the host)
+/// if its flag was given. Returns whether it succeeded.
+pub fn run_standalone(args: &Args) -> Option<Result<bool>> {
+    #[cfg(feature = "metrics")]
+    if args.bench_latency {
+        return Some(crate::bench::run_latency_bench(args));
+    }
+    #[cfg(feature = "midi")]
+    if args.list_midi_ports {
+        return Some(crate::midi::print_ports().map(|()| true));
+    }
+    let _ = args;
+    None
+}
+
+/// MIDI input from `--midi-port`. Input stops when this is dropped; without
+/// the `midi` feature there is never any.
+pub struct MidiInput {
+    #[cfg(feature = "midi")]
+    _connection: Option<midir::MidiInputConnection<()>>,
+}
+
+impl MidiInput {
+    pub fn start(
+        args: &Args,
+        params: &[ParamInfo],
+        scale: Option<Scale>,
+        producer: CommandProducer,
+        session: SharedSessionCounters,
+    ) -> Result<Self> {
+        #[cfg(feature = "midi")]
+        {
+            let connection = match &args.midi_port {
+                Some(port) => Some(crate::midi::start_midi_input(
+                    port,
+                    crate::midi::MidiConfig {
+                        cc_map: &args.cc_map,
+                        params,
+                        bend_range: args.bend_range,
+                        scale,
+                        producer,
+                        session,
+                        verbose: args.verbose,
+                    },
+                )?),
+                None => None,
+            };
+            Ok(Self { _connection: connection })
+        }
+        #[cfg(not(feature = "midi"))]
+        {
+            let _ = (args, params, scale, producer, session);
+            Ok(Self {})
+        }
+    }
+}
+
+/// Requests from the plugin about its editor, forwarded to the main thread.
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub enum GuiRequest {
+    /// The plugin asked for its editor to be resized.
+    Resize { width: u32, height: u32 },
+    /// The plugin's editor changed whether or how it can be resized.
+    ResizeHintsChanged,
+    /// The plugin's editor was closed from its side (e.g. a floating window's
+    /// close button).
+    Closed { was_destroyed: bool },
+}
+
+/// The plugin's editor window from `--show-gui`. Without the `gui` feature
+/// there is never one, and every call does nothing.
+pub struct Editor {
+    #[cfg(feature = "gui")]
+    window: Option<crate::gui::PluginWindow>,
+}
+
+impl Editor {
+    pub fn open(args: &Args, instance: &mut PluginInstance<OscClapHost>, title: &str) -> Result<Self> {
+        #[cfg(feature = "gui")]
+        {
+            let window = match args.show_gui {
+                true => Some(crate::gui::PluginWindow::open(instance, title)?),
+                false => None,
+            };
+            Ok(Self { window })
+        }
+        #[cfg(not(feature = "gui"))]
+        {
+            let _ = (args, instance, title);
+            Ok(Self {})
+        }
+    }
+
+    /// Handles pending window events and returns how long the main loop may
+    /// wait for its next message: `idle`, or less while a window needs its
+    /// events pumped.
+    pub fn pump(&mut self, instance: &mut PluginInstance<OscClapHost>, idle: Duration) -> Duration {
+        #[cfg(feature = "gui")]
+        {
+            if self.window.as_mut().is_some_and(|window| !window.pump(instance)) {
+                self.window = None;
+            }
+            if self.window.is_some() { crate::gui::POLL_INTERVAL } else { idle }
+        }
+        #[cfg(not(feature = "gui"))]
+        {
+            let _ = instance;
+            idle
+        }
+    }
+
+    pub fn handle(&mut self, request: GuiRequest, instance: &mut PluginInstance<OscClapHost>) {
+        #[cfg(feature = "gui")]
+        match request {
+            GuiRequest::Resize { width, height } => {
+                if let Some(window) = &mut self.window {
+                    window.request_resize(width, height);
+                }
+            }
+            GuiRequest::ResizeHintsChanged => {
+                if let Some(window) = &mut self.window {
+                    window.resize_hints_changed(instance);
+                }
+            }
+            GuiRequest::Closed { was_destroyed } => {
+                if let Some(mut window) = self.window.take() {
+                    window.close(instance, was_destroyed);
+                }
+            }
+        }
+        #[cfg(not(feature = "gui"))]
+        let _ = (request, instance);
+    }
+
+    /// Destroys the editor, if one is open.
+    pub fn close(&mut self, instance: &mut PluginInstance<OscClapHost>) {
+        #[cfg(feature = "gui")]
+        if let Some(mut window) = self.window.take() {
+            window.close(instance, false);
+        }
+        #[cfg(not(feature = "gui"))]
+        let _ = instance;
+    }
+}
@@ -0,0 +1,211 @@
+use crate::plugin::ParamInfo;
+use anyhow::{anyhow, Context, Result};
+use rosc::{OscMessage, OscPacket, OscType};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Something a controller might want to light up, reported by the audio
+/// thread for every event that reaches the plugin and every parameter change
+/// the plugin reports back.
+#[derive(Debug, Clone, Copy)]
+pub enum Notification {
+    ParamChanged { param_id: u32, value: f64 },
+    Note { key: i32, on: bool },
+    /// The master bus after a `/host/gain` or `/host/mute`; `gain` is linear.
+    MasterGain { gain: f32, muted: bool },
+}
+
+pub type NotificationProducer = Producer<Notification>;
+
+const NOTIFICATION_QUEUE_CAPACITY: usize = 4096;
+
+/// A param written by the feedback target is not echoed back to it for this long.
+const ECHO_WINDOW: Duration = Duration::from_millis(250);
+
+/// Params recently set by the feedback target itself, keyed by param id.
+/// Written by the OSC thread, read by the feedback thread.
+#[derive(Clone)]
+pub struct EchoGuard {
+    source: IpAddr,
+    recent: Arc<Mutex<HashMap<u32, Instant>>>,
+}
+
+impl EchoGuard {
+    /// Records that `from` set `param_id`, if `from` is the feedback target.
+    pub fn record(&self, from: SocketAddr, param_id: u32) {
+        if from.ip() == self.source {
+            self.recent
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(param_id, Instant::now());
+        }
+    }
+
+    fn is_echo(&self, param_id: u32) -> bool {
+        self.recent
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&param_id)
+            .is_some_and(|at| at.elapsed() < ECHO_WINDOW)
+    }
+}
+
+/// Param id and note key → LED index. Lines are `param <id> <led>` or
+/// `note <key> <led>`; `#` starts a comment.
+#[derive(Default)]
+pub struct LedMap {
+    params: HashMap<u32, i32>,
+    notes: HashMap<i32, i32>,
+}
+
+impl LedMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read LED map {}", path.display()))?;
+
+        let mut map = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let [kind, id, led] = parts[..] else {
+                return Err(anyhow!("{}:{}: expected 'param|note id led'", path.display(), i + 1));
+            };
+            let led = led
+                .parse::<i32>()
+                .with_context(|| format!("{}:{}: invalid LED index '{}'", path.display(), i + 1, led))?;
+            match kind {
+                "param" => {
+                    let id = id
+                        .parse::<u32>()
+                        .with_context(|| format!("{}:{}: invalid param id '{}'", path.display(), i + 1, id))?;
+                    map.params.insert(id, led);
+                }
+                "note" => {
+                    let key = id
+                        .parse::<i32>()
+                        .with_context(|| format!("{}:{}: invalid note key '{}'", path.display(), i + 1, id))?;
+                    map.notes.insert(key, led);
+                }
+                _ => return Err(anyhow!("{}:{}: unknown kind '{}'", path.display(), i + 1, kind)),
+            }
+        }
+        Ok(map)
+    }
+
+    // With no entries of a kind, ids pass through unchanged; otherwise only
+    // mapped ids have an LED.
+    fn param_led(&self, param_id: u32) -> Option<i32> {
+        if self.params.is_empty() {
+            Some(param_id as i32)
+        } else {
+            self.params.get(&param_id).copied()
+        }
+    }
+
+    fn note_led(&self, key: i32) -> Option<i32> {
+        if self.notes.is_empty() {
+            Some(key)
+        } else {
+            self.notes.get(&key).copied()
+        }
+    }
+}
+
+pub struct FeedbackConfig {
+    pub target: SocketAddr,
+    pub rate_hz: f64,
+    pub map: LedMap,
+    pub params: HashMap<u32, ParamInfo>,
+}
+
+/// Starts the feedback thread. Returns the producer for the audio thread and
+/// the echo guard for the OSC thread.
+pub fn start_feedback(config: FeedbackConfig) -> Result<(NotificationProducer, EchoGuard)> {
+    let socket = UdpSocket::bind("0.0.0.0:0").context("Failed to bind LED feedback socket")?;
+    let (producer, consumer) = RingBuffer::new(NOTIFICATION_QUEUE_CAPACITY);
+    let echo = EchoGuard {
+        source: config.target.ip(),
+        recent: Arc::new(Mutex::new(HashMap::new())),
+    };
+
+    let target = config.target;
+    let thread_echo = echo.clone();
+    thread::Builder::new()
+        .name("led-feedback".into())
+        .spawn(move || run_feedback(socket, consumer, config, thread_echo))
+        .context("Failed to spawn LED feedback thread")?;
+
+    log::info!("Sending LED feedback to {}", target);
+    Ok((producer, echo))
+}
+
+fn run_feedback(socket: UdpSocket, mut consumer: Consumer<Notification>, config: FeedbackConfig, echo: EchoGuard) {
+    let interval = Duration::from_secs_f64(1.0 / config.rate_hz.max(1.0));
+    // Latest state per LED since the last flush, and what was last sent.
+    let mut pending_params: HashMap<i32, f32> = HashMap::new();
+    let mut pending_notes: HashMap<i32, bool> = HashMap::new();
+    let mut sent_params: HashMap<i32, f32> = HashMap::new();
+    let mut pending_master: Option<(f32, bool)> = None;
+
+    loop {
+        thread::sleep(interval);
+
+        while let Ok(notification) = consumer.pop() {
+            match notification {
+                Notification::ParamChanged { param_id, value } => {
+                    if echo.is_echo(param_id) {
+                        continue;
+                    }
+                    let (Some(param), Some(led)) = (config.params.get(&param_id), config.map.param_led(param_id)) else {
+                        continue;
+                    };
+                    pending_params.insert(led, param.value_to_normalized(value) as f32);
+                }
+                Notification::Note { key, on } => {
+                    if let Some(led) = config.map.note_led(key) {
+                        pending_notes.insert(led, on);
+                    }
+                }
+                Notification::MasterGain { gain, muted } => pending_master = Some((gain, muted)),
+            }
+        }
+
+        for (led, value) in pending_params.drain() {
+            if sent_params.get(&led) == Some(&value) {
+                continue;
+            }
+            sent_params.insert(led, value);
+            send(&socket, config.target, "/led/param", vec![OscType::Int(led), OscType::Float(value)]);
+        }
+        for (led, on) in pending_notes.drain() {
+            send(&socket, config.target, "/led/note", vec![OscType::Int(led), OscType::Int(on as i32)]);
+        }
+        if let Some((gain, muted)) = pending_master.take() {
+            send(&socket, config.target, "/host/gain", vec![OscType::Float(gain), OscType::Int(muted as i32)]);
+        }
+    }
+}
+
+fn send(socket: &UdpSocket, target: SocketAddr, addr: &str, args: Vec<OscType>) {
+    let packet = OscPacket::Message(OscMessage {
+        addr: addr.to_string(),
+        args,
+    });
+    match rosc::encoder::encode(&packet) {
+        Ok(bytes) => {
+            if let Err(e) = socket.send_to(&bytes, target) {
+                log::debug!("Failed to send LED feedback to {}: {}", target, e);
+            }
+        }
+        Err(e) => log::warn!("Failed to encode LED feedback {}: {:?}", addr, e),
+    }
+}
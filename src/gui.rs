@@ -0,0 +1,203 @@
+//! Plugin editor windows (`--show-gui`). A GUI that can be embedded gets a
+//! winit window as its parent; one that can only float opens its own. All of
+//! this runs on the main thread: the main loop pumps window events between
+//! main-thread messages instead of handing the thread to an event loop.
+
+use crate::engine::OscClapHost;
+use anyhow::{anyhow, Context, Result};
+use clack_extensions::gui::{GuiApiType, GuiConfiguration, GuiSize, PluginGui, Window as ClapWindow};
+use clack_host::prelude::*;
+use std::ffi::CString;
+use std::time::Duration;
+use winit::dpi::{LogicalSize, PhysicalSize, Size};
+use winit::event::{Event, WindowEvent};
+use winit::event_loop::EventLoop;
+use winit::platform::pump_events::EventLoopExtPumpEvents;
+use winit::window::{Window, WindowBuilder};
+
+/// How often the main loop pumps window events while a GUI is open.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Used when the plugin doesn't report a size of its own.
+const DEFAULT_SIZE: GuiSize = GuiSize { width: 640, height: 480 };
+
+pub struct PluginWindow {
+    gui: PluginGui,
+    api_type: GuiApiType<'static>,
+    event_loop: EventLoop<()>,
+    /// Parent window of an embedded GUI; `None` when the GUI floats.
+    window: Option<Window>,
+    resizable: bool,
+}
+
+impl PluginWindow {
+    /// Creates and shows the plugin's GUI, embedded if the plugin supports it.
+    pub fn open(instance: &mut PluginInstance<OscClapHost>, title: &str) -> Result<Self> {
+        let gui: PluginGui = instance
+            .plugin_handle()
+            .get_extension()
+            .ok_or_else(|| anyhow!("Plugin has no GUI (no gui extension)"))?;
+        let api_type = GuiApiType::default_for_current_platform()
+            .ok_or_else(|| anyhow!("No CLAP GUI API for this platform"))?;
+        let event_loop = EventLoop::new().context("Failed to create window event loop")?;
+
+        let mut handle = instance.plugin_handle();
+        let embedded = GuiConfiguration {
+            api_type,
+            is_floating: false,
+        };
+        let floating = GuiConfiguration {
+            api_type,
+            is_floating: true,
+        };
+
+        let mut plugin_window = Self {
+            gui,
+            api_type,
+            event_loop,
+            window: None,
+            resizable: false,
+        };
+
+        if gui.is_api_supported(&mut handle, embedded) {
+            gui.create(&mut handle, embedded)
+                .map_err(|e| anyhow!("Plugin failed to create its GUI: {:?}", e))?;
+
+            let window = WindowBuilder::new()
+                .with_title(title)
+                .build(&plugin_window.event_loop)
+                .context("Failed to create plugin window")?;
+            let parent = ClapWindow::from_window(&window).ok_or_else(|| anyhow!("Unsupported window handle"))?;
+            // SAFETY: the parent window outlives the GUI; `close` destroys the
+            // GUI before the window is dropped.
+            unsafe { gui.set_parent(&mut handle, parent) }
+                .map_err(|e| anyhow!("Plugin failed to attach its GUI: {:?}", e))?;
+            if !api_type.uses_logical_size() {
+                let _ = gui.set_scale(&mut handle, window.scale_factor());
+            }
+
+            plugin_window.resizable = gui.can_resize(&mut handle);
+            window.set_resizable(plugin_window.resizable);
+            let size = gui.get_size(&mut handle).unwrap_or(DEFAULT_SIZE);
+            let _ = window.request_inner_size(plugin_window.to_window_size(size));
+            plugin_window.window = Some(window);
+            log::info!("Opened embedded plugin GUI ({}x{})", size.width, size.height);
+        } else if gui.is_api_supported(&mut handle, floating) {
+            gui.create(&mut handle, floating)
+                .map_err(|e| anyhow!("Plugin failed to create its GUI: {:?}", e))?;
+            if let Ok(title) = CString::new(title) {
+                gui.suggest_title(&mut handle, &title);
+            }
+            log::info!("Opened floating plugin GUI");
+        } else {
+            return Err(anyhow!("Plugin supports neither an embedded nor a floating GUI on this platform"));
+        }
+
+        gui.show(&mut handle)
+            .map_err(|e| anyhow!("Plugin failed to show its GUI: {:?}", e))?;
+        Ok(plugin_window)
+    }
+
+    /// Handles pending window events. Returns false once the user has closed
+    /// the window; the GUI is destroyed by then.
+    pub fn pump(&mut self, instance: &mut PluginInstance<OscClapHost>) -> bool {
+        let mut events = Vec::new();
+        self.event_loop.pump_events(Some(Duration::ZERO), |event, _| {
+            if let Event::WindowEvent { event, .. } = event {
+                events.push(event);
+            }
+        });
+
+        for event in events {
+            match event {
+                WindowEvent::CloseRequested => {
+                    self.close(instance, false);
+                    return false;
+                }
+                WindowEvent::Resized(size) => self.window_resized(instance, size),
+                WindowEvent::ScaleFactorChanged { scale_factor, .. } if !self.api_type.uses_logical_size() => {
+                    let _ = self.gui.set_scale(&mut instance.plugin_handle(), scale_factor);
+                }
+                _ => {}
+            }
+        }
+        true
+    }
+
+    /// The plugin asked for a new size (`clap_host_gui.request_resize`).
+    pub fn request_resize(&mut self, width: u32, height: u32) {
+        let size = self.to_window_size(GuiSize { width, height });
+        if let Some(window) = &self.window {
+            let _ = window.request_inner_size(size);
+        }
+    }
+
+    /// The plugin's resize hints changed (`clap_host_gui.resize_hints_changed`):
+    /// re-read whether the GUI can be resized and update the window to match.
+    pub fn resize_hints_changed(&mut self, instance: &mut PluginInstance<OscClapHost>) {
+        let Some(window) = &self.window else {
+            return;
+        };
+        let mut handle = instance.plugin_handle();
+        self.resizable = self.gui.can_resize(&mut handle);
+        window.set_resizable(self.resizable);
+        if !self.resizable {
+            if let Some(size) = self.gui.get_size(&mut handle) {
+                let _ = window.request_inner_size(self.to_window_size(size));
+            }
+        }
+    }
+
+    /// Destroys the GUI and its window. `already_destroyed` is set when the
+    /// plugin reported the GUI gone itself.
+    pub fn close(&mut self, instance: &mut PluginInstance<OscClapHost>, already_destroyed: bool) {
+        if !already_destroyed {
+            self.gui.destroy(&mut instance.plugin_handle());
+        }
+        self.window = None;
+        log::info!("Closed plugin GUI");
+    }
+
+    /// The user dragged the window edge. Resizable GUIs get the closest size
+    /// they accept; fixed ones snap the window back to their own size.
+    fn window_resized(&mut self, instance: &mut PluginInstance<OscClapHost>, size: PhysicalSize<u32>) {
+        let Some(window) = &self.window else {
+            return;
+        };
+        let mut handle = instance.plugin_handle();
+        let requested = if self.api_type.uses_logical_size() {
+            let logical: LogicalSize<u32> = size.to_logical(window.scale_factor());
+            GuiSize {
+                width: logical.width,
+                height: logical.height,
+            }
+        } else {
+            GuiSize {
+                width: size.width,
+                height: size.height,
+            }
+        };
+
+        let accepted = if self.resizable {
+            let adjusted = self.gui.adjust_size(&mut handle, requested).unwrap_or(requested);
+            match self.gui.set_size(&mut handle, adjusted) {
+                Ok(()) => adjusted,
+                Err(_) => self.gui.get_size(&mut handle).unwrap_or(requested),
+            }
+        } else {
+            self.gui.get_size(&mut handle).unwrap_or(requested)
+        };
+        if accepted != requested {
+            let _ = window.request_inner_size(self.to_window_size(accepted));
+        }
+    }
+
+    /// GUI sizes are logical on APIs that say so (Cocoa) and physical otherwise.
+    fn to_window_size(&self, size: GuiSize) -> Size {
+        if self.api_type.uses_logical_size() {
+            LogicalSize::new(size.width, size.height).into()
+        } else {
+            PhysicalSize::new(size.width, size.height).into()
+        }
+    }
+}
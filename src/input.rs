@@ -0,0 +1,179 @@
+//! Audio input capture for effect plugins. The input stream's callback writes
+//! whole interleaved frames into a ring buffer; the output callback drains it
+//! into the plugin's input buffers each block.
+
+use anyhow::{anyhow, Context, Result};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{Device, FromSample, InputCallbackInfo, SampleFormat, SizedSample, Stream, StreamConfig};
+use rtrb::{Consumer, Producer, RingBuffer};
+
+/// Keeps the input stream alive; dropping it stops capture.
+pub struct InputCapture {
+    _stream: Stream,
+}
+
+/// Audio-thread end of the capture ring buffer.
+pub struct InputSource {
+    consumer: Consumer<f32>,
+    channels: usize,
+    /// Samples allowed to queue up before the oldest are dropped, so drift
+    /// between the input and output clocks can't grow the latency unbounded.
+    max_buffered: usize,
+}
+
+/// Blocks of headroom in the ring buffer, and blocks kept before dropping.
+const RING_BLOCKS: usize = 8;
+const MAX_BUFFERED_BLOCKS: usize = 3;
+
+/// Opens `device` for capture at the output's sample rate. There is no
+/// resampling, so an input device that can't run at that rate is an error.
+/// `plugin_channels` is only used to report how channels will be mapped.
+pub fn start_input(
+    device: &Device,
+    sample_rate: u32,
+    max_block_frames: u32,
+    plugin_channels: usize,
+) -> Result<(InputCapture, InputSource)> {
+    let name = device.name().unwrap_or_default();
+    let default_config = device
+        .default_input_config()
+        .with_context(|| format!("Failed to get default input config for {}", name))?;
+
+    let supported = device
+        .supported_input_configs()
+        .context("Failed to get supported input configs")?
+        .filter(|cfg| cfg.min_sample_rate().0 <= sample_rate && cfg.max_sample_rate().0 >= sample_rate)
+        .max_by_key(|cfg| {
+            (
+                cfg.channels() == default_config.channels(),
+                cfg.sample_format() == SampleFormat::F32,
+            )
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "Input device {} does not support {}Hz (the output sample rate; default input rate is {}Hz). \
+                 Pick a --sample-rate both devices support.",
+                name,
+                sample_rate,
+                default_config.sample_rate().0
+            )
+        })?
+        .with_sample_rate(cpal::SampleRate(sample_rate));
+
+    let channels = supported.channels() as usize;
+    let sample_format = supported.sample_format();
+    let config = StreamConfig {
+        channels: supported.channels(),
+        sample_rate: cpal::SampleRate(sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+    };
+
+    let block = max_block_frames as usize * channels;
+    let (producer, consumer) = RingBuffer::new(block * RING_BLOCKS);
+    let stream = build_input_stream_for_sample_format(device, producer, &config, sample_format, channels)
+        .context("Failed to build input stream")?;
+    stream.play().context("Failed to start input stream")?;
+
+    log::info!(
+        "Capturing audio input from {}: {}Hz, {} channels, sample format {}",
+        name,
+        sample_rate,
+        channels,
+        sample_format
+    );
+    if plugin_channels > channels {
+        log::info!("Repeating {} input channel(s) across the plugin's {}", channels, plugin_channels);
+    } else if plugin_channels < channels {
+        log::info!(
+            "Plugin takes {} input channel(s); dropping the device's other {}",
+            plugin_channels,
+            channels - plugin_channels
+        );
+    }
+
+    Ok((
+        InputCapture { _stream: stream },
+        InputSource {
+            consumer,
+            channels,
+            max_buffered: block * MAX_BUFFERED_BLOCKS,
+        },
+    ))
+}
+
+fn build_input_stream_for_sample_format(
+    device: &Device,
+    producer: Producer<f32>,
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+    channels: usize,
+) -> Result<Stream> {
+    let err = |e| log::error!("Audio input stream error: {}", e);
+
+    let stream = match sample_format {
+        SampleFormat::I8 => device.build_input_stream(config, make_capture::<i8>(producer, channels), err, None),
+        SampleFormat::I16 => device.build_input_stream(config, make_capture::<i16>(producer, channels), err, None),
+        SampleFormat::I32 => device.build_input_stream(config, make_capture::<i32>(producer, channels), err, None),
+        SampleFormat::U8 => device.build_input_stream(config, make_capture::<u8>(producer, channels), err, None),
+        SampleFormat::U16 => device.build_input_stream(config, make_capture::<u16>(producer, channels), err, None),
+        SampleFormat::U32 => device.build_input_stream(config, make_capture::<u32>(producer, channels), err, None),
+        SampleFormat::F32 => device.build_input_stream(config, make_capture::<f32>(producer, channels), err, None),
+        SampleFormat::F64 => device.build_input_stream(config, make_capture::<f64>(producer, channels), err, None),
+        other => return Err(anyhow!("Unsupported input sample format {}", other)),
+    };
+    Ok(stream?)
+}
+
+/// Writes whole frames only, so the reader never sees half a frame. Frames
+/// that don't fit are dropped.
+fn make_capture<S: SizedSample>(mut producer: Producer<f32>, channels: usize) -> impl FnMut(&[S], &InputCallbackInfo)
+where
+    f32: FromSample<S>,
+{
+    move |data, _info| {
+        let frames = (producer.slots() / channels).min(data.len() / channels);
+        if let Ok(chunk) = producer.write_chunk_uninit(frames * channels) {
+            chunk.fill_from_iter(data.iter().map(|&s| f32::from_sample(s)));
+        }
+    }
+}
+
+impl InputSource {
+    /// Deinterleaves up to `frame_count` captured frames into `planar`
+    /// (`channel_count` planes of `frame_count`, cleared by the caller).
+    /// Channels are mapped like the plugin's output onto the device: device
+    /// channels repeat when the plugin has more (a mono mic feeds both
+    /// sides), and are averaged when it has fewer. Frames that haven't
+    /// arrived yet are left as they are, i.e. silent.
+    pub fn fill(&mut self, planar: &mut [f32], frame_count: usize, channel_count: usize) {
+        let excess = self.consumer.slots().saturating_sub(self.max_buffered);
+        let excess = excess - excess % self.channels;
+        if excess > 0 {
+            if let Ok(chunk) = self.consumer.read_chunk(excess) {
+                chunk.commit_all();
+            }
+        }
+
+        let frames = (self.consumer.slots() / self.channels).min(frame_count);
+        let Ok(chunk) = self.consumer.read_chunk(frames * self.channels) else {
+            return;
+        };
+        let (first, second) = chunk.as_slices();
+        for (i, &sample) in first.iter().chain(second).enumerate() {
+            let frame = i / self.channels;
+            let source = i % self.channels;
+            if self.channels <= channel_count {
+                let mut channel = source;
+                while channel < channel_count {
+                    planar[channel * frame_count + frame] = sample;
+                    channel += self.channels;
+                }
+            } else {
+                let channel = source % channel_count;
+                let sources = (channel..self.channels).step_by(channel_count).len();
+                planar[channel * frame_count + frame] += sample / sources as f32;
+            }
+        }
+        chunk.commit_all();
+    }
+}
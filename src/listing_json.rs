@@ -0,0 +1,170 @@
+//! `--json` output for `--print-osc`, `--list-plugins`, `--list-presets`,
+//! `--list-devices` and `--list-backends`, for tools that generate controller
+//! layouts. Every document carries `format_version`, which goes up when a
+//! field is renamed, removed or changes meaning; new fields are added without
+//! bumping it.
+
+use crate::device::{
+    list_backends, list_input_devices, list_output_devices, select_device, select_input_device, DeviceInfo,
+    DeviceSelector,
+};
+use crate::osc::BUILTIN_ADDRESSES;
+use crate::plugin::{list_plugins_in_bundle, ParamInfo, PluginDescriptorInfo};
+use crate::presets::list_presets;
+use anyhow::{Context, Result};
+use clack_host::prelude::*;
+use cpal::traits::DeviceTrait;
+use cpal::{Host, SupportedBufferSize, SupportedStreamConfig, SupportedStreamConfigRange};
+use serde_json::{json, Value};
+
+const FORMAT_VERSION: u32 = 1;
+
+fn print(mut document: Value) -> Result<()> {
+    document["format_version"] = json!(FORMAT_VERSION);
+    let text = serde_json::to_string_pretty(&document).context("Failed to encode JSON")?;
+    println!("{}", text);
+    Ok(())
+}
+
+pub fn print_plugins(bundle: &PluginBundle) -> Result<()> {
+    let plugins = list_plugins_in_bundle(bundle)?;
+    print(json!({ "plugins": plugins.iter().map(descriptor_json).collect::<Vec<_>>() }))
+}
+
+pub fn print_presets(bundle: &PluginBundle) -> Result<()> {
+    let presets: Vec<Value> = list_presets(bundle)?
+        .iter()
+        .map(|preset| {
+            json!({
+                "name": preset.name,
+                "location_uri": preset.location_uri,
+                "load_key": preset.load_key,
+                "provider": preset.provider,
+                "plugin_ids": preset.plugin_ids,
+            })
+        })
+        .collect();
+    print(json!({ "presets": presets }))
+}
+
+/// `plugins` holds each plugin's descriptor and params in chain order.
+pub fn print_osc_api(plugins: &[(&PluginDescriptorInfo, &[ParamInfo])]) -> Result<()> {
+    let plugins: Vec<Value> = plugins
+        .iter()
+        .enumerate()
+        .map(|(index, (descriptor, params))| {
+            let mut plugin = descriptor_json(descriptor);
+            plugin["chain_index"] = json!(index);
+            plugin["params"] = json!(params.iter().map(param_json).collect::<Vec<_>>());
+            plugin
+        })
+        .collect();
+    print(json!({
+        "addresses": BUILTIN_ADDRESSES,
+        "plugins": plugins,
+    }))
+}
+
+pub fn print_devices(host: &Host) -> Result<()> {
+    let outputs = list_output_devices(host)?
+        .iter()
+        .map(|info| {
+            let device = select_device(host, Some(&DeviceSelector::Index(info.index))).ok();
+            let configs = device
+                .as_ref()
+                .and_then(|device| device.supported_output_configs().ok())
+                .map(|configs| configs.map(|c| config_json(&c)).collect());
+            let default = device.as_ref().and_then(|device| device.default_output_config().ok());
+            device_json(info, configs, default)
+        })
+        .collect::<Vec<_>>();
+    let inputs = list_input_devices(host)?
+        .iter()
+        .map(|info| {
+            let device = select_input_device(host, Some(&DeviceSelector::Index(info.index))).ok();
+            let configs = device
+                .as_ref()
+                .and_then(|device| device.supported_input_configs().ok())
+                .map(|configs| configs.map(|c| config_json(&c)).collect());
+            let default = device.as_ref().and_then(|device| device.default_input_config().ok());
+            device_json(info, configs, default)
+        })
+        .collect::<Vec<_>>();
+    print(json!({
+        "host": host.id().name(),
+        "outputs": outputs,
+        "inputs": inputs,
+    }))
+}
+
+pub fn print_backends() -> Result<()> {
+    let backends: Vec<Value> = list_backends()
+        .into_iter()
+        .map(|(name, is_default)| json!({ "name": name, "is_default": is_default }))
+        .collect();
+    print(json!({ "backends": backends }))
+}
+
+fn descriptor_json(plugin: &PluginDescriptorInfo) -> Value {
+    json!({
+        "index": plugin.index,
+        "id": plugin.id,
+        "name": plugin.name,
+        "vendor": plugin.vendor,
+        "version": plugin.version,
+        "features": plugin.features,
+    })
+}
+
+fn param_json(param: &ParamInfo) -> Value {
+    json!({
+        "id": param.id,
+        "name": param.name,
+        "module": param.module,
+        "min_value": param.min_value,
+        "max_value": param.max_value,
+        "default_value": param.default_value,
+        "min_text": param.min_text,
+        "max_text": param.max_text,
+        "default_text": param.default_text,
+        "is_stepped": param.is_stepped,
+        "is_automatable": param.is_automatable,
+        "is_modulatable": param.is_modulatable,
+        "is_modulatable_per_note_id": param.is_modulatable_per_note_id,
+        "steps": param
+            .step_texts
+            .iter()
+            .map(|(value, text)| json!({ "value": value, "text": text }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// `configs` and `default` are `null` when the device couldn't be queried.
+fn device_json(info: &DeviceInfo, configs: Option<Vec<Value>>, default: Option<SupportedStreamConfig>) -> Value {
+    json!({
+        "index": info.index,
+        "name": info.name,
+        "is_default": info.is_default,
+        "default_config": default.map(|config| json!({
+            "channels": config.channels(),
+            "sample_rate": config.sample_rate().0,
+            "sample_format": config.sample_format().to_string(),
+        })),
+        "supported_configs": configs,
+    })
+}
+
+fn config_json(config: &SupportedStreamConfigRange) -> Value {
+    let (min_buffer_size, max_buffer_size) = match config.buffer_size() {
+        SupportedBufferSize::Range { min, max } => (Some(*min), Some(*max)),
+        SupportedBufferSize::Unknown => (None, None),
+    };
+    json!({
+        "channels": config.channels(),
+        "min_sample_rate": config.min_sample_rate().0,
+        "max_sample_rate": config.max_sample_rate().0,
+        "sample_format": config.sample_format().to_string(),
+        "min_buffer_size": min_buffer_size,
+        "max_buffer_size": max_buffer_size,
+    })
+}
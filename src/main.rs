@@ -1,45 +1,179 @@
+mod alias;
 mod args;
+#[cfg(feature = "metrics")]
+mod bench;
+mod bend;
+mod chain;
+mod check;
+mod config_file;
+mod crash;
 mod device;
 mod engine;
+mod event_stats;
+mod features;
+mod feedback;
+#[cfg(feature = "gui")]
+mod gui;
+mod input;
+mod listing_json;
+mod meter;
+mod mix;
+#[cfg(feature = "midi")]
+mod midi;
 mod osc;
+mod osc_map;
+mod osc_tcp;
+mod output;
+mod param_flush;
+mod param_names;
+mod param_out;
 mod plugin;
+mod presets;
+mod probe;
+mod queue;
+mod ramp;
+mod record;
+mod reload;
+mod render;
+mod runtime_config;
+mod schedule;
+mod script;
+mod self_test;
+mod session;
+mod sustain;
+mod test_tone;
+#[cfg(test)]
+mod test_plugin;
+mod transport;
+mod tuning;
+mod voices;
+mod state;
+#[cfg(any(test, feature = "metrics"))]
+mod testing;
 
 use anyhow::{Context, Result};
-use clap::Parser;
 use cpal::traits::DeviceTrait;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use args::Args;
-use device::{get_cpal_host, get_device_config, print_devices, select_device};
-use engine::{AudioEngine, MainThreadMessage, OscClapHost, OscClapHostMainThread, OscClapHostShared};
-use osc::{create_command_queue, start_osc_receiver};
-use plugin::{dump_patch_state, enumerate_params, load_bundle, print_osc_api, print_plugins, select_plugin_id};
+use device::{
+    get_cpal_host, get_device_config, get_headless_config, output_routes, print_backends, print_configs, print_devices,
+    requested_channels, select_device, select_input_device, AudioConfig, ConfigRequest, DeviceSelector,
+};
+use crash::{install_panic_hook, DEVICE_LOST_EXIT_CODE, FORCED_EXIT_CODE, PANIC_EXIT_CODE};
+use chain::ChainedPlugin;
+use engine::{
+    AudioEngine, ChainPlugin, EngineOptions, MainThreadMessage, OfflineRenderer, OscClapHost, OscClapHostMainThread,
+    OscClapHostShared,
+};
+use event_stats::create_event_stats;
+use features::{Editor, MidiInput};
+use feedback::{start_feedback, FeedbackConfig, LedMap};
+use input::start_input;
+use alias::AliasTable;
+use osc_map::OscMap;
+use osc::{
+    create_command_queue, notify, push_command, push_command_to, start_osc_receiver, ChainParams, Command, CommandProducer, OscReceiverState,
+    ParamRescan,
+};
+use output::{sink_from_url, AudioSink, NullSink};
+use param_flush::ParamFlush;
+use param_names::ParamNames;
+use param_out::{start_param_sender, ParamSenderTargets};
+use plugin::{
+    enumerate_params, find_descriptor, flush_params, live_value_text, load_bundle, param_value, print_osc_api,
+    print_plugins, ParamInfo, query_audio_ports, query_latency, query_voice_info, resolve_param_assignments,
+    supports_note_expressions, select_plugin_id, text_to_value,
+};
+use presets::{load_preset, print_presets, PresetLocation};
+use probe::{print_probe_warnings, probe_setup};
+use queue::SheddingQueue;
+use record::Recorder;
+use reload::watch_aliases;
+use runtime_config::{RuntimeConfig, SharedRuntimeConfig};
+use script::run_script;
+use self_test::start_self_test;
+use session::{SessionCounters, SessionReporter};
+use mix::{db_to_gain, MasterBus, MixControl, Mixer, MAX_GAIN_DB};
+use test_tone::{check_tone, TestTone, TestToneTarget, ToneControl};
+use state::{dump_state, restore_state};
+use tuning::{Scale, Tuner};
+use voices::{RetriggerMode, VoiceMap};
 
+use clack_extensions::voice_info::{VoiceInfo, VoiceInfoFlags};
 use clack_host::prelude::*;
-use crossbeam_channel::unbounded;
+use clack_host::process::StartedPluginAudioProcessor;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use rosc::OscType;
+
+/// Lets `assert_no_alloc` see allocations made while the audio callback runs.
+#[cfg(feature = "alloc-check")]
+#[global_allocator]
+static ALLOCATOR: assert_no_alloc::AllocDisabler = assert_no_alloc::AllocDisabler;
 
 fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let args = Args::parse();
+    let args = Args::parse_with_features();
+    features::reject_disabled_flags(&args)?;
 
-    let cpal_host = get_cpal_host();
+    if args.list_backends {
+        if args.json {
+            return listing_json::print_backends();
+        }
+        print_backends();
+        return Ok(());
+    }
+
+    let cpal_host = get_cpal_host(args.audio_backend.as_deref())?;
+    if args.audio_backend.is_some() {
+        log::info!("Audio backend: {}", cpal_host.id().name());
+    }
 
     if args.list_devices {
+        if args.json {
+            return listing_json::print_devices(&cpal_host);
+        }
         return print_devices(&cpal_host);
     }
 
+    if args.list_configs {
+        return print_configs(&cpal_host, args.device.as_ref());
+    }
+
+    if let Some(result) = features::run_standalone(&args) {
+        if !result? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let plugin_path = args
         .plugin_path
         .as_ref()
         .context("Plugin path is required")?;
 
-    let bundle = load_bundle(plugin_path)?;
+    let mut bundle = load_bundle(plugin_path)?;
 
     if args.list_plugins {
+        if args.json {
+            return listing_json::print_plugins(&bundle);
+        }
         return print_plugins(&bundle);
     }
 
+    if args.list_presets {
+        if args.json {
+            return listing_json::print_presets(&bundle);
+        }
+        return print_presets(&bundle);
+    }
+
     let plugin_id = select_plugin_id(
         &bundle,
         args.plugin_id.as_deref(),
@@ -55,20 +189,45 @@ fn main() -> Result<()> {
         "0.1.0",
     )?;
 
-    let (sender, _receiver) = unbounded();
+    let (event_stats_producer, mut event_stats) = create_event_stats();
+    let session = SessionCounters::new();
+    let session_reporter = SessionReporter::new(session.clone(), event_stats.shared(), args.session_report.clone());
+
+    let (main_sender, main_receiver) = unbounded();
+    install_panic_hook(
+        main_sender.clone(),
+        args.notify.clone(),
+        args.keep_running_on_panic,
+        session_reporter.clone(),
+    );
 
     let mut instance = PluginInstance::<OscClapHost>::new(
-        |_| OscClapHostShared::new(sender.clone()),
+        |_| OscClapHostShared::new(main_sender.clone(), 0),
         |shared| OscClapHostMainThread::new(shared),
         &bundle,
         &plugin_id,
         &host_info,
     )?;
 
-    let params = enumerate_params(&mut instance);
+    let mut params = enumerate_params(&mut instance);
+    let mut descriptor = find_descriptor(&bundle, &plugin_id)?;
+
+    let mut chain = Vec::with_capacity(args.chain.len());
+    for spec in &args.chain {
+        let plugin = ChainedPlugin::load(spec, chain.len() + 1, &main_sender, &host_info)?;
+        log::info!("Chained plugin {}: {}", chain.len() + 1, plugin.descriptor.name);
+        chain.push(plugin);
+    }
 
+    if args.print_osc && args.json {
+        let mut plugins = vec![(&descriptor, params.as_slice())];
+        plugins.extend(chain.iter().map(|p| (&p.descriptor, p.params.as_slice())));
+        return listing_json::print_osc_api(&plugins);
+    }
     if args.print_osc {
-        print_osc_api(&params);
+        let mut tables = vec![(descriptor.name.as_str(), params.as_slice())];
+        tables.extend(chain.iter().map(|p| (p.descriptor.name.as_str(), p.params.as_slice())));
+        print_osc_api(&tables);
         return Ok(());
     }
 
@@ -78,94 +237,1382 @@ fn main() -> Result<()> {
         .map(|p| p.id)
         .collect();
 
-    let device = select_device(&cpal_host, args.device)?;
-    log::info!("Using audio device: {}", device.name().unwrap_or_default());
+    let audio_ports = query_audio_ports(&mut instance);
+    let setup_warnings = probe_setup(&descriptor, &audio_ports, args.input_device.is_some());
+    // The device plays whatever comes out of the end of the chain.
+    let plugin_channels = chain.last().map_or(&audio_ports, |p| &p.audio_ports).main_output_channels();
 
-    let audio_config = get_device_config(
-        &device,
-        args.sample_rate,
-        args.channels,
-        args.buffer_size,
-    )?;
+    if args.check {
+        let report =
+            check::run_check(&args, &cpal_host, plugin_channels, &params, &per_note_mod_params, &setup_warnings);
+        report.print();
+        if report.has_errors() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    print_probe_warnings(&setup_warnings);
+    if args.strict_setup && !setup_warnings.is_empty() {
+        anyhow::bail!("Refusing to start because of setup warnings (--strict-setup)");
+    }
+
+    let headless = args.output.is_some() || args.no_audio || args.render.is_some();
+
+    let (device, audio_config) = if headless {
+        // Without a device, the highest routed channel decides the width.
+        let channels = args.channels.or(args.output_channels.iter().copied().max());
+        let config = get_headless_config(args.sample_rate, channels, plugin_channels, args.buffer_size);
+        (None, config)
+    } else {
+        let device = select_device(&cpal_host, args.device.as_ref())?;
+        log::info!("Using audio device: {}", device.name().unwrap_or_default());
+
+        let request = ConfigRequest {
+            sample_rate: args.sample_rate,
+            channels: requested_channels(&device, args.channels, &args.output_channels)?,
+            plugin_channels,
+            buffer_size: args.buffer_size,
+            sample_format: args.sample_format,
+            strict: args.strict_config,
+        };
+        let config = get_device_config(&device, request)?;
+        (Some(device), config)
+    };
 
     log::info!(
-        "Audio config: {}Hz, {} channels, buffer size {}",
+        "Audio config: {}Hz, {} channels, buffer size {}, sample format {}",
         audio_config.sample_rate,
         audio_config.channels,
-        audio_config.buffer_size
+        audio_config.buffer_size,
+        audio_config.sample_format
     );
+    let output_channels = output_routes(&args.output_channels, audio_config.channels)?;
+
+    let runtime_config: SharedRuntimeConfig = Arc::new(RwLock::new(RuntimeConfig {
+        plugin_path: plugin_path.display().to_string(),
+        plugin_id: descriptor.id.clone(),
+        plugin_name: descriptor.name.clone(),
+        audio_backend: cpal_host.id().name().to_string(),
+        output: match (&args.output, &device) {
+            (Some(url), _) => url.clone(),
+            (None, Some(device)) => device.name().unwrap_or_default(),
+            (None, None) => args.out.as_ref().map_or("none".to_string(), |path| path.display().to_string()),
+        },
+        sample_rate: audio_config.sample_rate,
+        buffer_size: audio_config.buffer_size,
+        channels: audio_config.channels,
+        output_channels: (!args.output_channels.is_empty()).then(|| {
+            args.output_channels.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(",")
+        }),
+        sample_format: audio_config.sample_format.to_string(),
+        osc_bind: args.osc_bind.to_string(),
+        osc_port: args.osc_port,
+        osc_tcp: args.osc_tcp.then(|| format!("{:?}", args.osc_tcp_framing).to_lowercase()),
+        osc_tcp_port: args.osc_tcp.then(|| args.osc_tcp_port.unwrap_or(args.osc_port)),
+        osc_buffer_size: args.osc_buffer_size,
+        queue_capacity: args.queue_capacity,
+        param_coalescing: !args.no_param_coalescing && !args.deterministic,
+        osc_send: args
+            .osc_send_port
+            .map(|port| SocketAddr::new(args.osc_send_host, port).to_string()),
+        feedback: args
+            .feedback_port
+            .map(|port| SocketAddr::new(args.osc_send_host, port).to_string()),
+        alias_file: args.alias_file.as_ref().map(|p| p.display().to_string()),
+        osc_map: args.osc_map.as_ref().map(|p| p.display().to_string()),
+        init_script: args.init_script.as_ref().map(|p| p.display().to_string()),
+        default_release_velocity: args.default_release_velocity,
+        bend_range: args.bend_range,
+        tuning: args.tuning.as_ref().map(|p| p.display().to_string()),
+        edo: args.edo,
+        normalized_params: args.normalized_params,
+        strict_setup: args.strict_setup,
+        strict_config: args.strict_config,
+        device_reconnect: args.device_reconnect,
+        master_gain_db: args.master_gain.min(MAX_GAIN_DB),
+        limiter: args.limiter,
+        verbose: args.verbose,
+        latency: 0,
+        voices: None,
+    }));
 
     let plugin_audio_config = PluginAudioConfiguration {
         sample_rate: audio_config.sample_rate as f64,
         min_frames_count: 1,
-        max_frames_count: audio_config.buffer_size,
+        max_frames_count: audio_config.max_callback_frames,
     };
 
+    // Startup values go in while the plugin is inactive, the only time the
+    // main thread may flush params.
+    if let Some(path) = &args.load_state {
+        restore_state(&mut instance, &descriptor.id, path)?;
+    }
+    if let Some(uri) = &args.preset {
+        let location = PresetLocation::parse(uri)?;
+        load_preset(&mut instance, &location, args.preset_load_key.as_deref())
+            .with_context(|| format!("Failed to load --preset {}", uri))?;
+        log::info!("Loaded preset {} {}", uri, args.preset_load_key.as_deref().unwrap_or(""));
+    }
+    // The state or preset can change the parameter table that --param names refer to.
+    if args.load_state.is_some() || args.preset.is_some() {
+        params = enumerate_params(&mut instance);
+    }
+    let startup_params = resolve_param_assignments(&args.param, &params)?;
+    if !startup_params.is_empty() {
+        flush_params(&mut instance, &startup_params)?;
+        log::info!("Set {} parameters from --param", startup_params.len());
+    }
+
     let stopped_processor = instance.activate(|_, _| (), plugin_audio_config)?;
     let audio_processor = stopped_processor
         .start_processing()
         .map_err(|e| anyhow::anyhow!("Failed to start processing: {:?}", e))?;
 
-    let (command_producer, command_consumer) = create_command_queue(1024);
+    let mut plugins = vec![ChainPlugin {
+        processor: audio_processor,
+        ports: audio_ports.clone(),
+        is_instrument: descriptor.has_feature("instrument"),
+        param_values: params
+            .iter()
+            .filter_map(|p| param_value(&mut instance, p.id).map(|value| (p.id, value)))
+            .collect(),
+        param_ranges: params.iter().map(|p| (p.id, p.min_value, p.max_value)).collect(),
+    }];
+    for chained in &mut chain {
+        let processor = chained
+            .instance
+            .activate(|_, _| (), plugin_audio_config)?
+            .start_processing()
+            .map_err(|e| anyhow::anyhow!("Failed to start processing {}: {:?}", chained.descriptor.name, e))?;
+        plugins.push(ChainPlugin {
+            processor,
+            ports: chained.audio_ports.clone(),
+            is_instrument: chained.descriptor.has_feature("instrument"),
+            param_values: chained
+                .params
+                .iter()
+                .filter_map(|p| param_value(&mut chained.instance, p.id).map(|value| (p.id, value)))
+                .collect(),
+            param_ranges: chained.params.iter().map(|p| (p.id, p.min_value, p.max_value)).collect(),
+        });
+    }
+    update_latency(&mut instance, &mut chain, &runtime_config, &args.notify);
+    let voice_info = update_voices(&mut instance, &runtime_config, &args.notify);
+    // A plugin that can play one key twice keeps retriggered voices ringing.
+    let retrigger_mode = args.retrigger_mode.unwrap_or(match voice_info {
+        Some(info) if info.flags.contains(VoiceInfoFlags::SUPPORTS_OVERLAPPING_NOTES) => RetriggerMode::Stack,
+        _ => RetriggerMode::Choke,
+    });
 
-    let _osc_handle = start_osc_receiver(args.osc_port, command_producer, per_note_mod_params, args.verbose)?;
+    let (command_producer, command_consumer) = create_command_queue(args.queue_capacity as usize);
+    let sample_position = Arc::new(AtomicU64::new(0));
+    let silence = Arc::new(AtomicBool::new(false));
+    let panic = Arc::new(AtomicBool::new(false));
 
-    let cpal_config = cpal::StreamConfig {
-        channels: audio_config.channels,
-        sample_rate: cpal::SampleRate(audio_config.sample_rate),
-        buffer_size: cpal::BufferSize::Fixed(audio_config.buffer_size),
+    let (notifications, feedback_echo) = match args.led_feedback {
+        Some(target) => {
+            let map = match &args.led_map {
+                Some(path) => LedMap::load(path)?,
+                None => LedMap::default(),
+            };
+            let (producer, echo) = start_feedback(FeedbackConfig {
+                target,
+                rate_hz: args.led_rate,
+                map,
+                params: params.iter().map(|p| (p.id, p.clone())).collect(),
+            })?;
+            (Some(producer), Some(echo))
+        }
+        None => (None, None),
     };
 
-    let (_engine, main_receiver) = AudioEngine::new(
-        &device,
-        cpal_config,
-        audio_config.sample_format,
-        audio_processor,
-        command_consumer,
-        audio_config.channels as usize,
-        audio_config.buffer_size as usize * 2,
-        args.verbose,
+    let param_output = match (args.osc_send_port, args.feedback_port) {
+        (None, None) => None,
+        (changed, value) => Some(start_param_sender(ParamSenderTargets {
+            changed: changed.map(|port| SocketAddr::new(args.osc_send_host, port)),
+            value: value.map(|port| SocketAddr::new(args.osc_send_host, port)),
+        })?),
+    };
+
+    let plugin_input_channels = audio_ports
+        .main_input()
+        .map_or(0, |port| audio_ports.inputs[port].channel_count as usize);
+
+    let (_input_capture, input) = match &args.input_device {
+        Some(selector) => {
+            let input_device = select_input_device(&cpal_host, Some(selector))?;
+            let (capture, source) = start_input(
+                &input_device,
+                audio_config.sample_rate,
+                audio_config.max_callback_frames,
+                plugin_input_channels,
+            )?;
+            (Some(capture), Some(source))
+        }
+        None => (None, None),
+    };
+
+    if args.test_tone_target == TestToneTarget::Plugin && plugin_input_channels == 0 {
+        log::warn!("--test-tone-target plugin has no effect: the plugin has no audio inputs");
+    }
+    if let Some(frequency) = args.test_tone {
+        check_tone(frequency, args.test_tone_gain, audio_config.sample_rate)
+            .map_err(|e| anyhow::anyhow!("--test-tone: {}", e))?;
+    }
+    let tone_control = ToneControl::new(
+        args.test_tone.unwrap_or(440.0),
+        args.test_tone_gain,
+        args.test_tone.is_some(),
+    );
+    let test_tone = TestTone::new(
+        tone_control.clone(),
+        args.test_signal,
+        args.test_tone_target,
+        audio_config.sample_rate,
+        args.test_tone_duration,
+    );
+    let mix_control = MixControl::new(audio_config.channels as usize);
+    let master = MasterBus::new(db_to_gain(args.master_gain), args.limiter, audio_config.sample_rate);
+    let mixer = Mixer::new(mix_control.clone(), master);
+
+    let aliases = match &args.alias_file {
+        Some(path) => AliasTable::load(path)?,
+        None => AliasTable::default(),
+    };
+    if !aliases.is_empty() {
+        log::info!("Loaded {} OSC aliases", aliases.len());
+    }
+    for collision in aliases.collisions() {
+        log::warn!(
+            "Alias {} shadows built-in {}; it is available as {} instead. Update controller templates.",
+            collision.original, collision.builtin, collision.rewritten
+        );
+    }
+
+    let osc_map = match &args.osc_map {
+        Some(path) => OscMap::load(path)?,
+        None => OscMap::default(),
+    };
+    if !osc_map.is_empty() {
+        log::info!("Loaded {} custom OSC addresses", osc_map.len());
+    }
+    for address in osc_map.overrides() {
+        log::info!("Custom OSC address {} replaces the built-in one", address);
+    }
+
+    let scale = match (&args.tuning, args.edo) {
+        (Some(path), _) => Some(Scale::load(path)?),
+        (None, Some(steps)) => Some(Scale::edo(steps)),
+        (None, None) => None,
+    };
+    let note_expressions = supports_note_expressions(&mut instance);
+    if let Some(scale) = &scale {
+        if note_expressions {
+            log::info!("Tuning: {} ({} notes per period)", scale.description, scale.len());
+        } else {
+            log::warn!("The plugin doesn't support note expressions, so it can't be retuned; notes play in 12-TET");
+        }
+    }
+
+    let alias_reloads = match (&args.alias_file, args.watch_config) {
+        (Some(path), true) => Some(watch_aliases(
+            path.clone(),
+            params.iter().map(|p| (p.id, p.clone())).collect(),
+            args.notify.clone(),
+        )?),
+        _ => None,
+    };
+
+    let (param_rescans_sender, param_rescans) = unbounded();
+    let param_flush = ParamFlush::new(1 + chain.len());
+
+    let mut osc_state = OscReceiverState {
+        // Coalescing goes by the wall clock, which a deterministic render can't.
+        queue: SheddingQueue::new(command_producer.clone(), !args.no_param_coalescing && !args.deterministic),
+        // From `params` rather than the set above: --preset may have changed them.
+        per_note_mod_params: params.iter().filter(|p| p.is_modulatable_per_note_id).map(|p| p.id).collect(),
+        params: params.iter().map(|p| (p.id, p.clone())).collect::<HashMap<_, _>>(),
+        param_names: ParamNames::new(&params),
+        chain: chain.iter().map(|p| ChainParams::new(&p.params)).collect(),
+        aliases,
+        osc_map,
+        main_thread_sender: main_sender.clone(),
+        default_release_velocity: args.default_release_velocity,
+        bend_range: args.bend_range,
+        tuner: Tuner::new(scale.clone(), note_expressions),
+        normalized_params: args.normalized_params,
+        ramp_default_ms: args.ramp_default_ms,
+        runtime_config: runtime_config.clone(),
+        sample_position: sample_position.clone(),
+        cues: HashMap::new(),
+        event_stats: event_stats.shared(),
+        feedback_echo,
+        alias_reloads,
+        param_rescans: Some(param_rescans),
+        test_tone: tone_control,
+        mix: mix_control,
+        voices: VoiceMap::new(retrigger_mode),
+        param_flush: param_flush.clone(),
+        panic: panic.clone(),
+        session: session.clone(),
+        verbose: args.verbose,
+    };
+
+    if let Some(path) = &args.init_script {
+        let applied = run_script(path, &mut osc_state)?;
+        log::info!("Applied {} messages from {}", applied, path.display());
+    }
+
+    if let (Some(script), Some(out)) = (&args.render, &args.out) {
+        let options = EngineOptions {
+            plugins,
+            command_consumer,
+            sample_position: sample_position.clone(),
+            event_stats: event_stats_producer,
+            notifications: None,
+            param_output: None,
+            recorder: None,
+            input: None,
+            test_tone,
+            mixer,
+            silence: Arc::new(AtomicBool::new(false)),
+            panic: panic.clone(),
+            session: session.clone(),
+            channel_count: audio_config.channels as usize,
+            output_channels: output_channels.clone(),
+            verbose: args.verbose,
+        };
+        let mut renderer = OfflineRenderer::new(options, audio_config.sample_rate, audio_config.buffer_size as usize);
+        let options = render::RenderOptions {
+            tail: args.render_tail,
+            duration: args.render_duration,
+            deterministic: args.deterministic,
+            sample_rate: audio_config.sample_rate,
+            channel_count: audio_config.channels as usize,
+        };
+        let rendered = render::render_to_wav(
+            script,
+            out,
+            options,
+            &mut osc_state,
+            &mut renderer,
+            || {
+                event_stats.collect();
+                run_main_thread_callbacks(&main_receiver, &mut instance, &mut chain);
+            },
+        );
+        deactivate_plugins(renderer.finish(), &main_receiver, &mut instance, &mut chain);
+        let rendered = rendered?;
+        log::info!(
+            "Rendered {} messages, {:.2}s of audio to {}",
+            rendered.messages,
+            rendered.frames as f64 / audio_config.sample_rate as f64,
+            out.display()
+        );
+        if let Some(sha256) = &rendered.sha256 {
+            println!("{}  {}", sha256, out.display());
+        }
+        session.record_file(out.clone());
+        event_stats.collect();
+        session_reporter.finish("render complete");
+        return Ok(());
+    }
+
+    let (_osc_handle, _) = start_osc_receiver(
+        args.osc_bind,
+        args.osc_port,
+        args.osc_tcp.then(|| (args.osc_tcp_port.unwrap_or(args.osc_port), args.osc_tcp_framing)),
+        args.osc_buffer_size as usize,
+        osc_state,
+    )?;
+
+    let _midi_input = MidiInput::start(
+        &args,
+        &params,
+        scale.filter(|_| note_expressions),
+        command_producer.clone(),
+        session.clone(),
     )?;
 
+    let (mut recorder, record_producer) = match &args.record {
+        Some(path) => {
+            let (recorder, producer) = Recorder::start(path, audio_config.sample_rate, audio_config.channels as usize)?;
+            (Some(recorder), Some(producer))
+        }
+        None => (None, None),
+    };
+
+    // Found again by name after a dropout, since indices shift when a USB
+    // interface comes back.
+    let device_name = device.as_ref().map(|device| device.name().unwrap_or_default());
+    let options = EngineOptions {
+        plugins,
+        command_consumer,
+        sample_position: sample_position.clone(),
+        event_stats: event_stats_producer,
+        notifications,
+        param_output,
+        recorder: record_producer,
+        input,
+        test_tone,
+        mixer,
+        silence: silence.clone(),
+        panic: panic.clone(),
+        session: session.clone(),
+        channel_count: audio_config.channels as usize,
+        output_channels,
+        verbose: args.verbose,
+    };
+    let mut engine = match device {
+        Some(device) => {
+            let cpal_config = cpal::StreamConfig {
+                channels: audio_config.channels,
+                sample_rate: cpal::SampleRate(audio_config.sample_rate),
+                buffer_size: if audio_config.fixed_buffer_size {
+                    cpal::BufferSize::Fixed(audio_config.buffer_size)
+                } else {
+                    cpal::BufferSize::Default
+                },
+            };
+
+            AudioEngine::new(
+                &device,
+                cpal_config,
+                audio_config.sample_format,
+                main_sender.clone(),
+                audio_config.max_callback_frames as usize,
+                options,
+            )?
+        }
+        None => {
+            let sink: Box<dyn AudioSink> = match &args.output {
+                Some(url) => sink_from_url(url, audio_config.sample_rate, audio_config.channels as usize)?,
+                None => Box::new(NullSink),
+            };
+
+            AudioEngine::new_clocked(sink, audio_config.sample_rate, audio_config.buffer_size as usize, options)?
+        }
+    };
+
     log::info!(
-        "OSC CLAP Host running. Listening for OSC on 127.0.0.1:{}",
-        args.osc_port
+        "OSC CLAP Host running. Listening for OSC on {}",
+        SocketAddr::new(args.osc_bind, args.osc_port)
     );
     log::info!("Press Ctrl+C to stop.");
 
-    // Set up Ctrl+C handler
-    let (shutdown_tx, shutdown_rx) = std::sync::mpsc::channel();
+    if args.self_test {
+        let sweep_param = params.iter().find(|p| p.is_automatable).cloned();
+        start_self_test(command_producer.clone(), sweep_param, session.clone(), main_sender.clone())?;
+    }
+
+    let mut editor = Editor::open(&args, &mut instance, &descriptor.name)?;
+
+    // Set up Ctrl+C handler; a second Ctrl+C exits even if shutdown hangs
+    let shutdown_sender = main_sender.clone();
+    let interrupted = AtomicBool::new(false);
     ctrlc::set_handler(move || {
-        let _ = shutdown_tx.send(());
+        if interrupted.swap(true, Ordering::SeqCst) {
+            eprintln!("Forcing exit");
+            std::process::exit(FORCED_EXIT_CODE);
+        }
+        let _ = shutdown_sender.send(MainThreadMessage::Shutdown);
     }).expect("Error setting Ctrl+C handler");
 
     // Main loop: handle main thread callbacks or wait for shutdown
-    loop {
+    let mut audio_watch = AudioWatch::new(sample_position.load(Ordering::Relaxed));
+    // `(device_lost, message)` of a stream error not yet acted on.
+    let mut stream_error: Option<(bool, String)> = None;
+    let mut exit_code = 0;
+    let mut late_callbacks = 0;
+    let mut queue_window = Instant::now();
+    let mut dropped_before = 0;
+    let mut stats_due = args.stats_interval.map(|secs| (Duration::from_secs_f64(secs.max(0.1)), Instant::now()));
+    let mut stats_before = session.queue_stats();
+    let exit_reason = loop {
+        event_stats.collect();
+        let audio_stalled = audio_watch.check(sample_position.load(Ordering::Relaxed));
+
+        // The audio thread only counts late callbacks; they are logged here.
+        let late = session.late_callbacks();
+        if late > late_callbacks {
+            log::warn!(
+                "Audio underrun: {} late device callback(s), {} in total; a larger --buffer-size may help",
+                late - late_callbacks,
+                late
+            );
+            late_callbacks = late;
+        }
+
+        if queue_window.elapsed() >= Duration::from_secs(1) {
+            queue_window = Instant::now();
+            session.roll_queue_window();
+            let dropped = session.queue_stats().dropped;
+            if dropped > dropped_before {
+                log::warn!(
+                    "Command queue full: dropped {} command(s) in the last second, {} in total",
+                    dropped - dropped_before,
+                    dropped
+                );
+                dropped_before = dropped;
+            }
+        }
+        if let Some((interval, last)) = &mut stats_due {
+            if last.elapsed() >= *interval {
+                *last = Instant::now();
+                let stats = session.queue_stats();
+                log::info!(
+                    "Commands: {} received, {} queued, {} dropped; {} events delivered; queue peak {:.0}%",
+                    stats.received - stats_before.received,
+                    stats.queued - stats_before.queued,
+                    stats.dropped - stats_before.dropped,
+                    stats.delivered - stats_before.delivered,
+                    stats.peak_occupancy * 100.0
+                );
+                stats_before = stats;
+            }
+        }
+
+        // A lost device is reopened at once; other stream errors only once
+        // audio has actually stopped, since many backends recover from them.
+        if stream_error.as_ref().is_some_and(|(device_lost, _)| *device_lost || audio_stalled) {
+            let (_, message) = stream_error.take().expect("checked above");
+            if !args.device_reconnect {
+                log::error!("Audio stream failed ({}); exiting because --device-reconnect is off", message);
+                exit_code = DEVICE_LOST_EXIT_CODE;
+                break "audio device lost";
+            }
+            log::warn!("Audio stream failed ({}); reopening it. OSC commands are queued meanwhile", message);
+            match reopen_audio(&mut engine, &cpal_host, device_name.as_deref().unwrap_or_default()) {
+                Ok(name) => {
+                    log::info!("Audio resumed on {}", name);
+                    runtime_config.write().unwrap_or_else(|e| e.into_inner()).output = name;
+                }
+                Err(e) => {
+                    log::error!("Giving up on audio output: {:#}", e);
+                    exit_code = DEVICE_LOST_EXIT_CODE;
+                    break "audio device lost";
+                }
+            }
+        }
+
+        // The editor window needs its events pumped more often than that.
+        let poll_interval = editor.pump(&mut instance, std::time::Duration::from_millis(100));
+
         // Check for main thread messages (non-blocking with timeout)
-        match main_receiver.recv_timeout(std::time::Duration::from_millis(100)) {
+        match main_receiver.recv_timeout(poll_interval) {
             Ok(message) => match message {
                 MainThreadMessage::RunOnMainThread => {
+                    // The request doesn't say which plugin made it; an extra
+                    // callback is harmless.
                     instance.call_on_main_thread_callback();
+                    for chained in &mut chain {
+                        chained.instance.call_on_main_thread_callback();
+                    }
                 }
-                MainThreadMessage::DumpPatchState => {
-                    match dump_patch_state(&mut instance, &params) {
-                        Ok(filename) => log::info!("Patch state saved to: {}", filename),
-                        Err(e) => log::error!("Failed to dump patch state: {}", e),
+                MainThreadMessage::SaveState { path, reply_to } => {
+                    match dump_state(&mut instance, &descriptor.id, path, reply_to) {
+                        Ok(Some(path)) => session.record_file(path),
+                        Ok(None) => {}
+                        Err(e) => log::error!("Failed to save patch state: {:#}", e),
                     }
                 }
-            },
-            Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
-                // Check for shutdown signal
-                if shutdown_rx.try_recv().is_ok() {
+                MainThreadMessage::LoadState { path } => match restore_state(&mut instance, &descriptor.id, &path) {
+                    Ok(()) => rescan_params(&engine, &mut instance, &mut params, &mut chain, 0, &param_rescans_sender),
+                    Err(e) => log::error!("Failed to load patch state: {:#}", e),
+                },
+                MainThreadMessage::Panic { thread, message } => {
+                    log::error!("Shutting down after panic in thread '{}': {}", thread, message);
+                    shut_down_after_panic(
+                        &command_producer,
+                        &silence,
+                        &mut instance,
+                        &descriptor.id,
+                        &audio_config,
+                        chain.len() + 1,
+                        &session,
+                    );
+                    if let Some(recorder) = recorder.take() {
+                        finish_recording(recorder, &session);
+                    }
+                    event_stats.collect();
+                    session_reporter.finish(&format!("panic in thread '{}'", thread));
+                    std::process::exit(PANIC_EXIT_CODE);
+                }
+                MainThreadMessage::SelfTestDone { passed } => {
+                    if !passed {
+                        exit_code = 1;
+                    }
+                    break "self-test finished";
+                }
+                MainThreadMessage::Shutdown => {
                     log::info!("Shutting down...");
-                    break;
+                    break "interrupted";
                 }
-            }
+                // Errors from a stream already replaced are stale.
+                MainThreadMessage::StreamError {
+                    generation,
+                    device_lost,
+                    message,
+                } => {
+                    if engine.is_current_stream(generation) && (device_lost || stream_error.is_none()) {
+                        stream_error = Some((device_lost, message));
+                    }
+                }
+                MainThreadMessage::RestartPlugin { plugin } => {
+                    if let Err(e) = restart_plugin(&engine, plugin, &mut instance, &mut chain, plugin_audio_config) {
+                        log::error!("Plugin {} could not be restarted and is now silent: {:#}", plugin, e);
+                        param_flush.set_stopped(plugin, true);
+                    }
+                    // A restart is usually how a plugin's latency changes.
+                    update_latency(&mut instance, &mut chain, &runtime_config, &args.notify);
+                    if plugin == 0 {
+                        update_voices(&mut instance, &runtime_config, &args.notify);
+                    }
+                }
+                MainThreadMessage::LatencyChanged => {
+                    update_latency(&mut instance, &mut chain, &runtime_config, &args.notify);
+                }
+                MainThreadMessage::VoiceInfoChanged => {
+                    update_voices(&mut instance, &runtime_config, &args.notify);
+                }
+                MainThreadMessage::LoadPreset {
+                    location_uri,
+                    load_key,
+                    reply_to,
+                } => {
+                    let result = PresetLocation::parse(&location_uri)
+                        .and_then(|location| load_preset(&mut instance, &location, load_key.as_deref()));
+                    let replies: Vec<SocketAddr> = reply_to.into_iter().collect();
+                    match result {
+                        Ok(()) => {
+                            log::info!("Loaded preset {} {}", location_uri, load_key.as_deref().unwrap_or(""));
+                            rescan_params(&engine, &mut instance, &mut params, &mut chain, 0, &param_rescans_sender);
+                            notify(
+                                &replies,
+                                "/preset/loaded",
+                                vec![
+                                    OscType::String(location_uri),
+                                    OscType::String(load_key.unwrap_or_default()),
+                                ],
+                            );
+                        }
+                        Err(e) => {
+                            log::error!("Failed to load preset: {:#}", e);
+                            notify(&replies, "/host/error", vec![OscType::String(format!("/preset/load: {:#}", e))]);
+                        }
+                    }
+                }
+                // Also how presets picked in the plugin's own editor reach the parameter table.
+                MainThreadMessage::PresetLoaded { plugin } => {
+                    rescan_params(&engine, &mut instance, &mut params, &mut chain, plugin, &param_rescans_sender);
+                }
+                // Even a full rescan from a plugin that is still processing,
+                // which CLAP forbids, only calls its params extension here;
+                // nothing waits on the audio thread.
+                MainThreadMessage::ParamsChanged { plugin, all } => {
+                    if args.verbose {
+                        let what = if all { "all its params" } else { "its param info" };
+                        log::info!("[MAIN] Plugin {} rescanned {}; re-reading them", plugin, what);
+                    }
+                    rescan_params(&engine, &mut instance, &mut params, &mut chain, plugin, &param_rescans_sender);
+                }
+                MainThreadMessage::ParamCleared { plugin, param_id } => {
+                    if args.verbose {
+                        log::info!("[MAIN] Plugin {} cleared param {}", plugin, param_id);
+                    }
+                    if !push_command_to(&command_producer, Some(plugin), Command::ForgetParam { param_id }) {
+                        log::warn!("Command queue full; a ramp on cleared param {} may keep running", param_id);
+                    }
+                }
+                MainThreadMessage::LoadPlugin {
+                    path,
+                    plugin_id,
+                    reply_to,
+                } => {
+                    let replies: Vec<SocketAddr> = args.notify.iter().copied().chain(reply_to).collect();
+                    let request = LoadRequest {
+                        path: &path,
+                        plugin_id: plugin_id.as_deref(),
+                        main_sender: &main_sender,
+                        host_info: &host_info,
+                        config: plugin_audio_config,
+                    };
+                    let result = load_plugin(
+                        &engine,
+                        request,
+                        &mut instance,
+                        // The editor belongs to the plugin being replaced.
+                        |instance| editor.close(instance),
+                    );
+                    let mut loaded = match result {
+                        Ok(loaded) => loaded,
+                        Err(e) => {
+                            log::error!("Keeping the current plugin: {:#}", e);
+                            notify(&replies, "/host/error", vec![OscType::String(format!("/plugin/load: {:#}", e))]);
+                            continue;
+                        }
+                    };
+                    loaded.swap_with_main(&mut instance, &mut bundle, &mut descriptor, &mut params);
+                    // `loaded` now holds the old plugin, already deactivated.
+                    drop(loaded);
+
+                    {
+                        let mut config = runtime_config.write().unwrap_or_else(|e| e.into_inner());
+                        config.plugin_path = path.display().to_string();
+                        config.plugin_id = descriptor.id.clone();
+                        config.plugin_name = descriptor.name.clone();
+                    }
+                    let _ = param_rescans_sender.send(ParamRescan {
+                        plugin: 0,
+                        params: ChainParams::new(&params),
+                        replaced: true,
+                    });
+                    update_latency(&mut instance, &mut chain, &runtime_config, &args.notify);
+                    update_voices(&mut instance, &runtime_config, &args.notify);
+                    log::info!("Loaded plugin: {} ({}, {} params)", descriptor.name, descriptor.id, params.len());
+                    notify(
+                        &replies,
+                        "/plugin/loaded",
+                        vec![
+                            OscType::String(descriptor.name.clone()),
+                            OscType::String(descriptor.id.clone()),
+                            OscType::Int(params.len() as i32),
+                        ],
+                    );
+                    if args.verbose {
+                        let mut tables = vec![(descriptor.name.as_str(), params.as_slice())];
+                        tables.extend(chain.iter().map(|p| (p.descriptor.name.as_str(), p.params.as_slice())));
+                        print_osc_api(&tables);
+                    }
+                }
+                // Only the audio thread may flush an active plugin, and it does
+                // so on every block it processes. A plugin left inactive by a
+                // failed restart has no blocks, so it is flushed here, with the
+                // parameter values sent to it since.
+                MainThreadMessage::FlushRequested { plugin } => {
+                    let target = match plugin {
+                        0 => &mut instance,
+                        index => &mut chain[index - 1].instance,
+                    };
+                    if !target.is_active() {
+                        match flush_inactive_plugin(target, plugin, &param_flush) {
+                            Ok(()) if args.verbose => log::info!("[MAIN] Flushed inactive plugin {}", plugin),
+                            Ok(()) => {}
+                            Err(e) => log::warn!("Plugin {} asked for a parameter flush that failed: {:#}", plugin, e),
+                        }
+                    } else if audio_stalled {
+                        log::warn!("Plugin {} asked for a parameter flush; it will happen when audio resumes", plugin);
+                    } else if args.verbose {
+                        log::info!("[MAIN] Plugin {} requested a parameter flush", plugin);
+                    }
+                }
+                MainThreadMessage::ParamText {
+                    plugin,
+                    param_id,
+                    reply_to,
+                    address,
+                } => {
+                    let target = match plugin {
+                        0 => &mut instance,
+                        index => &mut chain[index - 1].instance,
+                    };
+                    match live_value_text(target, param_id) {
+                        Some((value, text)) => {
+                            notify(
+                                &[reply_to],
+                                address,
+                                vec![OscType::Int(param_id as i32), OscType::Double(value), OscType::String(text)],
+                            );
+                        }
+                        None => log::warn!("{}: plugin {} has no value for parameter {}", address, plugin, param_id),
+                    }
+                }
+                MainThreadMessage::SetParamFromText { param_id, text } => {
+                    match text_to_value(&mut instance, param_id, &text) {
+                        Ok(value) => {
+                            if !push_command(&command_producer, Command::ParamSet { param_id, value }) {
+                                log::warn!("Command queue full, dropping text parameter change");
+                            }
+                        }
+                        Err(e) => log::warn!("{:#}", e),
+                    }
+                }
+                MainThreadMessage::Gui(request) => editor.handle(request, &mut instance),
+            },
+            Err(crossbeam_channel::RecvTimeoutError::Timeout) => {}
             Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
-                break;
+                break "main thread channel closed";
             }
         }
+    };
+
+    if features::METRICS && (args.event_stats || args.verbose) {
+        event_stats.collect();
+        event_stats.shared().lock().unwrap_or_else(|e| e.into_inner()).print();
+    }
+    editor.close(&mut instance);
+
+    silence_plugins(&command_producer, &silence, &audio_config, chain.len() + 1);
+    let processors = engine.shutdown(std::time::Duration::from_secs(1));
+    deactivate_plugins(processors, &main_receiver, &mut instance, &mut chain);
+    if let Some(recorder) = recorder {
+        finish_recording(recorder, &session);
+    }
+
+    event_stats.collect();
+    session_reporter.finish(exit_reason);
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+
+    Ok(())
+}
+
+/// Attempts to reopen the audio stream after a dropout.
+const RECONNECT_ATTEMPTS: u32 = 6;
+const RECONNECT_FIRST_DELAY: std::time::Duration = std::time::Duration::from_millis(250);
+const RECONNECT_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(4);
+/// From this attempt on, the system default device is tried too.
+const RECONNECT_DEFAULT_FROM: u32 = 3;
+
+/// Reopens the audio stream after the device failed, with the same config,
+/// waiting longer before each attempt. The device is looked up again by
+/// `device_name`; if it stays away, later attempts fall back to the system
+/// default. Returns the name of the device now playing.
+fn reopen_audio(engine: &mut AudioEngine, host: &cpal::Host, device_name: &str) -> Result<String> {
+    let mut delay = RECONNECT_FIRST_DELAY;
+    for attempt in 1..=RECONNECT_ATTEMPTS {
+        std::thread::sleep(delay);
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+
+        let mut selectors = vec![DeviceSelector::Name(device_name.to_string())];
+        if attempt >= RECONNECT_DEFAULT_FROM {
+            selectors.push(DeviceSelector::Default);
+        }
+        for selector in &selectors {
+            let device = match select_device(host, Some(selector)) {
+                Ok(device) => device,
+                Err(e) => {
+                    log::warn!("Reconnect attempt {}/{}: {:#}", attempt, RECONNECT_ATTEMPTS, e);
+                    continue;
+                }
+            };
+            let name = device.name().unwrap_or_default();
+            log::info!("Reconnect attempt {}/{}: opening {}", attempt, RECONNECT_ATTEMPTS, name);
+            match engine.reopen_stream(&device) {
+                Ok(()) => return Ok(name),
+                Err(e) => log::warn!("Reconnect attempt {}/{} on {} failed: {:#}", attempt, RECONNECT_ATTEMPTS, name, e),
+            }
+        }
+    }
+    anyhow::bail!("the audio stream could not be reopened after {} attempts", RECONNECT_ATTEMPTS)
+}
+
+/// How long the audio clock may stand still before the host says so.
+const AUDIO_STALL_WARNING: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Notices when audio stops advancing, e.g. because the device was
+/// suspended, so OSC changes piling up in the queue don't go unexplained.
+struct AudioWatch {
+    position: u64,
+    since: std::time::Instant,
+    stalled: bool,
+}
+
+impl AudioWatch {
+    fn new(position: u64) -> Self {
+        Self {
+            position,
+            since: std::time::Instant::now(),
+            stalled: false,
+        }
+    }
+
+    /// Takes the current sample position; true while audio is stalled.
+    fn check(&mut self, position: u64) -> bool {
+        if position != self.position {
+            if self.stalled {
+                log::info!("Audio resumed; queued changes are being applied");
+            }
+            *self = Self::new(position);
+            return false;
+        }
+        if !self.stalled && self.since.elapsed() >= AUDIO_STALL_WARNING {
+            log::warn!(
+                "Audio has not advanced for {:?}; parameter and note changes are queued until it resumes",
+                AUDIO_STALL_WARNING
+            );
+            self.stalled = true;
+        }
+        self.stalled
+    }
+}
+
+/// Runs the main-thread callbacks the plugins have asked for. Other pending
+/// messages are dropped.
+fn run_main_thread_callbacks(
+    main_receiver: &Receiver<MainThreadMessage>,
+    instance: &mut PluginInstance<OscClapHost>,
+    chain: &mut [ChainedPlugin],
+) {
+    while let Ok(message) = main_receiver.try_recv() {
+        match message {
+            MainThreadMessage::RunOnMainThread => {
+                instance.call_on_main_thread_callback();
+                for chained in chain.iter_mut() {
+                    chained.instance.call_on_main_thread_callback();
+                }
+            }
+            // Only the live engine can take a plugin out to restart it.
+            MainThreadMessage::RestartPlugin { plugin } => {
+                log::warn!("Plugin {} asked for a restart; ignored while rendering or shutting down", plugin);
+            }
+            MainThreadMessage::LoadPlugin { path, .. } => {
+                log::warn!("/plugin/load {} ignored while rendering or shutting down", path.display());
+            }
+            MainThreadMessage::LoadPreset { location_uri, .. } => {
+                log::warn!("/preset/load {} ignored while rendering or shutting down", location_uri);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Re-reads every plugin's latency and publishes the chain's total, which
+/// `/host/latency` reports and timetag scheduling compensates for. A change
+/// is logged and sent to the `--notify` addresses.
+fn update_latency(
+    instance: &mut PluginInstance<OscClapHost>,
+    chain: &mut [ChainedPlugin],
+    runtime_config: &SharedRuntimeConfig,
+    notify_targets: &[SocketAddr],
+) {
+    let latencies: Vec<u32> = std::iter::once(instance)
+        .chain(chain.iter_mut().map(|p| &mut p.instance))
+        .map(query_latency)
+        .collect();
+    let total: u32 = latencies.iter().sum();
+
+    let args = {
+        let mut config = runtime_config.write().unwrap_or_else(|e| e.into_inner());
+        if config.latency == total {
+            return;
+        }
+        config.latency = total;
+        config.latency_args()
+    };
+    if latencies.len() > 1 {
+        log::info!("Plugin latency: {} samples in total ({:?} per plugin)", total, latencies);
+    } else {
+        log::info!("Plugin latency: {} samples", total);
+    }
+    notify(notify_targets, "/host/latency", args);
+}
+
+/// Re-reads the parameters of `plugin` (chain index) after a state or
+/// preset load or a rescan it asked for, which can add or remove parameters
+/// or change their values, ranges, names or per-note modulation support,
+/// and passes them to the OSC thread and the engine.
+fn rescan_params(
+    engine: &AudioEngine,
+    instance: &mut PluginInstance<OscClapHost>,
+    params: &mut Vec<ParamInfo>,
+    chain: &mut [ChainedPlugin],
+    plugin: usize,
+    rescans: &Sender<ParamRescan>,
+) {
+    let (instance, params) = match plugin {
+        0 => (instance, params),
+        index => {
+            let chained = &mut chain[index - 1];
+            (&mut chained.instance, &mut chained.params)
+        }
+    };
+    let count = params.len();
+    *params = enumerate_params(instance);
+    if params.len() != count {
+        log::info!("Plugin {} now has {} params (was {})", plugin, params.len(), count);
+    }
+    let values = params
+        .iter()
+        .filter_map(|p| param_value(instance, p.id).map(|value| (p.id, value)))
+        .collect();
+    engine.update_params(plugin, values, params.iter().map(|p| (p.id, p.min_value, p.max_value)).collect());
+    let _ = rescans.send(ParamRescan {
+        plugin,
+        params: ChainParams::new(params),
+        replaced: false,
+    });
+}
+
+/// Re-reads the main plugin's voice count and capacity, which `/voices`
+/// reports. A change is logged and sent to the `--notify` addresses.
+fn update_voices(
+    instance: &mut PluginInstance<OscClapHost>,
+    runtime_config: &SharedRuntimeConfig,
+    notify_targets: &[SocketAddr],
+) -> Option<VoiceInfo> {
+    let info = query_voice_info(instance);
+    let voices = info.as_ref().map(|info| (info.voice_count, info.voice_capacity));
+
+    let args = {
+        let mut config = runtime_config.write().unwrap_or_else(|e| e.into_inner());
+        if config.voices == voices {
+            return info;
+        }
+        config.voices = voices;
+        config.voices_args()
+    };
+    match voices {
+        Some((count, capacity)) => log::info!("Plugin voices: {} in use, capacity {}", count, capacity),
+        None => log::info!("Plugin no longer reports its voices"),
     }
+    notify(notify_targets, "/voices", args);
+    info
+}
 
+/// Flushes inactive plugin `plugin` (chain index) with the parameter values
+/// the OSC thread handed over for it, if any.
+fn flush_inactive_plugin(
+    target: &mut PluginInstance<OscClapHost>,
+    plugin: usize,
+    param_flush: &ParamFlush,
+) -> Result<()> {
+    flush_params(target, &param_flush.take(plugin))
+}
+
+/// Deactivates and re-activates plugin `index` of the chain (0 is the main
+/// plugin) after it asked for a restart. The rest of the chain keeps playing
+/// meanwhile. A plugin that fails to come back stays out of the chain.
+fn restart_plugin(
+    engine: &AudioEngine,
+    index: usize,
+    instance: &mut PluginInstance<OscClapHost>,
+    chain: &mut [ChainedPlugin],
+    config: PluginAudioConfiguration,
+) -> Result<()> {
+    // If the audio thread doesn't let go, the plugin keeps running as it was.
+    let Some(processor) = engine.take_plugin(index, std::time::Duration::from_secs(1)) else {
+        log::warn!("Plugin {} asked for a restart; skipped because it could not be taken out of the chain", index);
+        return Ok(());
+    };
+    let target = match index {
+        0 => instance,
+        index => &mut chain[index - 1].instance,
+    };
+    target.deactivate(processor.stop_processing());
+    let processor = target
+        .activate(|_, _| (), config)
+        .context("Failed to activate")?
+        .start_processing()
+        .map_err(|e| anyhow::anyhow!("Failed to start processing: {:?}", e))?;
+    engine.return_plugin(index, processor);
+    log::info!("Restarted plugin {}", index);
     Ok(())
 }
+
+/// The plugin `/plugin/load` asked for, and what it is opened and activated with.
+struct LoadRequest<'a> {
+    path: &'a Path,
+    plugin_id: Option<&'a str>,
+    main_sender: &'a Sender<MainThreadMessage>,
+    host_info: &'a HostInfo,
+    /// The running plugin's audio configuration, which the new one takes over.
+    config: PluginAudioConfiguration,
+}
+
+/// Loads the plugin `/plugin/load` asked for and puts it in the chain in
+/// place of the main plugin, which keeps playing until the new one is active
+/// and stays if anything fails. `before_swap` runs on the old plugin just
+/// before it is stopped. Returns the new plugin; `swap_with_main` then
+/// trades it for the old one, now inactive, on the main thread's side.
+fn load_plugin(
+    engine: &AudioEngine,
+    request: LoadRequest,
+    instance: &mut PluginInstance<OscClapHost>,
+    before_swap: impl FnOnce(&mut PluginInstance<OscClapHost>),
+) -> Result<ChainedPlugin> {
+    let LoadRequest {
+        path,
+        plugin_id,
+        main_sender,
+        host_info,
+        config,
+    } = request;
+    let mut loaded = ChainedPlugin::open(path, plugin_id, 0, main_sender, host_info)
+        .with_context(|| format!("Failed to load {}", path.display()))?;
+    let processor = loaded
+        .instance
+        .activate(|_, _| (), config)
+        .context("Failed to activate")?
+        .start_processing()
+        .map_err(|e| anyhow::anyhow!("Failed to start processing: {:?}", e))?;
+    let plugin = ChainPlugin {
+        processor,
+        ports: loaded.audio_ports.clone(),
+        is_instrument: loaded.descriptor.has_feature("instrument"),
+        param_values: loaded
+            .params
+            .iter()
+            .filter_map(|p| param_value(&mut loaded.instance, p.id).map(|value| (p.id, value)))
+            .collect(),
+        param_ranges: loaded.params.iter().map(|p| (p.id, p.min_value, p.max_value)).collect(),
+    };
+
+    // The stage is silent from here until the new plugin is in.
+    let Some(old) = engine.take_plugin(0, std::time::Duration::from_secs(1)) else {
+        loaded.instance.deactivate(plugin.processor.stop_processing());
+        anyhow::bail!("the current plugin could not be taken out of the chain");
+    };
+    before_swap(instance);
+    instance.deactivate(old.stop_processing());
+    engine.replace_plugin(0, plugin);
+    Ok(loaded)
+}
+
+/// Stops and deactivates the plugins the engine handed back, in chain order.
+fn deactivate_plugins(
+    processors: Vec<Option<StartedPluginAudioProcessor<OscClapHost>>>,
+    main_receiver: &Receiver<MainThreadMessage>,
+    instance: &mut PluginInstance<OscClapHost>,
+    chain: &mut [ChainedPlugin],
+) {
+    // Callbacks the plugins asked for must run before they are deactivated.
+    run_main_thread_callbacks(main_receiver, instance, chain);
+    if processors.is_empty() {
+        log::warn!("Exiting without deactivating the plugins");
+    }
+    let instances = std::iter::once(instance).chain(chain.iter_mut().map(|p| &mut p.instance));
+    // A plugin whose restart failed is already inactive.
+    for (instance, processor) in instances.zip(processors) {
+        if let Some(processor) = processor {
+            instance.deactivate(processor.stop_processing());
+        }
+    }
+    log::info!("Plugins deactivated");
+}
+
+/// Finalizes the `--record` file once the audio has stopped.
+fn finish_recording(recorder: Recorder, session: &SessionCounters) {
+    match recorder.finish() {
+        Ok(path) => session.record_file(path),
+        Err(e) => log::error!("{:#}", e),
+    }
+}
+
+/// Best-effort cleanup after another thread panicked: silence the plugins and
+/// save the patch state before exiting.
+fn shut_down_after_panic(
+    command_producer: &CommandProducer,
+    silence: &AtomicBool,
+    instance: &mut PluginInstance<OscClapHost>,
+    plugin_id: &str,
+    audio_config: &AudioConfig,
+    plugin_count: usize,
+    session: &SessionCounters,
+) {
+    silence_plugins(command_producer, silence, audio_config, plugin_count);
+
+    match dump_state(instance, plugin_id, None, None) {
+        Ok(Some(path)) => session.record_file(path),
+        Ok(None) => {}
+        Err(e) => log::error!("Failed to save patch state: {:#}", e),
+    }
+}
+
+/// Chokes every note in each of the `plugin_count` plugins and lets the audio
+/// thread fade out.
+fn silence_plugins(command_producer: &CommandProducer, silence: &AtomicBool, audio_config: &AudioConfig, plugin_count: usize) {
+    let block = std::time::Duration::from_secs_f64(audio_config.buffer_size as f64 / audio_config.sample_rate as f64);
+
+    let choke_all = Command::NoteChoke {
+        note_id: -1,
+        key: -1,
+        channel: -1,
+        port: -1,
+    };
+    for plugin in 0..plugin_count {
+        if !push_command_to(command_producer, Some(plugin), choke_all.clone()) {
+            log::warn!("Command queue full, could not choke notes");
+        }
+    }
+    std::thread::sleep(block * 2);
+
+    silence.store(true, Ordering::Relaxed);
+    std::thread::sleep(block * 2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osc::Reply;
+    use rosc::OscMessage;
+    use std::path::Path;
+    use test_tone::TestSignal;
+    use testing::RecordingSocket;
+
+    #[test]
+    fn shutdown_deactivates_the_plugin() {
+        let (main_sender, main_receiver) = unbounded();
+        let mut instance = test_plugin::instance(main_sender);
+        let config = PluginAudioConfiguration {
+            sample_rate: 48000.0,
+            min_frames_count: 1,
+            max_frames_count: 512,
+        };
+        let processor = instance
+            .activate(|_, _| (), config)
+            .unwrap()
+            .start_processing()
+            .unwrap();
+        assert_eq!(test_plugin::activations(), 1);
+
+        deactivate_plugins(vec![Some(processor)], &main_receiver, &mut instance, &mut []);
+        assert_eq!(test_plugin::deactivations(), 1);
+    }
+
+    #[test]
+    fn params_set_while_a_plugin_is_stopped_are_flushed_on_the_main_thread() {
+        let (main_sender, main_receiver) = unbounded();
+        let mut instance = test_plugin::instance(main_sender.clone());
+        let params = enumerate_params(&mut instance);
+
+        let (command_producer, mut command_consumer) = create_command_queue(16);
+        let mut state = OscReceiverState {
+            params: params.iter().map(|p| (p.id, p.clone())).collect(),
+            ..OscReceiverState::detached(SheddingQueue::new(command_producer, false), main_sender)
+        };
+        // As after a failed restart: the plugin is inactive and out of the chain.
+        state.param_flush.set_stopped(0, true);
+        let set = OscMessage {
+            addr: "/param/set".to_string(),
+            args: vec![OscType::Int(test_plugin::GAIN as i32), OscType::Float(0.25)],
+        };
+        state.handle_message(&set, None).unwrap();
+        state.queue.flush();
+        assert!(command_consumer.pop().is_err(), "the set bypasses the audio thread's queue");
+
+        let Ok(MainThreadMessage::FlushRequested { plugin: 0 }) = main_receiver.try_recv() else {
+            panic!("expected a flush request for the main plugin");
+        };
+        flush_inactive_plugin(&mut instance, 0, &state.param_flush).unwrap();
+        assert_eq!(param_value(&mut instance, test_plugin::GAIN), Some(0.25));
+    }
+
+    #[test]
+    fn latency_is_read_from_the_plugin_and_answered_on_latency() {
+        let (main_sender, main_receiver) = unbounded();
+        let mut instance = test_plugin::instance(main_sender.clone());
+        let config = PluginAudioConfiguration {
+            sample_rate: 48000.0,
+            min_frames_count: 1,
+            max_frames_count: 512,
+        };
+        let processor = instance
+            .activate(|_, _| (), config)
+            .unwrap()
+            .start_processing()
+            .unwrap();
+        assert_eq!(query_latency(&mut instance), test_plugin::LATENCY);
+
+        let (command_producer, _command_consumer) = create_command_queue(16);
+        let mut state = OscReceiverState::detached(SheddingQueue::new(command_producer, false), main_sender);
+        state.runtime_config.write().unwrap().sample_rate = 48000;
+        update_latency(&mut instance, &mut [], &state.runtime_config, &[]);
+
+        let socket = RecordingSocket::default();
+        let reply = Reply {
+            socket: &socket,
+            addr: "127.0.0.1:9000".parse().unwrap(),
+        };
+        let query = OscMessage {
+            addr: "/latency".to_string(),
+            args: Vec::new(),
+        };
+        state.handle_message(&query, Some(&reply)).unwrap();
+        deactivate_plugins(vec![Some(processor)], &main_receiver, &mut instance, &mut []);
+
+        let replies = socket.messages();
+        assert_eq!(replies.len(), 1, "{:?}", replies);
+        assert_eq!(replies[0].addr, "/latency");
+        let ms = (test_plugin::LATENCY as f64 * 1000.0 / 48000.0) as f32;
+        assert_eq!(replies[0].args, vec![OscType::Int(test_plugin::LATENCY as i32), OscType::Float(ms)]);
+    }
+
+    /// Renders `script` through the test plugin with `--deterministic`. The
+    /// plugin is silent, so a white-noise test signal goes to the output to
+    /// give the hash something to cover.
+    fn render_with_test_plugin(script: &Path, out: &Path) -> render::RenderSummary {
+        let (main_sender, main_receiver) = unbounded();
+        let mut instance = test_plugin::instance(main_sender.clone());
+        let config = PluginAudioConfiguration {
+            sample_rate: 48000.0,
+            min_frames_count: 1,
+            max_frames_count: 256,
+        };
+        let processor = instance
+            .activate(|_, _| (), config)
+            .unwrap()
+            .start_processing()
+            .unwrap();
+        let plugins = vec![ChainPlugin {
+            processor,
+            ports: query_audio_ports(&mut instance),
+            is_instrument: true,
+            param_values: Vec::new(),
+            param_ranges: Vec::new(),
+        }];
+
+        let (command_producer, command_consumer) = create_command_queue(64);
+        let mut state = OscReceiverState::detached(SheddingQueue::new(command_producer, false), main_sender.clone());
+        let (event_stats, _event_stats) = create_event_stats();
+        let tone = ToneControl::new(440.0, 0.5, true);
+        let test_tone = TestTone::new(tone, TestSignal::White, TestToneTarget::Output, 48000, None);
+        let options = EngineOptions {
+            plugins,
+            command_consumer,
+            sample_position: state.sample_position.clone(),
+            event_stats,
+            notifications: None,
+            param_output: None,
+            recorder: None,
+            input: None,
+            test_tone,
+            mixer: Mixer::new(state.mix.clone(), MasterBus::new(1.0, false, 48000)),
+            silence: Arc::new(AtomicBool::new(false)),
+            panic: Arc::new(AtomicBool::new(false)),
+            session: SessionCounters::new(),
+            channel_count: 2,
+            output_channels: vec![0, 1],
+            verbose: false,
+        };
+        let mut renderer = OfflineRenderer::new(options, 48000, 256);
+        let options = render::RenderOptions {
+            tail: 0.1,
+            duration: None,
+            deterministic: true,
+            sample_rate: 48000,
+            channel_count: 2,
+        };
+        let rendered = render::render_to_wav(script, out, options, &mut state, &mut renderer, || {});
+        deactivate_plugins(renderer.finish(), &main_receiver, &mut instance, &mut []);
+        rendered.unwrap()
+    }
+
+    #[test]
+    fn deterministic_renders_hash_the_same() {
+        let path = |name: &str| std::env::temp_dir().join(format!("oscclaphost-{}-{}", std::process::id(), name));
+        let script = path("deterministic.txt");
+        std::fs::write(&script, "0 /note/on 1 60 0.8\n2400 /note/off 1 60 0.0\n").unwrap();
+
+        let first = render_with_test_plugin(&script, &path("deterministic-1.wav"));
+        let second = render_with_test_plugin(&script, &path("deterministic-2.wav"));
+        for name in ["deterministic.txt", "deterministic-1.wav", "deterministic-2.wav"] {
+            let _ = std::fs::remove_file(path(name));
+        }
+
+        assert!(first.sha256.is_some());
+        assert_eq!(first.sha256, second.sha256);
+    }
+}
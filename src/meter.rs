@@ -0,0 +1,63 @@
+//! Output level metering. The audio thread measures the RMS of every block
+//! it plays; other threads read the results without locking.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Blocks quieter than this (about -80 dBFS) count as silent.
+pub const SILENCE_RMS: f32 = 1e-4;
+
+pub struct OutputMeter {
+    /// RMS of the last block, as `f32` bits.
+    rms: AtomicU32,
+    /// Loudest block RMS since the last `take_peak`, as `f32` bits.
+    peak: AtomicU32,
+    /// Blocks louder than `SILENCE_RMS`.
+    loud_blocks: AtomicU64,
+}
+
+impl OutputMeter {
+    pub fn new() -> Self {
+        Self {
+            rms: AtomicU32::new(0),
+            peak: AtomicU32::new(0),
+            loud_blocks: AtomicU64::new(0),
+        }
+    }
+
+    /// Called by the audio thread with every channel of the block it is
+    /// about to play. Lock-free.
+    pub fn record(&self, samples: &[f32]) {
+        let rms = rms(samples);
+        self.rms.store(rms.to_bits(), Ordering::Relaxed);
+        // Non-negative floats order the same as their bit patterns.
+        self.peak.fetch_max(rms.to_bits(), Ordering::Relaxed);
+        if rms > SILENCE_RMS {
+            self.loud_blocks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn rms(&self) -> f32 {
+        f32::from_bits(self.rms.load(Ordering::Relaxed))
+    }
+
+    /// The loudest block since the previous call.
+    pub fn take_peak(&self) -> f32 {
+        f32::from_bits(self.peak.swap(0, Ordering::Relaxed))
+    }
+
+    pub fn loud_blocks(&self) -> u64 {
+        self.loud_blocks.load(Ordering::Relaxed)
+    }
+}
+
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f32 = samples.iter().map(|s| s * s).sum();
+    (sum / samples.len() as f32).sqrt()
+}
+
+pub fn to_dbfs(rms: f32) -> f32 {
+    20.0 * rms.max(1e-10).log10()
+}
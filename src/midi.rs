@@ -0,0 +1,235 @@
+//! MIDI input (`--midi-port`). Messages are translated into the same
+//! `Command`s OSC produces and pushed onto the shared command queue from
+//! midir's callback thread, so the audio thread can't tell the two apart.
+
+use crate::osc::{push_command, Command, CommandProducer};
+use crate::param_names::ParamNames;
+use crate::plugin::ParamInfo;
+use crate::session::SharedSessionCounters;
+use crate::tuning::Scale;
+use anyhow::{anyhow, Context, Result};
+use midir::{MidiInput, MidiInputConnection, MidiInputPort};
+use std::collections::HashMap;
+
+const CLIENT_NAME: &str = "clap-osc-host";
+
+const CC_SUSTAIN: u8 = 64;
+
+/// `NOTE_EXPRESSIONS` id used for channel pressure.
+const EXPRESSION_PRESSURE: i32 = 6;
+
+pub fn print_ports() -> Result<()> {
+    let midi_in = MidiInput::new(CLIENT_NAME).context("Failed to open MIDI input")?;
+    let ports = midi_in.ports();
+    if ports.is_empty() {
+        println!("No MIDI input ports found.");
+        return Ok(());
+    }
+    println!("MIDI input ports:");
+    for (index, port) in ports.iter().enumerate() {
+        println!("  [{}] {}", index, midi_in.port_name(port).unwrap_or_default());
+    }
+    Ok(())
+}
+
+/// How MIDI input is translated and where the commands go.
+pub struct MidiConfig<'a> {
+    /// `--cc-map` entries, resolved against `params`.
+    pub cc_map: &'a [String],
+    pub params: &'a [ParamInfo],
+    pub bend_range: f64,
+    pub scale: Option<Scale>,
+    pub producer: CommandProducer,
+    pub session: SharedSessionCounters,
+    pub verbose: bool,
+}
+
+/// Opens `port` (an index from `--list-midi-ports` or part of a port name)
+/// and starts translating. Input stops when the connection is dropped.
+pub fn start_midi_input(port: &str, config: MidiConfig) -> Result<MidiInputConnection<()>> {
+    let MidiConfig {
+        cc_map,
+        params,
+        bend_range,
+        scale,
+        producer,
+        session,
+        verbose,
+    } = config;
+    let mut translator = MidiTranslator::new(parse_cc_map(cc_map, params)?, bend_range, scale);
+    let midi_in = MidiInput::new(CLIENT_NAME).context("Failed to open MIDI input")?;
+    let (port, name) = select_port(&midi_in, port)?;
+
+    let connection = midi_in
+        .connect(
+            &port,
+            "clap-osc-host-input",
+            move |_timestamp, message, _| {
+                let Some(cmd) = translator.translate(message) else {
+                    return;
+                };
+                if verbose {
+                    log::info!("[MIDI-RECV] {:02x?} -> {:?}", message, cmd);
+                }
+                let queued = push_command(&producer, cmd);
+                session.record_command(queued);
+                if !queued && verbose {
+                    log::info!("[MIDI-RECV] Command queue full, dropped");
+                }
+            },
+            (),
+        )
+        .map_err(|e| anyhow!("Failed to connect to MIDI port '{}': {}", name, e))?;
+
+    log::info!("MIDI input from '{}'", name);
+    Ok(connection)
+}
+
+fn select_port(midi_in: &MidiInput, query: &str) -> Result<(MidiInputPort, String)> {
+    let ports = midi_in.ports();
+    let named: Vec<(MidiInputPort, String)> = ports
+        .into_iter()
+        .map(|port| {
+            let name = midi_in.port_name(&port).unwrap_or_default();
+            (port, name)
+        })
+        .collect();
+
+    if let Ok(index) = query.parse::<usize>() {
+        return named
+            .into_iter()
+            .nth(index)
+            .ok_or_else(|| anyhow!("MIDI port index {} out of range (see --list-midi-ports)", index));
+    }
+    let query_lower = query.to_lowercase();
+    named
+        .into_iter()
+        .find(|(_, name)| name.to_lowercase().contains(&query_lower))
+        .ok_or_else(|| anyhow!("No MIDI port matching '{}' (see --list-midi-ports)", query))
+}
+
+/// Parses `--cc-map` entries (`74:1234` or `74:Cutoff`) into the params each
+/// controller drives. Targets are param ids or names as `/param/setname` takes them.
+fn parse_cc_map(entries: &[String], params: &[ParamInfo]) -> Result<HashMap<u8, ParamInfo>> {
+    let names = ParamNames::new(params);
+    let mut map = HashMap::new();
+    for entry in entries {
+        let (cc, target) = entry
+            .split_once(':')
+            .ok_or_else(|| anyhow!("--cc-map entry '{}' must be cc:param", entry))?;
+        let cc: u8 = cc
+            .trim()
+            .parse()
+            .ok()
+            .filter(|cc| *cc < 128)
+            .ok_or_else(|| anyhow!("--cc-map entry '{}': controller must be 0-127", entry))?;
+        let param_id = match target.trim().parse::<u32>() {
+            Ok(id) => id,
+            Err(_) => names.resolve(target).map_err(|e| anyhow!("--cc-map entry '{}': {}", entry, e))?,
+        };
+        let param = params
+            .iter()
+            .find(|p| p.id == param_id)
+            .ok_or_else(|| anyhow!("--cc-map entry '{}': unknown parameter id {}", entry, param_id))?;
+        map.insert(cc, param.clone());
+    }
+    Ok(map)
+}
+
+/// Turns raw MIDI bytes into commands. Every note on gets a fresh note id so
+/// per-note modulation and expressions can address the voice.
+struct MidiTranslator {
+    next_note_id: i32,
+    /// Note id of the sounding note per (channel, key).
+    active: HashMap<(u8, u8), i32>,
+    cc_map: HashMap<u8, ParamInfo>,
+    /// Semitones a full pitch bend reaches either way, `--bend-range`.
+    bend_range: f64,
+    /// `--tuning` or `--edo`, when the plugin can be retuned.
+    scale: Option<Scale>,
+}
+
+impl MidiTranslator {
+    fn new(cc_map: HashMap<u8, ParamInfo>, bend_range: f64, scale: Option<Scale>) -> Self {
+        Self {
+            next_note_id: 0,
+            active: HashMap::new(),
+            cc_map,
+            bend_range,
+            scale,
+        }
+    }
+
+    fn translate(&mut self, message: &[u8]) -> Option<Command> {
+        let (&status, data) = message.split_first()?;
+        let channel = status & 0x0f;
+        let data1 = data.first().copied().unwrap_or(0);
+        let data2 = data.get(1).copied().unwrap_or(0);
+
+        match status & 0xf0 {
+            0x90 if data2 > 0 => {
+                let note_id = self.next_note_id;
+                self.next_note_id = self.next_note_id.wrapping_add(1) & i32::MAX;
+                self.active.insert((channel, data1), note_id);
+                Some(Command::NoteOn {
+                    note_id,
+                    key: data1 as i32,
+                    velocity: data2 as f32 / 127.0,
+                    channel: channel as i32,
+                    port: 0,
+                    tuning: self.scale.as_ref().map_or(0.0, |scale| scale.offset(data1 as i32)),
+                })
+            }
+            0x80 | 0x90 => Some(Command::NoteOff {
+                note_id: self.active.remove(&(channel, data1)).unwrap_or(-1),
+                key: data1 as i32,
+                velocity: if status & 0xf0 == 0x80 { data2 as f32 / 127.0 } else { 0.0 },
+                channel: channel as i32,
+                port: 0,
+            }),
+            // Polyphonic aftertouch
+            0xa0 => Some(Command::NoteExpression {
+                expression_id: EXPRESSION_PRESSURE,
+                note_id: self.active.get(&(channel, data1)).copied().unwrap_or(-1),
+                key: data1 as i32,
+                channel: channel as i32,
+                port: -1,
+                value: data2 as f64 / 127.0,
+            }),
+            0xb0 => {
+                // The sustain pedal, unless --cc-map gives CC 64 a parameter.
+                if data1 == CC_SUSTAIN && !self.cc_map.contains_key(&data1) {
+                    return Some(Command::Sustain {
+                        channel: channel as i32,
+                        down: data2 >= 64,
+                    });
+                }
+                let Some(param) = self.cc_map.get(&data1) else {
+                    log::debug!("Unmapped MIDI CC {} on channel {}", data1, channel);
+                    return None;
+                };
+                Some(Command::ParamSet {
+                    param_id: param.id,
+                    value: param.normalized_to_value(data2 as f64 / 127.0),
+                })
+            }
+            // Channel pressure applies to every voice on the channel.
+            0xd0 => Some(Command::NoteExpression {
+                expression_id: EXPRESSION_PRESSURE,
+                note_id: -1,
+                key: -1,
+                channel: channel as i32,
+                port: -1,
+                value: data1 as f64 / 127.0,
+            }),
+            0xe0 => {
+                let bend = (((data2 as i32) << 7) | data1 as i32) - 8192;
+                Some(Command::ChannelBend {
+                    channel: channel as i32,
+                    semitones: bend as f64 / 8192.0 * self.bend_range,
+                })
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Host-level output gain (`/mix/master`, `/mix/channel`) for quick mixing
+//! without touching the plugin. The OSC thread sets the gains through
+//! `MixControl`; the audio thread ramps each channel from the gain of the
+//! previous block to the new one, so abrupt changes don't zipper.
+//!
+//! On top of that sits the master bus (`/host/gain`, `/host/mute`,
+//! `--master-gain`, `--limiter`). Its changes travel through the command
+//! queue, so timed bundles schedule them, and are smoothed over
+//! `MASTER_RAMP_MS` sample by sample.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Gains are clamped to ±4 (about +12 dB). A negative gain inverts the phase.
+pub const MAX_GAIN: f32 = 4.0;
+
+/// `/host/gain` and `--master-gain` are clamped to this many dB.
+pub const MAX_GAIN_DB: f32 = 12.0;
+/// At or below this many dB the master gain is silence.
+pub const MIN_GAIN_DB: f32 = -96.0;
+/// Master gain and mute changes fade over this long.
+const MASTER_RAMP_MS: f32 = 10.0;
+/// The limiter passes samples below this level untouched and bends louder
+/// ones smoothly towards full scale.
+const LIMITER_KNEE: f32 = 0.8;
+
+/// Master bus changes, sent through the command queue.
+#[derive(Debug, Clone, Copy)]
+pub enum MasterChange {
+    /// Linear gain, from `db_to_gain`.
+    Gain(f32),
+    Mute(bool),
+}
+
+/// Clamps to `MAX_GAIN_DB` and turns anything at or below `MIN_GAIN_DB`
+/// into silence.
+pub fn db_to_gain(db: f32) -> f32 {
+    if db <= MIN_GAIN_DB {
+        0.0
+    } else {
+        10f32.powf(db.min(MAX_GAIN_DB) / 20.0)
+    }
+}
+
+/// Gains shared between the OSC thread and the mixer.
+pub struct MixControl {
+    master: AtomicU32,
+    /// One per device output channel.
+    channels: Vec<AtomicU32>,
+}
+
+pub type SharedMixControl = Arc<MixControl>;
+
+impl MixControl {
+    /// All gains start at 1.
+    pub fn new(channel_count: usize) -> SharedMixControl {
+        Arc::new(Self {
+            master: AtomicU32::new(1.0f32.to_bits()),
+            channels: (0..channel_count).map(|_| AtomicU32::new(1.0f32.to_bits())).collect(),
+        })
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Returns the gain after clamping.
+    pub fn set_master(&self, gain: f32) -> f32 {
+        let gain = gain.clamp(-MAX_GAIN, MAX_GAIN);
+        self.master.store(gain.to_bits(), Ordering::Relaxed);
+        gain
+    }
+
+    /// Returns the gain after clamping, or `None` if the device has no such
+    /// channel.
+    pub fn set_channel(&self, channel: usize, gain: f32) -> Option<f32> {
+        let slot = self.channels.get(channel)?;
+        let gain = gain.clamp(-MAX_GAIN, MAX_GAIN);
+        slot.store(gain.to_bits(), Ordering::Relaxed);
+        Some(gain)
+    }
+
+    /// What the channel is multiplied by: its own gain times the master.
+    fn gain(&self, channel: usize) -> f32 {
+        let master = f32::from_bits(self.master.load(Ordering::Relaxed));
+        let own = self.channels.get(channel).map_or(1.0, |g| f32::from_bits(g.load(Ordering::Relaxed)));
+        master * own
+    }
+}
+
+/// Master gain, mute and limiter, owned by the audio thread.
+pub struct MasterBus {
+    /// Linear gain, not counting the mute.
+    gain: f32,
+    muted: bool,
+    limiter: bool,
+    /// Where the fade is now, and how far it still moves per sample.
+    current: f32,
+    step: f32,
+    remaining: usize,
+    ramp_frames: usize,
+}
+
+impl MasterBus {
+    /// `gain` is linear; use `db_to_gain` for `--master-gain`.
+    pub fn new(gain: f32, limiter: bool, sample_rate: u32) -> Self {
+        Self {
+            gain,
+            muted: false,
+            limiter,
+            current: gain,
+            step: 0.0,
+            remaining: 0,
+            ramp_frames: ((sample_rate as f32 * MASTER_RAMP_MS / 1000.0) as usize).max(1),
+        }
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Starts a fade from wherever the current one is towards the new gain.
+    pub fn apply(&mut self, change: MasterChange) {
+        match change {
+            MasterChange::Gain(gain) => self.gain = gain,
+            MasterChange::Mute(muted) => self.muted = muted,
+        }
+        let target = if self.muted { 0.0 } else { self.gain };
+        self.remaining = self.ramp_frames;
+        self.step = (target - self.current) / self.ramp_frames as f32;
+    }
+
+    fn process(&mut self, channel_buffers: &mut [f32], frame_count: usize) {
+        let fading = self.remaining.min(frame_count);
+        let start = self.current;
+        self.remaining -= fading;
+        if fading > 0 {
+            // Land exactly on the target rather than on the sum of the steps.
+            self.current = match self.remaining {
+                0 if self.muted => 0.0,
+                0 => self.gain,
+                _ => start + self.step * fading as f32,
+            };
+        }
+        if fading > 0 || start != 1.0 {
+            for channel in channel_buffers.chunks_exact_mut(frame_count) {
+                for (i, sample) in channel.iter_mut().enumerate() {
+                    *sample *= if i < fading { start + self.step * (i + 1) as f32 } else { self.current };
+                }
+            }
+        }
+        if self.limiter {
+            channel_buffers.iter_mut().for_each(|sample| *sample = soft_clip(*sample));
+        }
+    }
+}
+
+/// Identity below `LIMITER_KNEE`, then a tanh curve that never reaches full
+/// scale, with no kink at the knee.
+fn soft_clip(sample: f32) -> f32 {
+    let level = sample.abs();
+    if level <= LIMITER_KNEE {
+        return sample;
+    }
+    let headroom = 1.0 - LIMITER_KNEE;
+    (LIMITER_KNEE + headroom * ((level - LIMITER_KNEE) / headroom).tanh()).copysign(sample)
+}
+
+/// Audio-thread side of the mixer.
+pub struct Mixer {
+    control: SharedMixControl,
+    /// Per channel, the gain the last block ended on.
+    current: Vec<f32>,
+    master: MasterBus,
+}
+
+impl Mixer {
+    pub fn new(control: SharedMixControl, master: MasterBus) -> Self {
+        let current = vec![1.0; control.channel_count()];
+        Self { control, current, master }
+    }
+
+    pub fn master_mut(&mut self) -> &mut MasterBus {
+        &mut self.master
+    }
+
+    /// Applies the gains to a planar block, ramping linearly across it from
+    /// the previous block's gain where one changed, then the master bus.
+    /// Allocation-free.
+    pub fn apply(&mut self, channel_buffers: &mut [f32], frame_count: usize) {
+        if frame_count == 0 {
+            return;
+        }
+        self.apply_channels(channel_buffers, frame_count);
+        self.master.process(channel_buffers, frame_count);
+    }
+
+    fn apply_channels(&mut self, channel_buffers: &mut [f32], frame_count: usize) {
+        for (ch, channel) in channel_buffers.chunks_exact_mut(frame_count).enumerate() {
+            let Some(current) = self.current.get_mut(ch) else {
+                break;
+            };
+            let target = self.control.gain(ch);
+            if target == *current {
+                if target != 1.0 {
+                    channel.iter_mut().for_each(|sample| *sample *= target);
+                }
+                continue;
+            }
+            let step = (target - *current) / frame_count as f32;
+            for (i, sample) in channel.iter_mut().enumerate() {
+                *sample *= *current + step * (i + 1) as f32;
+            }
+            *current = target;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn master_gain_halves_the_output_once_the_ramp_is_over() {
+        let sample_rate = 48000;
+        let ramp_frames = (sample_rate as f32 * MASTER_RAMP_MS / 1000.0) as usize;
+        let mut mixer = Mixer::new(MixControl::new(2), MasterBus::new(1.0, false, sample_rate));
+        mixer.master_mut().apply(MasterChange::Gain(0.5));
+
+        // Two planar channels, one block longer than the ramp, then another.
+        let frames = ramp_frames + 64;
+        let mut block = vec![0.8; 2 * frames];
+        mixer.apply(&mut block, frames);
+        for channel in block.chunks_exact(frames) {
+            assert!(channel[0] < 0.8 && channel[0] > 0.4, "{}", channel[0]);
+            assert!(channel[..ramp_frames].windows(2).all(|pair| pair[1] < pair[0]), "the fade only goes down");
+            assert!(channel[ramp_frames..].iter().all(|&sample| sample == 0.4));
+        }
+
+        let mut block = vec![0.8; 2 * frames];
+        mixer.apply(&mut block, frames);
+        assert!(block.iter().all(|&sample| sample == 0.4));
+    }
+}
@@ -1,11 +1,37 @@
+use crate::alias::{AliasTable, AliasValue};
+use crate::bend::EXPRESSION_TUNING;
+use crate::engine::MainThreadMessage;
+use crate::event_stats::{EventStats, SharedEventStats};
+use crate::osc_map::OscMap;
+use crate::crash::OSC_THREAD_NAME;
+use crate::feedback::EchoGuard;
+use crate::param_flush::{ParamFlush, SharedParamFlush};
+use crate::param_names::ParamNames;
+use crate::plugin::ParamInfo;
+use crate::queue::SheddingQueue;
+use crate::reload::load_aliases;
+use crate::runtime_config::{RuntimeConfig, SharedRuntimeConfig};
+use crate::session::{SessionCounters, SharedSessionCounters};
+use crate::mix::{db_to_gain, MasterChange, MixControl, SharedMixControl};
+use crate::test_tone::{check_tone, SharedToneControl, ToneControl};
+use crate::osc_tcp::{start_tcp_listener, TcpFraming, TcpPacket};
+use crate::tuning::{split_key, Tuner};
+use crate::transport::{TransportChange, MAX_TEMPO, MIN_TEMPO};
+use crate::voices::{RetriggerMode, VoiceMap};
 use anyhow::{Context, Result};
-use rosc::{OscMessage, OscPacket, OscType};
+use crossbeam_channel::{Receiver, Sender};
+use rosc::{OscMessage, OscPacket, OscTime, OscType};
 use rtrb::{Producer, RingBuffer};
-use std::collections::HashSet;
-use std::net::UdpSocket;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Debug, Clone)]
 pub enum Command {
@@ -15,6 +41,9 @@ pub enum Command {
         velocity: f32,
         channel: i32,
         port: i32,
+        /// Semitones the note sounds away from `key`, from a fractional key
+        /// or `--tuning`. Sent as a tuning expression after the note-on.
+        tuning: f64,
     },
     NoteOff {
         note_id: i32,
@@ -33,6 +62,9 @@ pub enum Command {
         param_id: u32,
         value: f64,
     },
+    /// Several sets that reach the plugin in the same block, on the same
+    /// sample, as `(param_id, value)` pairs.
+    ParamSetMany { pairs: Vec<(u32, f64)> },
     ParamMod {
         note_id: i32,
         param_id: u32,
@@ -41,124 +73,1390 @@ pub enum Command {
         channel: i32,
         port: i32,
     },
-    DumpPatchState,
+    NoteExpression {
+        /// Index into `NOTE_EXPRESSIONS`, i.e. the CLAP expression id.
+        expression_id: i32,
+        note_id: i32,
+        key: i32,
+        channel: i32,
+        port: i32,
+        value: f64,
+    },
+    /// Move a parameter linearly to `target` over `duration_ms`, one value
+    /// per block.
+    ParamRamp {
+        param_id: u32,
+        target: f64,
+        duration_ms: f64,
+    },
+    /// Add `delta` to a parameter's current value, clamped to its range.
+    /// The audio thread knows the current value, so it does the sum.
+    ParamInc { param_id: u32, delta: f64 },
+    /// Release every note on `channel` (all channels when negative), letting
+    /// each voice's release play out, unlike a choke.
+    AllNotesOff { channel: i32, velocity: f32 },
+    Transport(TransportChange),
+    /// Hold note-offs on `channel` (every channel when negative) while
+    /// `down`, and send them when it lifts.
+    Sustain { channel: i32, down: bool },
+    /// Bend every note on `channel` by `semitones`, including notes started
+    /// later, through per-note tuning expressions.
+    ChannelBend { channel: i32, semitones: f64 },
+    /// `/host/gain` and `/host/mute`, applied by the mixer's master bus.
+    Master(MasterChange),
+    /// Choke and release every voice in every plugin, and drop notes,
+    /// expressions and per-voice modulation held for later blocks.
+    Panic,
+    /// Stop any ramp on a parameter the plugin cleared and forget its last
+    /// value. Sent by the main thread, never parsed from OSC.
+    ForgetParam { param_id: u32 },
+}
+
+/// CLAP note expressions in `CLAP_NOTE_EXPRESSION_*` id order. `/note/expression`
+/// (or `/note/expr`) takes either the id or the name.
+pub const NOTE_EXPRESSIONS: &[&str] = &["volume", "pan", "tuning", "vibrato", "expression", "brightness", "pressure"];
+
+/// Largest tuning, in semitones either way, that CLAP plugins accept.
+pub const MAX_TUNING: f64 = 120.0;
+
+/// Most pairs one `/param/setMany` may carry, so one batch fits in a block.
+pub const MAX_SET_MANY_PAIRS: usize = 256;
+
+/// A command plus the absolute sample position it should take effect at.
+/// `None` means as soon as possible, at the start of the next block.
+#[derive(Debug, Clone)]
+pub struct ScheduledCommand {
+    pub sample_time: Option<u64>,
+    /// Index into the plugin chain, from a `/plugin/<index>/...` address.
+    /// `None` sends notes to the first instrument and params to the first plugin.
+    pub plugin: Option<usize>,
+    pub command: Command,
+}
+
+impl From<Command> for ScheduledCommand {
+    fn from(command: Command) -> Self {
+        Self {
+            sample_time: None,
+            plugin: None,
+            command,
+        }
+    }
+}
+
+/// Shared so the main thread (and future input sources) can enqueue alongside the
+/// OSC thread. Only non-realtime threads lock it; the audio thread owns the consumer.
+pub type CommandProducer = Arc<Mutex<Producer<ScheduledCommand>>>;
+
+/// Addresses the host handles itself. User aliases may not shadow these.
+pub const BUILTIN_ADDRESSES: &[&str] = &[
+    "/note/on",
+    "/note/off",
+    "/note/choke",
+    "/note/expression",
+    "/note/expr",
+    "/note/pitchbend",
+    "/note/play",
+    "/note/release",
+    "/note/all-off",
+    "/notes/off",
+    "/param/set",
+    "/param/setn",
+    "/param/setMany",
+    "/param/mod",
+    "/param/ramp",
+    "/param/inc",
+    "/param/setname",
+    "/param/setByName",
+    "/param/modname",
+    "/param/text",
+    "/param/get",
+    "/params/list",
+    "/plugin",
+    "/plugin/load",
+    "/panic",
+    "/pedal/sustain",
+    "/channel/bend",
+    "/pitchbend",
+    "/transport/tempo",
+    "/transport/play",
+    "/transport/stop",
+    "/transport/seek",
+    "/transport/position",
+    "/patchState",
+    "/state/save",
+    "/state/load",
+    "/loadState",
+    "/preset/load",
+    "/alias/add",
+    "/alias/remove",
+    "/alias/list",
+    "/host/status",
+    "/stats",
+    "/host/stats",
+    "/host/config",
+    "/host/latency",
+    "/latency",
+    "/voices",
+    "/voices/count",
+    "/host/eventStats",
+    "/host/reloadConfig",
+    "/host/testTone",
+    "/mix/master",
+    "/mix/channel",
+    "/host/gain",
+    "/host/mute",
+    "/cue/set",
+    "/cue/relative",
+];
+
+pub fn create_command_queue(capacity: usize) -> (CommandProducer, rtrb::Consumer<ScheduledCommand>) {
+    let (producer, consumer) = RingBuffer::new(capacity);
+    (Arc::new(Mutex::new(producer)), consumer)
+}
+
+pub fn push_command(producer: &CommandProducer, cmd: Command) -> bool {
+    push_command_to(producer, None, cmd)
+}
+
+/// Like `push_command`, for one plugin of the chain.
+pub fn push_command_to(producer: &CommandProducer, plugin: Option<usize>, cmd: Command) -> bool {
+    let mut producer = producer.lock().unwrap_or_else(|e| e.into_inner());
+    producer
+        .push(ScheduledCommand {
+            sample_time: None,
+            plugin,
+            command: cmd,
+        })
+        .is_ok()
+}
+
+/// Parameter tables of a plugin after the first in the chain (`--chain`),
+/// addressed as `/plugin/<index>/param/...`.
+pub struct ChainParams {
+    pub params: HashMap<u32, ParamInfo>,
+    pub per_note_mod_params: HashSet<u32>,
+    pub param_names: ParamNames,
+}
+
+impl ChainParams {
+    pub fn new(params: &[ParamInfo]) -> Self {
+        Self {
+            params: params.iter().map(|p| (p.id, p.clone())).collect(),
+            per_note_mod_params: params.iter().filter(|p| p.is_modulatable_per_note_id).map(|p| p.id).collect(),
+            param_names: ParamNames::new(params),
+        }
+    }
 }
 
-pub fn create_command_queue(capacity: usize) -> (Producer<Command>, rtrb::Consumer<Command>) {
-    RingBuffer::new(capacity)
+/// A plugin's parameters, sent from the main thread when they change.
+pub struct ParamRescan {
+    /// Chain index of the plugin.
+    pub plugin: usize,
+    pub params: ChainParams,
+    /// `/plugin/load` put a different plugin in.
+    pub replaced: bool,
 }
 
+/// Everything the OSC thread needs to turn packets into commands.
+pub struct OscReceiverState {
+    pub queue: SheddingQueue,
+    pub per_note_mod_params: HashSet<u32>,
+    pub params: HashMap<u32, ParamInfo>,
+    /// Resolves `/param/setname`, `/param/setByName` and `/param/modname` to ids.
+    pub param_names: ParamNames,
+    /// Plugins 1.. of the chain; the fields above describe plugin 0.
+    pub chain: Vec<ChainParams>,
+    pub aliases: AliasTable,
+    /// Custom addresses from `--osc-map`, rewritten before anything else.
+    pub osc_map: OscMap,
+    pub main_thread_sender: Sender<MainThreadMessage>,
+    /// Release velocity used when `/note/off` sends 0 or omits it.
+    pub default_release_velocity: Option<f32>,
+    /// `--bend-range`: `/channel/bend` is clamped to this many semitones either way.
+    pub bend_range: f64,
+    /// `--tuning` or `--edo`, applied to note-ons.
+    pub tuner: Tuner,
+    /// `--normalized-params`: `/param/set` values are 0..1 like `/param/setn`.
+    pub normalized_params: bool,
+    /// `--ramp-default-ms`: plain parameter sets ramp over this long.
+    pub ramp_default_ms: Option<f64>,
+    pub runtime_config: SharedRuntimeConfig,
+    /// Start of the next block the audio thread will process.
+    pub sample_position: Arc<AtomicU64>,
+    /// Named positions set by `/cue/set`, used to schedule cue-relative bundles.
+    pub cues: HashMap<String, u64>,
+    pub event_stats: SharedEventStats,
+    /// Set when LED feedback is on, so a controller's own writes aren't echoed to it.
+    pub feedback_echo: Option<EchoGuard>,
+    /// Alias tables reloaded by `--watch-config`, swapped in between packets.
+    pub alias_reloads: Option<Receiver<AliasTable>>,
+    /// Parameters re-read on the main thread after `/plugin/load`, a preset
+    /// load or a rescan the plugin asked for.
+    pub param_rescans: Option<Receiver<ParamRescan>>,
+    pub test_tone: SharedToneControl,
+    /// `/mix/master` and `/mix/channel` gains, read by the audio thread.
+    pub mix: SharedMixControl,
+    /// Note ids handed out by `/note/play`, per held key.
+    pub voices: VoiceMap,
+    /// Parameter values for plugins that have stopped processing, which the
+    /// main thread applies instead of the audio thread.
+    pub param_flush: SharedParamFlush,
+    /// Raised by an immediate `/panic`; the audio thread handles it at the
+    /// start of its next block, ahead of anything queued.
+    pub panic: Arc<AtomicBool>,
+    /// Message and drop counts for the session summary.
+    pub session: SharedSessionCounters,
+    pub verbose: bool,
+}
+
+/// Where replies are written: a UDP socket, or a TCP client's connection.
+pub trait OscSend {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+    /// Whether long runs of replies have to be spaced out so the receiver's
+    /// socket buffer doesn't overflow. A stream connection does its own flow
+    /// control.
+    fn needs_pacing(&self) -> bool {
+        true
+    }
+}
+
+/// The subset of `UdpSocket` the receive loop needs, so error handling can be
+/// exercised against a scripted socket instead of the network.
+pub trait OscSocket: OscSend + Send {
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+}
+
+impl OscSend for UdpSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, addr)
+    }
+}
+
+impl OscSocket for UdpSocket {
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+}
+
+/// Listens for UDP on `bind:port`, and for TCP on the same address when
+/// `tcp` names a framing. UDP packets over `buffer_size` bytes are dropped.
+/// Returns the UDP address actually bound, which has the real port when
+/// `port` is 0.
 pub fn start_osc_receiver(
+    bind: IpAddr,
     port: u16,
-    mut producer: Producer<Command>,
-    per_note_mod_params: HashSet<u32>,
-    verbose: bool,
-) -> Result<thread::JoinHandle<()>> {
-    let socket = UdpSocket::bind(format!("127.0.0.1:{}", port))
-        .context(format!("Failed to bind OSC socket on port {}", port))?;
+    tcp: Option<(u16, TcpFraming)>,
+    buffer_size: usize,
+    state: OscReceiverState,
+) -> Result<(thread::JoinHandle<()>, SocketAddr)> {
+    let requested = SocketAddr::new(bind, port);
+    let socket = bind_udp(&requested.to_string())
+        .context(format!("Failed to bind OSC socket on {}", requested))?;
+    // A rebind reuses the bound port, so port 0 doesn't move to a new one.
+    let local_addr = socket.local_addr().unwrap_or(requested);
+    let bind_addr = local_addr.to_string();
+
+    log::info!("OSC receiver listening on {}", bind_addr);
+    if !bind.is_loopback() {
+        log::warn!(
+            "OSC is reachable from other machines on {}; anyone who can reach it can control the host",
+            bind_addr
+        );
+    }
+    let tcp_packets = match tcp {
+        Some((tcp_port, framing)) => Some(start_tcp_listener(SocketAddr::new(bind, tcp_port), framing)?),
+        None => None,
+    };
+
+    let handle = thread::Builder::new()
+        .name(OSC_THREAD_NAME.into())
+        .spawn(move || {
+            run_receiver(socket, move || bind_udp(&bind_addr), tcp_packets, buffer_size, state);
+        })
+        .context("Failed to spawn OSC receiver thread")?;
+
+    Ok((handle, local_addr))
+}
+
+/// The read timeout lets the loop retry commands held back by the shedding
+/// queue even when no packets are arriving. It also bounds how long the last
+/// value of a coalesced param sweep waits, so it is kept short.
+pub fn bind_udp(addr: &str) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(addr)?;
+    socket.set_read_timeout(Some(RECV_TIMEOUT))?;
+    Ok(socket)
+}
+
+const RECV_TIMEOUT: Duration = Duration::from_millis(2);
+
+/// How the receive loop should react to a `recv_from` error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvErrorClass {
+    /// Read timeout or non-blocking wakeup; nothing to report.
+    Idle,
+    /// The packet didn't fit in the buffer (Windows reports this as an
+    /// error, other systems truncate silently).
+    TooLarge,
+    /// The socket is still usable (e.g. ECONNRESET from an earlier send to an
+    /// unreachable host). Counted and logged at a limited rate.
+    Transient,
+    /// The socket is gone; it has to be rebound.
+    Fatal,
+}
+
+pub fn classify_recv_error(e: &io::Error) -> RecvErrorClass {
+    const EBADF: i32 = 9;
+    const WSAENOTSOCK: i32 = 10038;
+    const WSAEMSGSIZE: i32 = 10040;
+
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => RecvErrorClass::Idle,
+        io::ErrorKind::NotConnected | io::ErrorKind::BrokenPipe | io::ErrorKind::InvalidInput => {
+            RecvErrorClass::Fatal
+        }
+        _ if matches!(e.raw_os_error(), Some(EBADF) | Some(WSAENOTSOCK)) => RecvErrorClass::Fatal,
+        _ if e.raw_os_error() == Some(WSAEMSGSIZE) => RecvErrorClass::TooLarge,
+        _ => RecvErrorClass::Transient,
+    }
+}
 
-    log::info!("OSC receiver listening on 127.0.0.1:{}", port);
+fn log_packet_too_large(from: Option<SocketAddr>, buffer_size: usize) {
+    let from = from.map_or_else(|| "a client".to_string(), |addr| addr.to_string());
+    log::warn!(
+        "Dropped an OSC packet from {}: packet too large for the {}-byte buffer (raise --osc-buffer-size, up to 65536)",
+        from,
+        buffer_size
+    );
+}
+
+/// Logs at most once per interval and reports how many were suppressed.
+struct RateLimitedLog {
+    interval: Duration,
+    last: Option<Instant>,
+    suppressed: u64,
+    total: u64,
+}
+
+impl RateLimitedLog {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+            suppressed: 0,
+            total: 0,
+        }
+    }
+
+    fn record(&mut self, e: &io::Error) {
+        self.total += 1;
+        let now = Instant::now();
+        if self.last.is_some_and(|last| now.duration_since(last) < self.interval) {
+            self.suppressed += 1;
+            return;
+        }
+        if self.suppressed > 0 {
+            log::warn!(
+                "OSC receive error: {} ({} similar errors suppressed, {} total)",
+                e, self.suppressed, self.total
+            );
+        } else {
+            log::warn!("OSC receive error: {}", e);
+        }
+        self.last = Some(now);
+        self.suppressed = 0;
+    }
+}
+
+const TRANSIENT_STORM_THRESHOLD: u32 = 100;
+const REBIND_BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+/// Largest UDP datagram, so big bundles aren't truncated.
+pub const DEFAULT_RECV_BUFFER_SIZE: usize = 65536;
+
+/// Packets from `tcp` are picked up between UDP reads, so they wait at most
+/// one read timeout. UDP packets that don't fit in `buffer_size` bytes are
+/// dropped whole rather than decoded from a truncated copy.
+pub fn run_receiver<S: OscSocket>(
+    mut socket: S,
+    mut rebind: impl FnMut() -> io::Result<S>,
+    tcp: Option<Receiver<TcpPacket>>,
+    buffer_size: usize,
+    mut state: OscReceiverState,
+) {
+    // One spare byte: a read that fills it means the packet was cut short.
+    let mut buf = vec![0u8; buffer_size + 1];
+    let mut transient_log = RateLimitedLog::new(Duration::from_secs(1));
+    let mut consecutive_transient = 0u32;
+
+    loop {
+        state.queue.flush();
+        state.apply_alias_reloads();
+        state.apply_param_rescans();
 
-    let handle = thread::spawn(move || {
-        let mut buf = [0u8; 4096];
+        for TcpPacket { packet, from, reply } in tcp.iter().flat_map(|tcp| tcp.try_iter()) {
+            if state.verbose {
+                log::info!("[OSC-RECV] Received a TCP packet from {}", from);
+            }
+            let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+                let reply = Reply { socket: &reply, addr: from };
+                process_packet(&packet, &mut state, &reply, None);
+            }));
+            if handled.is_err() {
+                log::warn!("Dropped OSC packet from {} after a panic while handling it", from);
+            }
+        }
 
-        loop {
-            match socket.recv_from(&mut buf) {
-                Ok((size, addr)) => {
-                    if verbose {
-                        log::info!("[OSC-RECV] Received {} bytes from {}", size, addr);
+        match socket.recv_from(&mut buf) {
+            Ok((size, addr)) => {
+                consecutive_transient = 0;
+                if size > buffer_size {
+                    log_packet_too_large(Some(addr), buffer_size);
+                    continue;
+                }
+                if state.verbose {
+                    log::info!("[OSC-RECV] Received {} bytes from {}", size, addr);
+                }
+                // A panic while handling one packet is reported by the panic hook,
+                // which decides whether the host shuts down; the packet is lost either way.
+                let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+                    match rosc::decoder::decode_udp(&buf[..size]) {
+                        Ok((_, packet)) => {
+                            let reply = Reply { socket: &socket, addr };
+                            process_packet(&packet, &mut state, &reply, None);
+                        }
+                        Err(e) => log::warn!("Dropped malformed OSC packet ({} bytes) from {}: {:?}", size, addr, e),
                     }
-                    if let Ok((_, packet)) = rosc::decoder::decode_udp(&buf[..size]) {
-                        process_packet(&packet, &mut producer, &per_note_mod_params, verbose);
+                }));
+                if handled.is_err() {
+                    log::warn!("Dropped OSC packet from {} after a panic while handling it", addr);
+                }
+            }
+            Err(e) => match classify_recv_error(&e) {
+                RecvErrorClass::Idle => {}
+                RecvErrorClass::TooLarge => log_packet_too_large(None, buffer_size),
+                RecvErrorClass::Transient => {
+                    transient_log.record(&e);
+                    consecutive_transient += 1;
+                    // Some stacks deliver these in a tight storm; back off briefly
+                    // instead of spinning a core.
+                    if consecutive_transient >= TRANSIENT_STORM_THRESHOLD {
+                        thread::sleep(Duration::from_millis(1));
                     }
                 }
-                Err(e) => {
-                    log::error!("OSC receive error: {}", e);
+                RecvErrorClass::Fatal => {
+                    log::error!("OSC socket failed: {}; rebinding", e);
+                    socket = rebind_with_backoff(&mut rebind);
+                    consecutive_transient = 0;
                 }
+            },
+        }
+    }
+}
+
+fn rebind_with_backoff<S>(rebind: &mut impl FnMut() -> io::Result<S>) -> S {
+    let mut backoff = Duration::from_millis(100);
+    loop {
+        match rebind() {
+            Ok(socket) => {
+                log::info!("OSC socket rebound");
+                return socket;
+            }
+            Err(e) => {
+                log::warn!("OSC rebind failed: {}; retrying in {:?}", e, backoff);
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(REBIND_BACKOFF_MAX);
             }
         }
+    }
+}
+
+/// Sends one message to each of `targets` (the `--notify` addresses) from a
+/// throwaway socket. Best effort; failures are ignored.
+pub fn notify(targets: &[SocketAddr], addr: &str, args: Vec<OscType>) {
+    if targets.is_empty() {
+        return;
+    }
+    let packet = OscPacket::Message(OscMessage {
+        addr: addr.to_string(),
+        args,
     });
+    let (Ok(bytes), Ok(socket)) = (rosc::encoder::encode(&packet), UdpSocket::bind("0.0.0.0:0")) else {
+        return;
+    };
+    for target in targets {
+        let _ = socket.send_to(&bytes, target);
+    }
+}
 
-    Ok(handle)
+/// Replies a UDP query sends back to back before pausing.
+const PARAM_LIST_BURST: usize = 32;
+/// The pause between bursts, long enough for a client to drain its socket.
+const PARAM_LIST_PAUSE: Duration = Duration::from_millis(2);
+
+/// Where responses to query-style messages go: back to the sender.
+pub struct Reply<'a> {
+    pub socket: &'a dyn OscSend,
+    pub addr: SocketAddr,
 }
 
-fn process_packet(
-    packet: &OscPacket,
-    producer: &mut Producer<Command>,
-    per_note_mod_params: &HashSet<u32>,
-    verbose: bool,
-) {
+impl Reply<'_> {
+    pub fn send(&self, addr: &str, args: Vec<OscType>) {
+        let packet = OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args,
+        });
+        match rosc::encoder::encode(&packet) {
+            Ok(bytes) => {
+                if let Err(e) = self.socket.send_to(&bytes, self.addr) {
+                    log::warn!("Failed to send OSC reply to {}: {}", self.addr, e);
+                }
+            }
+            Err(e) => log::warn!("Failed to encode OSC reply {}: {:?}", addr, e),
+        }
+    }
+}
+
+/// `/params/list`: one `/params/entry` per parameter in id order, then
+/// `/params/end count`. Over UDP the entries go out in bursts so a plugin
+/// with thousands of parameters doesn't overrun the client; the OSC thread
+/// handles nothing else meanwhile.
+fn send_param_list(reply: &Reply, params: &HashMap<u32, ParamInfo>, per_note_mod_params: &HashSet<u32>) {
+    let mut sorted: Vec<&ParamInfo> = params.values().collect();
+    sorted.sort_by_key(|p| p.id);
+    for (i, param) in sorted.iter().enumerate() {
+        if i > 0 && i % PARAM_LIST_BURST == 0 && reply.socket.needs_pacing() {
+            thread::sleep(PARAM_LIST_PAUSE);
+        }
+        reply.send(
+            "/params/entry",
+            vec![
+                OscType::Int(param.id as i32),
+                OscType::String(param.name.clone()),
+                OscType::String(param.module.clone()),
+                OscType::Double(param.min_value),
+                OscType::Double(param.max_value),
+                OscType::Double(param.default_value),
+                OscType::Int(param.is_stepped as i32),
+                OscType::Int(per_note_mod_params.contains(&param.id) as i32),
+            ],
+        );
+    }
+    reply.send("/params/end", vec![OscType::Int(sorted.len() as i32)]);
+}
+
+/// `sample_time` is inherited from the enclosing bundle, if any.
+fn process_packet(packet: &OscPacket, state: &mut OscReceiverState, reply: &Reply, sample_time: Option<u64>) {
     match packet {
         OscPacket::Message(msg) => {
-            if verbose {
+            if state.verbose {
                 log::info!("[OSC-PARSE] Message: {} args={:?}", msg.addr, msg.args);
             }
-            if let Some(cmd) = parse_message(msg, per_note_mod_params) {
-                if verbose {
-                    log::info!("[OSC-QUEUE] Pushing command: {:?}", cmd);
-                }
-                if producer.push(cmd).is_err() {
-                    log::warn!("Command queue full, dropping OSC message");
+            match state.handle_message_at(msg, Some(reply), sample_time) {
+                Ok(()) => {}
+                Err(ParseError::UnknownAddress(addr)) => {
+                    log::debug!("Unknown OSC address: {}", addr);
                 }
+                Err(e) => log::warn!("{}", e),
             }
         }
         OscPacket::Bundle(bundle) => {
+            if let Some(OscPacket::Message(first)) = bundle.content.first() {
+                if first.addr == "/cue/relative" {
+                    process_cue_relative_bundle(first, &bundle.timetag, &bundle.content[1..], state, reply);
+                    return;
+                }
+            }
+            let sample_time = timetag_sample_time(&bundle.timetag, state).or(sample_time);
             for p in &bundle.content {
-                process_packet(p, producer, per_note_mod_params, verbose);
+                process_packet(p, state, reply, sample_time);
+            }
+        }
+    }
+}
+
+/// Maps a bundle's absolute NTP timetag onto the audio clock by its distance
+/// from now. `None` for the "immediately" timetag. Times already past resolve
+/// to the next block, where they play at offset 0. Events go to the plugins
+/// early by the chain's latency, so their audio comes out at the timetag.
+fn timetag_sample_time(timetag: &OscTime, state: &OscReceiverState) -> Option<u64> {
+    if timetag.seconds == 0 && timetag.fractional == 1 {
+        return None;
+    }
+    let delay = SystemTime::from(*timetag)
+        .duration_since(SystemTime::now())
+        .unwrap_or_default();
+    let (sample_rate, latency) = {
+        let config = state.runtime_config.read().unwrap_or_else(|e| e.into_inner());
+        (config.sample_rate, config.latency)
+    };
+    let sample_time = (state.sample_position.load(Ordering::Relaxed)
+        + (delay.as_secs_f64() * sample_rate as f64).round() as u64)
+        .saturating_sub(latency as u64);
+
+    if state.verbose {
+        log::info!("[OSC-BUNDLE] Timetag {:.6}s ahead -> sample {}", delay.as_secs_f64(), sample_time);
+    }
+    Some(sample_time)
+}
+
+/// Schedules every message in the bundle at the named cue plus the bundle's
+/// timetag, read as an offset in seconds rather than absolute NTP time.
+fn process_cue_relative_bundle(
+    cue_msg: &OscMessage,
+    timetag: &OscTime,
+    content: &[OscPacket],
+    state: &mut OscReceiverState,
+    reply: &Reply,
+) {
+    let Some(OscType::String(name)) = cue_msg.args.first() else {
+        log::warn!("/cue/relative requires 1 arg: name:s");
+        return;
+    };
+    let Some(&cue) = state.cues.get(name) else {
+        log::warn!("/cue/relative: no cue named '{}', ignoring bundle", name);
+        return;
+    };
+
+    let sample_rate = state.runtime_config.read().unwrap_or_else(|e| e.into_inner()).sample_rate;
+    let offset_secs = timetag.seconds as f64 + timetag.fractional as f64 / 4_294_967_296.0;
+    let sample_time = cue + (offset_secs * sample_rate as f64).round() as u64;
+
+    if state.verbose {
+        log::info!("[OSC-CUE] Bundle relative to '{}' (+{:.6}s) -> sample {}", name, offset_secs, sample_time);
+    }
+
+    for packet in content {
+        match packet {
+            OscPacket::Message(msg) => match state.handle_message_at(msg, Some(reply), Some(sample_time)) {
+                Ok(()) => {}
+                Err(ParseError::UnknownAddress(addr)) => log::debug!("Unknown OSC address: {}", addr),
+                Err(e) => log::warn!("{}", e),
+            },
+            OscPacket::Bundle(_) => log::warn!("/cue/relative: nested bundles are not supported, skipping"),
+        }
+    }
+}
+
+impl OscReceiverState {
+    /// A receiver with no plugin parameters, aliases, controllers or session
+    /// files attached, for the latency benchmark and tests.
+    pub fn detached(queue: SheddingQueue, main_thread_sender: Sender<MainThreadMessage>) -> Self {
+        Self {
+            queue,
+            per_note_mod_params: HashSet::new(),
+            params: HashMap::new(),
+            param_names: ParamNames::default(),
+            chain: Vec::new(),
+            aliases: AliasTable::default(),
+            osc_map: OscMap::default(),
+            main_thread_sender,
+            default_release_velocity: None,
+            bend_range: 2.0,
+            tuner: Tuner::new(None, true),
+            normalized_params: false,
+            ramp_default_ms: None,
+            runtime_config: Arc::new(RwLock::new(RuntimeConfig::default())),
+            sample_position: Arc::new(AtomicU64::new(0)),
+            cues: HashMap::new(),
+            event_stats: Arc::new(Mutex::new(EventStats::default())),
+            feedback_echo: None,
+            alias_reloads: None,
+            param_rescans: None,
+            test_tone: ToneControl::new(440.0, 0.0, false),
+            mix: MixControl::new(2),
+            voices: VoiceMap::new(RetriggerMode::Choke),
+            param_flush: ParamFlush::new(1),
+            panic: Arc::new(AtomicBool::new(false)),
+            session: SessionCounters::new(),
+            verbose: false,
+        }
+    }
+
+    /// Applies one message: host-side addresses are handled here, everything
+    /// else is parsed into a `Command` and queued for the audio thread.
+    pub fn handle_message(&mut self, msg: &OscMessage, reply: Option<&Reply>) -> Result<(), ParseError> {
+        self.handle_message_at(msg, reply, None)
+    }
+
+    /// Like `handle_message`, but queued commands take effect at `sample_time`.
+    pub fn handle_message_at(
+        &mut self,
+        msg: &OscMessage,
+        reply: Option<&Reply>,
+        sample_time: Option<u64>,
+    ) -> Result<(), ParseError> {
+        self.session.record_osc_message(&msg.addr);
+        let mapped = self.osc_map.rewrite(msg).map_err(invalid)?;
+        let msg = mapped.as_ref().unwrap_or(msg);
+        if let Some((index, addr)) = split_plugin_address(&msg.addr) {
+            let plugin = index
+                .parse::<usize>()
+                .ok()
+                .filter(|&plugin| plugin <= self.chain.len())
+                .ok_or_else(|| invalid(format!("{}: no plugin {} in the chain", msg.addr, index)))?;
+            let msg = OscMessage {
+                addr: addr.to_string(),
+                args: msg.args.clone(),
+            };
+            return self.handle_plugin_message(&msg, reply, Some(plugin), sample_time);
+        }
+        if handle_alias_message(msg, self, reply, sample_time)? {
+            return Ok(());
+        }
+        if msg.addr == "/cue/set" {
+            let Some(OscType::String(name)) = msg.args.first() else {
+                return Err(invalid("/cue/set requires 1 arg: name:s"));
+            };
+            let position = self.sample_position.load(Ordering::Relaxed);
+            if self.verbose {
+                log::info!("[OSC-CUE] Cue '{}' set at sample {}", name, position);
+            }
+            self.cues.insert(name.clone(), position);
+            return Ok(());
+        }
+        if msg.addr == "/cue/relative" {
+            return Err(invalid("/cue/relative is only valid as the first message of a bundle"));
+        }
+        if msg.addr == "/host/stats" {
+            if let Some(reply) = reply {
+                reply.send("/host/stats", self.session.queue_stats().args());
+            }
+            return Ok(());
+        }
+
+        if msg.addr == "/stats" {
+            if let Some(reply) = reply {
+                reply.send("/stats", self.session.stats_args());
+            }
+            return Ok(());
+        }
+
+        if msg.addr == "/host/status" {
+            if let Some(reply) = reply {
+                let status = self.queue.status();
+                reply.send(
+                    "/host/status",
+                    vec![
+                        OscType::Int(status.level as i32),
+                        OscType::String(status.level.to_string()),
+                        OscType::Float(status.occupancy),
+                        OscType::Int(status.pending as i32),
+                        OscType::Long(status.dropped as i64),
+                    ],
+                );
+            }
+            return Ok(());
+        }
+
+        if matches!(msg.addr.as_str(), "/host/latency" | "/latency") {
+            if let Some(reply) = reply {
+                let args = self.runtime_config.read().unwrap_or_else(|e| e.into_inner()).latency_args();
+                reply.send(&msg.addr, args);
+            }
+            return Ok(());
+        }
+
+        if matches!(msg.addr.as_str(), "/voices" | "/voices/count") {
+            if let Some(reply) = reply {
+                let args = self.runtime_config.read().unwrap_or_else(|e| e.into_inner()).voices_args();
+                reply.send(&msg.addr, args);
+            }
+            return Ok(());
+        }
+
+        if msg.addr == "/host/testTone" {
+            let frequency = required(&msg.args, 0, get_f32, "/host/testTone", "freq")?;
+            let gain = required(&msg.args, 1, get_f32, "/host/testTone", "gain")?;
+            let on = required(&msg.args, 2, get_i32, "/host/testTone", "on")? != 0;
+            let sample_rate = self.runtime_config.read().unwrap_or_else(|e| e.into_inner()).sample_rate;
+            check_tone(frequency, gain, sample_rate).map_err(|e| invalid(format!("/host/testTone: {}", e)))?;
+            log::info!("Test tone {} ({} Hz, gain {})", if on { "on" } else { "off" }, frequency, gain);
+            self.test_tone.set(frequency, gain, on);
+            return Ok(());
+        }
+
+        if msg.addr == "/mix/master" {
+            let gain = required(&msg.args, 0, get_f32, "/mix/master", "gain")?;
+            if !gain.is_finite() {
+                return Err(invalid("/mix/master: gain is not a number"));
+            }
+            let gain = self.mix.set_master(gain);
+            if self.verbose {
+                log::info!("[OSC-MIX] Master gain {}", gain);
+            }
+            return Ok(());
+        }
+
+        if msg.addr == "/mix/channel" {
+            let channel = required(&msg.args, 0, get_i32, "/mix/channel", "ch")?;
+            let gain = required(&msg.args, 1, get_f32, "/mix/channel", "gain")?;
+            if !gain.is_finite() {
+                return Err(invalid("/mix/channel: gain is not a number"));
+            }
+            let count = self.mix.channel_count();
+            let gain = usize::try_from(channel)
+                .ok()
+                .and_then(|channel| self.mix.set_channel(channel, gain))
+                .ok_or_else(|| invalid(format!("/mix/channel: channel {} is not in 0..{}", channel, count)))?;
+            if self.verbose {
+                log::info!("[OSC-MIX] Channel {} gain {}", channel, gain);
+            }
+            return Ok(());
+        }
+
+        if msg.addr == "/host/reloadConfig" {
+            self.reload_aliases(reply);
+            return Ok(());
+        }
+
+        if msg.addr == "/host/config" {
+            if let Some(reply) = reply {
+                let config = self.runtime_config.read().unwrap_or_else(|e| e.into_inner());
+                let entries = config.entries();
+                for (key, value) in &entries {
+                    reply.send("/host/config", vec![OscType::String(key.to_string()), value.clone()]);
+                }
+                reply.send("/host/config/end", vec![OscType::Int(entries.len() as i32)]);
+            }
+            return Ok(());
+        }
+
+        if msg.addr == "/host/eventStats" && crate::features::METRICS {
+            if let Some(reply) = reply {
+                let stats = self.event_stats.lock().unwrap_or_else(|e| e.into_inner());
+                for args in stats.osc_replies() {
+                    reply.send("/host/eventStats", args);
+                }
+                reply.send("/host/eventStats/end", vec![OscType::Long(stats.blocks as i64)]);
+            }
+            return Ok(());
+        }
+
+        if msg.addr == "/patchState" {
+            // State calls are main-thread only; the audio thread never sees this.
+            let path = match msg.args.first() {
+                Some(OscType::String(path)) => Some(PathBuf::from(path)),
+                Some(_) => return Err(invalid("/patchState takes an optional path:s")),
+                None => None,
+            };
+            let reply_to = if path.is_none() { reply.map(|r| r.addr) } else { None };
+            let _ = self.main_thread_sender.send(MainThreadMessage::SaveState { path, reply_to });
+            return Ok(());
+        }
+
+        if msg.addr == "/plugin/load" {
+            let Some(OscType::String(path)) = msg.args.first() else {
+                return Err(invalid("/plugin/load requires path:s and an optional plugin_id:s"));
+            };
+            let plugin_id = match msg.args.get(1) {
+                Some(OscType::String(id)) => Some(id.clone()),
+                Some(_) => return Err(invalid("/plugin/load plugin_id must be a string")),
+                None => None,
+            };
+            let _ = self.main_thread_sender.send(MainThreadMessage::LoadPlugin {
+                path: PathBuf::from(path),
+                plugin_id,
+                reply_to: reply.map(|r| r.addr),
+            });
+            return Ok(());
+        }
+
+        if msg.addr == "/preset/load" {
+            let Some(OscType::String(location_uri)) = msg.args.first() else {
+                return Err(invalid("/preset/load requires location_uri:s and an optional load_key:s"));
+            };
+            let load_key = match msg.args.get(1) {
+                Some(OscType::String(key)) => Some(key.clone()),
+                Some(_) => return Err(invalid("/preset/load load_key must be a string")),
+                None => None,
+            };
+            let _ = self.main_thread_sender.send(MainThreadMessage::LoadPreset {
+                location_uri: location_uri.clone(),
+                load_key,
+                reply_to: reply.map(|r| r.addr),
+            });
+            return Ok(());
+        }
+
+        if matches!(msg.addr.as_str(), "/state/save" | "/state/load" | "/loadState") {
+            let Some(OscType::String(path)) = msg.args.first() else {
+                return Err(invalid(format!("{} requires 1 arg: path:s", msg.addr)));
+            };
+            let path = PathBuf::from(path);
+            let message = if msg.addr == "/state/save" {
+                MainThreadMessage::SaveState {
+                    path: Some(path),
+                    reply_to: None,
+                }
+            } else {
+                MainThreadMessage::LoadState { path }
+            };
+            let _ = self.main_thread_sender.send(message);
+            return Ok(());
+        }
+
+        self.handle_plugin_message(msg, reply, None, sample_time)
+    }
+
+    /// Note and param messages, for `plugin` in the chain or, with `None`,
+    /// routed by the audio thread (notes to the first instrument, params to
+    /// the first plugin).
+    fn handle_plugin_message(
+        &mut self,
+        msg: &OscMessage,
+        reply: Option<&Reply>,
+        plugin: Option<usize>,
+        sample_time: Option<u64>,
+    ) -> Result<(), ParseError> {
+        if matches!(msg.addr.as_str(), "/note/play" | "/note/release" | "/note/all-off") {
+            for (plugin, mut cmd) in self.voice_commands(msg, plugin)? {
+                self.apply_default_release_velocity(&mut cmd);
+                self.tuner.apply(&mut cmd);
+                enqueue(self, cmd, plugin, sample_time);
+            }
+            return Ok(());
+        }
+        let (params, per_note_mod_params, param_names) = match plugin.unwrap_or(0) {
+            0 => (&self.params, &self.per_note_mod_params, &self.param_names),
+            index => {
+                let chained = &self.chain[index - 1];
+                (&chained.params, &chained.per_note_mod_params, &chained.param_names)
+            }
+        };
+        if msg.addr == "/params/list" {
+            if let Some(reply) = reply {
+                send_param_list(reply, params, per_note_mod_params);
+            }
+            return Ok(());
+        }
+        if msg.addr == "/param/text" || msg.addr == "/param/get" {
+            // value_to_text is a main-thread call; the main loop sends the reply.
+            let address = if msg.addr == "/param/get" { "/param/get" } else { "/param/text" };
+            let param_id = required(&msg.args, 0, get_u32, address, "param_id")?;
+            if !params.contains_key(&param_id) {
+                return Err(invalid(format!("{}: unknown parameter id {}", address, param_id)));
+            }
+            if let Some(reply) = reply {
+                let _ = self.main_thread_sender.send(MainThreadMessage::ParamText {
+                    plugin: plugin.unwrap_or(0),
+                    param_id,
+                    reply_to: reply.addr,
+                    address,
+                });
+            }
+            return Ok(());
+        }
+        let named = param_names.rewrite(msg).map_err(invalid)?;
+        let msg = named.as_ref().unwrap_or(msg);
+        let mut cmd = parse_message(msg, per_note_mod_params)?;
+        if is_normalized_set(&msg.addr, self.normalized_params) {
+            denormalize(&mut cmd, &msg.addr, |id| params.get(&id))?;
+        }
+        check_param(&mut cmd, &msg.addr, |id| params.get(&id), self.verbose)?;
+        // Stepped params jump; anything else ramps if --ramp-default-ms asks for it.
+        let stepped = |id| params.get(&id).is_some_and(|p| p.is_stepped);
+        cmd = match (cmd, self.ramp_default_ms) {
+            (Command::ParamRamp { param_id, target, duration_ms }, _) if duration_ms <= 0.0 || stepped(param_id) => {
+                Command::ParamSet { param_id, value: target }
+            }
+            (Command::ParamSet { param_id, value }, Some(duration_ms)) if !stepped(param_id) => Command::ParamRamp {
+                param_id,
+                target: value,
+                duration_ms,
+            },
+            (cmd, _) => cmd,
+        };
+        self.apply_default_release_velocity(&mut cmd);
+        self.tuner.apply(&mut cmd);
+        if let Command::ChannelBend { semitones, .. } = &mut cmd {
+            *semitones = semitones.clamp(-self.bend_range, self.bend_range);
+        }
+        if let Command::Panic = cmd {
+            // The panic ends every voice, so none are left for /note/release.
+            self.voices.clear();
+        }
+        // Controller feedback only tracks the first plugin's params.
+        if let (Command::ParamSet { param_id, .. } | Command::ParamRamp { param_id, .. }, None | Some(0)) = (&cmd, plugin) {
+            self.record_controller_write(reply, *param_id);
+        }
+        if let (Command::ParamSetMany { pairs }, None | Some(0)) = (&cmd, plugin) {
+            for (param_id, _) in pairs {
+                self.record_controller_write(reply, *param_id);
+            }
+        }
+        enqueue(self, cmd, plugin, sample_time);
+        Ok(())
+    }
+
+    /// `/note/play`, `/note/release` and `/note/all-off`, with the plugin each
+    /// resulting command goes to. Note ids come from the voice map.
+    fn voice_commands(
+        &mut self,
+        msg: &OscMessage,
+        plugin: Option<usize>,
+    ) -> Result<Vec<(Option<usize>, Command)>, ParseError> {
+        let args = &msg.args;
+        match msg.addr.as_str() {
+            "/note/play" => {
+                if args.len() < 2 {
+                    return Err(invalid("/note/play requires at least 2 args: key, vel"));
+                }
+                let key = required(args, 0, get_i32, "/note/play", "key")?;
+                let velocity = required(args, 1, get_f32, "/note/play", "vel")?;
+                let channel = args.get(2).and_then(get_i32).unwrap_or(0);
+                let port = args.get(3).and_then(get_i32).unwrap_or(0);
+                let commands = self.voices.play(plugin, key, velocity, channel, port);
+                Ok(commands.into_iter().map(|cmd| (plugin, cmd)).collect())
+            }
+            "/note/release" => {
+                if args.is_empty() {
+                    return Err(invalid("/note/release requires at least 1 arg: key"));
+                }
+                let key = required(args, 0, get_i32, "/note/release", "key")?;
+                let velocity = args.get(1).and_then(get_f32).unwrap_or(0.0);
+                let channel = args.get(2).and_then(get_i32).unwrap_or(0);
+                let port = args.get(3).and_then(get_i32).unwrap_or(0);
+                Ok(vec![(plugin, self.voices.release(plugin, key, velocity, channel, port))])
+            }
+            _ => Ok(self.voices.all_off(plugin)),
+        }
+    }
+
+    fn apply_default_release_velocity(&self, cmd: &mut Command) {
+        if let (Command::NoteOff { velocity, .. } | Command::AllNotesOff { velocity, .. }, Some(default)) =
+            (cmd, self.default_release_velocity)
+        {
+            if *velocity == 0.0 {
+                *velocity = default;
+            }
+        }
+    }
+
+    fn apply_alias_reloads(&mut self) {
+        let Some(reloads) = &self.alias_reloads else {
+            return;
+        };
+        if let Some(table) = reloads.try_iter().last() {
+            self.aliases = table;
+        }
+    }
+
+    fn apply_param_rescans(&mut self) {
+        let Some(rescans) = &self.param_rescans else {
+            return;
+        };
+        for rescan in rescans.try_iter() {
+            match rescan.plugin {
+                0 => {
+                    self.params = rescan.params.params;
+                    self.per_note_mod_params = rescan.params.per_note_mod_params;
+                    self.param_names = rescan.params.param_names;
+                }
+                index => match self.chain.get_mut(index - 1) {
+                    Some(chained) => *chained = rescan.params,
+                    None => continue,
+                },
+            }
+            // The old plugin's voices went with it.
+            if rescan.replaced {
+                self.voices.clear();
             }
         }
     }
+
+    /// `/host/reloadConfig`: replies `/host/reloadConfig alias_count:i32`, or
+    /// `/host/error message:s` and keeps the current aliases.
+    fn reload_aliases(&mut self, reply: Option<&Reply>) {
+        let result = match self.aliases.path() {
+            Some(path) => load_aliases(path, &self.params),
+            None => Err(anyhow::anyhow!("No --alias-file to reload")),
+        };
+        match result {
+            Ok(table) => {
+                log::info!("Reloaded {} aliases", table.len());
+                self.aliases = table;
+                if let Some(reply) = reply {
+                    reply.send("/host/reloadConfig", vec![OscType::Int(self.aliases.len() as i32)]);
+                }
+            }
+            Err(e) => {
+                log::error!("Keeping previous aliases: {:#}", e);
+                if let Some(reply) = reply {
+                    reply.send("/host/error", vec![OscType::String(format!("{:#}", e))]);
+                }
+            }
+        }
+    }
+
+    fn record_controller_write(&self, reply: Option<&Reply>, param_id: u32) {
+        if let (Some(echo), Some(reply)) = (&self.feedback_echo, reply) {
+            echo.record(reply.addr, param_id);
+        }
+    }
+}
+
+fn enqueue(state: &mut OscReceiverState, cmd: Command, plugin: Option<usize>, sample_time: Option<u64>) {
+    // An immediate panic skips the queue, so a full buffer can't hold it up.
+    // It is logged here rather than on the audio thread.
+    if let Command::Panic = cmd {
+        match sample_time {
+            None => {
+                log::warn!("Panic: releasing all notes");
+                state.panic.store(true, Ordering::Relaxed);
+                return;
+            }
+            Some(sample_time) => log::warn!("Panic at sample {}: releasing all notes then", sample_time),
+        }
+    }
+    // A stopped plugin processes no blocks, so its parameter changes go to
+    // the main thread to be flushed instead.
+    let target = plugin.unwrap_or(0);
+    if state.param_flush.hand_over(target, &cmd) {
+        if state.verbose {
+            log::info!("[OSC-QUEUE] Plugin {} is stopped; flushing {:?} on the main thread", target, cmd);
+        }
+        let _ = state.main_thread_sender.send(MainThreadMessage::FlushRequested { plugin: target });
+        return;
+    }
+    if state.verbose {
+        log::info!("[OSC-QUEUE] Pushing command: {:?}", cmd);
+    }
+    let scheduled = ScheduledCommand {
+        sample_time,
+        plugin,
+        command: cmd,
+    };
+    let queued = state.queue.push(scheduled);
+    state.session.record_command(queued);
+    if !queued && state.verbose {
+        log::info!("[OSC-QUEUE] Dropped under load (stage '{}')", state.queue.status().level);
+    }
 }
 
-fn parse_message(msg: &OscMessage, per_note_mod_params: &HashSet<u32>) -> Option<Command> {
+/// Handles `/alias/*` management and user alias addresses. Returns true if
+/// the message was consumed.
+fn handle_alias_message(
+    msg: &OscMessage,
+    state: &mut OscReceiverState,
+    reply: Option<&Reply>,
+    sample_time: Option<u64>,
+) -> Result<bool, ParseError> {
+    match msg.addr.as_str() {
+        "/alias/add" => {
+            let address = msg.args.first().and_then(|a| match a {
+                OscType::String(s) => Some(s.clone()),
+                _ => None,
+            });
+            let param_id = msg.args.get(1).and_then(get_u32);
+            let (Some(address), Some(param_id)) = (address, param_id) else {
+                return Err(invalid("/alias/add requires 2 args: address:s, param_id:i32"));
+            };
+            if !state.params.contains_key(&param_id) {
+                return Err(invalid(format!("/alias/add: unknown parameter id {}", param_id)));
+            }
+            state
+                .aliases
+                .add(&address, param_id)
+                .map_err(|e| invalid(format!("/alias/add: {:#}", e)))?;
+            log::info!("Alias {} -> param {}", address, param_id);
+            Ok(true)
+        }
+        "/alias/remove" => {
+            let Some(OscType::String(address)) = msg.args.first() else {
+                return Err(invalid("/alias/remove requires 1 arg: address:s"));
+            };
+            match state.aliases.remove(address) {
+                Ok(true) => log::info!("Removed alias {}", address),
+                Ok(false) => return Err(invalid(format!("/alias/remove: no alias {}", address))),
+                Err(e) => return Err(invalid(format!("/alias/remove: {:#}", e))),
+            }
+            Ok(true)
+        }
+        "/alias/list" => {
+            if let Some(reply) = reply {
+                for (address, param_id) in state.aliases.iter() {
+                    reply.send(
+                        "/alias/entry",
+                        vec![OscType::String(address.to_string()), OscType::Long(param_id as i64)],
+                    );
+                }
+                reply.send("/alias/end", vec![OscType::Int(state.aliases.len() as i32)]);
+            }
+            Ok(true)
+        }
+        _ => match state.aliases.resolve(msg) {
+            Some(resolved) => {
+                let (param_id, value) = resolved?;
+                state.record_controller_write(reply, param_id);
+                apply_alias(state, param_id, value, sample_time);
+                Ok(true)
+            }
+            None => Ok(false),
+        },
+    }
+}
+
+fn apply_alias(state: &mut OscReceiverState, param_id: u32, value: AliasValue, sample_time: Option<u64>) {
+    match value {
+        AliasValue::Plain(value) => enqueue(state, Command::ParamSet { param_id, value }, None, sample_time),
+        AliasValue::Normalized(normalized) => match state.params.get(&param_id) {
+            Some(param) => {
+                let value = param.normalized_to_value(normalized);
+                enqueue(state, Command::ParamSet { param_id, value }, None, sample_time);
+            }
+            None => log::warn!("Alias target parameter {} no longer exists", param_id),
+        },
+        AliasValue::Text(text) => {
+            // text_to_value is a main-thread call; the main loop enqueues the result.
+            let _ = state
+                .main_thread_sender
+                .send(MainThreadMessage::SetParamFromText { param_id, text });
+        }
+    }
+}
+
+/// Why an OSC message could not be turned into a `Command`.
+#[derive(Debug, Clone)]
+pub enum ParseError {
+    UnknownAddress(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownAddress(addr) => write!(f, "Unknown OSC address: {}", addr),
+            ParseError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+pub fn parse_message(msg: &OscMessage, per_note_mod_params: &HashSet<u32>) -> Result<Command, ParseError> {
     match msg.addr.as_str() {
         "/note/on" => parse_note_on(&msg.args),
         "/note/off" => parse_note_off(&msg.args),
         "/note/choke" => parse_note_choke(&msg.args),
-        "/param/set" => parse_param_set(&msg.args),
+        "/notes/off" => parse_notes_off(&msg.args),
+        "/note/expression" | "/note/expr" => parse_note_expression(&msg.args),
+        "/note/pitchbend" => parse_note_pitchbend(&msg.args),
+        "/param/set" | "/param/setn" => parse_param_set(&msg.addr, &msg.args),
+        "/param/setMany" => parse_param_set_many(&msg.args),
         "/param/mod" => parse_param_mod(&msg.args, per_note_mod_params),
-        "/patchState" => Some(Command::DumpPatchState),
-        _ => {
-            log::debug!("Unknown OSC address: {}", msg.addr);
-            None
+        "/param/ramp" => parse_param_ramp(&msg.args),
+        "/param/inc" => parse_param_inc(&msg.args),
+        "/panic" => Ok(Command::Panic),
+        "/pedal/sustain" => {
+            let channel = required(&msg.args, 0, get_i32, "/pedal/sustain", "channel")?;
+            let down = required(&msg.args, 1, get_i32, "/pedal/sustain", "down")? != 0;
+            Ok(Command::Sustain { channel, down })
+        }
+        "/channel/bend" | "/pitchbend" => {
+            let channel = required(&msg.args, 0, get_i32, &msg.addr, "chan")?;
+            let semitones = required(&msg.args, 1, get_f64, &msg.addr, "semitones")?;
+            if !semitones.is_finite() {
+                return Err(invalid(format!("{}: semitones is not a number", msg.addr)));
+            }
+            Ok(Command::ChannelBend { channel, semitones })
+        }
+        "/transport/tempo" | "/transport/play" | "/transport/stop" | "/transport/seek" | "/transport/position" => {
+            parse_transport(&msg.addr, &msg.args)
+        }
+        "/host/gain" => {
+            let db = required(&msg.args, 0, get_f32, "/host/gain", "db")?;
+            if db.is_nan() {
+                return Err(invalid("/host/gain: gain is not a number"));
+            }
+            Ok(Command::Master(MasterChange::Gain(db_to_gain(db))))
+        }
+        "/host/mute" => {
+            let muted = required(&msg.args, 0, get_i32, "/host/mute", "on")? != 0;
+            Ok(Command::Master(MasterChange::Mute(muted)))
         }
+        _ => Err(ParseError::UnknownAddress(msg.addr.clone())),
     }
 }
 
-fn parse_note_on(args: &[OscType]) -> Option<Command> {
+/// Splits `/plugin/<index>/param/set`, or the short form `/<index>/param/set`,
+/// into the index and `/param/set`. Only note (including `/notes/off`) and
+/// param (including `/params/list`) addresses can be sent to a specific plugin.
+pub fn split_plugin_address(addr: &str) -> Option<(&str, &str)> {
+    let rest = match addr.strip_prefix("/plugin/") {
+        Some(rest) => rest,
+        None => addr
+            .strip_prefix('/')
+            .filter(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))?,
+    };
+    let slash = rest.find('/')?;
+    let (index, addr) = rest.split_at(slash);
+    (addr.starts_with("/note/") || addr.starts_with("/notes/") || addr.starts_with("/param/") || addr == "/params/list")
+        .then_some((index, addr))
+}
+
+fn parse_transport(addr: &str, args: &[OscType]) -> Result<Command, ParseError> {
+    let change = match addr {
+        "/transport/play" => TransportChange::Play,
+        "/transport/stop" => TransportChange::Stop,
+        "/transport/tempo" => {
+            let bpm = required(args, 0, get_f64, addr, "bpm")?;
+            if !(MIN_TEMPO..=MAX_TEMPO).contains(&bpm) {
+                return Err(invalid(format!(
+                    "/transport/tempo: {} bpm is outside {}-{}",
+                    bpm, MIN_TEMPO, MAX_TEMPO
+                )));
+            }
+            TransportChange::Tempo(bpm)
+        }
+        _ => {
+            let beats = required(args, 0, get_f64, addr, "beats")?;
+            if !beats.is_finite() || beats < 0.0 {
+                return Err(invalid(format!("{}: position must be >= 0 beats, got {}", addr, beats)));
+            }
+            TransportChange::Seek(beats)
+        }
+    };
+    Ok(Command::Transport(change))
+}
+
+fn parse_note_on(args: &[OscType]) -> Result<Command, ParseError> {
     if args.len() < 3 {
-        log::warn!("/note/on requires at least 3 args: note_id, key, vel");
-        return None;
+        return Err(invalid("/note/on requires at least 3 args: note_id, key, vel"));
     }
 
-    let note_id = get_i32(&args[0])?;
-    let key = get_i32(&args[1])?;
-    let velocity = get_f32(&args[2])?;
+    let note_id = required(args, 0, get_i32, "/note/on", "note_id")?;
+    let key = required(args, 1, get_f64, "/note/on", "key")?;
+    if !key.is_finite() {
+        return Err(invalid("/note/on: key is not a number"));
+    }
+    // A fractional key plays on the nearest key, tuned the rest of the way.
+    let (key, tuning) = split_key(key);
+    let velocity = required(args, 2, get_f32, "/note/on", "vel")?;
     let channel = args.get(3).and_then(get_i32).unwrap_or(0);
     let port = args.get(4).and_then(get_i32).unwrap_or(0);
 
-    Some(Command::NoteOn {
+    Ok(Command::NoteOn {
         note_id,
         key,
         velocity,
         channel,
         port,
+        tuning,
     })
 }
 
-fn parse_note_off(args: &[OscType]) -> Option<Command> {
-    if args.len() < 3 {
-        log::warn!("/note/off requires at least 3 args: note_id, key, vel");
-        return None;
+fn parse_note_off(args: &[OscType]) -> Result<Command, ParseError> {
+    if args.len() < 2 {
+        return Err(invalid("/note/off requires at least 2 args: note_id, key"));
     }
 
-    let note_id = get_i32(&args[0])?;
-    let key = get_i32(&args[1])?;
-    let velocity = get_f32(&args[2])?;
+    let note_id = required(args, 0, get_i32, "/note/off", "note_id")?;
+    let key = required(args, 1, get_key, "/note/off", "key")?;
+    // Omitted and zero velocity both mean "unspecified"; the receiver may substitute a default.
+    let velocity = args.get(2).and_then(get_f32).unwrap_or(0.0);
     let channel = args.get(3).and_then(get_i32).unwrap_or(0);
     let port = args.get(4).and_then(get_i32).unwrap_or(0);
 
-    Some(Command::NoteOff {
+    Ok(Command::NoteOff {
         note_id,
         key,
         velocity,
@@ -167,60 +1465,272 @@ fn parse_note_off(args: &[OscType]) -> Option<Command> {
     })
 }
 
-fn parse_note_choke(args: &[OscType]) -> Option<Command> {
+fn parse_note_choke(args: &[OscType]) -> Result<Command, ParseError> {
     if args.is_empty() {
-        log::warn!("/note/choke requires at least 1 arg: note_id");
-        return None;
+        return Err(invalid("/note/choke requires at least 1 arg: note_id"));
     }
 
-    let note_id = get_i32(&args[0])?;
-    let key = args.get(1).and_then(get_i32).unwrap_or(-1);
+    let note_id = required(args, 0, get_i32, "/note/choke", "note_id")?;
+    let key = args.get(1).and_then(get_key).unwrap_or(-1);
     let channel = args.get(2).and_then(get_i32).unwrap_or(-1);
     let port = args.get(3).and_then(get_i32).unwrap_or(-1);
 
-    Some(Command::NoteChoke {
+    Ok(Command::NoteChoke {
+        note_id,
+        key,
+        channel,
+        port,
+    })
+}
+
+fn parse_notes_off(args: &[OscType]) -> Result<Command, ParseError> {
+    let channel = required(args, 0, get_i32, "/notes/off", "channel")?;
+    Ok(Command::AllNotesOff { channel, velocity: 0.0 })
+}
+
+/// Whether `addr` carries a 0..1 value that has to be mapped onto the
+/// parameter's range before it reaches the audio thread.
+pub fn is_normalized_set(addr: &str, normalized_params: bool) -> bool {
+    addr == "/param/setn"
+        || (normalized_params && matches!(addr, "/param/set" | "/param/setMany" | "/param/ramp" | "/param/inc"))
+}
+
+/// Maps a normalized `ParamSet` or `ParamRamp` target into plugin units, clamping to the range and
+/// snapping stepped params. A `ParamInc` delta is taken as a fraction of the range.
+pub fn denormalize<'a>(
+    cmd: &mut Command,
+    addr: &str,
+    param: impl Fn(u32) -> Option<&'a ParamInfo>,
+) -> Result<(), ParseError> {
+    if let Command::ParamSet { param_id, value } | Command::ParamRamp { param_id, target: value, .. } = cmd {
+        let info = param(*param_id).ok_or_else(|| invalid(format!("{}: unknown parameter id {}", addr, param_id)))?;
+        *value = info.normalized_to_value(*value);
+    }
+    if let Command::ParamInc { param_id, delta } = cmd {
+        let info = param(*param_id).ok_or_else(|| invalid(format!("{}: unknown parameter id {}", addr, param_id)))?;
+        *delta *= info.max_value - info.min_value;
+    }
+    if let Command::ParamSetMany { pairs } = cmd {
+        for (param_id, value) in pairs {
+            let info = param(*param_id).ok_or_else(|| invalid(format!("{}: unknown parameter id {}", addr, param_id)))?;
+            *value = info.normalized_to_value(*value);
+        }
+    }
+    Ok(())
+}
+
+/// Rejects parameter commands for ids the plugin didn't enumerate, and clamps
+/// set and ramp targets to the parameter's range, so mistakes are reported
+/// here instead of reaching the plugin as events it ignores.
+pub fn check_param<'a>(
+    cmd: &mut Command,
+    addr: &str,
+    param: impl Fn(u32) -> Option<&'a ParamInfo>,
+    verbose: bool,
+) -> Result<(), ParseError> {
+    let param_id = match cmd {
+        Command::ParamSetMany { pairs } => return check_param_pairs(pairs, addr, param, verbose),
+        Command::ParamSet { param_id, .. }
+        | Command::ParamRamp { param_id, .. }
+        | Command::ParamInc { param_id, .. }
+        | Command::ParamMod { param_id, .. } => *param_id,
+        _ => return Ok(()),
+    };
+    let info = param(param_id).ok_or_else(|| invalid(format!("{}: unknown parameter id {}", addr, param_id)))?;
+    if let Command::ParamSet { value, .. } | Command::ParamRamp { target: value, .. } = cmd {
+        clamp_value(addr, info, value, verbose)?;
+    }
+    Ok(())
+}
+
+/// `check_param` for every pair of a `/param/setMany`; one bad pair rejects
+/// the whole batch.
+fn check_param_pairs<'a>(
+    pairs: &mut [(u32, f64)],
+    addr: &str,
+    param: impl Fn(u32) -> Option<&'a ParamInfo>,
+    verbose: bool,
+) -> Result<(), ParseError> {
+    for (param_id, value) in pairs {
+        let info = param(*param_id).ok_or_else(|| invalid(format!("{}: unknown parameter id {}", addr, param_id)))?;
+        clamp_value(addr, info, value, verbose)?;
+    }
+    Ok(())
+}
+
+fn clamp_value(addr: &str, info: &ParamInfo, value: &mut f64, verbose: bool) -> Result<(), ParseError> {
+    if !value.is_finite() {
+        return Err(invalid(format!("{}: value for parameter {} is not a number", addr, info.id)));
+    }
+    let clamped = value.max(info.min_value).min(info.max_value);
+    if clamped != *value && verbose {
+        log::info!(
+            "[OSC-PARAM] {} {} = {} is outside {}..{}; clamped to {}",
+            addr,
+            info.id,
+            value,
+            info.min_value,
+            info.max_value,
+            clamped
+        );
+    }
+    *value = clamped;
+    Ok(())
+}
+
+fn parse_note_expression(args: &[OscType]) -> Result<Command, ParseError> {
+    if args.len() < 3 {
+        return Err(invalid("/note/expression requires at least 3 args: note_id, expression, value"));
+    }
+
+    let note_id = required(args, 0, get_i32, "/note/expression", "note_id")?;
+    let expression_id = match &args[1] {
+        OscType::String(name) => NOTE_EXPRESSIONS
+            .iter()
+            .position(|e| e.eq_ignore_ascii_case(name))
+            .map(|i| i as i32),
+        other => get_i32(other).filter(|&id| id >= 0 && (id as usize) < NOTE_EXPRESSIONS.len()),
+    }
+    .ok_or_else(|| {
+        invalid(format!(
+            "/note/expression: unknown expression {:?} (expected 0-{} or one of {})",
+            args[1],
+            NOTE_EXPRESSIONS.len() - 1,
+            NOTE_EXPRESSIONS.join(", ")
+        ))
+    })?;
+    let value = required(args, 2, get_f64, "/note/expression", "value")?;
+    let key = args.get(3).and_then(get_key).unwrap_or(-1);
+    let channel = args.get(4).and_then(get_i32).unwrap_or(-1);
+    let port = args.get(5).and_then(get_i32).unwrap_or(-1);
+
+    Ok(Command::NoteExpression {
+        expression_id,
         note_id,
         key,
         channel,
         port,
+        value,
     })
 }
 
-fn parse_param_set(args: &[OscType]) -> Option<Command> {
+/// CLAP's tuning expression is already in semitones, so the value is passed
+/// through as is, within the -120..120 the spec allows. A negative `note_id`
+/// bends every note matching the key, channel and port instead.
+fn parse_note_pitchbend(args: &[OscType]) -> Result<Command, ParseError> {
     if args.len() < 2 {
-        log::warn!("/param/set requires 2 args: param_id, value");
-        return None;
+        return Err(invalid("/note/pitchbend requires at least 2 args: note_id, semitones"));
+    }
+
+    let note_id = required(args, 0, get_i32, "/note/pitchbend", "note_id")?;
+    let semitones = required(args, 1, get_f64, "/note/pitchbend", "semitones")?;
+    if !(-MAX_TUNING..=MAX_TUNING).contains(&semitones) {
+        return Err(invalid(format!(
+            "/note/pitchbend: {} semitones is outside -{}..{}",
+            semitones, MAX_TUNING, MAX_TUNING
+        )));
+    }
+
+    Ok(Command::NoteExpression {
+        expression_id: EXPRESSION_TUNING,
+        note_id,
+        key: args.get(2).and_then(get_key).unwrap_or(-1),
+        channel: args.get(3).and_then(get_i32).unwrap_or(-1),
+        port: args.get(4).and_then(get_i32).unwrap_or(-1),
+        value: semitones,
+    })
+}
+
+fn parse_param_set(addr: &str, args: &[OscType]) -> Result<Command, ParseError> {
+    if args.len() < 2 {
+        return Err(invalid(format!("{} requires 2 args: param_id, value", addr)));
     }
 
-    let param_id = get_u32(&args[0])?;
-    let value = get_f64(&args[1])?;
+    let param_id = required(args, 0, get_u32, addr, "param_id")?;
+    let value = required(args, 1, get_f64, addr, "value")?;
 
-    Some(Command::ParamSet { param_id, value })
+    Ok(Command::ParamSet { param_id, value })
 }
 
-fn parse_param_mod(args: &[OscType], per_note_mod_params: &HashSet<u32>) -> Option<Command> {
+fn parse_param_set_many(args: &[OscType]) -> Result<Command, ParseError> {
+    if args.is_empty() || args.len() % 2 != 0 {
+        return Err(invalid(format!(
+            "/param/setMany requires param_id, value pairs; got {} args",
+            args.len()
+        )));
+    }
+    if args.len() / 2 > MAX_SET_MANY_PAIRS {
+        return Err(invalid(format!(
+            "/param/setMany takes at most {} pairs; got {}",
+            MAX_SET_MANY_PAIRS,
+            args.len() / 2
+        )));
+    }
+    let pairs = (0..args.len())
+        .step_by(2)
+        .map(|i| {
+            let param_id = required(args, i, get_u32, "/param/setMany", "param_id")?;
+            let value = required(args, i + 1, get_f64, "/param/setMany", "value")?;
+            Ok((param_id, value))
+        })
+        .collect::<Result<_, ParseError>>()?;
+    Ok(Command::ParamSetMany { pairs })
+}
+
+fn parse_param_ramp(args: &[OscType]) -> Result<Command, ParseError> {
     if args.len() < 3 {
-        log::warn!("/param/mod requires at least 3 args: note_id, param_id, amount");
-        return None;
+        return Err(invalid("/param/ramp requires 3 args: param_id, target, duration_ms"));
+    }
+
+    let param_id = required(args, 0, get_u32, "/param/ramp", "param_id")?;
+    let target = required(args, 1, get_f64, "/param/ramp", "target")?;
+    let duration_ms = required(args, 2, get_f64, "/param/ramp", "duration_ms")?;
+    if !target.is_finite() || !duration_ms.is_finite() {
+        return Err(invalid("/param/ramp: target and duration_ms must be numbers"));
+    }
+
+    Ok(Command::ParamRamp {
+        param_id,
+        target,
+        duration_ms,
+    })
+}
+
+fn parse_param_inc(args: &[OscType]) -> Result<Command, ParseError> {
+    if args.len() < 2 {
+        return Err(invalid("/param/inc requires 2 args: param_id, delta"));
+    }
+
+    let param_id = required(args, 0, get_u32, "/param/inc", "param_id")?;
+    let delta = required(args, 1, get_f64, "/param/inc", "delta")?;
+    if !delta.is_finite() {
+        return Err(invalid("/param/inc: delta is not a number"));
     }
 
-    let note_id = get_i32(&args[0])?;
-    let param_id = get_u32(&args[1])?;
-    let amount = get_f64(&args[2])?;
+    Ok(Command::ParamInc { param_id, delta })
+}
+
+fn parse_param_mod(args: &[OscType], per_note_mod_params: &HashSet<u32>) -> Result<Command, ParseError> {
+    if args.len() < 3 {
+        return Err(invalid("/param/mod requires at least 3 args: note_id, param_id, amount"));
+    }
+
+    let note_id = required(args, 0, get_i32, "/param/mod", "note_id")?;
+    let param_id = required(args, 1, get_u32, "/param/mod", "param_id")?;
+    let amount = required(args, 2, get_f64, "/param/mod", "amount")?;
 
     if !per_note_mod_params.contains(&param_id) {
-        log::warn!(
+        return Err(invalid(format!(
             "Parameter {} does not support per-note modulation, ignoring /param/mod",
             param_id
-        );
-        return None;
+        )));
     }
 
-    let key = args.get(3).and_then(get_i32).unwrap_or(-1);
+    let key = args.get(3).and_then(get_key).unwrap_or(-1);
     let channel = args.get(4).and_then(get_i32).unwrap_or(-1);
     let port = args.get(5).and_then(get_i32).unwrap_or(-1);
 
-    Some(Command::ParamMod {
+    Ok(Command::ParamMod {
         note_id,
         param_id,
         amount,
@@ -230,42 +1740,677 @@ fn parse_param_mod(args: &[OscType], per_note_mod_params: &HashSet<u32>) -> Opti
     })
 }
 
-fn get_i32(arg: &OscType) -> Option<i32> {
+fn invalid(message: impl Into<String>) -> ParseError {
+    ParseError::Invalid(message.into())
+}
+
+fn required<T>(
+    args: &[OscType],
+    index: usize,
+    get: fn(&OscType) -> Option<T>,
+    addr: &str,
+    name: &str,
+) -> Result<T, ParseError> {
+    match args.get(index) {
+        Some(OscType::Nil) => Err(invalid(format!(
+            "{}: argument {} ({}) is nil and has no default",
+            addr, index, name
+        ))),
+        arg => arg
+            .and_then(get)
+            .ok_or_else(|| invalid(format!("{}: argument {} ({}) has the wrong type", addr, index, name))),
+    }
+}
+
+// The getters coerce loosely typed clients: booleans become 1/0 and chars
+// their code point for integer arguments. Nil yields `None`, so optional
+// arguments fall back to their default.
+
+pub fn get_i32(arg: &OscType) -> Option<i32> {
     match arg {
         OscType::Int(v) => Some(*v),
         OscType::Long(v) => Some(*v as i32),
         OscType::Float(v) => Some(*v as i32),
         OscType::Double(v) => Some(*v as i32),
+        OscType::Bool(v) => Some(*v as i32),
+        OscType::Char(v) => Some(*v as i32),
         _ => None,
     }
 }
 
-fn get_u32(arg: &OscType) -> Option<u32> {
+/// A key argument, rounded the way `/note/on` rounds fractional keys, so a
+/// note-off with the same key finds the note.
+fn get_key(arg: &OscType) -> Option<i32> {
+    get_f64(arg).filter(|key| key.is_finite()).map(|key| split_key(key).0)
+}
+
+pub fn get_u32(arg: &OscType) -> Option<u32> {
     match arg {
         OscType::Int(v) => Some(*v as u32),
         OscType::Long(v) => Some(*v as u32),
         OscType::Float(v) => Some(*v as u32),
         OscType::Double(v) => Some(*v as u32),
+        OscType::Bool(v) => Some(*v as u32),
+        OscType::Char(v) => Some(*v as u32),
         _ => None,
     }
 }
 
-fn get_f32(arg: &OscType) -> Option<f32> {
+pub fn get_f32(arg: &OscType) -> Option<f32> {
     match arg {
         OscType::Float(v) => Some(*v),
         OscType::Double(v) => Some(*v as f32),
         OscType::Int(v) => Some(*v as f32),
         OscType::Long(v) => Some(*v as f32),
+        OscType::Bool(v) => Some(if *v { 1.0 } else { 0.0 }),
         _ => None,
     }
 }
 
-fn get_f64(arg: &OscType) -> Option<f64> {
+pub fn get_f64(arg: &OscType) -> Option<f64> {
     match arg {
         OscType::Double(v) => Some(*v),
         OscType::Float(v) => Some(*v as f64),
         OscType::Int(v) => Some(*v as f64),
         OscType::Long(v) => Some(*v as f64),
+        OscType::Bool(v) => Some(if *v { 1.0 } else { 0.0 }),
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc_tcp::{accept_clients, frame};
+    use crate::testing::{detached_receiver, encode_message, next_command, param_info, RecordingSocket};
+    use std::collections::VecDeque;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::AtomicUsize;
+
+    /// Plays back a fixed list of reads, then times out like an idle socket.
+    struct ScriptedSocket {
+        script: Mutex<VecDeque<io::Result<Vec<u8>>>>,
+    }
+
+    impl ScriptedSocket {
+        fn new(script: Vec<io::Result<Vec<u8>>>) -> Self {
+            Self {
+                script: Mutex::new(script.into()),
+            }
+        }
+    }
+
+    impl OscSend for ScriptedSocket {
+        fn send_to(&self, buf: &[u8], _addr: SocketAddr) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+    }
+
+    impl OscSocket for ScriptedSocket {
+        fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            let next = self.script.lock().unwrap().pop_front();
+            match next {
+                Some(Ok(packet)) => {
+                    // Cut short to the buffer, as a UDP read is.
+                    let size = packet.len().min(buf.len());
+                    buf[..size].copy_from_slice(&packet[..size]);
+                    Ok((size, "127.0.0.1:9000".parse().unwrap()))
+                }
+                Some(Err(e)) => Err(e),
+                None => {
+                    thread::sleep(Duration::from_millis(1));
+                    Err(io::ErrorKind::TimedOut.into())
+                }
+            }
+        }
+    }
+
+    fn note_on() -> Vec<u8> {
+        encode_message("/note/on", vec![OscType::Int(1), OscType::Int(60), OscType::Float(0.8)])
+    }
+
+    fn note_message(note_id: i32) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: "/note/on".to_string(),
+            args: vec![OscType::Int(note_id), OscType::Int(60), OscType::Float(0.8)],
+        })
+    }
+
+    fn bundle(timetag: OscTime, content: Vec<OscPacket>) -> OscPacket {
+        OscPacket::Bundle(rosc::OscBundle { timetag, content })
+    }
+
+    const IMMEDIATELY: OscTime = OscTime {
+        seconds: 0,
+        fractional: 1,
+    };
+
+    fn process(state: &mut OscReceiverState, packet: &OscPacket) {
+        let socket = ScriptedSocket::new(Vec::new());
+        let reply = Reply {
+            socket: &socket,
+            addr: "127.0.0.1:9000".parse().unwrap(),
+        };
+        process_packet(packet, state, &reply, None);
+        state.queue.flush();
+    }
+
+    fn timed_receiver() -> (OscReceiverState, rtrb::Consumer<ScheduledCommand>) {
+        let (state, consumer) = detached_receiver(16);
+        state.runtime_config.write().unwrap().sample_rate = 48000;
+        state.sample_position.store(1_000_000, Ordering::Relaxed);
+        (state, consumer)
+    }
+
+    #[test]
+    fn bundle_timetags_become_sample_times() {
+        let (mut state, mut consumer) = timed_receiver();
+        let now = SystemTime::now();
+        let at = |secs: f64| OscTime::try_from(now + Duration::from_secs_f64(secs)).unwrap();
+        // Two timed bundles in one packet, as a sequencer sends a phrase.
+        let packet = bundle(
+            IMMEDIATELY,
+            vec![bundle(at(0.5), vec![note_message(1)]), bundle(at(0.6), vec![note_message(2)])],
+        );
+        process(&mut state, &packet);
+
+        let first = consumer.pop().unwrap().sample_time.expect("timed");
+        let second = consumer.pop().unwrap().sample_time.expect("timed");
+        // Wall-clock time passes while the test runs; allow 10ms of it.
+        assert!(first.abs_diff(1_000_000 + 24_000) < 480, "first at {}", first);
+        assert!((second - first).abs_diff(4_800) < 480, "second at {}", second);
+    }
+
+    #[test]
+    fn immediate_and_past_bundles() {
+        let (mut state, mut consumer) = timed_receiver();
+        process(&mut state, &bundle(IMMEDIATELY, vec![note_message(1)]));
+        assert_eq!(consumer.pop().unwrap().sample_time, None);
+
+        let past = OscTime::try_from(SystemTime::now() - Duration::from_secs(1)).unwrap();
+        process(&mut state, &bundle(past, vec![note_message(2)]));
+        assert_eq!(consumer.pop().unwrap().sample_time, Some(1_000_000));
+    }
+
+    #[test]
+    fn cue_relative_bundles_count_from_the_cue() {
+        let (mut state, mut consumer) = timed_receiver();
+        state.cues.insert("verse".to_string(), 5_000);
+        let cue = OscPacket::Message(OscMessage {
+            addr: "/cue/relative".to_string(),
+            args: vec![OscType::String("verse".to_string())],
+        });
+        let one_and_a_half = OscTime {
+            seconds: 1,
+            fractional: 1 << 31,
+        };
+        process(&mut state, &bundle(one_and_a_half, vec![cue, note_message(1)]));
+        assert_eq!(consumer.pop().unwrap().sample_time, Some(5_000 + 72_000));
+    }
+
+    fn parse(addr: &str, args: Vec<OscType>) -> Result<Command, ParseError> {
+        let msg = OscMessage {
+            addr: addr.to_string(),
+            args,
+        };
+        parse_message(&msg, &HashSet::new())
+    }
+
+    #[test]
+    fn parses_every_note_expression() {
+        for (id, name) in NOTE_EXPRESSIONS.iter().enumerate() {
+            let by_id = parse("/note/expression", vec![OscType::Int(7), OscType::Int(id as i32), OscType::Float(0.5)]);
+            let by_name = parse(
+                "/note/expr",
+                vec![OscType::Int(7), OscType::String(name.to_uppercase()), OscType::Double(0.5)],
+            );
+            for cmd in [by_id, by_name] {
+                match cmd {
+                    Ok(Command::NoteExpression {
+                        expression_id,
+                        note_id: 7,
+                        key: -1,
+                        channel: -1,
+                        port: -1,
+                        value,
+                    }) => {
+                        assert_eq!(expression_id, id as i32, "{}", name);
+                        assert_eq!(value, 0.5);
+                    }
+                    other => panic!("{}: {:?}", name, other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn note_expression_takes_key_channel_and_port() {
+        let cmd = parse(
+            "/note/expression",
+            vec![
+                OscType::Int(-1),
+                OscType::String("pressure".to_string()),
+                OscType::Float(1.0),
+                OscType::Float(61.6),
+                OscType::Int(2),
+                OscType::Int(0),
+            ],
+        );
+        assert!(matches!(
+            cmd,
+            Ok(Command::NoteExpression {
+                expression_id: 6,
+                note_id: -1,
+                key: 62,
+                channel: 2,
+                port: 0,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_note_expressions() {
+        let expressions = [
+            OscType::Int(NOTE_EXPRESSIONS.len() as i32),
+            OscType::Int(-1),
+            OscType::String("wobble".to_string()),
+        ];
+        for expression in expressions {
+            let cmd = parse("/note/expression", vec![OscType::Int(1), expression, OscType::Float(0.5)]);
+            assert!(matches!(cmd, Err(ParseError::Invalid(_))), "{:?}", cmd);
+        }
+        assert!(parse("/note/expression", vec![OscType::Int(1), OscType::Int(0)]).is_err());
+    }
+
+    fn message(addr: &str, args: Vec<OscType>) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args,
+        })
+    }
+
+    /// A receiver for a plugin with a 0..1 cutoff (1) and a -12..12 tune (2).
+    fn param_receiver() -> (OscReceiverState, rtrb::Consumer<ScheduledCommand>) {
+        let (mut state, consumer) = detached_receiver(16);
+        for info in [param_info(1, "Cutoff", 0.0, 1.0), param_info(2, "Tune", -12.0, 12.0)] {
+            state.params.insert(info.id, info);
+        }
+        (state, consumer)
+    }
+
+    #[test]
+    fn unknown_param_ids_are_rejected_before_the_queue() {
+        let (mut state, mut consumer) = param_receiver();
+        process(&mut state, &message("/param/set", vec![OscType::Int(99), OscType::Float(0.5)]));
+        process(&mut state, &message("/param/inc", vec![OscType::Int(99), OscType::Float(0.1)]));
+        let ramp = vec![OscType::Int(99), OscType::Float(0.5), OscType::Float(50.0)];
+        process(&mut state, &message("/param/ramp", ramp));
+        // One bad pair rejects the whole batch.
+        let batch = vec![OscType::Int(1), OscType::Float(0.5), OscType::Int(99), OscType::Float(0.5)];
+        process(&mut state, &message("/param/setMany", batch));
+        assert!(consumer.pop().is_err());
+
+        process(&mut state, &message("/param/set", vec![OscType::Int(1), OscType::Float(0.5)]));
+        assert!(matches!(consumer.pop().unwrap().command, Command::ParamSet { param_id: 1, value } if value == 0.5));
+    }
+
+    #[test]
+    fn param_values_are_clamped_to_their_range() {
+        let (mut state, mut consumer) = param_receiver();
+        process(&mut state, &message("/param/set", vec![OscType::Int(1), OscType::Float(1.5)]));
+        process(&mut state, &message("/param/ramp", vec![OscType::Int(2), OscType::Float(-20.0), OscType::Float(50.0)]));
+        let batch = vec![OscType::Int(1), OscType::Float(-0.5), OscType::Int(2), OscType::Float(6.0)];
+        process(&mut state, &message("/param/setMany", batch));
+        process(&mut state, &message("/param/set", vec![OscType::Int(2), OscType::Double(f64::NAN)]));
+
+        let commands: Vec<Command> = std::iter::from_fn(|| consumer.pop().ok()).map(|s| s.command).collect();
+        assert_eq!(commands.len(), 3, "{:?}", commands);
+        assert!(matches!(commands[0], Command::ParamSet { param_id: 1, value } if value == 1.0));
+        assert!(matches!(commands[1], Command::ParamRamp { param_id: 2, target, .. } if target == -12.0));
+        match &commands[2] {
+            Command::ParamSetMany { pairs } => assert_eq!(pairs, &vec![(1, 0.0), (2, 6.0)]),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn numeric_getters_coerce_bools_and_chars() {
+        assert_eq!(get_i32(&OscType::Bool(true)), Some(1));
+        assert_eq!(get_i32(&OscType::Bool(false)), Some(0));
+        assert_eq!(get_i32(&OscType::Char('A')), Some(65));
+        assert_eq!(get_u32(&OscType::Bool(true)), Some(1));
+        assert_eq!(get_u32(&OscType::Char('a')), Some(97));
+        assert_eq!(get_f32(&OscType::Bool(true)), Some(1.0));
+        assert_eq!(get_f64(&OscType::Bool(false)), Some(0.0));
+        // A char is a code, not an amount.
+        assert_eq!(get_f32(&OscType::Char('A')), None);
+        assert_eq!(get_f64(&OscType::Char('A')), None);
+        assert_eq!(get_i32(&OscType::Nil), None);
+        assert_eq!(get_u32(&OscType::Nil), None);
+        assert_eq!(get_f64(&OscType::Nil), None);
+    }
+
+    #[test]
+    fn nil_takes_the_default_of_optional_arguments_only() {
+        let cmd = parse(
+            "/note/on",
+            vec![OscType::Int(1), OscType::Int(60), OscType::Float(0.8), OscType::Nil, OscType::Int(1)],
+        );
+        assert!(matches!(cmd, Ok(Command::NoteOn { channel: 0, port: 1, .. })), "{:?}", cmd);
+
+        match parse("/note/on", vec![OscType::Int(1), OscType::Nil, OscType::Float(0.8)]) {
+            Err(ParseError::Invalid(message)) => assert!(message.contains("is nil"), "{}", message),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bools_toggle_a_stepped_parameter() {
+        let (mut state, mut consumer) = detached_receiver(16);
+        let mut bypass = param_info(5, "Bypass", 0.0, 1.0);
+        bypass.is_stepped = true;
+        state.params.insert(bypass.id, bypass);
+
+        for on in [true, false] {
+            process(&mut state, &message("/param/set", vec![OscType::Int(5), OscType::Bool(on)]));
+            let cmd = consumer.pop().unwrap().command;
+            let expected = if on { 1.0 } else { 0.0 };
+            assert!(matches!(cmd, Command::ParamSet { param_id: 5, value } if value == expected), "{:?}", cmd);
+        }
+    }
+
+    #[test]
+    fn pitchbends_are_checked_and_routed() {
+        for semitones in [-120.5, 121.0, f64::NAN] {
+            let cmd = parse("/note/pitchbend", vec![OscType::Int(1), OscType::Double(semitones)]);
+            assert!(matches!(cmd, Err(ParseError::Invalid(_))), "{}: {:?}", semitones, cmd);
+        }
+        let cmd = parse("/note/pitchbend", vec![OscType::Int(1), OscType::Double(-120.0)]);
+        assert!(matches!(cmd, Ok(Command::NoteExpression { value, .. }) if value == -120.0), "{:?}", cmd);
+
+        let cmd = parse("/pitchbend", vec![OscType::Int(2), OscType::Float(-0.5)]);
+        assert!(matches!(cmd, Ok(Command::ChannelBend { channel: 2, semitones }) if semitones == -0.5), "{:?}", cmd);
+    }
+
+    #[test]
+    fn transport_position_is_an_alias_for_seek() {
+        for addr in ["/transport/seek", "/transport/position"] {
+            let cmd = parse(addr, vec![OscType::Float(8.5)]);
+            assert!(
+                matches!(cmd, Ok(Command::Transport(TransportChange::Seek(beats))) if beats == 8.5),
+                "{}: {:?}",
+                addr,
+                cmd
+            );
+            let cmd = parse(addr, vec![OscType::Float(-1.0)]);
+            assert!(matches!(cmd, Err(ParseError::Invalid(_))), "{}: {:?}", addr, cmd);
+        }
+    }
+
+    #[test]
+    fn param_set_many_parses_pairs() {
+        let args = vec![OscType::Int(1), OscType::Float(0.5), OscType::Long(7), OscType::Double(-3.0)];
+        match parse("/param/setMany", args) {
+            Ok(Command::ParamSetMany { pairs }) => assert_eq!(pairs, vec![(1, 0.5), (7, -3.0)]),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn param_set_many_is_limited_in_size() {
+        let pairs = |count: usize| (0..count).flat_map(|i| [OscType::Int(i as i32), OscType::Float(0.5)]).collect();
+        assert!(parse("/param/setMany", pairs(MAX_SET_MANY_PAIRS)).is_ok());
+        let cmd = parse("/param/setMany", pairs(MAX_SET_MANY_PAIRS + 1));
+        assert!(matches!(cmd, Err(ParseError::Invalid(_))), "{:?}", cmd);
+    }
+
+    #[test]
+    fn param_set_many_rejects_malformed_pairs() {
+        let malformed = [
+            vec![],
+            vec![OscType::Int(1)],
+            vec![OscType::Int(1), OscType::Float(0.5), OscType::Int(2)],
+            vec![OscType::String("cutoff".to_string()), OscType::Float(0.5)],
+            vec![OscType::Int(1), OscType::String("half".to_string())],
+        ];
+        for args in malformed {
+            let cmd = parse("/param/setMany", args.clone());
+            assert!(matches!(cmd, Err(ParseError::Invalid(_))), "{:?}: {:?}", args, cmd);
+        }
+    }
+
+    #[test]
+    fn param_inc_and_ramp_reject_non_finite_values() {
+        let cmd = parse("/param/inc", vec![OscType::Int(3), OscType::Double(-0.25)]);
+        assert!(matches!(cmd, Ok(Command::ParamInc { param_id: 3, delta }) if delta == -0.25));
+
+        for bad in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let cmd = parse("/param/inc", vec![OscType::Int(3), OscType::Double(bad)]);
+            assert!(matches!(cmd, Err(ParseError::Invalid(_))), "{:?}", cmd);
+            let cmd = parse("/param/ramp", vec![OscType::Int(3), OscType::Double(bad), OscType::Float(100.0)]);
+            assert!(matches!(cmd, Err(ParseError::Invalid(_))), "{:?}", cmd);
+            let cmd = parse("/param/ramp", vec![OscType::Int(3), OscType::Float(0.5), OscType::Double(bad)]);
+            assert!(matches!(cmd, Err(ParseError::Invalid(_))), "{:?}", cmd);
+        }
+    }
+
+    /// The replies to `/params/list` from a plugin with `count` parameters.
+    fn list_params(count: usize, socket: &RecordingSocket) -> Vec<OscMessage> {
+        let (mut state, _consumer) = detached_receiver(16);
+        // Inserted in reverse so the listing has to sort them.
+        state.params = (0..count as u32).rev().map(|id| (id, param_info(id, &format!("P{}", id), 0.0, 1.0))).collect();
+        let reply = Reply {
+            socket,
+            addr: "127.0.0.1:9000".parse().unwrap(),
+        };
+        let msg = OscMessage {
+            addr: "/params/list".to_string(),
+            args: Vec::new(),
+        };
+        state.handle_message(&msg, Some(&reply)).unwrap();
+        socket.messages()
+    }
+
+    #[test]
+    fn long_param_lists_are_sent_whole_in_paced_bursts() {
+        let count = PARAM_LIST_BURST * 3 + 5;
+        let socket = RecordingSocket::default();
+        let replies = list_params(count, &socket);
+
+        assert_eq!(replies.len(), count + 1);
+        for (id, entry) in replies[..count].iter().enumerate() {
+            assert_eq!(entry.addr, "/params/entry");
+            assert_eq!(entry.args[0], OscType::Int(id as i32));
+            assert_eq!(entry.args[1], OscType::String(format!("P{}", id)));
+        }
+        assert_eq!(replies[count].addr, "/params/end");
+        assert_eq!(replies[count].args, vec![OscType::Int(count as i32)]);
+
+        // Each burst after the first waits out the pause.
+        let times = socket.send_times();
+        for burst in 1..=3 {
+            let first = burst * PARAM_LIST_BURST;
+            assert!(times[first] - times[first - 1] >= PARAM_LIST_PAUSE, "no pause before entry {}", first);
+        }
+    }
+
+    #[test]
+    fn param_lists_over_a_stream_are_complete() {
+        let count = PARAM_LIST_BURST * 3 + 5;
+        let replies = list_params(count, &RecordingSocket::stream());
+
+        assert_eq!(replies.len(), count + 1);
+        assert!(replies[..count].iter().all(|entry| entry.addr == "/params/entry"));
+        assert_eq!(replies[count].args, vec![OscType::Int(count as i32)]);
+    }
+
+    #[test]
+    fn preset_load_is_handed_to_the_main_thread() {
+        let (producer, _consumer) = create_command_queue(16);
+        let (main_sender, main_receiver) = crossbeam_channel::unbounded();
+        let mut state = OscReceiverState::detached(SheddingQueue::new(producer, false), main_sender);
+        let socket = RecordingSocket::default();
+        let reply = Reply {
+            socket: &socket,
+            addr: "127.0.0.1:9001".parse().unwrap(),
+        };
+        let message = |args: Vec<OscType>| OscMessage {
+            addr: "/preset/load".to_string(),
+            args,
+        };
+
+        let args = vec![OscType::String("plugin:".to_string()), OscType::String("bass".to_string())];
+        state.handle_message(&message(args), Some(&reply)).unwrap();
+        match main_receiver.try_recv() {
+            Ok(MainThreadMessage::LoadPreset {
+                location_uri,
+                load_key,
+                reply_to,
+            }) => {
+                assert_eq!(location_uri, "plugin:");
+                assert_eq!(load_key.as_deref(), Some("bass"));
+                assert_eq!(reply_to, Some(reply.addr));
+            }
+            _ => panic!("expected a LoadPreset message"),
+        }
+
+        for args in [vec![], vec![OscType::Int(1)], vec![OscType::String("plugin:".to_string()), OscType::Int(2)]] {
+            let cmd = state.handle_message(&message(args), None);
+            assert!(matches!(cmd, Err(ParseError::Invalid(_))), "{:?}", cmd);
+        }
+        assert!(main_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn classifies_receive_errors() {
+        use RecvErrorClass::*;
+        let class = |e: io::Error| classify_recv_error(&e);
+        assert_eq!(class(io::ErrorKind::WouldBlock.into()), Idle);
+        assert_eq!(class(io::ErrorKind::TimedOut.into()), Idle);
+        assert_eq!(class(io::ErrorKind::ConnectionReset.into()), Transient);
+        assert_eq!(class(io::ErrorKind::ConnectionRefused.into()), Transient);
+        assert_eq!(class(io::ErrorKind::NotConnected.into()), Fatal);
+        assert_eq!(class(io::ErrorKind::BrokenPipe.into()), Fatal);
+        assert_eq!(class(io::Error::from_raw_os_error(9)), Fatal);
+        assert_eq!(class(io::Error::from_raw_os_error(10038)), Fatal);
+        assert_eq!(class(io::Error::from_raw_os_error(10040)), TooLarge);
+    }
+
+    #[test]
+    fn fatal_error_rebinds_with_backoff() {
+        let (state, mut consumer) = detached_receiver(64);
+        let socket = ScriptedSocket::new(vec![Err(io::Error::from_raw_os_error(9))]);
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let rebind = {
+            let attempts = attempts.clone();
+            move || {
+                // The first rebind fails, so the second waits out the backoff.
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err(io::Error::from(io::ErrorKind::AddrInUse))
+                } else {
+                    Ok(ScriptedSocket::new(vec![Ok(note_on())]))
+                }
+            }
+        };
+
+        let started = Instant::now();
+        thread::spawn(move || run_receiver(socket, rebind, None, DEFAULT_RECV_BUFFER_SIZE, state));
+        let scheduled = next_command(&mut consumer, Duration::from_secs(5)).expect("note from the rebound socket");
+
+        assert!(matches!(scheduled.command, Command::NoteOn { key: 60, .. }));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn transient_errors_keep_the_socket() {
+        let (state, mut consumer) = detached_receiver(64);
+        let mut script: Vec<io::Result<Vec<u8>>> = (0..TRANSIENT_STORM_THRESHOLD + 10)
+            .map(|_| Err(io::ErrorKind::ConnectionReset.into()))
+            .collect();
+        script.push(Ok(note_on()));
+        let socket = ScriptedSocket::new(script);
+        let rebinds = Arc::new(AtomicUsize::new(0));
+        let rebind = {
+            let rebinds = rebinds.clone();
+            move || -> io::Result<ScriptedSocket> {
+                rebinds.fetch_add(1, Ordering::SeqCst);
+                Ok(ScriptedSocket::new(Vec::new()))
+            }
+        };
+
+        thread::spawn(move || run_receiver(socket, rebind, None, DEFAULT_RECV_BUFFER_SIZE, state));
+        let scheduled = next_command(&mut consumer, Duration::from_secs(5)).expect("note after the error storm");
+
+        assert!(matches!(scheduled.command, Command::NoteOn { key: 60, .. }));
+        assert_eq!(rebinds.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn tcp_packets_are_enqueued() {
+        for framing in [TcpFraming::Slip, TcpFraming::Length] {
+            let (state, mut consumer) = detached_receiver(64);
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let tcp = accept_clients(listener, framing).unwrap();
+            let socket = ScriptedSocket::new(Vec::new());
+            let rebind = || -> io::Result<ScriptedSocket> { unreachable!("the idle UDP socket doesn't fail") };
+            thread::spawn(move || run_receiver(socket, rebind, Some(tcp), DEFAULT_RECV_BUFFER_SIZE, state));
+
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(&frame(&note_on(), framing)).unwrap();
+
+            let scheduled = next_command(&mut consumer, Duration::from_secs(5)).expect("note from the TCP client");
+            assert!(matches!(scheduled.command, Command::NoteOn { key: 60, .. }), "{:?}", framing);
+        }
+    }
+
+    #[test]
+    fn ephemeral_port_on_all_interfaces_receives() {
+        let (state, mut consumer) = detached_receiver(64);
+        let bind = "0.0.0.0".parse().unwrap();
+        let (_handle, local_addr) = start_osc_receiver(bind, 0, None, DEFAULT_RECV_BUFFER_SIZE, state).unwrap();
+        assert_ne!(local_addr.port(), 0);
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(&note_on(), ("127.0.0.1", local_addr.port())).unwrap();
+        let scheduled = next_command(&mut consumer, Duration::from_secs(5)).expect("note over loopback");
+
+        assert!(matches!(scheduled.command, Command::NoteOn { key: 60, .. }));
+    }
+
+    #[test]
+    fn oversized_packet_is_dropped_whole() {
+        let (state, mut consumer) = detached_receiver(64);
+        let packet = note_on();
+        let buffer_size = packet.len() - 1;
+        let socket = ScriptedSocket::new(vec![Ok(packet)]);
+        let rebind = || -> io::Result<ScriptedSocket> { unreachable!("an oversized packet is not a socket failure") };
+
+        thread::spawn(move || run_receiver(socket, rebind, None, buffer_size, state));
+
+        assert!(next_command(&mut consumer, Duration::from_millis(200)).is_none());
+    }
+
+    #[test]
+    fn bundles_past_the_buffer_size_need_a_bigger_buffer() {
+        let notes = 150;
+        let packet = rosc::encoder::encode(&bundle(IMMEDIATELY, (0..notes).map(note_message).collect())).unwrap();
+        assert!(packet.len() > 4096, "{} bytes", packet.len());
+
+        for (buffer_size, expected) in [(4096, 0), (DEFAULT_RECV_BUFFER_SIZE, notes)] {
+            let (state, mut consumer) = detached_receiver(256);
+            let socket = ScriptedSocket::new(vec![Ok(packet.clone())]);
+            let rebind = || -> io::Result<ScriptedSocket> { unreachable!("a dropped packet is not a socket failure") };
+            thread::spawn(move || run_receiver(socket, rebind, None, buffer_size, state));
+
+            let mut received = 0;
+            while next_command(&mut consumer, Duration::from_millis(200)).is_some() {
+                received += 1;
+            }
+            assert_eq!(received, expected, "with a {}-byte buffer", buffer_size);
+        }
+    }
+}
@@ -0,0 +1,478 @@
+//! Custom OSC addresses (`--osc-map mappings.json`), so a client can keep its
+//! own address conventions. Each entry maps an address and its argument
+//! layout onto one of the host's commands:
+//!
+//! ```json
+//! {
+//!   "/n": { "cmd": "NoteOn", "args": ["key", "velocity"] },
+//!   "/kick": { "cmd": "NoteOn", "args": ["velocity"], "fixed": { "key": 36, "channel": 9 } },
+//!   "/fx/cutoff": { "cmd": "ParamSet", "args": ["value"], "fixed": { "param_id": 12 }, "plugin": 1 }
+//! }
+//! ```
+//!
+//! A mapped message is rewritten to the built-in address it stands for before
+//! anything else looks at it, so it gets the same parsing, normalization and
+//! range checks. Mapped addresses win over built-in ones and aliases;
+//! unmapped addresses are routed as usual.
+
+use crate::osc::{split_plugin_address, BUILTIN_ADDRESSES};
+use anyhow::{anyhow, bail, Context, Result};
+use rosc::{OscMessage, OscType};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// One argument of a built-in address.
+struct Field {
+    name: &'static str,
+    /// Sent when the mapping leaves the field out or the message is too
+    /// short. Fields without one must be mapped or fixed.
+    default: Option<i32>,
+}
+
+const fn required(name: &'static str) -> Field {
+    Field { name, default: None }
+}
+
+const fn optional(name: &'static str, default: i32) -> Field {
+    Field {
+        name,
+        default: Some(default),
+    }
+}
+
+/// A command a mapping can target, with the built-in address it is rewritten
+/// to and that address's arguments in order.
+struct Target {
+    cmd: &'static str,
+    address: &'static str,
+    fields: &'static [Field],
+}
+
+const TARGETS: &[Target] = &[
+    Target {
+        cmd: "NoteOn",
+        address: "/note/on",
+        fields: &[
+            optional("note_id", -1),
+            required("key"),
+            required("velocity"),
+            optional("channel", 0),
+            optional("port", 0),
+        ],
+    },
+    Target {
+        cmd: "NoteOff",
+        address: "/note/off",
+        fields: &[
+            optional("note_id", -1),
+            required("key"),
+            optional("velocity", 0),
+            optional("channel", 0),
+            optional("port", 0),
+        ],
+    },
+    Target {
+        cmd: "NoteChoke",
+        address: "/note/choke",
+        fields: &[
+            optional("note_id", -1),
+            optional("key", -1),
+            optional("channel", -1),
+            optional("port", -1),
+        ],
+    },
+    Target {
+        cmd: "AllNotesOff",
+        address: "/notes/off",
+        fields: &[optional("channel", -1)],
+    },
+    Target {
+        cmd: "NoteExpression",
+        address: "/note/expression",
+        fields: &[
+            optional("note_id", -1),
+            required("expression"),
+            required("value"),
+            optional("key", -1),
+            optional("channel", -1),
+            optional("port", -1),
+        ],
+    },
+    Target {
+        cmd: "NotePitchBend",
+        address: "/note/pitchbend",
+        fields: &[
+            optional("note_id", -1),
+            required("semitones"),
+            optional("key", -1),
+            optional("channel", -1),
+            optional("port", -1),
+        ],
+    },
+    Target {
+        cmd: "ParamSet",
+        address: "/param/set",
+        fields: &[required("param_id"), required("value")],
+    },
+    Target {
+        cmd: "ParamMod",
+        address: "/param/mod",
+        fields: &[
+            optional("note_id", -1),
+            required("param_id"),
+            required("amount"),
+            optional("key", -1),
+            optional("channel", -1),
+            optional("port", -1),
+        ],
+    },
+    Target {
+        cmd: "ParamRamp",
+        address: "/param/ramp",
+        fields: &[required("param_id"), required("target"), required("duration_ms")],
+    },
+    Target {
+        cmd: "ParamInc",
+        address: "/param/inc",
+        fields: &[required("param_id"), required("delta")],
+    },
+    Target {
+        cmd: "Sustain",
+        address: "/pedal/sustain",
+        fields: &[optional("channel", -1), required("down")],
+    },
+    Target {
+        cmd: "ChannelBend",
+        address: "/channel/bend",
+        fields: &[optional("channel", 0), required("semitones")],
+    },
+    Target {
+        cmd: "Panic",
+        address: "/panic",
+        fields: &[],
+    },
+    Target {
+        cmd: "Play",
+        address: "/transport/play",
+        fields: &[],
+    },
+    Target {
+        cmd: "Stop",
+        address: "/transport/stop",
+        fields: &[],
+    },
+    Target {
+        cmd: "Tempo",
+        address: "/transport/tempo",
+        fields: &[required("bpm")],
+    },
+    Target {
+        cmd: "Seek",
+        address: "/transport/seek",
+        fields: &[required("beats")],
+    },
+    Target {
+        cmd: "Gain",
+        address: "/host/gain",
+        fields: &[required("db")],
+    },
+    Target {
+        cmd: "Mute",
+        address: "/host/mute",
+        fields: &[required("on")],
+    },
+];
+
+/// Where the value of one built-in argument comes from.
+enum Source {
+    /// The incoming message's argument at this index, or the default if it
+    /// is missing.
+    Arg(usize, Option<i32>),
+    Fixed(OscType),
+    Default(i32),
+}
+
+struct Mapping {
+    /// The built-in address, with a `/plugin/<index>` prefix if the mapping
+    /// targets a later plugin in the chain.
+    address: String,
+    sources: Vec<(&'static str, Source)>,
+}
+
+/// Custom addresses loaded from `--osc-map`, applied on the OSC thread.
+#[derive(Default)]
+pub struct OscMap {
+    mappings: BTreeMap<String, Mapping>,
+}
+
+impl OscMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read OSC map {}", path.display()))?;
+        Self::parse(&text, &path.display().to_string())
+    }
+
+    /// Parses the JSON of an OSC map, naming it `source` in errors.
+    fn parse(text: &str, source: &str) -> Result<Self> {
+        let json: Value = serde_json::from_str(text).with_context(|| format!("Failed to parse OSC map {}", source))?;
+        let Value::Object(entries) = json else {
+            bail!("{}: expected a JSON object of address mappings", source);
+        };
+
+        let mut mappings = BTreeMap::new();
+        for (address, entry) in entries {
+            let mapping = parse_mapping(&address, &entry).map_err(|e| anyhow!("{}: {}: {}", source, address, e))?;
+            mappings.insert(address, mapping);
+        }
+        Ok(Self { mappings })
+    }
+
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// Mapped addresses that hide a built-in one.
+    pub fn overrides(&self) -> impl Iterator<Item = &str> {
+        self.mappings
+            .keys()
+            .map(String::as_str)
+            .filter(|address| BUILTIN_ADDRESSES.contains(address))
+    }
+
+    /// The built-in message a mapped address stands for, or `None` if the
+    /// address isn't mapped.
+    pub fn rewrite(&self, msg: &OscMessage) -> Result<Option<OscMessage>, String> {
+        let Some(mapping) = self.mappings.get(&msg.addr) else {
+            return Ok(None);
+        };
+        let args = mapping
+            .sources
+            .iter()
+            .map(|(name, source)| match source {
+                Source::Arg(index, default) => msg
+                    .args
+                    .get(*index)
+                    .cloned()
+                    .or_else(|| default.map(OscType::Int))
+                    .ok_or_else(|| format!("{} requires arg {} ({})", msg.addr, index + 1, name)),
+                Source::Fixed(value) => Ok(value.clone()),
+                Source::Default(value) => Ok(OscType::Int(*value)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Some(OscMessage {
+            addr: mapping.address.clone(),
+            args,
+        }))
+    }
+}
+
+fn parse_mapping(address: &str, entry: &Value) -> Result<Mapping> {
+    if !address.starts_with('/') {
+        bail!("addresses must start with '/'");
+    }
+    let Value::Object(entry) = entry else {
+        bail!("expected an object with \"cmd\" and \"args\"");
+    };
+    if let Some(key) = entry
+        .keys()
+        .find(|key| !matches!(key.as_str(), "cmd" | "args" | "fixed" | "plugin"))
+    {
+        bail!("unknown key \"{}\" (expected cmd, args, fixed, plugin)", key);
+    }
+
+    let cmd = entry
+        .get("cmd")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("\"cmd\" must name a command"))?;
+    let target = TARGETS.iter().find(|t| t.cmd == cmd).ok_or_else(|| {
+        let names: Vec<_> = TARGETS.iter().map(|t| t.cmd).collect();
+        anyhow!("unknown command \"{}\" (expected one of {})", cmd, names.join(", "))
+    })?;
+
+    let args = match entry.get("args") {
+        None => Vec::new(),
+        Some(Value::Array(args)) => args
+            .iter()
+            .map(|arg| {
+                arg.as_str()
+                    .ok_or_else(|| anyhow!("\"args\" must be a list of argument names"))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        Some(_) => bail!("\"args\" must be a list of argument names"),
+    };
+    let empty = Map::new();
+    let fixed = match entry.get("fixed") {
+        None => &empty,
+        Some(Value::Object(fixed)) => fixed,
+        Some(_) => bail!("\"fixed\" must be an object of argument values"),
+    };
+
+    let field_names = || target.fields.iter().map(|f| f.name).collect::<Vec<_>>().join(", ");
+    for name in args.iter().copied().chain(fixed.keys().map(String::as_str)) {
+        if !target.fields.iter().any(|f| f.name == name) {
+            if target.fields.is_empty() {
+                bail!("{} takes no arguments, but the mapping names \"{}\"", cmd, name);
+            }
+            bail!("{} has no argument \"{}\" (expected {})", cmd, name, field_names());
+        }
+    }
+
+    let mut sources = Vec::with_capacity(target.fields.len());
+    for field in target.fields {
+        let mut positions = args.iter().enumerate().filter(|(_, name)| **name == field.name);
+        let position = positions.next().map(|(i, _)| i);
+        if positions.next().is_some() {
+            bail!("\"{}\" appears more than once in \"args\"", field.name);
+        }
+        let source = match (position, fixed.get(field.name), field.default) {
+            (Some(_), Some(_), _) => bail!("\"{}\" is both in \"args\" and \"fixed\"", field.name),
+            (Some(index), None, default) => Source::Arg(index, default),
+            (None, Some(value), _) => Source::Fixed(
+                json_to_osc(value)
+                    .ok_or_else(|| anyhow!("\"fixed\".{} must be a number, string or bool", field.name))?,
+            ),
+            (None, None, Some(default)) => Source::Default(default),
+            (None, None, None) => bail!("{} needs \"{}\" in \"args\" or \"fixed\"", cmd, field.name),
+        };
+        sources.push((field.name, source));
+    }
+
+    let address = match entry.get("plugin") {
+        None => target.address.to_string(),
+        Some(plugin) => {
+            let plugin = plugin
+                .as_u64()
+                .ok_or_else(|| anyhow!("\"plugin\" must be a chain index (0 is the first plugin)"))?;
+            let address = format!("/plugin/{}{}", plugin, target.address);
+            if split_plugin_address(&address).is_none() {
+                bail!("{} can't be sent to a specific plugin", cmd);
+            }
+            address
+        }
+    };
+
+    Ok(Mapping { address, sources })
+}
+
+fn json_to_osc(value: &Value) -> Option<OscType> {
+    match value {
+        Value::Number(n) => match n.as_i64().and_then(|n| i32::try_from(n).ok()) {
+            Some(n) => Some(OscType::Int(n)),
+            None => n.as_f64().map(OscType::Double),
+        },
+        Value::String(s) => Some(OscType::String(s.clone())),
+        Value::Bool(b) => Some(OscType::Int(*b as i32)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc::Command;
+    use crate::testing::detached_receiver;
+
+    const MAP: &str = r#"{
+        "/n": { "cmd": "NoteOn", "args": ["key", "velocity"] },
+        "/kick": { "cmd": "NoteOn", "args": ["velocity"], "fixed": { "key": 36, "channel": 9 } },
+        "/fx/cutoff": { "cmd": "ParamSet", "args": ["value"], "fixed": { "param_id": 12 }, "plugin": 1 },
+        "/panic": { "cmd": "AllNotesOff" }
+    }"#;
+
+    fn map() -> OscMap {
+        OscMap::parse(MAP, "map.json").unwrap()
+    }
+
+    fn message(addr: &str, args: Vec<OscType>) -> OscMessage {
+        OscMessage {
+            addr: addr.to_string(),
+            args,
+        }
+    }
+
+    fn parse_error(json: &str) -> String {
+        OscMap::parse(json, "map.json").err().unwrap().to_string()
+    }
+
+    #[test]
+    fn custom_addresses_produce_the_mapped_command() {
+        let (mut state, mut consumer) = detached_receiver(16);
+        state.osc_map = map();
+
+        state.handle_message(&message("/n", vec![OscType::Int(60), OscType::Float(0.5)]), None).unwrap();
+        let cmd = consumer.pop().unwrap().command;
+        assert!(
+            matches!(cmd, Command::NoteOn { note_id: -1, key: 60, velocity, channel: 0, .. } if velocity == 0.5),
+            "{:?}",
+            cmd
+        );
+
+        state.handle_message(&message("/kick", vec![OscType::Float(1.0)]), None).unwrap();
+        let cmd = consumer.pop().unwrap().command;
+        assert!(matches!(cmd, Command::NoteOn { key: 36, channel: 9, .. }), "{:?}", cmd);
+
+        state.handle_message(&message("/panic", vec![]), None).unwrap();
+        let cmd = consumer.pop().unwrap().command;
+        assert!(matches!(cmd, Command::AllNotesOff { channel: -1, .. }), "{:?}", cmd);
+    }
+
+    #[test]
+    fn mapped_messages_are_rewritten_to_the_builtin_address() {
+        let map = map();
+        let rewritten = map.rewrite(&message("/fx/cutoff", vec![OscType::Float(0.25)])).unwrap().unwrap();
+        assert_eq!(rewritten.addr, "/plugin/1/param/set");
+        assert_eq!(rewritten.args, vec![OscType::Int(12), OscType::Float(0.25)]);
+
+        let rewritten = map.rewrite(&message("/n", vec![OscType::Int(60), OscType::Float(0.5)])).unwrap().unwrap();
+        assert_eq!(rewritten.addr, "/note/on");
+        assert_eq!(
+            rewritten.args,
+            vec![OscType::Int(-1), OscType::Int(60), OscType::Float(0.5), OscType::Int(0), OscType::Int(0)]
+        );
+
+        assert!(map.rewrite(&message("/note/on", vec![])).unwrap().is_none(), "unmapped addresses are left alone");
+        let error = map.rewrite(&message("/n", vec![OscType::Int(60)])).unwrap_err();
+        assert_eq!(error, "/n requires arg 2 (velocity)");
+    }
+
+    #[test]
+    fn overrides_are_the_mapped_builtin_addresses() {
+        assert_eq!(map().overrides().collect::<Vec<_>>(), vec!["/panic"]);
+    }
+
+    #[test]
+    fn invalid_mappings_are_rejected_with_the_address() {
+        let cases = [
+            (r#"[]"#, "map.json: expected a JSON object of address mappings"),
+            (r#"{ "n": { "cmd": "Panic" } }"#, "map.json: n: addresses must start with '/'"),
+            (r#"{ "/n": { "cmd": "Boom" } }"#, "map.json: /n: unknown command \"Boom\""),
+            (r#"{ "/n": { "cmd": "Panic", "arg": [] } }"#, "map.json: /n: unknown key \"arg\""),
+            (r#"{ "/n": { "cmd": "Panic", "args": ["key"] } }"#, "Panic takes no arguments"),
+            (r#"{ "/n": { "cmd": "NoteOn", "args": ["pitch"] } }"#, "NoteOn has no argument \"pitch\""),
+            (
+                r#"{ "/n": { "cmd": "NoteOn", "args": ["key"] } }"#,
+                "NoteOn needs \"velocity\" in \"args\" or \"fixed\"",
+            ),
+            (r#"{ "/n": { "cmd": "NoteOn", "args": ["key", "key", "velocity"] } }"#, "\"key\" appears more than once"),
+            (
+                r#"{ "/n": { "cmd": "NoteOn", "args": ["key", "velocity"], "fixed": { "key": 60 } } }"#,
+                "\"key\" is both in \"args\" and \"fixed\"",
+            ),
+            (
+                r#"{ "/n": { "cmd": "NoteOn", "args": ["key"], "fixed": { "velocity": [1] } } }"#,
+                "\"fixed\".velocity must be a number, string or bool",
+            ),
+            (r#"{ "/n": { "cmd": "Play", "plugin": 1 } }"#, "Play can't be sent to a specific plugin"),
+        ];
+        for (json, expected) in cases {
+            let error = parse_error(json);
+            assert!(error.contains(expected), "{} gave {}", json, error);
+        }
+        assert!(parse_error("{").starts_with("Failed to parse OSC map map.json"));
+    }
+}
@@ -0,0 +1,333 @@
+//! OSC over TCP (`--osc-tcp`). Each client connection gets a reader thread
+//! that splits the stream into packets and hands them to the OSC thread,
+//! which handles them exactly like UDP packets. Replies go back over the
+//! connection they came from.
+
+use crate::osc::OscSend;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use rosc::OscPacket;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Packets larger than this close the connection; the framing is probably wrong.
+const MAX_PACKET_SIZE: usize = 1 << 20;
+
+/// How long a reply may wait on a client that isn't reading before its
+/// connection is dropped. Replies are written on the OSC thread.
+const REPLY_TIMEOUT: Duration = Duration::from_millis(100);
+
+const SLIP_END: u8 = 0xc0;
+const SLIP_ESC: u8 = 0xdb;
+const SLIP_ESC_END: u8 = 0xdc;
+const SLIP_ESC_ESC: u8 = 0xdd;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TcpFraming {
+    /// OSC 1.1: packets are SLIP-encoded (RFC 1055), double-END style.
+    Slip,
+    /// OSC 1.0: each packet is preceded by its size as a big-endian i32.
+    Length,
+}
+
+/// A packet read from a TCP client.
+pub struct TcpPacket {
+    pub packet: OscPacket,
+    pub from: SocketAddr,
+    pub reply: TcpReplier,
+}
+
+/// Writes replies back to the client a packet came from.
+#[derive(Clone)]
+pub struct TcpReplier {
+    stream: Arc<Mutex<TcpStream>>,
+    framing: TcpFraming,
+}
+
+impl TcpReplier {
+    fn new(stream: &TcpStream, framing: TcpFraming) -> io::Result<Self> {
+        let stream = stream.try_clone()?;
+        stream.set_write_timeout(Some(REPLY_TIMEOUT))?;
+        Ok(Self {
+            stream: Arc::new(Mutex::new(stream)),
+            framing,
+        })
+    }
+}
+
+impl OscSend for TcpReplier {
+    fn send_to(&self, buf: &[u8], _addr: SocketAddr) -> io::Result<usize> {
+        let framed = frame(buf, self.framing);
+        let mut stream = self.stream.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = stream.write_all(&framed) {
+            // Part of the frame may have gone out, so later replies would be
+            // misframed; the client has to reconnect.
+            let _ = stream.shutdown(Shutdown::Both);
+            return Err(e);
+        }
+        Ok(buf.len())
+    }
+
+    fn needs_pacing(&self) -> bool {
+        false
+    }
+}
+
+/// Listens on `addr` and returns the packets every client sends.
+pub fn start_tcp_listener(addr: SocketAddr, framing: TcpFraming) -> Result<Receiver<TcpPacket>> {
+    let listener = TcpListener::bind(addr).with_context(|| format!("Failed to listen for OSC over TCP on {}", addr))?;
+    let addr = listener.local_addr().unwrap_or(addr);
+    let receiver = accept_clients(listener, framing)?;
+    log::info!("OSC receiver listening on {} (TCP, {:?} framing)", addr, framing);
+    Ok(receiver)
+}
+
+/// Accepts clients on a listener that is already bound.
+pub fn accept_clients(listener: TcpListener, framing: TcpFraming) -> Result<Receiver<TcpPacket>> {
+    let (sender, receiver) = unbounded();
+
+    thread::Builder::new()
+        .name("osc-tcp-accept".into())
+        .spawn(move || accept_loop(listener, framing, sender))
+        .context("Failed to spawn OSC TCP accept thread")?;
+    Ok(receiver)
+}
+
+fn accept_loop(listener: TcpListener, framing: TcpFraming, sender: Sender<TcpPacket>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("OSC TCP accept failed: {}", e);
+                continue;
+            }
+        };
+        let Ok(from) = stream.peer_addr() else {
+            continue;
+        };
+        let _ = stream.set_nodelay(true);
+        let sender = sender.clone();
+        let spawned = thread::Builder::new()
+            .name(format!("osc-tcp-{}", from))
+            .spawn(move || {
+                log::info!("OSC TCP client connected: {}", from);
+                match read_loop(stream, from, framing, &sender) {
+                    Ok(()) => log::info!("OSC TCP client disconnected: {}", from),
+                    Err(e) => log::warn!("OSC TCP client {} dropped: {}", from, e),
+                }
+            });
+        if let Err(e) = spawned {
+            log::warn!("Failed to spawn a reader for OSC TCP client {}: {}", from, e);
+        }
+    }
+}
+
+fn read_loop(stream: TcpStream, from: SocketAddr, framing: TcpFraming, sender: &Sender<TcpPacket>) -> io::Result<()> {
+    let reply = TcpReplier::new(&stream, framing)?;
+    let mut reader = BufReader::new(stream);
+    loop {
+        let frame = match framing {
+            TcpFraming::Slip => read_slip_frame(&mut reader)?,
+            TcpFraming::Length => read_length_frame(&mut reader)?,
+        };
+        let Some(frame) = frame else {
+            return Ok(());
+        };
+        if frame.is_empty() {
+            continue;
+        }
+        match rosc::decoder::decode_udp(&frame) {
+            Ok((_, packet)) => {
+                let packet = TcpPacket {
+                    packet,
+                    from,
+                    reply: reply.clone(),
+                };
+                if sender.send(packet).is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) => log::warn!("Dropped malformed OSC packet from {}: {:?}", from, e),
+        }
+    }
+}
+
+/// `None` once the client closes the connection between packets.
+fn read_length_frame(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut size = [0u8; 4];
+    match reader.read_exact(&mut size) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let size = u32::from_be_bytes(size) as usize;
+    if size > MAX_PACKET_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("packet of {} bytes; is the client using SLIP framing?", size),
+        ));
+    }
+    let mut frame = vec![0u8; size];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+/// `None` once the client closes the connection.
+fn read_slip_frame(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut frame = Vec::new();
+    let mut escaped = false;
+    for byte in reader.bytes() {
+        match (byte?, escaped) {
+            (SLIP_END, false) => return Ok(Some(frame)),
+            (SLIP_ESC, false) => escaped = true,
+            (SLIP_ESC_END, true) => {
+                frame.push(SLIP_END);
+                escaped = false;
+            }
+            (SLIP_ESC_ESC, true) => {
+                frame.push(SLIP_ESC);
+                escaped = false;
+            }
+            (byte, _) => {
+                frame.push(byte);
+                escaped = false;
+            }
+        }
+        if frame.len() > MAX_PACKET_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "SLIP frame too large; is the client using length-prefixed framing?",
+            ));
+        }
+    }
+    Ok((!frame.is_empty()).then_some(frame))
+}
+
+/// `buf` framed as one packet on the stream.
+pub fn frame(buf: &[u8], framing: TcpFraming) -> Vec<u8> {
+    match framing {
+        TcpFraming::Slip => slip_encode(buf),
+        TcpFraming::Length => {
+            let mut framed = (buf.len() as u32).to_be_bytes().to_vec();
+            framed.extend_from_slice(buf);
+            framed
+        }
+    }
+}
+
+fn slip_encode(buf: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(buf.len() + 2);
+    framed.push(SLIP_END);
+    for &byte in buf {
+        match byte {
+            SLIP_END => framed.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => framed.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            byte => framed.push(byte),
+        }
+    }
+    framed.push(SLIP_END);
+    framed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn length_framed(packets: &[&[u8]]) -> Vec<u8> {
+        packets.iter().flat_map(|packet| frame(packet, TcpFraming::Length)).collect()
+    }
+
+    #[test]
+    fn slip_frames_round_trip_escaped_bytes() {
+        let packet = [1, SLIP_END, 2, SLIP_ESC, SLIP_ESC_END, SLIP_ESC_ESC];
+        let encoded = slip_encode(&packet);
+        assert_eq!(
+            encoded,
+            [SLIP_END, 1, SLIP_ESC, SLIP_ESC_END, 2, SLIP_ESC, SLIP_ESC_ESC, SLIP_ESC_END, SLIP_ESC_ESC, SLIP_END]
+        );
+
+        // Double-END framing yields an empty frame before each packet, which
+        // the reader skips.
+        let mut reader = io::Cursor::new(encoded);
+        assert_eq!(read_slip_frame(&mut reader).unwrap(), Some(Vec::new()));
+        assert_eq!(read_slip_frame(&mut reader).unwrap(), Some(packet.to_vec()));
+        assert_eq!(read_slip_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn slip_frames_end_at_the_end_of_the_stream() {
+        let mut reader = io::Cursor::new(vec![1, 2, SLIP_END, 3, 4]);
+        assert_eq!(read_slip_frame(&mut reader).unwrap(), Some(vec![1, 2]));
+        assert_eq!(read_slip_frame(&mut reader).unwrap(), Some(vec![3, 4]));
+        assert_eq!(read_slip_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn oversized_slip_frames_are_an_error() {
+        let mut reader = io::Cursor::new(vec![0u8; MAX_PACKET_SIZE + 1]);
+        let error = read_slip_frame(&mut reader).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn length_frames_are_read_in_order() {
+        let mut reader = io::Cursor::new(length_framed(&[b"first", b"", b"second"]));
+        assert_eq!(read_length_frame(&mut reader).unwrap(), Some(b"first".to_vec()));
+        assert_eq!(read_length_frame(&mut reader).unwrap(), Some(Vec::new()));
+        assert_eq!(read_length_frame(&mut reader).unwrap(), Some(b"second".to_vec()));
+        assert_eq!(read_length_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn bad_length_frames_are_an_error() {
+        let mut stream = length_framed(&[b"packet"]);
+        stream.truncate(stream.len() - 1);
+        let error = read_length_frame(&mut io::Cursor::new(stream)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        let oversized = ((MAX_PACKET_SIZE + 1) as u32).to_be_bytes();
+        let error = read_length_frame(&mut io::Cursor::new(oversized)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn replies_are_framed_for_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, from) = listener.accept().unwrap();
+
+        for framing in [TcpFraming::Slip, TcpFraming::Length] {
+            let reply = TcpReplier::new(&server, framing).unwrap();
+            assert_eq!(reply.send_to(b"reply", from).unwrap(), 5);
+            let mut reader = &client;
+            let frame = match framing {
+                TcpFraming::Slip => {
+                    assert_eq!(read_slip_frame(&mut reader).unwrap(), Some(Vec::new()));
+                    read_slip_frame(&mut reader).unwrap()
+                }
+                TcpFraming::Length => read_length_frame(&mut reader).unwrap(),
+            };
+            assert_eq!(frame, Some(b"reply".to_vec()));
+            assert_eq!(reply.stream.lock().unwrap().write_timeout().unwrap(), Some(REPLY_TIMEOUT));
+        }
+    }
+
+    #[test]
+    fn replies_to_a_client_that_stops_reading_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, from) = listener.accept().unwrap();
+        let reply = TcpReplier::new(&server, TcpFraming::Length).unwrap();
+
+        let chunk = vec![0u8; 1 << 16];
+        let error = (0..4096)
+            .find_map(|_| reply.send_to(&chunk, from).err())
+            .expect("the client's socket buffer fills up");
+        assert!(matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut), "{}", error);
+        assert!(reply.send_to(b"reply", from).is_err(), "the connection is shut down");
+    }
+}
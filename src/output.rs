@@ -0,0 +1,124 @@
+//! Non-device output destinations, driven by the clock-paced loop in
+//! `AudioEngine::new_clocked` instead of a cpal callback.
+//!
+//! UDP frame protocol (all fields little-endian):
+//!
+//! | bytes | field                                 |
+//! |-------|---------------------------------------|
+//! | 4     | magic `OCAF`                          |
+//! | 4     | sequence number (u32, wraps)          |
+//! | 4     | sample rate (u32)                     |
+//! | 2     | channel count (u16)                   |
+//! | 2     | frame count (u16)                     |
+//! | n     | `frames * channels` interleaved f32   |
+//!
+//! Receivers detect loss by watching for gaps in the sequence number.
+
+use anyhow::{anyhow, Context, Result};
+use std::io::ErrorKind;
+use std::net::{ToSocketAddrs, UdpSocket};
+
+pub const UDP_MAGIC: &[u8; 4] = b"OCAF";
+pub const UDP_HEADER_LEN: usize = 16;
+
+/// Keeps each datagram comfortably under a typical 1500-byte MTU for stereo.
+const MAX_FRAMES_PER_PACKET: usize = 128;
+
+pub trait AudioSink: Send {
+    /// Receives one processed block of interleaved f32 samples. Must not block.
+    fn write(&mut self, interleaved: &[f32], channel_count: usize);
+}
+
+/// Discards everything; used by `--no-audio` to run the plugin without output.
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn write(&mut self, _interleaved: &[f32], _channel_count: usize) {}
+}
+
+pub struct UdpAudioSink {
+    socket: UdpSocket,
+    sample_rate: u32,
+    sequence: u32,
+    packet: Vec<u8>,
+    dropped: u64,
+}
+
+impl UdpAudioSink {
+    pub fn connect(target: &str, sample_rate: u32, channel_count: usize) -> Result<Self> {
+        let addr = target
+            .to_socket_addrs()
+            .with_context(|| format!("Invalid output address '{}'", target))?
+            .next()
+            .ok_or_else(|| anyhow!("Output address '{}' did not resolve", target))?;
+
+        let local = if addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+        let socket = UdpSocket::bind(local).context("Failed to bind UDP output socket")?;
+        socket
+            .connect(addr)
+            .with_context(|| format!("Failed to connect UDP output to {}", addr))?;
+        socket
+            .set_nonblocking(true)
+            .context("Failed to make UDP output socket non-blocking")?;
+
+        log::info!("Streaming audio over UDP to {}", addr);
+
+        Ok(Self {
+            socket,
+            sample_rate,
+            sequence: 0,
+            packet: Vec::with_capacity(UDP_HEADER_LEN + MAX_FRAMES_PER_PACKET * channel_count * 4),
+            dropped: 0,
+        })
+    }
+
+    fn send_packet(&mut self, samples: &[f32], channel_count: usize) {
+        let frames = samples.len() / channel_count;
+
+        self.packet.clear();
+        self.packet.extend_from_slice(UDP_MAGIC);
+        self.packet.extend_from_slice(&self.sequence.to_le_bytes());
+        self.packet.extend_from_slice(&self.sample_rate.to_le_bytes());
+        self.packet.extend_from_slice(&(channel_count as u16).to_le_bytes());
+        self.packet.extend_from_slice(&(frames as u16).to_le_bytes());
+        for sample in samples {
+            self.packet.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        self.sequence = self.sequence.wrapping_add(1);
+
+        match self.socket.send(&self.packet) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                // The network stalled; drop rather than hold up the processing loop.
+                self.dropped += 1;
+                if self.dropped == 1 || self.dropped % 1000 == 0 {
+                    log::warn!("UDP audio output stalled, {} packets dropped so far", self.dropped);
+                }
+            }
+            Err(e) => {
+                self.dropped += 1;
+                if self.dropped == 1 || self.dropped % 1000 == 0 {
+                    log::warn!("UDP audio send error: {} ({} packets dropped)", e, self.dropped);
+                }
+            }
+        }
+    }
+}
+
+impl AudioSink for UdpAudioSink {
+    fn write(&mut self, interleaved: &[f32], channel_count: usize) {
+        for chunk in interleaved.chunks(MAX_FRAMES_PER_PACKET * channel_count) {
+            self.send_packet(chunk, channel_count);
+        }
+    }
+}
+
+/// Builds a sink from an `--output` URL. Only `udp://host:port` is supported.
+pub fn sink_from_url(url: &str, sample_rate: u32, channel_count: usize) -> Result<Box<dyn AudioSink>> {
+    match url.split_once("://") {
+        Some(("udp", target)) => Ok(Box::new(UdpAudioSink::connect(target, sample_rate, channel_count)?)),
+        Some((scheme, _)) => Err(anyhow!("Unsupported output scheme '{}', expected udp://host:port", scheme)),
+        None => Err(anyhow!("Invalid output '{}', expected udp://host:port", url)),
+    }
+}
@@ -0,0 +1,82 @@
+//! Parameter changes for plugins that have stopped processing. A plugin a
+//! failed restart left out of the chain gets no blocks, so nothing would
+//! take its queued `/param/set`s. The OSC thread hands them over here
+//! instead, and the main thread applies them with a params flush, which
+//! CLAP allows on the main thread while a plugin is inactive.
+
+use crate::osc::Command;
+use crossbeam_channel::{Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// One entry per plugin in the chain, shared between the OSC thread and the
+/// main thread.
+pub struct ParamFlush {
+    plugins: Vec<StoppedPlugin>,
+}
+
+struct StoppedPlugin {
+    stopped: AtomicBool,
+    sender: Sender<(u32, f64)>,
+    receiver: Receiver<(u32, f64)>,
+}
+
+pub type SharedParamFlush = Arc<ParamFlush>;
+
+impl ParamFlush {
+    /// All plugins start out processing.
+    pub fn new(plugin_count: usize) -> SharedParamFlush {
+        let plugins = (0..plugin_count)
+            .map(|_| {
+                let (sender, receiver) = crossbeam_channel::unbounded();
+                StoppedPlugin {
+                    stopped: AtomicBool::new(false),
+                    sender,
+                    receiver,
+                }
+            })
+            .collect();
+        Arc::new(Self { plugins })
+    }
+
+    /// Marks chain plugin `plugin` as stopped or processing again.
+    pub fn set_stopped(&self, plugin: usize, stopped: bool) {
+        if let Some(entry) = self.plugins.get(plugin) {
+            entry.stopped.store(stopped, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes the values `cmd` sets if it is for a stopped plugin, and returns
+    /// whether it did. A ramp jumps to its target, as there are no blocks to
+    /// ramp over.
+    pub fn hand_over(&self, plugin: usize, cmd: &Command) -> bool {
+        let Some(entry) = self.plugins.get(plugin).filter(|entry| entry.stopped.load(Ordering::Relaxed)) else {
+            return false;
+        };
+        match cmd {
+            Command::ParamSet { param_id, value }
+            | Command::ParamRamp {
+                param_id,
+                target: value,
+                ..
+            } => {
+                let _ = entry.sender.send((*param_id, *value));
+            }
+            Command::ParamSetMany { pairs } => {
+                for &pair in pairs {
+                    let _ = entry.sender.send(pair);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// The values handed over for `plugin` so far, oldest first.
+    pub fn take(&self, plugin: usize) -> Vec<(u32, f64)> {
+        self.plugins
+            .get(plugin)
+            .map(|entry| entry.receiver.try_iter().collect())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,260 @@
+//! Name-based parameter addressing (`/param/setname`, `/param/setByName`,
+//! `/param/modname`).
+//! Names are resolved on the OSC thread, so the audio thread still only sees
+//! numeric param ids.
+
+use crate::plugin::ParamInfo;
+use rosc::{OscMessage, OscType};
+
+/// How many suggestions an unknown name reports.
+const MAX_SUGGESTIONS: usize = 3;
+
+struct NamedParam {
+    id: u32,
+    /// `module/name`, or just `name` for params without a module.
+    path: String,
+    name_lower: String,
+    module_lower: String,
+    path_lower: String,
+}
+
+/// Case-insensitive lookup from parameter names to ids.
+#[derive(Default)]
+pub struct ParamNames {
+    params: Vec<NamedParam>,
+}
+
+impl ParamNames {
+    pub fn new<'a>(params: impl IntoIterator<Item = &'a ParamInfo>) -> Self {
+        let mut params: Vec<NamedParam> = params
+            .into_iter()
+            .map(|p| {
+                let path = if p.module.is_empty() {
+                    p.name.clone()
+                } else {
+                    format!("{}/{}", p.module.trim_end_matches('/'), p.name)
+                };
+                NamedParam {
+                    id: p.id,
+                    name_lower: p.name.to_lowercase(),
+                    module_lower: p.module.trim_end_matches('/').to_lowercase(),
+                    path_lower: path.to_lowercase(),
+                    path,
+                }
+            })
+            .collect();
+        params.sort_by_key(|p| p.id);
+        Self { params }
+    }
+
+    /// Resolves `Cutoff` or, to pick between duplicates, a module-qualified
+    /// `Filter 1/Cutoff` (any trailing part of the module path will do).
+    pub fn resolve(&self, query: &str) -> Result<u32, String> {
+        let query_lower = query.trim().to_lowercase();
+        let suffix = format!("/{}", query_lower);
+
+        let matches: Vec<&NamedParam> = if query_lower.contains('/') {
+            self.params
+                .iter()
+                .filter(|p| p.path_lower == query_lower || p.path_lower.ends_with(&suffix))
+                .collect()
+        } else {
+            self.params.iter().filter(|p| p.name_lower == query_lower).collect()
+        };
+
+        match matches.as_slice() {
+            [param] => Ok(param.id),
+            [] => Err(format!(
+                "No parameter named '{}'; closest matches: {}",
+                query,
+                self.closest(&query_lower).join(", ")
+            )),
+            several => Err(format!(
+                "Parameter name '{}' is ambiguous; qualify it with its module: {}",
+                query,
+                several.iter().map(|p| p.path.as_str()).collect::<Vec<_>>().join(", ")
+            )),
+        }
+    }
+
+    /// Resolves a name within one exact module path, as `/param/setByName`
+    /// gives them. An empty module matches params without one.
+    pub fn resolve_in_module(&self, module: &str, name: &str) -> Result<u32, String> {
+        let module_lower = module.trim().trim_end_matches('/').to_lowercase();
+        let name_lower = name.trim().to_lowercase();
+        let matches: Vec<&NamedParam> = self
+            .params
+            .iter()
+            .filter(|p| p.module_lower == module_lower && p.name_lower == name_lower)
+            .collect();
+
+        let path_lower = if module_lower.is_empty() {
+            name_lower
+        } else {
+            format!("{}/{}", module_lower, name_lower)
+        };
+        match matches.as_slice() {
+            [param] => Ok(param.id),
+            [] => Err(format!(
+                "No parameter named '{}' in module '{}'; closest matches: {}",
+                name,
+                module,
+                self.closest(&path_lower).join(", ")
+            )),
+            several => Err(format!(
+                "Parameter '{}' in module '{}' is ambiguous; ids: {}",
+                name,
+                module,
+                several.iter().map(|p| p.id.to_string()).collect::<Vec<_>>().join(", ")
+            )),
+        }
+    }
+
+    fn closest(&self, query_lower: &str) -> Vec<String> {
+        let mut scored: Vec<(usize, &NamedParam)> = self
+            .params
+            .iter()
+            .map(|p| {
+                let target = if query_lower.contains('/') { &p.path_lower } else { &p.name_lower };
+                (edit_distance(query_lower, target), p)
+            })
+            .collect();
+        scored.sort_by_key(|(distance, p)| (*distance, p.id));
+        scored
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, p)| format!("'{}' ({})", p.path, p.id))
+            .collect()
+    }
+
+    /// Rewrites `/param/setname`, `/param/setByName` and `/param/modname` into
+    /// their numeric `/param/set` and `/param/mod` forms. `Ok(None)` for other
+    /// addresses.
+    pub fn rewrite(&self, msg: &OscMessage) -> Result<Option<OscMessage>, String> {
+        if msg.addr == "/param/setByName" {
+            let (Some(OscType::String(module)), Some(OscType::String(name))) = (msg.args.first(), msg.args.get(1)) else {
+                return Err("/param/setByName requires 3 args: module:s, name:s, value".to_string());
+            };
+            let param_id = self.resolve_in_module(module, name)?;
+            let mut args = vec![OscType::Int(param_id as i32)];
+            args.extend(msg.args.iter().skip(2).cloned());
+            return Ok(Some(OscMessage {
+                addr: "/param/set".to_string(),
+                args,
+            }));
+        }
+        let (addr, name_index, usage) = match msg.addr.as_str() {
+            "/param/setname" => ("/param/set", 0, "/param/setname requires 2 args: name:s, value"),
+            "/param/modname" => (
+                "/param/mod",
+                1,
+                "/param/modname requires at least 3 args: note_id, name:s, amount",
+            ),
+            _ => return Ok(None),
+        };
+        let Some(OscType::String(name)) = msg.args.get(name_index) else {
+            return Err(usage.to_string());
+        };
+        let param_id = self.resolve(name)?;
+
+        let mut args = msg.args.clone();
+        args[name_index] = OscType::Int(param_id as i32);
+        Ok(Some(OscMessage {
+            addr: addr.to_string(),
+            args,
+        }))
+    }
+}
+
+/// Levenshtein distance over chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::param_info;
+
+    fn names() -> ParamNames {
+        let param = |id, module: &str, name| ParamInfo {
+            module: module.to_string(),
+            ..param_info(id, name, 0.0, 1.0)
+        };
+        ParamNames::new(&[
+            param(10, "Filter 1", "Cutoff"),
+            param(11, "Filter 2", "Cutoff"),
+            param(12, "Osc/A", "Level"),
+            param(13, "", "Volume"),
+            param(14, "Osc/B", "Level"),
+            param(15, "Osc/B", "level"),
+        ])
+    }
+
+    fn set_by_name(module: &str, name: &str) -> OscMessage {
+        OscMessage {
+            addr: "/param/setByName".to_string(),
+            args: vec![
+                OscType::String(module.to_string()),
+                OscType::String(name.to_string()),
+                OscType::Double(0.25),
+            ],
+        }
+    }
+
+    #[test]
+    fn set_by_name_resolves_exact_module_and_name() {
+        let rewritten = names().rewrite(&set_by_name("Filter 2", "Cutoff")).unwrap().unwrap();
+        assert_eq!(rewritten.addr, "/param/set");
+        assert_eq!(rewritten.args, vec![OscType::Int(11), OscType::Double(0.25)]);
+    }
+
+    #[test]
+    fn set_by_name_ignores_case() {
+        let names = names();
+        assert_eq!(names.resolve_in_module("FILTER 1", "cutoff"), Ok(10));
+        assert_eq!(names.resolve_in_module("osc/a/", "LEVEL"), Ok(12));
+        assert_eq!(names.resolve_in_module("", "volume"), Ok(13));
+    }
+
+    #[test]
+    fn set_by_name_reports_unknown_and_ambiguous_names() {
+        let names = names();
+        let unknown = names.resolve_in_module("Filter 1", "Cutof").unwrap_err();
+        assert!(unknown.contains("'Filter 1/Cutoff' (10)"), "{}", unknown);
+        // A module path has to match whole, not just its end.
+        assert!(names.resolve_in_module("A", "Level").is_err());
+        let ambiguous = names.resolve_in_module("Osc/B", "Level").unwrap_err();
+        assert!(ambiguous.contains("14, 15"), "{}", ambiguous);
+        assert!(names.rewrite(&set_by_name("Filter 3", "Cutoff")).is_err());
+    }
+
+    #[test]
+    fn setname_takes_a_module_suffix_for_duplicates() {
+        let names = names();
+        assert!(names.resolve("cutoff").unwrap_err().contains("ambiguous"));
+        assert_eq!(names.resolve("filter 2/CUTOFF"), Ok(11));
+        assert_eq!(names.resolve("A/Level"), Ok(12));
+        assert_eq!(names.resolve("Volume"), Ok(13));
+    }
+
+    #[test]
+    fn other_addresses_pass_through() {
+        let msg = OscMessage {
+            addr: "/param/set".to_string(),
+            args: vec![OscType::Int(1), OscType::Float(0.5)],
+        };
+        assert_eq!(names().rewrite(&msg), Ok(None));
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use rosc::{OscMessage, OscPacket, OscType};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+/// An event the plugin emitted from `process`: a parameter its own GUI or
+/// internal modulation moved, a voice that finished, or a note it played.
+#[derive(Debug, Clone, Copy)]
+pub enum PluginOutputEvent {
+    Value { param_id: u32, value: f64 },
+    Gesture { param_id: u32, begin: bool },
+    /// A voice finished its release. `plugin` is the chain index.
+    NoteEnd { plugin: usize, note_id: i32, key: i32, channel: i32 },
+    /// A note on or off sent out of one of the plugin's note ports.
+    NoteOut { plugin: usize, on: bool, note_id: i32, key: i32, velocity: f64, channel: i32, port: i32 },
+    /// A raw MIDI message sent out of one of the plugin's note ports.
+    Midi { plugin: usize, data: [u8; 3], port: i32 },
+}
+
+pub type PluginOutputProducer = Producer<PluginOutputEvent>;
+
+const PARAM_OUT_QUEUE_CAPACITY: usize = 4096;
+
+/// How long the sender sleeps when the queue is empty.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Where plugin output events go. At least one should be set.
+pub struct ParamSenderTargets {
+    /// `/param/changed param_id:i32 value:f32`, `/param/gesture param_id:i32 1|0`
+    /// and the note messages (`--osc-send-port`).
+    pub changed: Option<SocketAddr>,
+    /// `/param/value param_id:i32 value:f64` (`--feedback-port`).
+    pub value: Option<SocketAddr>,
+}
+
+/// Starts the thread that forwards plugin output events to `targets`.
+/// A value identical to the last one sent for that param is skipped.
+pub fn start_param_sender(targets: ParamSenderTargets) -> Result<PluginOutputProducer> {
+    let socket = UdpSocket::bind("0.0.0.0:0").context("Failed to bind OSC send socket")?;
+    let (producer, consumer) = RingBuffer::new(PARAM_OUT_QUEUE_CAPACITY);
+
+    for target in targets.changed.iter().chain(&targets.value) {
+        log::info!("Sending plugin parameter changes to {}", target);
+    }
+
+    thread::Builder::new()
+        .name("osc-sender".into())
+        .spawn(move || run_sender(socket, consumer, targets))
+        .context("Failed to spawn OSC sender thread")?;
+
+    Ok(producer)
+}
+
+fn run_sender(socket: UdpSocket, mut consumer: Consumer<PluginOutputEvent>, targets: ParamSenderTargets) {
+    let mut last_values: HashMap<u32, f64> = HashMap::new();
+
+    loop {
+        let Ok(event) = consumer.pop() else {
+            if consumer.is_abandoned() {
+                return;
+            }
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        };
+
+        match event {
+            PluginOutputEvent::Value { param_id, value } => {
+                if last_values.insert(param_id, value) == Some(value) {
+                    continue;
+                }
+                if let Some(target) = targets.changed {
+                    send(
+                        &socket,
+                        target,
+                        "/param/changed",
+                        vec![OscType::Int(param_id as i32), OscType::Float(value as f32)],
+                    );
+                }
+                if let Some(target) = targets.value {
+                    send(
+                        &socket,
+                        target,
+                        "/param/value",
+                        vec![OscType::Int(param_id as i32), OscType::Double(value)],
+                    );
+                }
+            }
+            PluginOutputEvent::Gesture { param_id, begin } => {
+                if let Some(target) = targets.changed {
+                    send(
+                        &socket,
+                        target,
+                        "/param/gesture",
+                        vec![OscType::Int(param_id as i32), OscType::Int(begin as i32)],
+                    );
+                }
+            }
+            PluginOutputEvent::NoteEnd {
+                plugin,
+                note_id,
+                key,
+                channel,
+            } => {
+                if let Some(target) = targets.changed {
+                    send(
+                        &socket,
+                        target,
+                        &plugin_address(plugin, "/note/end"),
+                        vec![OscType::Int(note_id), OscType::Int(key), OscType::Int(channel)],
+                    );
+                }
+            }
+            PluginOutputEvent::NoteOut {
+                plugin,
+                on,
+                note_id,
+                key,
+                velocity,
+                channel,
+                port,
+            } => {
+                if let Some(target) = targets.changed {
+                    let addr = if on { "/note/out/on" } else { "/note/out/off" };
+                    send(
+                        &socket,
+                        target,
+                        &plugin_address(plugin, addr),
+                        vec![
+                            OscType::Int(note_id),
+                            OscType::Int(key),
+                            OscType::Float(velocity as f32),
+                            OscType::Int(channel),
+                            OscType::Int(port),
+                        ],
+                    );
+                }
+            }
+            PluginOutputEvent::Midi { plugin, data, port } => {
+                if let Some(target) = targets.changed {
+                    send(
+                        &socket,
+                        target,
+                        &plugin_address(plugin, "/midi/out"),
+                        vec![
+                            OscType::Int(data[0] as i32),
+                            OscType::Int(data[1] as i32),
+                            OscType::Int(data[2] as i32),
+                            OscType::Int(port),
+                        ],
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Note messages from chained plugins carry the same `/plugin/<index>`
+/// prefix used to address them.
+fn plugin_address(plugin: usize, addr: &str) -> String {
+    match plugin {
+        0 => addr.to_string(),
+        index => format!("/plugin/{}{}", index, addr),
+    }
+}
+
+fn send(socket: &UdpSocket, target: SocketAddr, addr: &str, args: Vec<OscType>) {
+    let packet = OscPacket::Message(OscMessage {
+        addr: addr.to_string(),
+        args,
+    });
+    match rosc::encoder::encode(&packet) {
+        Ok(bytes) => {
+            if let Err(e) = socket.send_to(&bytes, target) {
+                log::debug!("Failed to send {} to {}: {}", addr, target, e);
+            }
+        }
+        Err(e) => log::warn!("Failed to encode {}: {:?}", addr, e),
+    }
+}
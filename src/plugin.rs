@@ -1,10 +1,17 @@
+use crate::param_names::ParamNames;
 use anyhow::{Context, Result, anyhow};
+use clack_extensions::audio_ports::{AudioPortFlags, AudioPortInfoBuffer, PluginAudioPorts};
+use clack_extensions::latency::PluginLatency;
+use clack_extensions::note_ports::{NoteDialects, NotePortInfoBuffer, PluginNotePorts};
 use clack_extensions::params::{ParamInfoBuffer, ParamInfoFlags, PluginParams};
+use clack_extensions::voice_info::{PluginVoiceInfo, VoiceInfo};
+use clack_host::events::event_types::ParamValueEvent;
+use clack_host::events::io::EventBuffer;
+use clack_host::events::{Match, Pckn};
 use clack_host::prelude::*;
-use clack_host::utils::ClapId;
-use std::ffi::CString;
-use std::fs::File;
-use std::io::Write;
+use clack_host::utils::{ClapId, Cookie};
+use std::ffi::{CStr, CString};
+use std::mem::MaybeUninit;
 use std::path::Path;
 
 #[derive(Debug, Clone)]
@@ -14,6 +21,13 @@ pub struct PluginDescriptorInfo {
     pub name: String,
     pub vendor: Option<String>,
     pub version: Option<String>,
+    pub features: Vec<String>,
+}
+
+impl PluginDescriptorInfo {
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -28,8 +42,42 @@ pub struct ParamInfo {
     pub is_modulatable_per_note_id: bool,
     pub is_automatable: bool,
     pub is_stepped: bool,
+    /// The plugin's rendering of `default_value`, e.g. "Lowpass" or "48 dB".
+    /// `None` if the plugin can't render it.
+    pub default_text: Option<String>,
+    /// The plugin's rendering of `min_value` and `max_value`, like `default_text`.
+    pub min_text: Option<String>,
+    pub max_text: Option<String>,
+    /// `(value, text)` for every step of a stepped param, up to `MAX_STEP_TEXTS` steps.
+    pub step_texts: Vec<(f64, String)>,
 }
 
+impl ParamInfo {
+    /// Maps 0..1 onto `min_value..max_value`, clamping out-of-range input and
+    /// snapping stepped params to the nearest step.
+    pub fn normalized_to_value(&self, normalized: f64) -> f64 {
+        let value = self.min_value + normalized.clamp(0.0, 1.0) * (self.max_value - self.min_value);
+        if self.is_stepped {
+            value.round()
+        } else {
+            value
+        }
+    }
+
+    /// Inverse of `normalized_to_value`, clamped to 0..1.
+    pub fn value_to_normalized(&self, value: f64) -> f64 {
+        let range = self.max_value - self.min_value;
+        if range <= 0.0 {
+            return 0.0;
+        }
+        ((value - self.min_value) / range).clamp(0.0, 1.0)
+    }
+}
+
+/// Stepped params with more steps than this (e.g. a MIDI note number) only
+/// get their default rendered.
+const MAX_STEP_TEXTS: f64 = 64.0;
+
 pub fn load_bundle(path: &Path) -> Result<PluginBundle> {
     unsafe { PluginBundle::load(path) }.context("Failed to load CLAP plugin bundle")
 }
@@ -47,12 +95,17 @@ pub fn list_plugins_in_bundle(bundle: &PluginBundle) -> Result<Vec<PluginDescrip
             let name = desc.name()?.to_str().ok()?.to_string();
             let vendor = desc.vendor().and_then(|v| v.to_str().ok().map(String::from));
             let version = desc.version().and_then(|v| v.to_str().ok().map(String::from));
+            let features = desc
+                .features()
+                .filter_map(|f| f.to_str().ok().map(String::from))
+                .collect();
             Some(PluginDescriptorInfo {
                 index: i,
                 id,
                 name,
                 vendor,
                 version,
+                features,
             })
         })
         .collect();
@@ -112,6 +165,112 @@ pub fn select_plugin_id(
     CString::new(selected.id.as_str()).context("Invalid plugin ID string")
 }
 
+pub fn find_descriptor(bundle: &PluginBundle, plugin_id: &CStr) -> Result<PluginDescriptorInfo> {
+    let id = plugin_id.to_str().context("Invalid plugin ID string")?;
+    list_plugins_in_bundle(bundle)?
+        .into_iter()
+        .find(|p| p.id == id)
+        .ok_or_else(|| anyhow!("Plugin with id '{}' not found", id))
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioPortSummary {
+    pub name: String,
+    pub channel_count: u32,
+    pub is_main: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AudioPortLayout {
+    pub inputs: Vec<AudioPortSummary>,
+    pub outputs: Vec<AudioPortSummary>,
+}
+
+impl AudioPortLayout {
+    /// Index of the port flagged as main, falling back to the first port.
+    pub fn main_input(&self) -> Option<usize> {
+        main_port(&self.inputs)
+    }
+
+    pub fn main_output(&self) -> Option<usize> {
+        main_port(&self.outputs)
+    }
+
+    /// Channel count of the main output port, if there is one.
+    pub fn main_output_channels(&self) -> Option<u16> {
+        self.main_output().map(|port| self.outputs[port].channel_count as u16)
+    }
+}
+
+fn main_port(ports: &[AudioPortSummary]) -> Option<usize> {
+    ports.iter().position(|p| p.is_main).or((!ports.is_empty()).then_some(0))
+}
+
+/// Reads the plugin's declared audio ports. Plugins without the audio-ports
+/// extension report no ports.
+pub fn query_audio_ports<H: HostHandlers>(instance: &mut PluginInstance<H>) -> AudioPortLayout {
+    let ports_ext: Option<PluginAudioPorts> = instance.plugin_handle().get_extension();
+
+    let Some(ports_ext) = ports_ext else {
+        return AudioPortLayout::default();
+    };
+
+    let mut handle = instance.plugin_handle();
+    let mut buffer = AudioPortInfoBuffer::new();
+    let mut layout = AudioPortLayout::default();
+
+    for is_input in [true, false] {
+        for i in 0..ports_ext.count(&mut handle, is_input) {
+            let Some(info) = ports_ext.get(&mut handle, i, is_input, &mut buffer) else {
+                continue;
+            };
+            let summary = AudioPortSummary {
+                name: String::from_utf8_lossy(info.name).trim_end_matches('\0').to_string(),
+                channel_count: info.channel_count,
+                is_main: info.flags.contains(AudioPortFlags::IS_MAIN),
+            };
+            if is_input {
+                layout.inputs.push(summary);
+            } else {
+                layout.outputs.push(summary);
+            }
+        }
+    }
+
+    layout
+}
+
+/// Latency the plugin reports through the latency extension, in samples.
+/// Only meaningful once the plugin is active; 0 without the extension.
+pub fn query_latency<H: HostHandlers>(instance: &mut PluginInstance<H>) -> u32 {
+    let latency_ext: Option<PluginLatency> = instance.plugin_handle().get_extension();
+    latency_ext.map_or(0, |ext| ext.get(&mut instance.plugin_handle()))
+}
+
+/// Voice count and capacity the plugin reports through the voice-info
+/// extension; `None` without it.
+pub fn query_voice_info<H: HostHandlers>(instance: &mut PluginInstance<H>) -> Option<VoiceInfo> {
+    let voice_info_ext: Option<PluginVoiceInfo> = instance.plugin_handle().get_extension();
+    voice_info_ext.and_then(|ext| ext.get(&mut instance.plugin_handle()))
+}
+
+/// Whether the plugin's first note input takes CLAP note events, the only
+/// dialect that carries note expressions. Plugins without the note-ports
+/// extension take no notes at all.
+pub fn supports_note_expressions<H: HostHandlers>(instance: &mut PluginInstance<H>) -> bool {
+    let note_ports_ext: Option<PluginNotePorts> = instance.plugin_handle().get_extension();
+    let Some(note_ports_ext) = note_ports_ext else {
+        return false;
+    };
+
+    let mut handle = instance.plugin_handle();
+    let mut buffer = NotePortInfoBuffer::new();
+    note_ports_ext.count(&mut handle, true) > 0
+        && note_ports_ext
+            .get(&mut handle, 0, true, &mut buffer)
+            .is_some_and(|info| info.supported_dialects.contains(NoteDialects::CLAP))
+}
+
 pub fn enumerate_params<H: HostHandlers>(
     instance: &mut PluginInstance<H>,
 ) -> Vec<ParamInfo> {
@@ -144,33 +303,215 @@ pub fn enumerate_params<H: HostHandlers>(
                     .contains(ParamInfoFlags::IS_MODULATABLE_PER_NOTE_ID),
                 is_automatable: info.flags.contains(ParamInfoFlags::IS_AUTOMATABLE),
                 is_stepped: info.flags.contains(ParamInfoFlags::IS_STEPPED),
+                default_text: None,
+                min_text: None,
+                max_text: None,
+                step_texts: Vec::new(),
             });
         }
     }
 
+    describe_values(instance, &mut result);
     result
 }
 
-pub fn print_osc_api(params: &[ParamInfo]) {
+/// Fills in `default_text`, `min_text`, `max_text` and, for stepped params,
+/// `step_texts` using the plugin's `value_to_text`. Plugins without it leave
+/// them empty.
+pub fn describe_values<H: HostHandlers>(instance: &mut PluginInstance<H>, params: &mut [ParamInfo]) {
+    for param in params {
+        param.default_text = value_to_text(instance, param.id, param.default_value);
+        param.min_text = value_to_text(instance, param.id, param.min_value);
+        param.max_text = value_to_text(instance, param.id, param.max_value);
+        if param.is_stepped && param.max_value - param.min_value < MAX_STEP_TEXTS {
+            let mut step = param.min_value.round();
+            while step <= param.max_value {
+                let Some(text) = value_to_text(instance, param.id, step) else {
+                    break;
+                };
+                param.step_texts.push((step, text));
+                step += 1.0;
+            }
+        }
+    }
+}
+
+/// Renders `value` the way the plugin displays it. Main thread only.
+pub fn value_to_text<H: HostHandlers>(instance: &mut PluginInstance<H>, param_id: u32, value: f64) -> Option<String> {
+    let params_ext: Option<PluginParams> = instance.plugin_handle().get_extension();
+    let clap_id = ClapId::from_raw(param_id)?;
+    let mut buffer = [MaybeUninit::<u8>::uninit(); 256];
+
+    let mut handle = instance.plugin_handle();
+    let text = params_ext?.value_to_text(&mut handle, clap_id, value, &mut buffer)?;
+    Some(String::from_utf8_lossy(text).trim_end_matches('\0').to_string())
+}
+
+/// The parameter's live value. Main thread only.
+pub fn param_value<H: HostHandlers>(instance: &mut PluginInstance<H>, param_id: u32) -> Option<f64> {
+    let params_ext: Option<PluginParams> = instance.plugin_handle().get_extension();
+    let clap_id = ClapId::from_raw(param_id)?;
+    params_ext?.get_value(&mut instance.plugin_handle(), clap_id)
+}
+
+/// The parameter's live value and how the plugin shows it, the raw number
+/// if it can't. What `/param/text` replies with. Main thread only.
+pub fn live_value_text<H: HostHandlers>(instance: &mut PluginInstance<H>, param_id: u32) -> Option<(f64, String)> {
+    let value = param_value(instance, param_id)?;
+    Some((value, format_value(value_to_text(instance, param_id, value).as_deref(), value)))
+}
+
+/// `text` if the plugin rendered one, otherwise the raw number.
+pub fn format_value(text: Option<&str>, value: f64) -> String {
+    match text {
+        Some(text) => text.to_string(),
+        None => format!("{:.4}", value),
+    }
+}
+
+/// `plugins` holds each plugin's name and params in chain order.
+pub fn print_osc_api(plugins: &[(&str, &[ParamInfo])]) {
     println!("=== OSC API ===\n");
 
     println!("Note Control:");
     println!("  /note/on     note_id:i32  key:i32  vel:f32  [chan:i32=0]  [port:i32=0]");
-    println!("  /note/off    note_id:i32  key:i32  vel:f32  [chan:i32=0]  [port:i32=0]");
+    println!("               (a fractional key, e.g. 60.33, plays the nearest key plus a tuning expression)");
+    println!("  /note/off    note_id:i32  key:i32  [vel:f32=0]  [chan:i32=0]  [port:i32=0]");
     println!("  /note/choke  note_id:i32  [key:i32=-1]  [chan:i32=-1]  [port:i32=-1]");
+    println!("  /note/expression  note_id:i32  expression:i32|s  value:f64  [key:i32=-1]  [chan:i32=-1]  [port:i32=-1]");
+    println!("  /note/expr        same as /note/expression");
+    println!("                    expression: 0 volume, 1 pan, 2 tuning, 3 vibrato, 4 expression, 5 brightness, 6 pressure");
+    println!("  /note/pitchbend   note_id:i32  semitones:f64  [key:i32=-1]  [chan:i32=-1]  [port:i32=-1]");
+    println!("                    (per-note tuning; CLAP tuning is in semitones, -120..120, so 0.5 is a quarter tone;");
+    println!("                     note_id -1 bends every note matching key, chan and port)");
+    println!("  /note/play     key:i32  vel:f32  [chan:i32=0]  [port:i32=0]   (host assigns the note id)");
+    println!("  /note/release  key:i32  [vel:f32=0]  [chan:i32=0]  [port:i32=0]   (ends the voice /note/play started)");
+    println!("  /note/all-off  (releases every voice started by /note/play; --retrigger-mode choke|stack)");
+    println!("  /notes/off   chan:i32   (note off for every note on the channel, -1 for all channels;");
+    println!("                           releases play out, unlike /note/choke which cuts voices at once)");
+    println!("  /panic       (choke and release every note in every plugin, ahead of anything queued)");
+    println!("  /pedal/sustain chan:i32  down:i32   (hold note-offs on the channel, -1 for all, until the pedal lifts)");
+    println!("  /channel/bend  chan:i32  semitones:f64   (tunes every note on the channel, within --bend-range)");
+    println!("  /pitchbend     same as /channel/bend");
+    println!("  (a /note/off velocity of 0 is replaced by --default-release-velocity if set;");
+    println!("   with -v the [AUDIO-EVENT] log shows the release velocity each plugin received)");
     println!();
 
     println!("Parameter Control:");
-    println!("  /param/set   param_id:i32  value:f64");
+    println!("  /param/set   param_id:i32  value:f64   (0..1 with --normalized-params)");
+    println!("  /param/setn  param_id:i32  value:f64   (0..1 mapped onto the param range)");
+    println!("  /param/setMany  param_id:i32  value:f64  ...   (pairs, all applied on the same sample)");
     println!("  /param/mod   note_id:i32  param_id:i32  amount:f64  [key:i32=-1]  [chan:i32=-1]  [port:i32=-1]");
+    println!("  /param/ramp  param_id:i32  target:f64  duration_ms:f64   (linear, one value per block; stepped params jump)");
+    println!("  /param/inc   param_id:i32  delta:f64   (adds to the current value, clamped to the range)");
+    println!("  /param/setname  name:s  value:f64   (name or module/name, case-insensitive)");
+    println!("  /param/setByName  module:s  name:s  value:f64   (exact module, \"\" for none; case-insensitive)");
+    println!("  /param/text  param_id:i32  -> /param/text param_id:i32  value:f64  text:s   (live value as the plugin shows it)");
+    println!("  /param/get   param_id:i32  -> /param/get param_id:i32  value:f64  text:s   (same as /param/text)");
+    println!("  /params/list   -> /params/entry id:i32  name:s  module:s  min:f64  max:f64  default:f64  stepped:i32");
+    println!("                    perNoteMod:i32   per param in id order, then /params/end count:i32");
+    println!("  /param/modname  note_id:i32  name:s  amount:f64  [key:i32=-1]  [chan:i32=-1]  [port:i32=-1]");
+    println!();
+
+    println!("Plugin Chain (--chain):");
+    println!("  /plugin/<index>/note/...    same args as /note/*, for one plugin of the chain");
+    println!("  /plugin/<index>/param/...   same args as /param/*, ids and names from that plugin's table");
+    println!("  /plugin/<index>/params/list   that plugin's /params/list");
+    println!("  /<index>/note/..., /<index>/param/...   short forms of the above");
+    println!("  (without the prefix, notes go to the first instrument and params to plugin 0)");
+    println!("  /plugin/load   path:s  [plugin_id:s]   (replaces plugin 0; replies");
+    println!("                             /plugin/loaded name:s id:s param_count:i32)");
+    println!();
+
+    println!("Transport:");
+    println!("  /transport/tempo  bpm:f64     (1-999, default 120)");
+    println!("  /transport/play");
+    println!("  /transport/stop");
+    println!("  /transport/seek   beats:f64   (song position in quarter notes)");
+    println!("  /transport/position  same as /transport/seek");
+    println!();
+
+    println!("Host:");
+    println!("  /host/status   -> /host/status level:i32 stage:s occupancy:f32 pending:i32 dropped:i64");
+    println!("  /stats         -> /stats xruns:i64 late_callbacks:i64 blocks:i64 peak_dsp_load:f32");
+    println!("  /host/stats    -> /host/stats received:i64 queued:i64 dropped:i64 delivered:i64 peak_occupancy:f32");
+    println!("  /host/config   -> /host/config key:s value ... , /host/config/end count:i32");
+    println!("  /host/latency  -> /host/latency samples:i32  ms:f32   (total plugin latency)");
+    println!("  /latency       -> /latency samples:i32  ms:f32   (same as /host/latency)");
+    println!("  /voices        -> /voices count:i32  capacity:i32   (main plugin's voices; /voices/count is the same)");
+    println!("  /host/eventStats -> /host/eventStats metric:s total:i64 max:i32 mean:f32 buckets:i64...,");
+    println!("                      /host/eventStats/end blocks:i64");
+    println!("  /host/testTone freq:f32  gain:f32  on:i32   (test signal on/off, see --test-tone)");
+    println!("  /mix/master gain:f32           (host output gain, -4..4; negative inverts phase)");
+    println!("  /mix/channel ch:i32  gain:f32  (gain of one output channel, times the master)");
+    println!("  /host/gain db:f32              (master gain in dB, up to +12; fades over 10 ms)");
+    println!("  /host/mute on:i32              (silence the output (1) or bring it back (0))");
+    println!("  /host/reloadConfig -> /host/reloadConfig alias_count:i32, or /host/error message:s");
+    println!();
+
+    println!("Plugin State:");
+    println!("  /patchState    [path:s]   (saves to path, or replies /patchState plugin_id:s state:s");
+    println!("                             with the CLAP state base64-encoded)");
+    println!("  /state/save    path:s");
+    println!("  /state/load    path:s     (a file written by /state/save or /patchState path,");
+    println!("                             or raw plugin state)");
+    println!("  /loadState     path:s     (same as /state/load)");
+    println!("  /preset/load   location_uri:s  [load_key:s]   (a preset from --list-presets; replies");
+    println!("                             /preset/loaded location_uri:s load_key:s)");
+    println!();
+
+    println!("Plugin parameter changes (sent to --osc-send-host:--osc-send-port):");
+    println!("  /param/changed param_id:i32  value:f32");
+    println!("  /param/gesture param_id:i32  begin:i32   (1 = gesture begin, 0 = end)");
+    println!("  /param/value   param_id:i32  value:f64   (to --osc-send-host:--feedback-port)");
     println!();
 
-    println!("=== Parameter Table ===\n");
+    println!("Plugin note output (sent to --osc-send-host:--osc-send-port, /plugin/<index> prefix for chained plugins):");
+    println!("  /note/end      note_id:i32  key:i32  chan:i32   (a voice finished its release)");
+    println!("  /note/out/on   note_id:i32  key:i32  vel:f32  chan:i32  port:i32");
+    println!("  /note/out/off  note_id:i32  key:i32  vel:f32  chan:i32  port:i32");
+    println!("  /midi/out      status:i32  data1:i32  data2:i32  port:i32");
+    println!();
+
+    println!("Notifications (sent to each --notify address):");
+    println!("  /host/panic    message:s   (a thread panicked; the host is shutting down)");
+    println!("  /host/error    message:s   (a --watch-config reload failed; previous aliases kept)");
+    println!();
+
+    println!("Cues:");
+    println!("  /cue/set       name:s   (marks the current sample position)");
+    println!("  /cue/relative  name:s   (first message of a bundle: the bundle timetag is read as");
+    println!("                           seconds after the cue and its messages are scheduled there)");
+    println!();
+
+    println!("Aliases:");
+    println!("  /alias/add     address:s  param_id:i32");
+    println!("  /alias/remove  address:s");
+    println!("  /alias/list    -> /alias/entry address:s param_id:i64 ..., /alias/end count:i32");
+    println!("  <alias>        value:f64   (same as /param/set)");
+    println!("  <alias>/norm   value:f64   (0..1 mapped onto the param range)");
+    println!("  <alias>/text   value:s     (parsed by the plugin, e.g. \"440 Hz\")");
+    println!();
+
+    for (index, (name, params)) in plugins.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        if plugins.len() == 1 {
+            println!("=== Parameter Table ===\n");
+        } else {
+            println!("=== Parameter Table: plugin {} ({}) ===\n", index, name);
+        }
+        print_param_table(params);
+    }
+}
+
+fn print_param_table(params: &[ParamInfo]) {
     println!(
-        "{:>8}  {:40}  {:30}  {:>12}  {:>12}  {:>12}  {:>8}  {:>12}",
-        "ID", "Name", "Module", "Min", "Max", "Default", "Stepped", "Per-Note Mod"
+        "{:>8}  {:40}  {:30}  {:>12}  {:>12}  {:>12}  {:16}  {:>8}  {:>12}",
+        "ID", "Name", "Module", "Min", "Max", "Default", "Default Text", "Stepped", "Per-Note Mod"
     );
-    println!("{}", "-".repeat(150));
+    println!("{}", "-".repeat(168));
 
     for param in params {
         let per_note = if param.is_modulatable_per_note_id {
@@ -181,16 +522,20 @@ pub fn print_osc_api(params: &[ParamInfo]) {
         let stepped = if param.is_stepped { "YES" } else { "NO" };
 
         println!(
-            "{:>8}  {:40}  {:30}  {:>12.4}  {:>12.4}  {:>12.4}  {:>8}  {:>12}",
+            "{:>8}  {:40}  {:30}  {:>12.4}  {:>12.4}  {:>12.4}  {:16}  {:>8}  {:>12}",
             param.id,
             truncate(&param.name, 40),
             truncate(&param.module, 30),
             param.min_value,
             param.max_value,
             param.default_value,
+            truncate(&format_value(param.default_text.as_deref(), param.default_value), 16),
             stepped,
             per_note,
         );
+        if let Some(texts) = value_texts(param) {
+            println!("{:>8}  {}", "", texts);
+        }
     }
 
     println!();
@@ -199,63 +544,133 @@ pub fn print_osc_api(params: &[ParamInfo]) {
     println!("Per-note modulatable: {}", per_note_count);
 }
 
+/// The line under a param's row in the table: every step of a stepped param
+/// as the plugin names it, or else its range in the plugin's units.
+fn value_texts(param: &ParamInfo) -> Option<String> {
+    if !param.step_texts.is_empty() {
+        let steps: Vec<String> = param.step_texts.iter().map(|(value, text)| format!("{} = {}", value, text)).collect();
+        Some(format!("steps: {}", steps.join(", ")))
+    } else if param.min_text.is_some() || param.max_text.is_some() {
+        Some(format!(
+            "range: {} .. {}",
+            format_value(param.min_text.as_deref(), param.min_value),
+            format_value(param.max_text.as_deref(), param.max_value)
+        ))
+    } else {
+        None
+    }
+}
+
 fn truncate(s: &str, max_len: usize) -> String {
-    if s.len() <= max_len {
+    if s.chars().count() <= max_len {
         s.to_string()
     } else {
-        format!("{}...", &s[..max_len - 3])
+        format!("{}...", s.chars().take(max_len - 3).collect::<String>())
     }
 }
 
-pub fn dump_patch_state<H: HostHandlers>(
+/// Converts display text (e.g. "440 Hz") to a plain value via the plugin.
+pub fn text_to_value<H: HostHandlers>(
     instance: &mut PluginInstance<H>,
-    params: &[ParamInfo],
-) -> Result<String> {
+    param_id: u32,
+    text: &str,
+) -> Result<f64> {
     let params_ext: Option<PluginParams> = instance.plugin_handle().get_extension();
-    
-    let Some(params_ext) = params_ext else {
-        return Err(anyhow!("Plugin does not support params extension"));
-    };
-
-    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
-    let filename = format!("patchState_{}.txt", timestamp);
-    let mut file = File::create(&filename).context("Failed to create patch state file")?;
-
-    writeln!(file, "=== Patch State Dump ===")?;
-    writeln!(file, "Timestamp: {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"))?;
-    writeln!(file)?;
-    writeln!(
-        file,
-        "{:>12}  {:40}  {:30}  {:>12}  {:>12}  {:>12}",
-        "ID", "Name", "Module", "Current", "Min", "Max"
-    )?;
-    writeln!(file, "{}", "-".repeat(120))?;
+    let params_ext = params_ext.ok_or_else(|| anyhow!("Plugin does not support params extension"))?;
+    let clap_id = ClapId::from_raw(param_id).ok_or_else(|| anyhow!("Invalid parameter id {}", param_id))?;
+    let text = CString::new(text).context("Parameter text contains a NUL byte")?;
 
     let mut handle = instance.plugin_handle();
-    
-    for param in params {
-        let param_id = match ClapId::from_raw(param.id) {
-            Some(id) => id,
-            None => continue,
+    params_ext
+        .text_to_value(&mut handle, clap_id, &text)
+        .ok_or_else(|| anyhow!("Plugin could not parse {:?} for parameter {}", text, param_id))
+}
+
+/// Resolves `--param` entries (`12=0.5` or `Cutoff=0.5`) to param ids and
+/// values in plugin units. Later entries for the same param win.
+pub fn resolve_param_assignments(entries: &[String], params: &[ParamInfo]) -> Result<Vec<(u32, f64)>> {
+    let names = ParamNames::new(params);
+    let mut values: Vec<(u32, f64)> = Vec::new();
+    for entry in entries {
+        let (target, value) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow!("--param entry '{}' must be param=value", entry))?;
+        let param_id = match target.trim().parse::<u32>() {
+            Ok(id) => id,
+            Err(_) => names.resolve(target).map_err(|e| anyhow!("--param entry '{}': {}", entry, e))?,
         };
-        
-        let current_value = params_ext.get_value(&mut handle, param_id).unwrap_or(f64::NAN);
-        
-        writeln!(
-            file,
-            "{:>12}  {:40}  {:30}  {:>12.6}  {:>12.4}  {:>12.4}",
-            param.id,
-            truncate(&param.name, 40),
-            truncate(&param.module, 30),
-            current_value,
-            param.min_value,
-            param.max_value,
-        )?;
+        if !params.iter().any(|p| p.id == param_id) {
+            return Err(anyhow!("--param entry '{}': unknown parameter id {}", entry, param_id));
+        }
+        let value: f64 = value
+            .trim()
+            .parse()
+            .with_context(|| format!("--param entry '{}': value must be a number", entry))?;
+        values.retain(|(id, _)| *id != param_id);
+        values.push((param_id, value));
+    }
+    Ok(values)
+}
+
+/// Sets parameter values through the params extension's flush. The main
+/// thread may only flush while the plugin is inactive, so call this before
+/// activating it.
+pub fn flush_params<H: HostHandlers>(instance: &mut PluginInstance<H>, values: &[(u32, f64)]) -> Result<()> {
+    let params_ext: Option<PluginParams> = instance.plugin_handle().get_extension();
+    let params_ext = params_ext.ok_or_else(|| anyhow!("Plugin does not support params extension"))?;
+
+    let mut input = EventBuffer::new();
+    for &(param_id, value) in values {
+        let clap_id = ClapId::from_raw(param_id).ok_or_else(|| anyhow!("Invalid parameter id {}", param_id))?;
+        let pckn = Pckn::new(Match::All, Match::All, Match::All, Match::All);
+        input.push(&ParamValueEvent::new(0, clap_id, pckn, value, Cookie::empty()));
     }
+    let mut output = EventBuffer::new();
+    params_ext.flush(
+        &mut instance.plugin_handle(),
+        &InputEvents::from_buffer(&input),
+        &mut OutputEvents::from_buffer(&mut output),
+    );
+    Ok(())
+}
 
-    writeln!(file)?;
-    writeln!(file, "Total parameters: {}", params.len())?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_plugin::{self, GAIN, WAVEFORM};
+    use crossbeam_channel::unbounded;
+
+    #[test]
+    fn stepped_params_list_their_step_names() {
+        let (main_sender, _main_receiver) = unbounded();
+        let mut instance = test_plugin::instance(main_sender);
+        let params = enumerate_params(&mut instance);
+
+        let waveform = params.iter().find(|p| p.id == WAVEFORM).unwrap();
+        assert!(waveform.is_stepped);
+        assert_eq!(waveform.default_text.as_deref(), Some("Sine"));
+        let steps: Vec<(f64, &str)> = waveform.step_texts.iter().map(|(value, text)| (*value, text.as_str())).collect();
+        assert_eq!(steps, [(0.0, "Sine"), (1.0, "Saw"), (2.0, "Square")]);
+        assert_eq!(value_texts(waveform).as_deref(), Some("steps: 0 = Sine, 1 = Saw, 2 = Square"));
+
+        let gain = params.iter().find(|p| p.id == GAIN).unwrap();
+        assert!(gain.step_texts.is_empty());
+        assert_eq!(value_texts(gain).as_deref(), Some("range: 0.00 .. 1.00"));
+    }
 
-    log::info!("Patch state dumped to: {}", filename);
-    Ok(filename)
+    #[test]
+    fn param_text_shows_the_live_step_name() {
+        let (main_sender, _main_receiver) = unbounded();
+        let mut instance = test_plugin::instance(main_sender);
+        assert_eq!(live_value_text(&mut instance, WAVEFORM), Some((0.0, "Sine".to_string())));
+
+        let value = text_to_value(&mut instance, WAVEFORM, "square").unwrap();
+        flush_params(&mut instance, &[(WAVEFORM, value)]).unwrap();
+        assert_eq!(live_value_text(&mut instance, WAVEFORM), Some((2.0, "Square".to_string())));
+
+        // A value the plugin can't render falls back to the number.
+        assert_eq!(value_to_text(&mut instance, WAVEFORM, 7.0), None);
+        assert_eq!(format_value(None, 7.0), "7.0000");
+        assert_eq!(live_value_text(&mut instance, 99), None);
+    }
 }
@@ -0,0 +1,304 @@
+//! Factory presets (`--list-presets`, `/preset/load`). Presets are found
+//! through the bundle's preset-discovery factory and loaded by the plugin
+//! itself through its preset-load extension; the host never reads preset
+//! files.
+
+use anyhow::{anyhow, Context, Result};
+use clack_extensions::preset_discovery::{
+    FileType, Flags, IndexerImpl, Location, LocationInfo, MetadataReceiverImpl, PresetDiscoveryFactory, Provider,
+    Soundpack, Timestamp, UniversalPluginId,
+};
+use clack_extensions::preset_load::PluginPresetLoad;
+use clack_host::prelude::*;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How the plugin-internal location is written in URIs.
+const PLUGIN_LOCATION_URI: &str = "plugin:";
+
+pub struct PresetInfo {
+    pub name: String,
+    /// `file:///path/to/preset` or `plugin:`, as `/preset/load` takes it.
+    pub location_uri: String,
+    /// Picks the preset inside a location holding several; `None` if the
+    /// location is the preset.
+    pub load_key: Option<String>,
+    pub provider: String,
+    /// Plugin ids the preset is for; empty if the provider doesn't say.
+    pub plugin_ids: Vec<String>,
+}
+
+/// Where a preset lives, owned so it can outlive the provider's callbacks.
+#[derive(Debug, Clone)]
+pub enum PresetLocation {
+    File(PathBuf),
+    /// Built into the plugin.
+    Plugin,
+}
+
+impl PresetLocation {
+    pub fn from_clap(location: Location) -> Self {
+        match location {
+            Location::File { path } => Self::File(PathBuf::from(lossy(path))),
+            Location::Plugin => Self::Plugin,
+        }
+    }
+
+    pub fn uri(&self) -> String {
+        match self {
+            Self::File(path) => format!("file://{}", path.display()),
+            Self::Plugin => PLUGIN_LOCATION_URI.to_string(),
+        }
+    }
+
+    /// Reads a URI printed by `--list-presets`. A bare absolute path is
+    /// taken as a file.
+    pub fn parse(uri: &str) -> Result<Self> {
+        if uri == PLUGIN_LOCATION_URI {
+            return Ok(Self::Plugin);
+        }
+        let path = uri.strip_prefix("file://").unwrap_or(uri);
+        if !Path::new(path).is_absolute() {
+            return Err(anyhow!("Preset location {} is neither file:///absolute/path nor {}", uri, PLUGIN_LOCATION_URI));
+        }
+        Ok(Self::File(PathBuf::from(path)))
+    }
+
+    /// Calls `f` with the location in the form CLAP takes it.
+    pub fn with_clap<T>(&self, f: impl FnOnce(Location) -> T) -> Result<T> {
+        match self {
+            Self::File(path) => {
+                let path = CString::new(path.to_string_lossy().as_bytes())
+                    .with_context(|| format!("Preset path {} contains a NUL byte", path.display()))?;
+                Ok(f(Location::File { path: &path }))
+            }
+            Self::Plugin => Ok(f(Location::Plugin)),
+        }
+    }
+}
+
+/// Collects the locations and file types a provider declares.
+#[derive(Default)]
+struct Indexer {
+    locations: Vec<PresetLocation>,
+    /// Extensions of preset files, without the dot.
+    extensions: Vec<String>,
+    /// A file type without an extension: every file in a directory location
+    /// may be a preset.
+    any_file: bool,
+}
+
+impl IndexerImpl for Indexer {
+    fn declare_filetype(&mut self, file_type: FileType) -> Result<(), HostError> {
+        match file_type.file_extension {
+            Some(extension) => self.extensions.push(lossy(extension)),
+            None => self.any_file = true,
+        }
+        Ok(())
+    }
+
+    fn declare_location(&mut self, location: LocationInfo) -> Result<(), HostError> {
+        self.locations.push(PresetLocation::from_clap(location.location));
+        Ok(())
+    }
+
+    fn declare_soundpack(&mut self, _soundpack: Soundpack) -> Result<(), HostError> {
+        Ok(())
+    }
+}
+
+/// Turns one location's metadata into `PresetInfo`s.
+struct Collector<'a> {
+    provider: &'a str,
+    location_uri: String,
+    presets: Vec<PresetInfo>,
+}
+
+impl MetadataReceiverImpl for Collector<'_> {
+    fn on_error(&mut self, error_code: i32, error_message: Option<&CStr>) {
+        log::warn!(
+            "Preset provider {} failed to read {}: {} (error {})",
+            self.provider,
+            self.location_uri,
+            error_message.map_or_else(|| "no message".to_string(), lossy),
+            error_code
+        );
+    }
+
+    fn begin_preset(&mut self, name: Option<&CStr>, load_key: Option<&CStr>) -> Result<(), HostError> {
+        self.presets.push(PresetInfo {
+            name: name.map_or_else(|| "(unnamed)".to_string(), lossy),
+            location_uri: self.location_uri.clone(),
+            load_key: load_key.map(lossy),
+            provider: self.provider.to_string(),
+            plugin_ids: Vec::new(),
+        });
+        Ok(())
+    }
+
+    fn add_plugin_id(&mut self, plugin_id: UniversalPluginId) {
+        if let Some(preset) = self.presets.last_mut() {
+            preset.plugin_ids.push(lossy(plugin_id.id));
+        }
+    }
+
+    fn set_soundpack_id(&mut self, _soundpack_id: &CStr) {}
+
+    fn set_flags(&mut self, _flags: Flags) {}
+
+    fn add_creator(&mut self, _creator: &CStr) {}
+
+    fn set_description(&mut self, _description: &CStr) {}
+
+    fn set_timestamps(&mut self, _creation_time: Option<Timestamp>, _modification_time: Option<Timestamp>) {}
+
+    fn add_feature(&mut self, _feature: &CStr) {}
+
+    fn add_extra_info(&mut self, _key: &CStr, _value: &CStr) {}
+}
+
+/// Every preset the bundle's providers know about, provider by provider.
+pub fn list_presets(bundle: &PluginBundle) -> Result<Vec<PresetInfo>> {
+    let factory = bundle
+        .get_factory::<PresetDiscoveryFactory>()
+        .context("The bundle has no preset-discovery factory")?;
+
+    let mut presets = Vec::new();
+    for descriptor in factory.provider_descriptors() {
+        let Some(id) = descriptor.id() else {
+            continue;
+        };
+        let name = descriptor.name().map_or_else(|| lossy(id), lossy);
+        let mut provider = match Provider::instantiate(Indexer::default(), bundle, id) {
+            Ok(provider) => provider,
+            Err(e) => {
+                log::warn!("Skipping preset provider {}: {:?}", name, e);
+                continue;
+            }
+        };
+
+        let indexer = provider.indexer();
+        let locations: Vec<PresetLocation> = indexer
+            .locations
+            .iter()
+            .flat_map(|location| expand_location(location, &indexer.extensions, indexer.any_file))
+            .collect();
+        for location in locations {
+            let mut collector = Collector {
+                provider: &name,
+                location_uri: location.uri(),
+                presets: Vec::new(),
+            };
+            location.with_clap(|location| provider.get_metadata(location, &mut collector))?;
+            presets.extend(collector.presets);
+        }
+    }
+    Ok(presets)
+}
+
+pub fn print_presets(bundle: &PluginBundle) -> Result<()> {
+    let presets = list_presets(bundle)?;
+
+    if presets.is_empty() {
+        println!("No presets found in bundle.");
+        return Ok(());
+    }
+
+    println!("Presets in bundle (load with /preset/load location_uri [load_key]):");
+    for preset in &presets {
+        println!("  {} [{}]", preset.name, preset.provider);
+        println!("      uri: {}", preset.location_uri);
+        if let Some(load_key) = &preset.load_key {
+            println!("      load-key: {}", load_key);
+        }
+    }
+
+    Ok(())
+}
+
+/// Asks the plugin to load a preset through its preset-load extension. The
+/// plugin may also report the failure to the host, which logs it.
+pub fn load_preset<H: HostHandlers>(
+    instance: &mut PluginInstance<H>,
+    location: &PresetLocation,
+    load_key: Option<&str>,
+) -> Result<()> {
+    let preset_load: PluginPresetLoad = instance
+        .plugin_handle()
+        .get_extension()
+        .context("The plugin doesn't support loading presets")?;
+    let load_key = load_key
+        .map(CString::new)
+        .transpose()
+        .context("Preset load key contains a NUL byte")?;
+    let mut handle = instance.plugin_handle();
+    location
+        .with_clap(|clap_location| preset_load.from_location(&mut handle, clap_location, load_key.as_deref()))?
+        .map_err(|e| anyhow!("The plugin could not load preset {}: {:?}", location.uri(), e))
+}
+
+/// A directory location stands for the preset files under it, which the
+/// host has to find itself.
+fn expand_location(location: &PresetLocation, extensions: &[String], any_file: bool) -> Vec<PresetLocation> {
+    let PresetLocation::File(path) = location else {
+        return vec![location.clone()];
+    };
+    if !path.is_dir() {
+        return vec![location.clone()];
+    }
+    let mut files = Vec::new();
+    collect_preset_files(path, extensions, any_file, &mut files);
+    files.sort();
+    files.into_iter().map(PresetLocation::File).collect()
+}
+
+fn collect_preset_files(dir: &Path, extensions: &[String], any_file: bool, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("Can't read preset directory {}: {}", dir.display(), e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_preset_files(&path, extensions, any_file, files);
+        } else if any_file
+            || path
+                .extension()
+                .is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e.as_str())))
+        {
+            files.push(path);
+        }
+    }
+}
+
+pub fn lossy(text: &CStr) -> String {
+    text.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_plugin;
+
+    #[test]
+    fn listed_uris_parse_back() {
+        let path = std::env::temp_dir().join("bass.fxp");
+        let uri = format!("file://{}", path.display());
+        assert_eq!(PresetLocation::parse(&uri).unwrap().uri(), uri);
+        assert_eq!(PresetLocation::parse(&path.display().to_string()).unwrap().uri(), uri);
+        assert!(matches!(PresetLocation::parse("plugin:"), Ok(PresetLocation::Plugin)));
+        assert!(PresetLocation::parse("presets/bass.fxp").is_err());
+    }
+
+    #[test]
+    fn plugins_without_preset_load_are_refused() {
+        let (main_sender, _main_receiver) = crossbeam_channel::unbounded();
+        let mut instance = test_plugin::instance(main_sender);
+        let error = load_preset(&mut instance, &PresetLocation::Plugin, None).unwrap_err();
+        assert!(error.to_string().contains("doesn't support loading presets"), "{}", error);
+    }
+}
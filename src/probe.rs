@@ -0,0 +1,43 @@
+use crate::plugin::{AudioPortLayout, PluginDescriptorInfo};
+
+/// Checks whether the plugin's role matches how the host was set up and
+/// returns a warning for each mismatch.
+pub fn probe_setup(
+    descriptor: &PluginDescriptorInfo,
+    ports: &AudioPortLayout,
+    input_configured: bool,
+) -> Vec<String> {
+    let has_feature = |feature: &str| descriptor.features.iter().any(|f| f == feature);
+    let input_channels: u32 = ports.inputs.iter().map(|p| p.channel_count).sum();
+
+    let mut warnings = Vec::new();
+
+    if has_feature("audio-effect") && input_channels > 0 && !input_configured {
+        warnings.push(format!(
+            "'{}' is an audio effect with {} input channel(s), but no audio input is configured, \
+             so it will only ever process silence. Configure an input source for the plugin.",
+            descriptor.name, input_channels
+        ));
+    }
+
+    if has_feature("instrument") && !has_feature("audio-effect") && input_configured && input_channels == 0 {
+        warnings.push(format!(
+            "'{}' is an instrument without audio inputs; the configured input source will be ignored.",
+            descriptor.name
+        ));
+    }
+
+    warnings
+}
+
+pub fn print_probe_warnings(warnings: &[String]) {
+    if warnings.is_empty() {
+        return;
+    }
+    let rule = "!".repeat(72);
+    log::warn!("{}", rule);
+    for warning in warnings {
+        log::warn!("{}", warning);
+    }
+    log::warn!("{}", rule);
+}
@@ -0,0 +1,382 @@
+use crate::osc::{Command, CommandProducer, ScheduledCommand};
+use rtrb::{Producer, PushError};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// With coalescing on, each parameter reaches the queue at most about once
+/// per tick; the values in between are replaced by later ones.
+const PARAM_TICK: Duration = Duration::from_millis(1);
+
+/// How aggressively the OSC thread is shedding load, based on how full the
+/// command queue is. Each level includes the behaviour of the ones below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DegradationLevel {
+    Normal = 0,
+    /// Param sets are held back and coalesced, keeping only the latest per param.
+    CoalesceParams = 1,
+    /// Per-note modulation and note expressions are dropped.
+    DropParamMods = 2,
+    /// New notes are dropped. Note-offs and chokes are never dropped.
+    DropNoteOns = 3,
+}
+
+impl DegradationLevel {
+    fn from_occupancy(occupancy: f32) -> Self {
+        if occupancy >= 0.9 {
+            DegradationLevel::DropNoteOns
+        } else if occupancy >= 0.75 {
+            DegradationLevel::DropParamMods
+        } else if occupancy >= 0.5 {
+            DegradationLevel::CoalesceParams
+        } else {
+            DegradationLevel::Normal
+        }
+    }
+}
+
+impl fmt::Display for DegradationLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DegradationLevel::Normal => "normal",
+            DegradationLevel::CoalesceParams => "coalesce-params",
+            DegradationLevel::DropParamMods => "drop-param-mods",
+            DegradationLevel::DropNoteOns => "drop-note-ons",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+enum Priority {
+    /// Must reach the plugin eventually or notes get stuck.
+    Critical,
+    ParamSet,
+    ParamMod,
+    NoteOn,
+}
+
+fn priority(cmd: &Command) -> Priority {
+    match cmd {
+        Command::NoteOn { .. } => Priority::NoteOn,
+        Command::ParamSet { .. } => Priority::ParamSet,
+        // Ramps and increments aren't coalesced like sets, so they shed with the modulation.
+        Command::ParamMod { .. }
+        | Command::NoteExpression { .. }
+        | Command::ChannelBend { .. }
+        | Command::ParamRamp { .. }
+        | Command::ParamInc { .. } => Priority::ParamMod,
+        // A lost stop would leave the clock running, a lost pedal lift would
+        // hold notes forever, a lost forget would leave a ramp on a parameter
+        // the plugin dropped, and a partly lost batch (usually a preset
+        // recall) a mix of two sounds.
+        Command::NoteOff { .. }
+        | Command::NoteChoke { .. }
+        | Command::AllNotesOff { .. }
+        | Command::Transport(_)
+        | Command::Master(_)
+        | Command::Sustain { .. }
+        | Command::Panic
+        | Command::ForgetParam { .. }
+        | Command::ParamSetMany { .. } => Priority::Critical,
+    }
+}
+
+pub struct QueueStatus {
+    pub level: DegradationLevel,
+    pub occupancy: f32,
+    pub pending: usize,
+    pub dropped: u64,
+}
+
+/// Front end to the command queue used by the OSC thread. Under overload it
+/// sheds the least important commands first and holds critical ones until
+/// there is room, so note-offs are never lost.
+pub struct SheddingQueue {
+    producer: CommandProducer,
+    level: DegradationLevel,
+    pending_critical: VecDeque<ScheduledCommand>,
+    /// Latest value per (plugin, param), in the order each was first deferred.
+    /// Only unscheduled sets are coalesced; a scheduled one is a distinct event.
+    pending_params: Vec<(Option<usize>, u32, f64)>,
+    dropped: u64,
+    /// Whether sets are coalesced per tick even when the queue isn't backing
+    /// up (`--no-param-coalescing` turns it off).
+    coalesce: bool,
+    /// When held-back sets were last let through.
+    last_param_flush: Instant,
+}
+
+impl SheddingQueue {
+    pub fn new(producer: CommandProducer, coalesce: bool) -> Self {
+        Self {
+            producer,
+            level: DegradationLevel::Normal,
+            pending_critical: VecDeque::new(),
+            pending_params: Vec::new(),
+            dropped: 0,
+            coalesce,
+            last_param_flush: Instant::now(),
+        }
+    }
+
+    /// Returns false if the command was dropped.
+    pub fn push(&mut self, cmd: ScheduledCommand) -> bool {
+        let producer = self.producer.clone();
+        let mut producer = producer.lock().unwrap_or_else(|e| e.into_inner());
+
+        self.update_level(&producer);
+        // Anything but an immediate set lets held sets through first, so a
+        // set followed by a note still reaches the plugin in that order.
+        let is_plain_set = matches!(cmd.command, Command::ParamSet { .. }) && cmd.sample_time.is_none();
+        let tick_elapsed = !self.coalesce || self.last_param_flush.elapsed() >= PARAM_TICK;
+        self.drain_pending(&mut producer, !is_plain_set || tick_elapsed);
+
+        // Sets still held back for coalescing would land after the batch
+        // and undo it.
+        if let Command::ParamSetMany { pairs } = &cmd.command {
+            self.pending_params
+                .retain(|(plugin, id, _)| *plugin != cmd.plugin || !pairs.iter().any(|(param_id, _)| param_id == id));
+        }
+
+        match priority(&cmd.command) {
+            Priority::Critical => {
+                if !self.pending_critical.is_empty() {
+                    // Keep critical commands in order behind ones already waiting.
+                    self.pending_critical.push_back(cmd);
+                } else if let Err(PushError::Full(cmd)) = producer.push(cmd) {
+                    self.pending_critical.push_back(cmd);
+                }
+                true
+            }
+            // Once anything is deferred, later sets for the same param must
+            // not overtake it. With coalescing on, a set that comes less than
+            // a tick after the last one let through waits for the next tick.
+            Priority::ParamSet
+                if cmd.sample_time.is_none()
+                    && (self.level >= DegradationLevel::CoalesceParams
+                        || !self.pending_params.is_empty()
+                        || !tick_elapsed) =>
+            {
+                if let Command::ParamSet { param_id, value } = cmd.command {
+                    let plugin = cmd.plugin;
+                    match self.pending_params.iter_mut().find(|(p, id, _)| *p == plugin && *id == param_id) {
+                        Some(slot) => slot.2 = value,
+                        None => self.pending_params.push((plugin, param_id, value)),
+                    }
+                }
+                true
+            }
+            Priority::ParamMod if self.level >= DegradationLevel::DropParamMods => self.reject(),
+            Priority::NoteOn if self.level >= DegradationLevel::DropNoteOns => self.reject(),
+            _ => {
+                if producer.push(cmd).is_err() {
+                    return self.reject();
+                }
+                if is_plain_set {
+                    self.last_param_flush = Instant::now();
+                }
+                true
+            }
+        }
+    }
+
+    /// Retries anything held back. Called periodically by the OSC thread even
+    /// when no packets arrive.
+    pub fn flush(&mut self) {
+        if self.pending_critical.is_empty() && self.pending_params.is_empty() && self.level == DegradationLevel::Normal {
+            return;
+        }
+        let producer = self.producer.clone();
+        let mut producer = producer.lock().unwrap_or_else(|e| e.into_inner());
+        self.update_level(&producer);
+        let tick_elapsed = !self.coalesce || self.last_param_flush.elapsed() >= PARAM_TICK;
+        self.drain_pending(&mut producer, tick_elapsed);
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        let producer = self.producer.lock().unwrap_or_else(|e| e.into_inner());
+        QueueStatus {
+            level: self.level,
+            occupancy: occupancy(&producer),
+            pending: self.pending_critical.len() + self.pending_params.len(),
+            dropped: self.dropped,
+        }
+    }
+
+    fn reject(&mut self) -> bool {
+        self.dropped += 1;
+        false
+    }
+
+    fn update_level(&mut self, producer: &Producer<ScheduledCommand>) {
+        let level = DegradationLevel::from_occupancy(occupancy(producer));
+        if level > self.level {
+            log::warn!("Command queue backing up, entering degradation stage '{}'", level);
+        } else if level < self.level {
+            log::info!("Command queue recovering, leaving stage '{}' for '{}'", self.level, level);
+        }
+        self.level = level;
+    }
+
+    /// Retries held-back critical commands, then, with `params`, held sets.
+    fn drain_pending(&mut self, producer: &mut Producer<ScheduledCommand>, params: bool) {
+        while let Some(cmd) = self.pending_critical.pop_front() {
+            if let Err(PushError::Full(cmd)) = producer.push(cmd) {
+                self.pending_critical.push_front(cmd);
+                return;
+            }
+        }
+        if !params || self.pending_params.is_empty() {
+            return;
+        }
+        self.last_param_flush = Instant::now();
+
+        // Deferred params only go back in once the queue has real headroom.
+        while !self.pending_params.is_empty()
+            && DegradationLevel::from_occupancy(occupancy(producer)) == DegradationLevel::Normal
+        {
+            let (plugin, param_id, value) = self.pending_params.remove(0);
+            let cmd = ScheduledCommand {
+                sample_time: None,
+                plugin,
+                command: Command::ParamSet { param_id, value },
+            };
+            if producer.push(cmd).is_err() {
+                self.pending_params.insert(0, (plugin, param_id, value));
+                return;
+            }
+        }
+    }
+}
+
+fn occupancy(producer: &Producer<ScheduledCommand>) -> f32 {
+    let capacity = producer.buffer().capacity();
+    (capacity - producer.slots()) as f32 / capacity as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc::create_command_queue;
+    use rtrb::Consumer;
+
+    fn unscheduled(command: Command) -> ScheduledCommand {
+        ScheduledCommand {
+            sample_time: None,
+            plugin: None,
+            command,
+        }
+    }
+
+    fn note_on(note_id: i32) -> Command {
+        Command::NoteOn {
+            note_id,
+            key: 60,
+            velocity: 0.8,
+            channel: 0,
+            port: 0,
+            tuning: 0.0,
+        }
+    }
+
+    fn note_off(note_id: i32) -> Command {
+        Command::NoteOff {
+            note_id,
+            key: 60,
+            velocity: 0.0,
+            channel: 0,
+            port: 0,
+        }
+    }
+
+    fn drain(consumer: &mut Consumer<ScheduledCommand>, into: &mut Vec<Command>) {
+        while let Ok(scheduled) = consumer.pop() {
+            into.push(scheduled.command);
+        }
+    }
+
+    #[test]
+    fn flood_never_loses_critical_commands() {
+        let (producer, mut consumer) = create_command_queue(16);
+        let mut queue = SheddingQueue::new(producer, false);
+        let mut received = Vec::new();
+
+        // Far more than fits, with the audio thread only reading now and then.
+        for note_id in 0..500 {
+            queue.push(unscheduled(note_on(note_id)));
+            queue.push(unscheduled(Command::ParamSet {
+                param_id: (note_id % 4) as u32,
+                value: note_id as f64,
+            }));
+            queue.push(unscheduled(Command::NoteExpression {
+                expression_id: 2,
+                note_id,
+                key: 60,
+                channel: 0,
+                port: 0,
+                value: 0.5,
+            }));
+            queue.push(unscheduled(note_off(note_id)));
+            if note_id % 4 == 0 {
+                queue.push(unscheduled(Command::Sustain { channel: 0, down: note_id % 8 == 0 }));
+            }
+            if note_id % 50 == 0 {
+                drain(&mut consumer, &mut received);
+            }
+        }
+        queue.push(unscheduled(Command::Panic));
+
+        while queue.status().pending > 0 {
+            drain(&mut consumer, &mut received);
+            queue.flush();
+        }
+        drain(&mut consumer, &mut received);
+
+        assert!(queue.status().dropped > 0, "the flood should have shed something");
+        let offs: Vec<i32> = received
+            .iter()
+            .filter_map(|cmd| match cmd {
+                Command::NoteOff { note_id, .. } => Some(*note_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(offs, (0..500).collect::<Vec<_>>(), "every note-off, in order");
+        let pedals = received.iter().filter(|cmd| matches!(cmd, Command::Sustain { .. })).count();
+        assert_eq!(pedals, 125);
+        let panic = received.iter().position(|cmd| matches!(cmd, Command::Panic));
+        let last_off = received.iter().rposition(|cmd| matches!(cmd, Command::NoteOff { .. }));
+        assert!(panic > last_off, "the panic stays behind the note-offs queued before it");
+    }
+
+    #[test]
+    fn held_sets_keep_only_the_latest_value() {
+        let (producer, mut consumer) = create_command_queue(16);
+        let mut queue = SheddingQueue::new(producer, false);
+
+        // Fill past the coalescing threshold so sets are held back.
+        for note_id in 0..8 {
+            queue.push(unscheduled(note_on(note_id)));
+        }
+        for value in 0..10 {
+            queue.push(unscheduled(Command::ParamSet {
+                param_id: 7,
+                value: value as f64,
+            }));
+        }
+        assert_eq!(queue.status().pending, 1);
+
+        let mut received = Vec::new();
+        drain(&mut consumer, &mut received);
+        queue.flush();
+        drain(&mut consumer, &mut received);
+
+        let sets: Vec<f64> = received
+            .iter()
+            .filter_map(|cmd| match cmd {
+                Command::ParamSet { param_id: 7, value } => Some(*value),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sets, vec![9.0]);
+    }
+}
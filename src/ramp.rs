@@ -0,0 +1,170 @@
+//! Parameter ramps (`/param/ramp`, `--ramp-default-ms`). The audio thread
+//! sends a ramping parameter one value at the start of every block, moving
+//! linearly from where the parameter was to the target, so plugins that
+//! don't smooth their own parameters don't step audibly.
+
+/// Ramps running at once; a ramp beyond this jumps straight to its target.
+const MAX_RAMPS: usize = 64;
+
+struct Ramp {
+    stage: usize,
+    param_id: u32,
+    value: f64,
+    target: f64,
+    /// Samples until `value` reaches `target`.
+    remaining: u64,
+}
+
+impl Ramp {
+    fn advance(&mut self, frames: u64) {
+        if frames >= self.remaining {
+            self.value = self.target;
+            self.remaining = 0;
+        } else {
+            self.value += (self.target - self.value) * frames as f64 / self.remaining as f64;
+            self.remaining -= frames;
+        }
+    }
+}
+
+/// Active ramps and the last known value of every parameter in the chain,
+/// which is where a new ramp starts from.
+pub struct Ramps {
+    active: Vec<Ramp>,
+    /// Per stage, `(param_id, value)` sorted by id.
+    values: Vec<Vec<(u32, f64)>>,
+}
+
+impl Ramps {
+    /// `values` holds each stage's parameter values at activation.
+    pub fn new(mut values: Vec<Vec<(u32, f64)>>) -> Self {
+        for stage in &mut values {
+            stage.sort_by_key(|(id, _)| *id);
+        }
+        Self {
+            active: Vec::with_capacity(MAX_RAMPS),
+            values,
+        }
+    }
+
+    /// Records a value the host sent or the plugin reported. Any ramp on the
+    /// parameter stops there.
+    pub fn set(&mut self, stage: usize, param_id: u32, value: f64) {
+        self.active.retain(|r| r.stage != stage || r.param_id != param_id);
+        store(&mut self.values, stage, param_id, value);
+    }
+
+    /// Starts a ramp to `target` over `samples`, replacing any ramp already
+    /// on the parameter. `elapsed` samples of it pass in the current block.
+    /// Returns the value to send now when the ramp can't run: the parameter's
+    /// value isn't known or too many ramps are active.
+    pub fn start(&mut self, stage: usize, param_id: u32, target: f64, samples: u64, elapsed: u64) -> Option<f64> {
+        self.active.retain(|r| r.stage != stage || r.param_id != param_id);
+        let Some(value) = self.value(stage, param_id) else {
+            store(&mut self.values, stage, param_id, target);
+            return Some(target);
+        };
+        if self.active.len() >= MAX_RAMPS {
+            store(&mut self.values, stage, param_id, target);
+            return Some(target);
+        }
+        let mut ramp = Ramp {
+            stage,
+            param_id,
+            value,
+            target,
+            remaining: samples,
+        };
+        ramp.advance(elapsed);
+        self.active.push(ramp);
+        None
+    }
+
+    /// Calls `send(stage, param_id, value)` with each ramp's value at the
+    /// start of this block, then moves the ramps on by `frame_count`.
+    /// Finished ramps are removed once their target has been sent.
+    pub fn next_block(&mut self, frame_count: usize, mut send: impl FnMut(usize, u32, f64)) {
+        let values = &mut self.values;
+        self.active.retain_mut(|ramp| {
+            send(ramp.stage, ramp.param_id, ramp.value);
+            store(values, ramp.stage, ramp.param_id, ramp.value);
+            if ramp.remaining == 0 {
+                return false;
+            }
+            ramp.advance(frame_count as u64);
+            true
+        });
+    }
+
+    /// Swaps in the values of a plugin that replaced the one at `stage`,
+    /// sorted by id, leaving the old ones in `values`. The old plugin's
+    /// ramps stop.
+    pub fn replace_stage(&mut self, stage: usize, values: &mut Vec<(u32, f64)>) {
+        self.active.retain(|r| r.stage != stage);
+        if let Some(current) = self.values.get_mut(stage) {
+            std::mem::swap(current, values);
+        }
+    }
+
+    /// Drops a parameter the plugin cleared: its ramp stops and, with no
+    /// known value, a later ramp on the same id jumps to its target.
+    pub fn forget(&mut self, stage: usize, param_id: u32) {
+        self.active.retain(|r| r.stage != stage || r.param_id != param_id);
+        if let Some(values) = self.values.get_mut(stage) {
+            if let Ok(i) = values.binary_search_by_key(&param_id, |(id, _)| *id) {
+                values.remove(i);
+            }
+        }
+    }
+
+    /// The parameter's last known value, mid-ramp included.
+    pub fn value(&self, stage: usize, param_id: u32) -> Option<f64> {
+        let stage = self.values.get(stage)?;
+        let i = stage.binary_search_by_key(&param_id, |(id, _)| *id).ok()?;
+        Some(stage[i].1)
+    }
+}
+
+/// Parameters not enumerated at startup are not tracked.
+fn store(values: &mut [Vec<(u32, f64)>], stage: usize, param_id: u32, value: f64) {
+    if let Some(stage) = values.get_mut(stage) {
+        if let Ok(i) = stage.binary_search_by_key(&param_id, |(id, _)| *id) {
+            stage[i].1 = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloaded_values_replace_the_stage_and_stop_its_ramps() {
+        let mut ramps = Ramps::new(vec![vec![(1, 0.0), (2, 0.5)], vec![(1, 0.25)]]);
+        assert_eq!(ramps.start(0, 1, 1.0, 1000, 0), None);
+        assert_eq!(ramps.start(1, 1, 1.0, 1000, 0), None);
+
+        // What the main thread re-read after a state load on plugin 0.
+        let mut reloaded = vec![(1, 0.75), (2, 0.125), (3, 1.0)];
+        ramps.replace_stage(0, &mut reloaded);
+        assert_eq!(reloaded, vec![(1, 0.0), (2, 0.5)]);
+        assert_eq!(ramps.value(0, 1), Some(0.75));
+        assert_eq!(ramps.value(0, 3), Some(1.0));
+
+        let mut sent = Vec::new();
+        ramps.next_block(256, |stage, param_id, value| sent.push((stage, param_id, value)));
+        assert_eq!(sent, vec![(1, 1, 0.25)]);
+    }
+
+    #[test]
+    fn ramps_reach_their_target() {
+        let mut ramps = Ramps::new(vec![vec![(1, 0.0)]]);
+        assert_eq!(ramps.start(0, 1, 1.0, 512, 0), None);
+        let mut sent = Vec::new();
+        for _ in 0..4 {
+            ramps.next_block(256, |_, _, value| sent.push(value));
+        }
+        assert_eq!(sent, vec![0.0, 0.5, 1.0]);
+        assert_eq!(ramps.value(0, 1), Some(1.0));
+    }
+}
@@ -0,0 +1,145 @@
+//! Live recording (`--record`). The audio thread copies each block's output
+//! into a ring buffer and never waits; a writer thread drains it into a WAV
+//! file. If the writer falls behind, whole blocks are dropped and counted.
+
+use anyhow::{Context, Result};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Seconds of audio the ring buffer holds before blocks are dropped.
+const BUFFER_SECONDS: usize = 2;
+
+/// How long the writer sleeps when the ring buffer is empty.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often the writer reports newly dropped frames.
+const DROP_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Audio-thread end of the recorder.
+pub struct RecordProducer {
+    producer: Producer<f32>,
+    dropped_frames: Arc<AtomicU64>,
+}
+
+impl RecordProducer {
+    /// Queues a planar block as interleaved samples, or drops it whole if
+    /// there isn't room. Never blocks.
+    pub fn push_planar(&mut self, channel_buffers: &[f32], channel_count: usize, frame_count: usize) {
+        let samples = frame_count * channel_count;
+        let Ok(chunk) = self.producer.write_chunk_uninit(samples) else {
+            self.dropped_frames.fetch_add(frame_count as u64, Ordering::Relaxed);
+            return;
+        };
+        let interleaved = (0..frame_count)
+            .flat_map(|frame| (0..channel_count).map(move |ch| channel_buffers[ch * frame_count + frame]));
+        chunk.fill_from_iter(interleaved);
+    }
+}
+
+/// Main-thread end of the recorder; owns the writer thread.
+pub struct Recorder {
+    path: PathBuf,
+    running: Arc<AtomicBool>,
+    dropped_frames: Arc<AtomicU64>,
+    thread: Option<JoinHandle<Result<u64>>>,
+}
+
+impl Recorder {
+    /// Creates `path` and starts the writer thread.
+    pub fn start(path: &Path, sample_rate: u32, channel_count: usize) -> Result<(Self, RecordProducer)> {
+        let spec = WavSpec {
+            channels: channel_count as u16,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let writer = WavWriter::create(path, spec).with_context(|| format!("Failed to create {}", path.display()))?;
+
+        let (producer, consumer) = RingBuffer::new(sample_rate as usize * channel_count * BUFFER_SECONDS);
+        let running = Arc::new(AtomicBool::new(true));
+        let dropped_frames = Arc::new(AtomicU64::new(0));
+
+        let thread_running = running.clone();
+        let thread_dropped = dropped_frames.clone();
+        let thread = thread::Builder::new()
+            .name("recorder".into())
+            .spawn(move || write_loop(writer, consumer, thread_running, thread_dropped))
+            .context("Failed to spawn recorder thread")?;
+
+        log::info!("Recording output to {}", path.display());
+        Ok((
+            Self {
+                path: path.to_path_buf(),
+                running,
+                dropped_frames: dropped_frames.clone(),
+                thread: Some(thread),
+            },
+            RecordProducer {
+                producer,
+                dropped_frames,
+            },
+        ))
+    }
+
+    /// Writes what is still buffered, finalizes the WAV header and returns
+    /// the file's path. Call after the audio has stopped.
+    pub fn finish(mut self) -> Result<PathBuf> {
+        self.running.store(false, Ordering::Relaxed);
+        let samples = match self.thread.take().map(|thread| thread.join()) {
+            Some(Ok(result)) => result?,
+            Some(Err(_)) => anyhow::bail!("Recorder thread panicked; {} may be incomplete", self.path.display()),
+            None => 0,
+        };
+        let dropped = self.dropped_frames.load(Ordering::Relaxed);
+        if dropped > 0 {
+            log::warn!("Recording dropped {} frames in total", dropped);
+        }
+        log::info!("Recorded {} samples to {}", samples, self.path.display());
+        Ok(self.path.clone())
+    }
+}
+
+fn write_loop(
+    mut writer: WavWriter<BufWriter<File>>,
+    mut consumer: Consumer<f32>,
+    running: Arc<AtomicBool>,
+    dropped_frames: Arc<AtomicU64>,
+) -> Result<u64> {
+    let mut written = 0u64;
+    let mut reported_drops = 0;
+    let mut last_report = Instant::now();
+
+    loop {
+        // Read the flag first so nothing queued before the stop is missed.
+        let stopping = !running.load(Ordering::Relaxed);
+        let available = consumer.slots();
+        if available > 0 {
+            let chunk = consumer.read_chunk(available).context("Recorder ring buffer read failed")?;
+            for sample in chunk {
+                writer.write_sample(sample).context("Failed to write recording")?;
+            }
+            written += available as u64;
+        } else if stopping {
+            break;
+        } else {
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        let dropped = dropped_frames.load(Ordering::Relaxed);
+        if dropped > reported_drops && last_report.elapsed() >= DROP_REPORT_INTERVAL {
+            log::warn!("Recorder fell behind; dropped {} frames so far", dropped);
+            reported_drops = dropped;
+            last_report = Instant::now();
+        }
+    }
+
+    writer.finalize().context("Failed to finalize recording")?;
+    Ok(written)
+}
@@ -0,0 +1,96 @@
+//! Live reload of the alias file. Tables are parsed and validated off the
+//! OSC thread's hot path and handed over whole, so the OSC thread only ever
+//! sees the old table or a complete, valid new one.
+
+use crate::alias::AliasTable;
+use crate::osc::notify;
+use crate::plugin::ParamInfo;
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::{Receiver, Sender};
+use rosc::OscType;
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// How often `--watch-config` checks the alias file for edits.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Loads the alias file and checks every alias against the plugin's params.
+/// Reports all problems at once instead of stopping at the first.
+pub fn load_aliases(path: &Path, params: &HashMap<u32, ParamInfo>) -> Result<AliasTable> {
+    let table = AliasTable::load(path)?;
+
+    let errors: Vec<String> = table
+        .iter()
+        .filter(|(_, param_id)| !params.contains_key(param_id))
+        .map(|(address, param_id)| format!("alias {} targets unknown parameter id {}", address, param_id))
+        .collect();
+    if !errors.is_empty() {
+        return Err(anyhow!("{}: {}", path.display(), errors.join("; ")));
+    }
+
+    for collision in table.collisions() {
+        log::warn!(
+            "Alias {} shadows built-in {}; it is available as {} instead. Update controller templates.",
+            collision.original, collision.builtin, collision.rewritten
+        );
+    }
+    Ok(table)
+}
+
+/// Starts a thread that reloads the alias file whenever its contents change
+/// and sends each valid table to the OSC thread. Invalid edits are logged and
+/// reported to `notify` as `/host/error message:s`; the old table stays.
+pub fn watch_aliases(
+    path: PathBuf,
+    params: HashMap<u32, ParamInfo>,
+    notify_targets: Vec<SocketAddr>,
+) -> Result<Receiver<AliasTable>> {
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+    let initial = fs::read(&path).ok();
+
+    thread::Builder::new()
+        .name("config-watcher".into())
+        .spawn(move || run_watcher(path, params, notify_targets, initial, sender))
+        .context("Failed to spawn config watcher thread")?;
+
+    Ok(receiver)
+}
+
+fn run_watcher(
+    path: PathBuf,
+    params: HashMap<u32, ParamInfo>,
+    notify_targets: Vec<SocketAddr>,
+    mut last_contents: Option<Vec<u8>>,
+    sender: Sender<AliasTable>,
+) {
+    log::info!("Watching {} for changes", path.display());
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+
+        // A missing file is usually an editor mid-save; wait for it to reappear.
+        let Ok(contents) = fs::read(&path) else {
+            continue;
+        };
+        if last_contents.as_ref() == Some(&contents) {
+            continue;
+        }
+        last_contents = Some(contents);
+
+        match load_aliases(&path, &params) {
+            Ok(table) => {
+                log::info!("Reloaded {} aliases from {}", table.len(), path.display());
+                if sender.send(table).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                log::error!("Keeping previous aliases: {:#}", e);
+                notify(&notify_targets, "/host/error", vec![OscType::String(format!("{:#}", e))]);
+            }
+        }
+    }
+}
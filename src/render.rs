@@ -0,0 +1,211 @@
+//! Offline rendering (`--render script --out file.wav`). Messages from a
+//! timed script go through the same handling as live OSC, stamped with their
+//! sample offset, and the chain is processed in fixed blocks with no audio
+//! device or wall clock involved, so the same script renders the same file.
+//! `--deterministic` also pins what the plugins can't be trusted to, and
+//! reports the file's SHA-256.
+
+use crate::engine::OfflineRenderer;
+use crate::osc::OscReceiverState;
+use crate::script::load_timed_script;
+use anyhow::{Context, Result};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+pub struct RenderSummary {
+    pub frames: u64,
+    pub messages: usize,
+    /// Hex SHA-256 of the written file, with `--deterministic`.
+    pub sha256: Option<String>,
+}
+
+/// How long to render and in what format.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderOptions {
+    /// Seconds rendered past the last message (`--render-tail`).
+    pub tail: f64,
+    /// Exact length in seconds, overriding `tail` (`--render-duration`).
+    pub duration: Option<f64>,
+    /// `--deterministic`.
+    pub deterministic: bool,
+    pub sample_rate: u32,
+    pub channel_count: usize,
+}
+
+/// Renders `script` into `out` until `tail` seconds after its last message,
+/// or for exactly `duration` seconds when given. `between_blocks` runs on
+/// this thread after every block, for main-thread work the plugins ask for.
+///
+/// With `deterministic`, denormals are flushed to zero before every block,
+/// a plugin that changes that is reported, and the file's SHA-256 is
+/// returned. The test signal's noise, the host's only randomness, is
+/// always seeded the same.
+pub fn render_to_wav(
+    script: &Path,
+    out: &Path,
+    options: RenderOptions,
+    state: &mut OscReceiverState,
+    renderer: &mut OfflineRenderer,
+    mut between_blocks: impl FnMut(),
+) -> Result<RenderSummary> {
+    let RenderOptions {
+        tail,
+        duration,
+        deterministic,
+        sample_rate,
+        channel_count,
+    } = options;
+    if deterministic && !float_mode::SUPPORTED {
+        log::warn!("--deterministic can't pin denormal handling on this CPU; the plugins' own setting is used");
+    }
+    let mut entries = load_timed_script(script)?;
+    // Stable, so messages at the same offset keep their line order.
+    entries.sort_by_key(|entry| entry.sample_time);
+    let last = entries.iter().filter_map(|entry| entry.sample_time).max().unwrap_or(0);
+    let end = match duration {
+        Some(seconds) => {
+            let end = (seconds.max(0.0) * sample_rate as f64).round() as u64;
+            let skipped = entries.iter().filter(|entry| entry.sample_time.unwrap_or(0) >= end).count();
+            if skipped > 0 {
+                log::warn!("{} messages in {} fall after --render-duration and are skipped", skipped, script.display());
+            }
+            end
+        }
+        None => last + (tail.max(0.0) * sample_rate as f64).round() as u64,
+    };
+
+    let spec = WavSpec {
+        channels: channel_count as u16,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer =
+        WavWriter::create(out, spec).with_context(|| format!("Failed to create {}", out.display()))?;
+
+    let mut entries = entries.into_iter().peekable();
+    let mut messages = 0;
+    let mut float_mode_warned = false;
+    while renderer.position() < end {
+        let block_start = renderer.position();
+        let block_end = block_start + renderer.block_frames() as u64;
+        while let Some(entry) = entries.next_if(|entry| entry.sample_time.unwrap_or(0) < block_end) {
+            let sample_time = entry.sample_time;
+            let result = entry
+                .message
+                .and_then(|msg| state.handle_message_at(&msg, None, sample_time).map_err(|e| e.to_string()));
+            match result {
+                Ok(()) => messages += 1,
+                Err(e) => log::warn!("{}:{}: {}", script.display(), entry.line, e),
+            }
+        }
+
+        let frames = (end - block_start).min(renderer.block_frames() as u64) as usize;
+        if deterministic {
+            float_mode::flush_denormals();
+        }
+        let block = renderer.render_block();
+        if deterministic && !float_mode::flushing_denormals() && !float_mode_warned {
+            log::warn!(
+                "A plugin turned off flush-to-zero while rendering; the output may differ between machines or runs"
+            );
+            float_mode_warned = true;
+        }
+        for &sample in &block[..frames * channel_count] {
+            writer
+                .write_sample(sample)
+                .with_context(|| format!("Failed to write {}", out.display()))?;
+        }
+        between_blocks();
+    }
+    writer
+        .finalize()
+        .with_context(|| format!("Failed to finish {}", out.display()))?;
+
+    let sha256 = match deterministic {
+        true => Some(file_sha256(out)?),
+        false => None,
+    };
+    Ok(RenderSummary {
+        frames: end,
+        messages,
+        sha256,
+    })
+}
+
+fn file_sha256(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("Failed to read back {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to read back {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Denormal handling of the rendering thread, which also runs the plugins.
+/// Flush-to-zero and denormals-are-zero make results independent of how a
+/// CPU treats tiny values, and of whatever mode the thread was left in.
+mod float_mode {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    mod imp {
+        use std::arch::asm;
+
+        /// MXCSR flush-to-zero (bit 15) and denormals-are-zero (bit 6).
+        const FTZ_DAZ: u32 = 0x8040;
+
+        fn read() -> u32 {
+            let mut csr = 0u32;
+            // SAFETY: stores MXCSR into a local.
+            unsafe { asm!("stmxcsr [{}]", in(reg) &mut csr, options(nostack, preserves_flags)) };
+            csr
+        }
+
+        pub fn flush_denormals() {
+            let csr = read() | FTZ_DAZ;
+            // SAFETY: only the denormal bits change; rounding and exception masks are kept.
+            unsafe { asm!("ldmxcsr [{}]", in(reg) &csr, options(nostack, preserves_flags)) };
+        }
+
+        pub fn flushing_denormals() -> bool {
+            read() & FTZ_DAZ == FTZ_DAZ
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    mod imp {
+        use std::arch::asm;
+
+        /// FPCR flush-to-zero, which covers denormal inputs too.
+        const FZ: u64 = 1 << 24;
+
+        fn read() -> u64 {
+            let fpcr: u64;
+            // SAFETY: reads FPCR.
+            unsafe { asm!("mrs {}, fpcr", out(reg) fpcr, options(nomem, nostack, preserves_flags)) };
+            fpcr
+        }
+
+        pub fn flush_denormals() {
+            // SAFETY: only the flush-to-zero bit changes.
+            unsafe { asm!("msr fpcr, {}", in(reg) read() | FZ, options(nomem, nostack, preserves_flags)) };
+        }
+
+        pub fn flushing_denormals() -> bool {
+            read() & FZ != 0
+        }
+    }
+
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    mod imp {
+        pub fn flush_denormals() {}
+
+        pub fn flushing_denormals() -> bool {
+            true
+        }
+    }
+
+    pub use imp::{flush_denormals, flushing_denormals};
+
+    pub const SUPPORTED: bool = cfg!(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"));
+}
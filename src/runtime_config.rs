@@ -0,0 +1,122 @@
+use rosc::OscType;
+use std::sync::{Arc, RwLock};
+
+/// The configuration the host is actually running with, after defaults and
+/// device negotiation have been applied. Shared with the OSC thread so
+/// `/host/config` reflects runtime changes.
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeConfig {
+    pub plugin_path: String,
+    pub plugin_id: String,
+    pub plugin_name: String,
+    /// The cpal host the devices come from, e.g. `ALSA` or `JACK`.
+    pub audio_backend: String,
+    pub output: String,
+    pub sample_rate: u32,
+    pub buffer_size: u32,
+    pub channels: u16,
+    /// `--output-channels` as given, e.g. `3,4`.
+    pub output_channels: Option<String>,
+    pub sample_format: String,
+    pub osc_bind: String,
+    pub osc_port: u16,
+    /// TCP framing when `--osc-tcp` is on.
+    pub osc_tcp: Option<String>,
+    /// Port `--osc-tcp` listens on, the OSC port unless `--osc-tcp-port` is given.
+    pub osc_tcp_port: Option<u16>,
+    /// Largest UDP packet accepted, `--osc-buffer-size`.
+    pub osc_buffer_size: u32,
+    /// Slots in the command queue, `--queue-capacity`.
+    pub queue_capacity: u32,
+    /// Off with `--no-param-coalescing`.
+    pub param_coalescing: bool,
+    pub osc_send: Option<String>,
+    pub feedback: Option<String>,
+    pub alias_file: Option<String>,
+    pub osc_map: Option<String>,
+    pub init_script: Option<String>,
+    pub default_release_velocity: Option<f32>,
+    /// `--bend-range`, in semitones.
+    pub bend_range: f64,
+    /// `--tuning` file or `--edo` steps.
+    pub tuning: Option<String>,
+    pub edo: Option<u32>,
+    pub normalized_params: bool,
+    pub strict_setup: bool,
+    pub strict_config: bool,
+    pub device_reconnect: bool,
+    /// `--master-gain` in dB, as clamped.
+    pub master_gain_db: f32,
+    pub limiter: bool,
+    pub verbose: bool,
+    /// Total latency of the plugin chain in samples, as the plugins report it.
+    pub latency: u32,
+    /// The main plugin's `(voice_count, voice_capacity)` from the voice-info
+    /// extension.
+    pub voices: Option<(u32, u32)>,
+}
+
+pub type SharedRuntimeConfig = Arc<RwLock<RuntimeConfig>>;
+
+impl RuntimeConfig {
+    /// Flattens the config into `(key, value)` pairs for `/host/config` replies.
+    pub fn entries(&self) -> Vec<(&'static str, OscType)> {
+        let string = |s: &str| OscType::String(s.to_string());
+        let optional = |s: &Option<String>| OscType::String(s.clone().unwrap_or_default());
+
+        vec![
+            ("plugin_path", string(&self.plugin_path)),
+            ("plugin_id", string(&self.plugin_id)),
+            ("plugin_name", string(&self.plugin_name)),
+            ("audio_backend", string(&self.audio_backend)),
+            ("output", string(&self.output)),
+            ("sample_rate", OscType::Int(self.sample_rate as i32)),
+            ("buffer_size", OscType::Int(self.buffer_size as i32)),
+            ("channels", OscType::Int(self.channels as i32)),
+            ("output_channels", optional(&self.output_channels)),
+            ("sample_format", string(&self.sample_format)),
+            ("osc_bind", string(&self.osc_bind)),
+            ("osc_port", OscType::Int(self.osc_port as i32)),
+            ("osc_tcp", optional(&self.osc_tcp)),
+            ("osc_tcp_port", self.osc_tcp_port.map_or(OscType::Nil, |port| OscType::Int(port as i32))),
+            ("osc_buffer_size", OscType::Int(self.osc_buffer_size as i32)),
+            ("queue_capacity", OscType::Int(self.queue_capacity as i32)),
+            ("param_coalescing", OscType::Bool(self.param_coalescing)),
+            ("osc_send", optional(&self.osc_send)),
+            ("feedback", optional(&self.feedback)),
+            ("alias_file", optional(&self.alias_file)),
+            ("osc_map", optional(&self.osc_map)),
+            ("init_script", optional(&self.init_script)),
+            (
+                "default_release_velocity",
+                self.default_release_velocity.map(OscType::Float).unwrap_or(OscType::Nil),
+            ),
+            ("bend_range", OscType::Double(self.bend_range)),
+            ("tuning", optional(&self.tuning)),
+            ("edo", self.edo.map_or(OscType::Nil, |steps| OscType::Int(steps as i32))),
+            ("normalized_params", OscType::Bool(self.normalized_params)),
+            ("strict_setup", OscType::Bool(self.strict_setup)),
+            ("strict_config", OscType::Bool(self.strict_config)),
+            ("device_reconnect", OscType::Bool(self.device_reconnect)),
+            ("master_gain_db", OscType::Float(self.master_gain_db)),
+            ("limiter", OscType::Bool(self.limiter)),
+            ("verbose", OscType::Bool(self.verbose)),
+            ("latency", OscType::Int(self.latency as i32)),
+            ("voice_count", self.voices.map_or(OscType::Nil, |(count, _)| OscType::Int(count as i32))),
+            ("voice_capacity", self.voices.map_or(OscType::Nil, |(_, capacity)| OscType::Int(capacity as i32))),
+        ]
+    }
+
+    /// Arguments of a `/host/latency` reply: samples and milliseconds.
+    pub fn latency_args(&self) -> Vec<OscType> {
+        let ms = self.latency as f64 * 1000.0 / self.sample_rate.max(1) as f64;
+        vec![OscType::Int(self.latency as i32), OscType::Float(ms as f32)]
+    }
+
+    /// Arguments of a `/voices` reply: voice count and capacity, both 0 when
+    /// the plugin doesn't report them.
+    pub fn voices_args(&self) -> Vec<OscType> {
+        let (count, capacity) = self.voices.unwrap_or((0, 0));
+        vec![OscType::Int(count as i32), OscType::Int(capacity as i32)]
+    }
+}
@@ -0,0 +1,391 @@
+//! Sample-accurate timing on the audio thread. Commands stamped with a sample
+//! time past the current block are held until their block comes; the rest
+//! are due now, at their frame offset into the block. Late commands land at
+//! offset 0. Nothing here allocates once the schedule is built.
+
+use crate::osc::{Command, ScheduledCommand};
+use rtrb::Consumer;
+
+/// Scheduled commands the audio thread holds for future blocks.
+pub const MAX_SCHEDULED: usize = 1024;
+
+/// Commands taken off the queue in one block. The rest stay queued for the
+/// next block, so a burst can't stretch one callback.
+pub const MAX_DRAINED: usize = 512;
+
+/// Commands due in one block, as `(frame offset, arrival order, chain
+/// stage, command)`.
+pub type Due = Vec<(u32, u32, usize, Command)>;
+
+pub struct Schedule {
+    /// Commands whose sample time falls after the current block.
+    held: Vec<ScheduledCommand>,
+    /// What `held` is rebuilt into each block, then swapped with it, so
+    /// holding commands back never allocates.
+    still_held: Vec<ScheduledCommand>,
+    due: Due,
+    /// Entries `due` holds without growing.
+    due_capacity: usize,
+}
+
+impl Schedule {
+    /// `due_capacity` is how many commands one block can take without the
+    /// due list growing.
+    pub fn new(due_capacity: usize) -> Self {
+        Self {
+            held: Vec::with_capacity(MAX_SCHEDULED),
+            still_held: Vec::with_capacity(MAX_SCHEDULED),
+            due: Vec::with_capacity(due_capacity),
+            due_capacity,
+        }
+    }
+
+    /// Collects what is due in the `frame_count` frames from `block_start`:
+    /// held commands first, since they arrived before anything in the queue,
+    /// then up to `MAX_DRAINED` from the queue. `stage_for` picks the chain
+    /// stage of each command. The result is in time order; commands at the
+    /// same time keep their arrival order. An immediate `panic` goes ahead of
+    /// everything, and the notes held for later blocks are dropped before
+    /// collecting. Whatever doesn't fit in the due list waits for the next
+    /// block.
+    pub fn collect(
+        &mut self,
+        queue: &mut Consumer<ScheduledCommand>,
+        block_start: u64,
+        frame_count: usize,
+        panic: bool,
+        stage_for: impl Fn(Option<usize>, &Command) -> usize,
+        verbose: bool,
+    ) -> &mut Due {
+        let block_end = block_start + frame_count as u64;
+        let offset_in_block =
+            |sample_time: Option<u64>| sample_time.map_or(0, |t| t.saturating_sub(block_start) as u32);
+
+        self.due.clear();
+        if panic {
+            self.drop_held_notes();
+            self.due.push((0, 0, 0, Command::Panic));
+        }
+        for held in self.held.drain(..) {
+            if held.sample_time.is_some_and(|t| t >= block_end) || !fits(&self.due, self.due_capacity, &held.command)
+            {
+                self.still_held.push(held);
+            } else {
+                let (time, stage) = (offset_in_block(held.sample_time), stage_for(held.plugin, &held.command));
+                push_due(&mut self.due, time, stage, held.command);
+            }
+        }
+        std::mem::swap(&mut self.held, &mut self.still_held);
+
+        for _ in 0..MAX_DRAINED {
+            match queue.peek() {
+                Ok(next) if fits(&self.due, self.due_capacity, &next.command) => {}
+                _ => break,
+            }
+            let Ok(scheduled) = queue.pop() else {
+                break;
+            };
+            let cmd = scheduled.command;
+            if verbose {
+                log::info!("[AUDIO-DEQUEUE] Processing command: {:?} at {:?}", cmd, scheduled.sample_time);
+            }
+            let time = match scheduled.sample_time {
+                Some(t) if t >= block_end => {
+                    if self.held.len() < MAX_SCHEDULED {
+                        self.held.push(ScheduledCommand {
+                            sample_time: Some(t),
+                            plugin: scheduled.plugin,
+                            command: cmd,
+                        });
+                        continue;
+                    }
+                    // No room to hold it; play it now rather than lose it.
+                    0
+                }
+                // Late commands land at the start of this block.
+                sample_time => offset_in_block(sample_time),
+            };
+            push_due(&mut self.due, time, stage_for(scheduled.plugin, &cmd), cmd);
+        }
+
+        // CLAP wants events in time order. Same-time events keep their
+        // arrival order through the sequence number, without the buffer a
+        // stable sort allocates.
+        self.due.sort_unstable_by_key(|(time, seq, _, _)| (*time, *seq));
+        &mut self.due
+    }
+
+    /// Drops notes, expressions and per-voice modulation held for later
+    /// blocks, after a panic.
+    pub fn drop_held_notes(&mut self) {
+        self.held.retain(|s| {
+            !matches!(
+                s.command,
+                Command::NoteOn { .. } | Command::NoteExpression { .. } | Command::ParamMod { .. }
+            )
+        });
+    }
+}
+
+/// Whether `cmd` fits in the due list without it growing past `capacity`.
+fn fits(due: &Due, capacity: usize, cmd: &Command) -> bool {
+    let entries = match cmd {
+        Command::ParamSetMany { pairs } => pairs.len(),
+        _ => 1,
+    };
+    due.len() + entries <= capacity
+}
+
+/// Adds a command to the block's due list, numbered in arrival order. A
+/// `ParamSetMany` becomes one set per pair, all at `time`, so the plugin
+/// gets them on the same sample.
+fn push_due(due: &mut Due, time: u32, stage: usize, cmd: Command) {
+    match cmd {
+        Command::ParamSetMany { pairs } => {
+            for &(param_id, value) in &pairs {
+                due.push((time, due.len() as u32, stage, Command::ParamSet { param_id, value }));
+            }
+            free_batch(pairs);
+        }
+        cmd => due.push((time, due.len() as u32, stage, cmd)),
+    }
+}
+
+/// Frees a `/param/setMany` batch, the only heap memory a command carries.
+/// It is one free per batch, so it is let through the allocation check
+/// rather than sent back to be freed elsewhere.
+fn free_batch(pairs: Vec<(u32, f64)>) {
+    #[cfg(feature = "alloc-check")]
+    assert_no_alloc::permit_alloc(move || drop(pairs));
+    #[cfg(not(feature = "alloc-check"))]
+    drop(pairs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc::create_command_queue;
+
+    fn note_on(note_id: i32, sample_time: Option<u64>) -> ScheduledCommand {
+        ScheduledCommand {
+            sample_time,
+            plugin: None,
+            command: Command::NoteOn {
+                note_id,
+                key: 60,
+                velocity: 0.8,
+                channel: 0,
+                port: 0,
+                tuning: 0.0,
+            },
+        }
+    }
+
+    fn note_ids(due: &Due) -> Vec<(u32, i32)> {
+        due.iter()
+            .filter_map(|(time, _, _, cmd)| match cmd {
+                Command::NoteOn { note_id, .. } => Some((*time, *note_id)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn timed_commands_land_on_their_frame() {
+        let (producer, mut consumer) = create_command_queue(16);
+        let mut schedule = Schedule::new(16);
+        {
+            let mut producer = producer.lock().unwrap();
+            producer.push(note_on(1, Some(1100))).unwrap();
+            producer.push(note_on(2, Some(1000 + 37))).unwrap();
+            producer.push(note_on(3, None)).unwrap();
+        }
+
+        let due = schedule.collect(&mut consumer, 1000, 256, false, |_, _| 0, false);
+        assert_eq!(note_ids(due), vec![(0, 3), (37, 2), (100, 1)]);
+    }
+
+    #[test]
+    fn late_commands_play_at_the_start_of_the_block() {
+        let (producer, mut consumer) = create_command_queue(16);
+        let mut schedule = Schedule::new(16);
+        producer.lock().unwrap().push(note_on(1, Some(10))).unwrap();
+
+        let due = schedule.collect(&mut consumer, 1000, 256, false, |_, _| 0, false);
+        assert_eq!(note_ids(due), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn future_commands_wait_for_their_block() {
+        let (producer, mut consumer) = create_command_queue(16);
+        let mut schedule = Schedule::new(16);
+        producer.lock().unwrap().push(note_on(1, Some(600))).unwrap();
+
+        assert!(note_ids(schedule.collect(&mut consumer, 0, 256, false, |_, _| 0, false)).is_empty());
+        assert!(note_ids(schedule.collect(&mut consumer, 256, 256, false, |_, _| 0, false)).is_empty());
+        // Held commands go before a queued one at the same frame.
+        producer.lock().unwrap().push(note_on(2, Some(600))).unwrap();
+        let due = schedule.collect(&mut consumer, 512, 256, false, |_, _| 0, false);
+        assert_eq!(note_ids(due), vec![(88, 1), (88, 2)]);
+    }
+
+    #[test]
+    fn batches_split_into_sets_on_one_frame() {
+        let (producer, mut consumer) = create_command_queue(16);
+        let mut schedule = Schedule::new(16);
+        producer
+            .lock()
+            .unwrap()
+            .push(ScheduledCommand {
+                sample_time: Some(20),
+                plugin: Some(1),
+                command: Command::ParamSetMany {
+                    pairs: vec![(1, 0.5), (2, 0.25)],
+                },
+            })
+            .unwrap();
+
+        let due = schedule.collect(&mut consumer, 0, 256, false, |plugin, _| plugin.unwrap_or(0), false);
+        let sets: Vec<_> = due
+            .iter()
+            .map(|(time, _, stage, cmd)| match cmd {
+                Command::ParamSet { param_id, value } => (*time, *stage, *param_id, *value),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(sets, vec![(20, 1, 1, 0.5), (20, 1, 2, 0.25)]);
+    }
+
+    #[test]
+    fn immediate_panic_goes_first() {
+        let (producer, mut consumer) = create_command_queue(16);
+        let mut schedule = Schedule::new(16);
+        producer.lock().unwrap().push(note_on(1, Some(300))).unwrap();
+        schedule.collect(&mut consumer, 0, 256, false, |_, _| 0, false);
+        producer.lock().unwrap().push(note_on(2, None)).unwrap();
+
+        let due = schedule.collect(&mut consumer, 256, 256, true, |_, _| 0, false);
+        assert!(matches!(due[0], (0, _, 0, Command::Panic)));
+        // The held note was dropped; the one queued after the panic plays.
+        assert_eq!(note_ids(due), vec![(0, 2)]);
+    }
+
+    #[test]
+    fn panic_drops_held_notes_only() {
+        let (producer, mut consumer) = create_command_queue(16);
+        let mut schedule = Schedule::new(16);
+        {
+            let mut producer = producer.lock().unwrap();
+            producer.push(note_on(1, Some(1000))).unwrap();
+            producer
+                .push(ScheduledCommand {
+                    sample_time: Some(1000),
+                    plugin: None,
+                    command: Command::NoteOff {
+                        note_id: 0,
+                        key: 60,
+                        velocity: 0.0,
+                        channel: 0,
+                        port: 0,
+                    },
+                })
+                .unwrap();
+        }
+        schedule.collect(&mut consumer, 0, 256, false, |_, _| 0, false);
+        schedule.drop_held_notes();
+
+        let due = schedule.collect(&mut consumer, 768, 256, false, |_, _| 0, false);
+        assert_eq!(due.len(), 1);
+        assert!(matches!(due[0], (232, _, 0, Command::NoteOff { .. })));
+    }
+
+    #[test]
+    fn a_burst_is_drained_over_several_blocks() {
+        let (producer, mut consumer) = create_command_queue(MAX_DRAINED + 10);
+        let mut schedule = Schedule::new(2 * MAX_DRAINED);
+        {
+            let mut producer = producer.lock().unwrap();
+            for note_id in 0..MAX_DRAINED as i32 + 10 {
+                producer.push(note_on(note_id, None)).unwrap();
+            }
+        }
+
+        let first = note_ids(schedule.collect(&mut consumer, 0, 256, false, |_, _| 0, false));
+        assert_eq!(first.len(), MAX_DRAINED);
+        assert_eq!(first.last(), Some(&(0, MAX_DRAINED as i32 - 1)));
+        let second = note_ids(schedule.collect(&mut consumer, 256, 256, false, |_, _| 0, false));
+        assert_eq!(second.first(), Some(&(0, MAX_DRAINED as i32)));
+        assert_eq!(second.len(), 10);
+    }
+
+    #[test]
+    fn batches_that_overflow_the_due_list_wait_for_the_next_block() {
+        let (producer, mut consumer) = create_command_queue(16);
+        let mut schedule = Schedule::new(8);
+        let batch = |first: u32| ScheduledCommand {
+            sample_time: None,
+            plugin: None,
+            command: Command::ParamSetMany {
+                pairs: (first..first + 5).map(|id| (id, 0.5)).collect(),
+            },
+        };
+        {
+            let mut producer = producer.lock().unwrap();
+            producer.push(batch(0)).unwrap();
+            producer.push(batch(10)).unwrap();
+        }
+
+        assert_eq!(schedule.collect(&mut consumer, 0, 256, false, |_, _| 0, false).len(), 5);
+        let due = schedule.collect(&mut consumer, 256, 256, false, |_, _| 0, false);
+        assert!(matches!(due[0], (0, _, 0, Command::ParamSet { param_id: 10, .. })));
+        assert_eq!(due.len(), 5);
+    }
+
+    #[test]
+    fn same_time_commands_keep_their_arrival_order() {
+        let (producer, mut consumer) = create_command_queue(128);
+        let mut schedule = Schedule::new(256);
+        {
+            let mut producer = producer.lock().unwrap();
+            for note_id in 0..100 {
+                let time = if note_id % 2 == 0 { 50 } else { 10 };
+                producer.push(note_on(note_id, Some(time))).unwrap();
+            }
+        }
+
+        let due = note_ids(schedule.collect(&mut consumer, 0, 256, false, |_, _| 0, false));
+        let odd = (1..100).step_by(2).map(|id| (10, id));
+        let even = (0..100).step_by(2).map(|id| (50, id));
+        assert_eq!(due, odd.chain(even).collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "alloc-check")]
+    #[test]
+    fn collecting_does_not_allocate() {
+        let (producer, mut consumer) = create_command_queue(MAX_DRAINED);
+        let mut schedule = Schedule::new(2 * MAX_DRAINED);
+        {
+            let mut producer = producer.lock().unwrap();
+            for note_id in 0..200 {
+                producer.push(note_on(note_id, Some(1000 - note_id as u64))).unwrap();
+            }
+            producer
+                .push(ScheduledCommand {
+                    sample_time: Some(5),
+                    plugin: None,
+                    command: Command::ParamSetMany {
+                        pairs: (0..64).map(|id| (id, 0.5)).collect(),
+                    },
+                })
+                .unwrap();
+        }
+
+        assert_no_alloc::reset_violation_count();
+        assert_no_alloc::assert_no_alloc(|| {
+            for block in 0..8 {
+                schedule.collect(&mut consumer, block * 256, 256, block == 2, |_, _| 0, false);
+            }
+        });
+        assert_eq!(assert_no_alloc::violation_count(), 0);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::osc::OscReceiverState;
+use anyhow::{Context, Result};
+use rosc::{OscMessage, OscType};
+use std::fs;
+use std::path::Path;
+
+/// One non-empty line of an OSC text script, e.g. `/param/set 12 0.5`.
+pub struct ScriptEntry {
+    pub line: usize,
+    /// Sample position the message takes effect at; only render scripts
+    /// (`--render`) have one.
+    pub sample_time: Option<u64>,
+    pub message: Result<OscMessage, String>,
+}
+
+/// Reads a script file where every line is `/address arg arg ...`.
+/// Blank lines and lines starting with `#` are ignored.
+pub fn load_script(path: &Path) -> Result<Vec<ScriptEntry>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read script {}", path.display()))?;
+    Ok(parse_script(&text))
+}
+
+pub fn parse_script(text: &str) -> Vec<ScriptEntry> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            parse_line(line).transpose().map(|message| ScriptEntry {
+                line: i + 1,
+                sample_time: None,
+                message,
+            })
+        })
+        .collect()
+}
+
+/// Reads a render script where every line is `sample_offset /address arg ...`.
+pub fn load_timed_script(path: &Path) -> Result<Vec<ScriptEntry>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read script {}", path.display()))?;
+    Ok(parse_timed_script(&text))
+}
+
+pub fn parse_timed_script(text: &str) -> Vec<ScriptEntry> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let (sample_time, message) = match parse_timed_line(line) {
+                Ok(None) => return None,
+                Ok(Some((sample_time, message))) => (Some(sample_time), Ok(message)),
+                Err(e) => (None, Err(e)),
+            };
+            Some(ScriptEntry {
+                line: i + 1,
+                sample_time,
+                message,
+            })
+        })
+        .collect()
+}
+
+/// Parses `sample_offset /address arg ...`; the rest of the line is read as
+/// by `parse_line`.
+pub fn parse_timed_line(line: &str) -> Result<Option<(u64, OscMessage)>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let (offset, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let sample_time = offset
+        .parse::<u64>()
+        .map_err(|_| format!("'{}' is not a sample offset (lines are `sample_offset /address args...`)", offset))?;
+    match parse_line(rest)? {
+        Some(message) => Ok(Some((sample_time, message))),
+        None => Err("missing OSC address".to_string()),
+    }
+}
+
+/// Parses a single script line into an OSC message. Integers become `Int`
+/// (or `Long` when they don't fit in 32 bits), numbers with a decimal point or exponent become `Float`, `true`/`false`
+/// become `Bool`, and anything else (optionally double-quoted) is a `String`.
+pub fn parse_line(line: &str) -> Result<Option<OscMessage>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut tokens = tokenize(line)?.into_iter();
+    let addr = tokens.next().ok_or_else(|| "missing OSC address".to_string())?;
+    if !addr.starts_with('/') {
+        return Err(format!("'{}' is not an OSC address (must start with '/')", addr));
+    }
+
+    let args = tokens.map(|token| parse_arg(&token)).collect();
+    Ok(Some(OscMessage { addr, args }))
+}
+
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let mut token = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(ch) => token.push(ch),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            // Quoted tokens are always strings; mark them so parse_arg keeps them verbatim.
+            tokens.push(format!("\"{}", token));
+        } else {
+            let mut token = String::new();
+            while let Some(&ch) = chars.peek() {
+                if ch.is_whitespace() {
+                    break;
+                }
+                token.push(ch);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_arg(token: &str) -> OscType {
+    if let Some(quoted) = token.strip_prefix('"') {
+        return OscType::String(quoted.to_string());
+    }
+    if let Ok(v) = token.parse::<i32>() {
+        return OscType::Int(v);
+    }
+    // Param ids routinely exceed i32::MAX and must not round-trip through f32.
+    if let Ok(v) = token.parse::<i64>() {
+        return OscType::Long(v);
+    }
+    if let Ok(v) = token.parse::<f32>() {
+        return OscType::Float(v);
+    }
+    match token {
+        "true" => OscType::Bool(true),
+        "false" => OscType::Bool(false),
+        _ => OscType::String(token.to_string()),
+    }
+}
+
+/// Runs every line of the script through the same handling as live OSC
+/// before the receiver starts. Bad lines are logged and skipped.
+pub fn run_script(path: &Path, state: &mut OscReceiverState) -> Result<usize> {
+    let mut applied = 0;
+
+    for entry in load_script(path)? {
+        let result = entry
+            .message
+            .and_then(|msg| state.handle_message(&msg, None).map_err(|e| e.to_string()));
+
+        match result {
+            Ok(()) => applied += 1,
+            Err(e) => log::warn!("{}:{}: {}", path.display(), entry.line, e),
+        }
+    }
+
+    Ok(applied)
+}
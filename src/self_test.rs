@@ -0,0 +1,139 @@
+//! `--self-test`: once audio is running, plays a short built-in sequence
+//! through the plugin and reports whether any sound came out. Commands go
+//! straight into the command queue, bypassing OSC, so a pass means the
+//! plugin and the audio output work and any silence lies with the sender.
+
+use crate::engine::MainThreadMessage;
+use crate::meter::{to_dbfs, SILENCE_RMS};
+use crate::osc::{push_command, Command, CommandProducer};
+use crate::plugin::ParamInfo;
+use crate::session::SharedSessionCounters;
+use anyhow::{Context, Result};
+use crossbeam_channel::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Spread across the keyboard, so a plugin with a narrow range still hears some.
+const KEYS: [i32; 4] = [36, 48, 60, 72];
+const VELOCITY: f32 = 0.8;
+/// Lets the stream settle before the first note.
+const WARM_UP: Duration = Duration::from_millis(300);
+const NOTE_SPACING: Duration = Duration::from_millis(150);
+const SWEEP_STEPS: u32 = 20;
+const SWEEP_STEP: Duration = Duration::from_millis(50);
+/// Release tails count as sound too.
+const RELEASE: Duration = Duration::from_millis(500);
+
+/// Runs the sequence on its own thread and sends `SelfTestDone` when it is
+/// over. `sweep_param` is swept from its minimum to its maximum while the
+/// notes are held.
+pub fn start_self_test(
+    producer: CommandProducer,
+    sweep_param: Option<ParamInfo>,
+    session: SharedSessionCounters,
+    main_sender: Sender<MainThreadMessage>,
+) -> Result<JoinHandle<()>> {
+    thread::Builder::new()
+        .name("self-test".into())
+        .spawn(move || {
+            let passed = run(&producer, sweep_param.as_ref(), &session);
+            let _ = main_sender.send(MainThreadMessage::SelfTestDone { passed });
+        })
+        .context("Failed to spawn self-test thread")
+}
+
+fn run(producer: &CommandProducer, sweep_param: Option<&ParamInfo>, session: &SharedSessionCounters) -> bool {
+    thread::sleep(WARM_UP);
+    let meter = session.output_meter();
+    let blocks_before = session.blocks();
+    let loud_before = meter.loud_blocks();
+    meter.take_peak();
+
+    for (note_id, &key) in KEYS.iter().enumerate() {
+        log::info!("Self-test: note on, key {}", key);
+        send(
+            producer,
+            Command::NoteOn {
+                note_id: note_id as i32,
+                key,
+                velocity: VELOCITY,
+                channel: 0,
+                port: 0,
+                tuning: 0.0,
+            },
+        );
+        thread::sleep(NOTE_SPACING);
+    }
+    log_level(session, "notes");
+
+    match sweep_param {
+        Some(param) => {
+            log::info!(
+                "Self-test: sweeping parameter {} ({}) from {} to {}",
+                param.id,
+                param.name,
+                param.min_value,
+                param.max_value
+            );
+            for step in 0..=SWEEP_STEPS {
+                let value = param.normalized_to_value(step as f64 / SWEEP_STEPS as f64);
+                send(
+                    producer,
+                    Command::ParamSet {
+                        param_id: param.id,
+                        value,
+                    },
+                );
+                thread::sleep(SWEEP_STEP);
+            }
+            log_level(session, "parameter sweep");
+        }
+        None => log::info!("Self-test: the plugin has no automatable parameter to sweep"),
+    }
+
+    for (note_id, &key) in KEYS.iter().enumerate() {
+        log::info!("Self-test: note off, key {}", key);
+        send(
+            producer,
+            Command::NoteOff {
+                note_id: note_id as i32,
+                key,
+                velocity: 0.0,
+                channel: 0,
+                port: 0,
+            },
+        );
+    }
+    thread::sleep(RELEASE);
+    log_level(session, "release");
+
+    let blocks = session.blocks() - blocks_before;
+    let loud = meter.loud_blocks() - loud_before;
+    if blocks == 0 {
+        log::error!("Self-test failed: no audio blocks were processed; the audio output isn't running");
+        false
+    } else if loud == 0 {
+        log::error!(
+            "Self-test failed: all {} blocks were silent (below {:.0} dBFS); the plugin made no sound, or something \
+             mutes it (a parameter, /host/mute, --master-gain)",
+            blocks,
+            to_dbfs(SILENCE_RMS)
+        );
+        false
+    } else {
+        log::info!("Self-test passed: {} of {} blocks had sound", loud, blocks);
+        true
+    }
+}
+
+fn send(producer: &CommandProducer, cmd: Command) {
+    if !push_command(producer, cmd) {
+        log::warn!("Self-test: command queue full, a step was dropped");
+    }
+}
+
+/// Logs the loudest block since the previous step.
+fn log_level(session: &SharedSessionCounters, step: &str) {
+    let peak = session.output_meter().take_peak();
+    log::info!("Self-test: output peak RMS during {}: {:.1} dBFS", step, to_dbfs(peak));
+}
@@ -0,0 +1,353 @@
+//! End-of-session summary. Counters are bumped wherever things happen (audio
+//! thread, OSC thread, main loop); the report is rendered once, by whichever
+//! exit path gets there first.
+
+use anyhow::{Context, Result};
+use rosc::OscType;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::{Duration, Instant};
+
+use crate::event_stats::SharedEventStats;
+use crate::meter::OutputMeter;
+
+/// Distinct OSC addresses counted individually; the rest share one bucket so
+/// a client spraying unique addresses can't grow the table without bound.
+const MAX_TRACKED_ADDRESSES: usize = 256;
+const OTHER_ADDRESSES: &str = "(other)";
+
+pub struct SessionCounters {
+    started: Instant,
+    blocks: AtomicU64,
+    /// Blocks whose processing took longer than the audio they produced.
+    xruns: AtomicU64,
+    /// Device callbacks whose playback timestamp came more than a block after
+    /// the previous one's: the device ran dry in between.
+    late_callbacks: AtomicU64,
+    /// Highest processing time / block duration seen, as `f32` bits.
+    peak_dsp_load: AtomicU32,
+    plugin_errors: AtomicU64,
+    /// Commands from OSC and MIDI, and how many of them made it into the
+    /// command queue (held-back ones count as queued).
+    commands_received: AtomicU64,
+    commands_queued: AtomicU64,
+    commands_dropped: AtomicU64,
+    /// Events the audio thread handed to the plugins.
+    events_delivered: AtomicU64,
+    /// Fullest the command queue has been, as `f32` bits: in the current
+    /// second, in the last whole second, and in the whole session.
+    queue_peak: AtomicU32,
+    queue_peak_last_second: AtomicU32,
+    queue_peak_session: AtomicU32,
+    /// Level of what the host plays, measured by the audio thread.
+    output_meter: OutputMeter,
+    osc_messages: Mutex<HashMap<String, u64>>,
+    files_written: Mutex<Vec<PathBuf>>,
+}
+
+pub type SharedSessionCounters = Arc<SessionCounters>;
+
+impl SessionCounters {
+    pub fn new() -> SharedSessionCounters {
+        Arc::new(Self {
+            started: Instant::now(),
+            blocks: AtomicU64::new(0),
+            xruns: AtomicU64::new(0),
+            late_callbacks: AtomicU64::new(0),
+            peak_dsp_load: AtomicU32::new(0),
+            plugin_errors: AtomicU64::new(0),
+            commands_received: AtomicU64::new(0),
+            commands_queued: AtomicU64::new(0),
+            commands_dropped: AtomicU64::new(0),
+            events_delivered: AtomicU64::new(0),
+            queue_peak: AtomicU32::new(0),
+            queue_peak_last_second: AtomicU32::new(0),
+            queue_peak_session: AtomicU32::new(0),
+            output_meter: OutputMeter::new(),
+            osc_messages: Mutex::new(HashMap::new()),
+            files_written: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Called by the audio thread once per block. Lock-free.
+    pub fn record_block(&self, elapsed: Duration, block_duration: Duration) {
+        self.blocks.fetch_add(1, Ordering::Relaxed);
+        if block_duration.is_zero() {
+            return;
+        }
+        let load = elapsed.as_secs_f32() / block_duration.as_secs_f32();
+        if load > 1.0 {
+            self.xruns.fetch_add(1, Ordering::Relaxed);
+        }
+        // Non-negative floats order the same as their bit patterns.
+        self.peak_dsp_load.fetch_max(load.to_bits(), Ordering::Relaxed);
+    }
+
+    /// The clocked loop fell behind the wall clock.
+    pub fn record_xrun(&self) {
+        self.xruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Called by the audio thread. Lock-free.
+    pub fn record_late_callback(&self) {
+        self.late_callbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn late_callbacks(&self) -> u64 {
+        self.late_callbacks.load(Ordering::Relaxed)
+    }
+
+    pub fn blocks(&self) -> u64 {
+        self.blocks.load(Ordering::Relaxed)
+    }
+
+    pub fn output_meter(&self) -> &OutputMeter {
+        &self.output_meter
+    }
+
+    /// Arguments of a `/stats` reply: xruns, late callbacks, blocks and peak
+    /// DSP load.
+    pub fn stats_args(&self) -> Vec<OscType> {
+        vec![
+            OscType::Long(self.xruns.load(Ordering::Relaxed) as i64),
+            OscType::Long(self.late_callbacks() as i64),
+            OscType::Long(self.blocks() as i64),
+            OscType::Float(f32::from_bits(self.peak_dsp_load.load(Ordering::Relaxed))),
+        ]
+    }
+
+    pub fn record_plugin_error(&self) {
+        self.plugin_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A command from OSC or MIDI, and whether it was queued or dropped.
+    pub fn record_command(&self, queued: bool) {
+        self.commands_received.fetch_add(1, Ordering::Relaxed);
+        if queued {
+            self.commands_queued.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.commands_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Called by the audio thread at the start of each block with how full
+    /// the command queue is, 0..1. Lock-free.
+    pub fn record_queue_occupancy(&self, occupancy: f32) {
+        // Non-negative floats order the same as their bit patterns.
+        self.queue_peak.fetch_max(occupancy.to_bits(), Ordering::Relaxed);
+        self.queue_peak_session.fetch_max(occupancy.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Called by the audio thread once per block. Lock-free.
+    pub fn record_events_delivered(&self, events: u32) {
+        self.events_delivered.fetch_add(events as u64, Ordering::Relaxed);
+    }
+
+    /// Starts a new one-second window for the queue peak. Called by the main
+    /// loop once a second.
+    pub fn roll_queue_window(&self) {
+        let peak = self.queue_peak.swap(0, Ordering::Relaxed);
+        self.queue_peak_last_second.store(peak, Ordering::Relaxed);
+    }
+
+    pub fn queue_stats(&self) -> QueueStats {
+        QueueStats {
+            received: self.commands_received.load(Ordering::Relaxed),
+            queued: self.commands_queued.load(Ordering::Relaxed),
+            dropped: self.commands_dropped.load(Ordering::Relaxed),
+            delivered: self.events_delivered.load(Ordering::Relaxed),
+            peak_occupancy: f32::from_bits(self.queue_peak_last_second.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub fn record_osc_message(&self, addr: &str) {
+        let mut messages = self.osc_messages.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = messages.get_mut(addr) {
+            *count += 1;
+            return;
+        }
+        let key = if messages.len() < MAX_TRACKED_ADDRESSES { addr } else { OTHER_ADDRESSES };
+        *messages.entry(key.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn record_file(&self, path: PathBuf) {
+        self.files_written.lock().unwrap_or_else(|e| e.into_inner()).push(path);
+    }
+}
+
+/// Command traffic so far, for `/host/stats` and `--stats-interval`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueueStats {
+    pub received: u64,
+    pub queued: u64,
+    pub dropped: u64,
+    pub delivered: u64,
+    /// Fullest the command queue was in the last whole second, 0..1.
+    pub peak_occupancy: f32,
+}
+
+impl QueueStats {
+    /// Arguments of a `/host/stats` reply.
+    pub fn args(&self) -> Vec<OscType> {
+        vec![
+            OscType::Long(self.received as i64),
+            OscType::Long(self.queued as i64),
+            OscType::Long(self.dropped as i64),
+            OscType::Long(self.delivered as i64),
+            OscType::Float(self.peak_occupancy),
+        ]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionReport {
+    pub exit_reason: String,
+    pub uptime: Duration,
+    pub blocks: u64,
+    pub xruns: u64,
+    pub late_callbacks: u64,
+    pub peak_dsp_load: f32,
+    /// Sorted by count, busiest first.
+    pub osc_messages: Vec<(String, u64)>,
+    pub commands_received: u64,
+    pub commands_dropped: u64,
+    pub events_delivered: u64,
+    pub peak_queue_occupancy: f32,
+    pub notes_played: u64,
+    pub param_changes: u64,
+    pub plugin_errors: u64,
+    pub files_written: Vec<PathBuf>,
+}
+
+impl SessionReport {
+    pub fn print(&self) {
+        println!("=== Session summary ({}) ===", self.exit_reason);
+        println!("uptime            {:.1}s", self.uptime.as_secs_f64());
+        println!("blocks processed  {}", self.blocks);
+        println!("xruns             {}", self.xruns);
+        println!("late callbacks    {}", self.late_callbacks);
+        println!("peak DSP load     {:.1}%", self.peak_dsp_load * 100.0);
+        println!("commands received {}", self.commands_received);
+        println!("commands dropped  {}", self.commands_dropped);
+        println!("events delivered  {}", self.events_delivered);
+        println!("peak queue        {:.1}%", self.peak_queue_occupancy * 100.0);
+        println!("notes played      {}", self.notes_played);
+        println!("param changes     {}", self.param_changes);
+        println!("plugin errors     {}", self.plugin_errors);
+        let total: u64 = self.osc_messages.iter().map(|(_, count)| count).sum();
+        println!("OSC messages      {}", total);
+        for (addr, count) in &self.osc_messages {
+            println!("    {:<24} {}", addr, count);
+        }
+        if !self.files_written.is_empty() {
+            println!("files written");
+            for path in &self.files_written {
+                println!("    {}", path.display());
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "exit_reason": self.exit_reason,
+            "uptime_secs": self.uptime.as_secs_f64(),
+            "blocks": self.blocks,
+            "xruns": self.xruns,
+            "late_callbacks": self.late_callbacks,
+            "peak_dsp_load": self.peak_dsp_load,
+            "osc_messages": self
+                .osc_messages
+                .iter()
+                .map(|(addr, count)| (addr.clone(), serde_json::Value::from(*count)))
+                .collect::<serde_json::Map<_, _>>(),
+            "commands_received": self.commands_received,
+            "commands_dropped": self.commands_dropped,
+            "events_delivered": self.events_delivered,
+            "peak_queue_occupancy": self.peak_queue_occupancy,
+            "notes_played": self.notes_played,
+            "param_changes": self.param_changes,
+            "plugin_errors": self.plugin_errors,
+            "files_written": self.files_written.iter().map(|p| p.display().to_string()).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Builds and emits the report. Shared with the panic hook, so it guards
+/// against reporting twice when several exit paths race.
+pub struct SessionReporter {
+    counters: SharedSessionCounters,
+    event_stats: SharedEventStats,
+    json_path: Option<PathBuf>,
+    reported: AtomicBool,
+}
+
+impl SessionReporter {
+    pub fn new(counters: SharedSessionCounters, event_stats: SharedEventStats, json_path: Option<PathBuf>) -> Arc<Self> {
+        Arc::new(Self {
+            counters,
+            event_stats,
+            json_path,
+            reported: AtomicBool::new(false),
+        })
+    }
+
+    pub fn report(&self, exit_reason: &str) -> SessionReport {
+        let counters = &self.counters;
+        // The panic hook may run on a thread that holds the stats lock.
+        let (notes_played, param_changes) = match self.event_stats.try_lock() {
+            Ok(stats) => (stats.notes_started.total, stats.params.total),
+            Err(TryLockError::Poisoned(e)) => (e.get_ref().notes_started.total, e.get_ref().params.total),
+            Err(TryLockError::WouldBlock) => (0, 0),
+        };
+        let mut osc_messages: Vec<(String, u64)> = counters
+            .osc_messages
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(addr, count)| (addr.clone(), *count))
+            .collect();
+        osc_messages.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        SessionReport {
+            exit_reason: exit_reason.to_string(),
+            uptime: counters.started.elapsed(),
+            blocks: counters.blocks.load(Ordering::Relaxed),
+            xruns: counters.xruns.load(Ordering::Relaxed),
+            late_callbacks: counters.late_callbacks(),
+            peak_dsp_load: f32::from_bits(counters.peak_dsp_load.load(Ordering::Relaxed)),
+            osc_messages,
+            commands_received: counters.commands_received.load(Ordering::Relaxed),
+            commands_dropped: counters.commands_dropped.load(Ordering::Relaxed),
+            events_delivered: counters.events_delivered.load(Ordering::Relaxed),
+            peak_queue_occupancy: f32::from_bits(counters.queue_peak_session.load(Ordering::Relaxed)),
+            notes_played,
+            param_changes,
+            plugin_errors: counters.plugin_errors.load(Ordering::Relaxed),
+            files_written: counters.files_written.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+        }
+    }
+
+    /// Prints the report and writes `--session-report` if given. Only the
+    /// first call does anything.
+    pub fn finish(&self, exit_reason: &str) {
+        if self.reported.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let report = self.report(exit_reason);
+        report.print();
+        if let Some(path) = &self.json_path {
+            match write_json(path, &report) {
+                Ok(()) => log::info!("Session report written to {}", path.display()),
+                Err(e) => log::error!("{:#}", e),
+            }
+        }
+    }
+}
+
+fn write_json(path: &Path, report: &SessionReport) -> Result<()> {
+    let json = serde_json::to_string_pretty(&report.to_json()).context("Failed to encode session report")?;
+    fs::write(path, json).with_context(|| format!("Failed to write session report {}", path.display()))
+}
@@ -0,0 +1,200 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clack_extensions::state::PluginState;
+use clack_host::prelude::*;
+use rosc::{OscMessage, OscPacket, OscType};
+use std::fs;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+
+/// First line of a state file. The format is this line, then the plugin id on
+/// its own line, then the plugin's opaque state bytes through to end of file.
+const STATE_FILE_MAGIC: &str = "clap-osc-host state v1";
+
+/// Asks the plugin to serialize its current state. Main thread only.
+pub fn save_state<H: HostHandlers>(instance: &mut PluginInstance<H>) -> Result<Vec<u8>> {
+    let state_ext: Option<PluginState> = instance.plugin_handle().get_extension();
+    let state_ext = state_ext.ok_or_else(|| anyhow!("Plugin does not support the state extension"))?;
+
+    let mut bytes = Vec::new();
+    let mut handle = instance.plugin_handle();
+    state_ext
+        .save(&mut handle, &mut bytes)
+        .map_err(|e| anyhow!("Plugin failed to save its state: {:?}", e))?;
+    Ok(bytes)
+}
+
+/// Replaces the plugin's state. Main thread only.
+pub fn load_state<H: HostHandlers>(instance: &mut PluginInstance<H>, state: &[u8]) -> Result<()> {
+    let state_ext: Option<PluginState> = instance.plugin_handle().get_extension();
+    let state_ext = state_ext.ok_or_else(|| anyhow!("Plugin does not support the state extension"))?;
+
+    let mut handle = instance.plugin_handle();
+    state_ext
+        .load(&mut handle, &mut &state[..])
+        .map_err(|e| anyhow!("Plugin failed to load its state: {:?}", e))
+}
+
+pub fn write_state_file(path: &Path, plugin_id: &str, state: &[u8]) -> Result<()> {
+    let mut contents = format!("{}\n{}\n", STATE_FILE_MAGIC, plugin_id).into_bytes();
+    contents.extend_from_slice(state);
+    fs::write(path, contents).with_context(|| format!("Failed to write state file {}", path.display()))
+}
+
+/// Reads a state file written by `write_state_file`, refusing one saved from
+/// a different plugin. A file without the header (e.g. a decoded `/patchState`
+/// reply) is passed through as raw plugin state.
+pub fn read_state_file(path: &Path, plugin_id: &str) -> Result<Vec<u8>> {
+    let contents = fs::read(path).with_context(|| format!("Failed to read state file {}", path.display()))?;
+
+    let header = format!("{}\n", STATE_FILE_MAGIC);
+    let Some(rest) = contents.strip_prefix(header.as_bytes()) else {
+        log::info!("{} has no state file header; passing it to the plugin as-is", path.display());
+        return Ok(contents);
+    };
+    let Some(newline) = rest.iter().position(|&b| b == b'\n') else {
+        return Err(anyhow!("{} has a truncated state file header", path.display()));
+    };
+    let saved_id = String::from_utf8_lossy(&rest[..newline]);
+    if saved_id != plugin_id {
+        return Err(anyhow!(
+            "{} was saved from plugin '{}', not '{}'",
+            path.display(),
+            saved_id,
+            plugin_id
+        ));
+    }
+    Ok(rest[newline + 1..].to_vec())
+}
+
+/// `patchState_<timestamp>.clapstate` in the working directory.
+pub fn default_state_path() -> PathBuf {
+    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
+    PathBuf::from(format!("patchState_{}.clapstate", timestamp))
+}
+
+/// Handles `/patchState`: writes the state to `path` (or a timestamped file
+/// when there is nowhere to reply), or sends it to `reply_to` as
+/// `/patchState plugin_id:s state:s` with the state base64-encoded. Returns
+/// the file written, if any.
+pub fn dump_state<H: HostHandlers>(
+    instance: &mut PluginInstance<H>,
+    plugin_id: &str,
+    path: Option<PathBuf>,
+    reply_to: Option<SocketAddr>,
+) -> Result<Option<PathBuf>> {
+    let state = save_state(instance)?;
+
+    if let (None, Some(target)) = (&path, reply_to) {
+        let packet = OscPacket::Message(OscMessage {
+            addr: "/patchState".to_string(),
+            args: vec![
+                OscType::String(plugin_id.to_string()),
+                OscType::String(BASE64.encode(&state)),
+            ],
+        });
+        let bytes = rosc::encoder::encode(&packet).map_err(|e| anyhow!("Failed to encode /patchState: {:?}", e))?;
+        let socket = UdpSocket::bind("0.0.0.0:0").context("Failed to bind reply socket")?;
+        socket
+            .send_to(&bytes, target)
+            .with_context(|| format!("Failed to send {} byte state to {}", bytes.len(), target))?;
+        log::info!("Sent {} byte patch state to {}", state.len(), target);
+        return Ok(None);
+    }
+
+    let path = path.unwrap_or_else(default_state_path);
+    write_state_file(&path, plugin_id, &state)?;
+    log::info!("Patch state saved to: {}", path.display());
+    Ok(Some(path))
+}
+
+/// Handles `/state/load` and `/loadState`.
+pub fn restore_state<H: HostHandlers>(instance: &mut PluginInstance<H>, plugin_id: &str, path: &Path) -> Result<()> {
+    let state = read_state_file(path, plugin_id)?;
+    load_state(instance, &state)?;
+    log::info!("Patch state loaded from: {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::{flush_params, param_value};
+    use crate::test_plugin::{self, GAIN};
+    use crossbeam_channel::unbounded;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("oscclaphost-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn state_file_round_trips_into_a_fresh_instance() {
+        let (main_sender, _main_receiver) = unbounded();
+        let plugin_id = test_plugin::PLUGIN_ID.to_str().unwrap();
+        let path = temp_path("round-trip.clapstate");
+
+        let mut saved_from = test_plugin::instance(main_sender.clone());
+        flush_params(&mut saved_from, &[(GAIN, 0.25)]).unwrap();
+        let written = dump_state(&mut saved_from, plugin_id, Some(path.clone()), None).unwrap();
+        assert_eq!(written.as_deref(), Some(path.as_path()));
+
+        let mut loaded_into = test_plugin::instance(main_sender);
+        assert_eq!(param_value(&mut loaded_into, GAIN), Some(0.5));
+        restore_state(&mut loaded_into, plugin_id, &path).unwrap();
+        let contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(param_value(&mut loaded_into, GAIN), Some(0.25));
+        assert!(contents.starts_with(format!("{}\n{}\n", STATE_FILE_MAGIC, plugin_id).as_bytes()));
+        assert_eq!(save_state(&mut loaded_into).unwrap(), save_state(&mut saved_from).unwrap());
+    }
+
+    #[test]
+    fn loading_state_reverts_a_changed_param() {
+        let (main_sender, _main_receiver) = unbounded();
+        let plugin_id = test_plugin::PLUGIN_ID.to_str().unwrap();
+        let path = temp_path("revert.clapstate");
+        let mut instance = test_plugin::instance(main_sender);
+
+        flush_params(&mut instance, &[(GAIN, 0.25)]).unwrap();
+        write_state_file(&path, plugin_id, &save_state(&mut instance).unwrap()).unwrap();
+        flush_params(&mut instance, &[(GAIN, 0.75)]).unwrap();
+        assert_eq!(param_value(&mut instance, GAIN), Some(0.75));
+
+        restore_state(&mut instance, plugin_id, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(param_value(&mut instance, GAIN), Some(0.25));
+    }
+
+    #[test]
+    fn missing_or_rejected_state_leaves_the_plugin_alone() {
+        let (main_sender, _main_receiver) = unbounded();
+        let plugin_id = test_plugin::PLUGIN_ID.to_str().unwrap();
+        let path = temp_path("truncated.clapstate");
+        let mut instance = test_plugin::instance(main_sender);
+
+        assert!(restore_state(&mut instance, plugin_id, &temp_path("missing.clapstate")).is_err());
+        // No header, so the bytes go to the plugin as-is, which wants sixteen.
+        fs::write(&path, [0u8; 3]).unwrap();
+        let rejected = restore_state(&mut instance, plugin_id, &path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(rejected.is_err());
+        assert_eq!(param_value(&mut instance, GAIN), Some(0.5));
+    }
+
+    #[test]
+    fn state_from_another_plugin_is_refused() {
+        let (main_sender, _main_receiver) = unbounded();
+        let path = temp_path("other-plugin.clapstate");
+        write_state_file(&path, "org.example.other", &[0u8; 16]).unwrap();
+
+        let mut instance = test_plugin::instance(main_sender);
+        let result = restore_state(&mut instance, test_plugin::PLUGIN_ID.to_str().unwrap(), &path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(param_value(&mut instance, GAIN), Some(0.5));
+    }
+}
@@ -0,0 +1,185 @@
+//! Host-side sustain pedal (`/pedal/sustain`, MIDI CC 64). While a channel's
+//! pedal is down the audio thread holds that channel's note-offs back and
+//! sends them in the block where the pedal lifts, so sustain works the same
+//! whether or not the plugin understands CC 64.
+
+use crate::osc::Command;
+
+/// Note-offs held at once across all channels; further ones go straight
+/// through rather than allocate.
+const MAX_HELD: usize = 512;
+const CHANNELS: usize = 16;
+
+/// A note-off held back by the pedal, with the chain stage it was meant for.
+#[derive(Debug, Clone, Copy)]
+pub struct HeldNote {
+    pub stage: usize,
+    pub note_id: i32,
+    pub key: i32,
+    pub velocity: f32,
+    pub channel: i32,
+    pub port: i32,
+}
+
+impl HeldNote {
+    pub fn note_off(&self) -> Command {
+        Command::NoteOff {
+            note_id: self.note_id,
+            key: self.key,
+            velocity: self.velocity,
+            channel: self.channel,
+            port: self.port,
+        }
+    }
+}
+
+/// Pedal state and held note-offs, owned by the audio thread.
+pub struct Sustain {
+    down: [bool; CHANNELS],
+    /// In arrival order. Never grows past the capacity it was created with.
+    held: Vec<HeldNote>,
+}
+
+impl Sustain {
+    pub fn new() -> Self {
+        Self {
+            down: [false; CHANNELS],
+            held: Vec::with_capacity(MAX_HELD),
+        }
+    }
+
+    /// Holds a note-off if its channel's pedal is down. Returns false if the
+    /// note-off should be sent now.
+    pub fn hold(&mut self, note: HeldNote) -> bool {
+        let down = channel_index(note.channel).is_some_and(|channel| self.down[channel]);
+        if !down || self.held.len() == MAX_HELD {
+            return false;
+        }
+        self.held.push(note);
+        true
+    }
+
+    /// Presses or lifts the pedal on `channel`, or on every channel when it
+    /// is negative. Lifting passes each note-off held on the channel to
+    /// `release`, oldest first.
+    pub fn set(&mut self, channel: i32, down: bool, mut release: impl FnMut(HeldNote)) {
+        let channels = match channel_index(channel) {
+            Some(channel) => channel..channel + 1,
+            None if channel < 0 => 0..CHANNELS,
+            None => return,
+        };
+        for channel in channels.clone() {
+            self.down[channel] = down;
+        }
+        if down {
+            return;
+        }
+        self.held.retain(|note| {
+            let lifted = channel_index(note.channel).is_some_and(|channel| channels.contains(&channel));
+            if lifted {
+                release(*note);
+            }
+            !lifted
+        });
+    }
+
+    /// A key pressed again while its previous note rings on under the pedal
+    /// retriggers: the old note-off is taken out so it can be sent just
+    /// before the new note starts, instead of stacking a second voice.
+    pub fn take_retrigger(&mut self, stage: usize, key: i32, channel: i32) -> Option<HeldNote> {
+        let index = self
+            .held
+            .iter()
+            .position(|note| note.stage == stage && note.key == key && note.channel == channel)?;
+        Some(self.held.remove(index))
+    }
+
+    /// Drops held note-offs on `channel` (every channel when negative) after
+    /// something else released those voices, such as `/notes/off` or a panic.
+    pub fn forget(&mut self, channel: i32) {
+        self.held.retain(|note| channel >= 0 && note.channel != channel);
+    }
+}
+
+fn channel_index(channel: i32) -> Option<usize> {
+    usize::try_from(channel).ok().filter(|&channel| channel < CHANNELS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(key: i32, channel: i32) -> HeldNote {
+        HeldNote { stage: 0, note_id: key, key, velocity: 0.5, channel, port: 0 }
+    }
+
+    fn lift(sustain: &mut Sustain, channel: i32) -> Vec<(i32, i32)> {
+        let mut released = Vec::new();
+        sustain.set(channel, false, |held| released.push((held.key, held.channel)));
+        released
+    }
+
+    #[test]
+    fn note_offs_are_held_until_the_pedal_lifts_then_released_in_order() {
+        let mut sustain = Sustain::new();
+        assert!(!sustain.hold(note(60, 0)), "nothing is held with the pedal up");
+
+        sustain.set(0, true, |_| panic!("pressing the pedal releases nothing"));
+        for key in [64, 60, 67] {
+            assert!(sustain.hold(note(key, 0)));
+        }
+        assert_eq!(lift(&mut sustain, 0), vec![(64, 0), (60, 0), (67, 0)]);
+        assert!(!sustain.hold(note(72, 0)), "the pedal is up again");
+        assert!(lift(&mut sustain, 0).is_empty());
+    }
+
+    #[test]
+    fn channels_sustain_independently() {
+        let mut sustain = Sustain::new();
+        sustain.set(0, true, |_| {});
+        sustain.set(1, true, |_| {});
+        assert!(sustain.hold(note(60, 0)));
+        assert!(sustain.hold(note(62, 1)));
+        assert!(!sustain.hold(note(64, 2)));
+
+        assert_eq!(lift(&mut sustain, 1), vec![(62, 1)]);
+        assert!(sustain.hold(note(65, 0)));
+        assert_eq!(lift(&mut sustain, -1), vec![(60, 0), (65, 0)]);
+    }
+
+    #[test]
+    fn a_held_key_pressed_again_retriggers() {
+        let mut sustain = Sustain::new();
+        sustain.set(0, true, |_| {});
+        assert!(sustain.hold(note(60, 0)));
+        assert!(sustain.hold(note(64, 0)));
+
+        assert!(sustain.take_retrigger(0, 60, 1).is_none(), "other channels don't match");
+        assert!(sustain.take_retrigger(1, 60, 0).is_none(), "other stages don't match");
+        let held = sustain.take_retrigger(0, 60, 0).unwrap();
+        assert!(matches!(held.note_off(), Command::NoteOff { key: 60, channel: 0, .. }));
+        assert_eq!(lift(&mut sustain, 0), vec![(64, 0)]);
+    }
+
+    #[test]
+    fn forgotten_note_offs_are_not_released() {
+        let mut sustain = Sustain::new();
+        sustain.set(-1, true, |_| {});
+        assert!(sustain.hold(note(60, 0)));
+        assert!(sustain.hold(note(62, 1)));
+        sustain.forget(0);
+        assert_eq!(lift(&mut sustain, -1), vec![(62, 1)]);
+    }
+
+    #[test]
+    fn note_offs_past_the_capacity_go_straight_through() {
+        let mut sustain = Sustain::new();
+        sustain.set(0, true, |_| {});
+        for key in 0..MAX_HELD as i32 {
+            assert!(sustain.hold(note(key, 0)));
+        }
+        assert!(!sustain.hold(note(-1, 0)));
+        assert_eq!(sustain.held.capacity(), MAX_HELD);
+        assert_eq!(lift(&mut sustain, 0).len(), MAX_HELD);
+    }
+}
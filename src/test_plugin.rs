@@ -0,0 +1,237 @@
+//! A do-nothing CLAP plugin built into the test binary, so the host's
+//! plugin lifecycle can be exercised without a bundle on disk. It counts
+//! the lifecycle calls it gets, has parameters and state for the host to
+//! set, read, save and load, and reports a fixed latency.
+
+use crate::engine::{MainThreadMessage, OscClapHost, OscClapHostMainThread, OscClapHostShared};
+use clack_extensions::latency::{PluginLatency, PluginLatencyImpl};
+use clack_extensions::params::{
+    ParamDisplayWriter, ParamInfo, ParamInfoFlags, ParamInfoWriter, PluginAudioProcessorParams,
+    PluginMainThreadParams, PluginParams,
+};
+use clack_extensions::state::{PluginState, PluginStateImpl};
+use clack_host::prelude::{HostInfo, PluginBundle, PluginInstance};
+use clack_plugin::clack_export_entry;
+use clack_plugin::events::event_types::ParamValueEvent;
+use clack_plugin::prelude::*;
+use clack_plugin::stream::{InputStream, OutputStream};
+use clack_plugin::utils::{ClapId, Cookie};
+use crossbeam_channel::Sender;
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+
+pub const PLUGIN_ID: &CStr = c"org.oscclaphost.test-plugin";
+
+/// A continuous parameter, 0..1, defaulting to 0.5.
+pub const GAIN: u32 = 1;
+/// A stepped parameter whose steps are named by `WAVEFORMS`, defaulting to the first.
+pub const WAVEFORM: u32 = 2;
+pub const WAVEFORMS: [&str; 3] = ["Sine", "Saw", "Square"];
+/// Samples of latency reported through the latency extension.
+pub const LATENCY: u32 = 64;
+
+thread_local! {
+    static ACTIVATIONS: Cell<usize> = const { Cell::new(0) };
+    static DEACTIVATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Activations on this thread. The host activates and deactivates from its
+/// main thread, which in a test is the test's own, so tests running in
+/// parallel don't see each other's counts.
+pub fn activations() -> usize {
+    ACTIVATIONS.with(Cell::get)
+}
+
+/// Deactivations on this thread.
+pub fn deactivations() -> usize {
+    DEACTIVATIONS.with(Cell::get)
+}
+
+pub struct TestPlugin;
+
+impl Plugin for TestPlugin {
+    type AudioProcessor<'a> = TestAudioProcessor;
+    type Shared<'a> = ();
+    type MainThread<'a> = TestMainThread;
+
+    fn declare_extensions(builder: &mut PluginExtensions<Self>, _shared: Option<&()>) {
+        builder
+            .register::<PluginParams>()
+            .register::<PluginState>()
+            .register::<PluginLatency>();
+    }
+}
+
+impl DefaultPluginFactory for TestPlugin {
+    fn get_descriptor() -> PluginDescriptor {
+        PluginDescriptor::new("org.oscclaphost.test-plugin", "Test Plugin")
+    }
+
+    fn new_shared(_host: HostSharedHandle<'_>) -> Result<Self::Shared<'_>, PluginError> {
+        Ok(())
+    }
+
+    fn new_main_thread<'a>(
+        _host: HostMainThreadHandle<'a>,
+        _shared: &'a Self::Shared<'a>,
+    ) -> Result<Self::MainThread<'a>, PluginError> {
+        Ok(TestMainThread { gain: 0.5, waveform: 0.0 })
+    }
+}
+
+/// Parameter values live here. Only a main-thread flush, which the host may
+/// call while the plugin is inactive, or a state load changes them.
+pub struct TestMainThread {
+    gain: f64,
+    waveform: f64,
+}
+
+impl TestMainThread {
+    fn value_mut(&mut self, param_id: u32) -> Option<&mut f64> {
+        match param_id {
+            GAIN => Some(&mut self.gain),
+            WAVEFORM => Some(&mut self.waveform),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> PluginMainThread<'a, ()> for TestMainThread {}
+
+impl PluginMainThreadParams for TestMainThread {
+    fn count(&mut self) -> u32 {
+        2
+    }
+
+    fn get_info(&mut self, param_index: u32, info: &mut ParamInfoWriter) {
+        let (id, flags, name, max_value, default_value) = match param_index {
+            0 => (GAIN, ParamInfoFlags::IS_AUTOMATABLE, &b"Gain"[..], 1.0, 0.5),
+            1 => (
+                WAVEFORM,
+                ParamInfoFlags::IS_AUTOMATABLE | ParamInfoFlags::IS_STEPPED,
+                &b"Waveform"[..],
+                (WAVEFORMS.len() - 1) as f64,
+                0.0,
+            ),
+            _ => return,
+        };
+        info.set(&ParamInfo {
+            id: ClapId::new(id),
+            flags,
+            cookie: Cookie::default(),
+            name,
+            module: b"",
+            min_value: 0.0,
+            max_value,
+            default_value,
+        });
+    }
+
+    fn get_value(&mut self, param_id: ClapId) -> Option<f64> {
+        self.value_mut(param_id.get()).copied()
+    }
+
+    fn value_to_text(&mut self, param_id: ClapId, value: f64, writer: &mut ParamDisplayWriter) -> std::fmt::Result {
+        match param_id.get() {
+            GAIN => write!(writer, "{:.2}", value),
+            WAVEFORM => {
+                let name = WAVEFORMS.get(value.round() as usize).ok_or(std::fmt::Error)?;
+                writer.write_str(name)
+            }
+            _ => Err(std::fmt::Error),
+        }
+    }
+
+    fn text_to_value(&mut self, param_id: ClapId, text: &CStr) -> Option<f64> {
+        let text = text.to_str().ok()?.trim();
+        match param_id.get() {
+            GAIN => text.parse().ok(),
+            WAVEFORM => WAVEFORMS.iter().position(|name| name.eq_ignore_ascii_case(text)).map(|step| step as f64),
+            _ => None,
+        }
+    }
+
+    fn flush(&mut self, input_parameter_changes: &InputEvents, _output_parameter_changes: &mut OutputEvents) {
+        for event in input_parameter_changes {
+            let Some(event) = event.as_event::<ParamValueEvent>() else {
+                continue;
+            };
+            if let Some(value) = event.param_id().and_then(|id| self.value_mut(id.get())) {
+                *value = event.value();
+            }
+        }
+    }
+}
+
+/// The state is the parameter values as little-endian `f64`s.
+impl PluginStateImpl for TestMainThread {
+    fn save(&mut self, output: &mut OutputStream) -> Result<(), PluginError> {
+        output.write_all(&self.gain.to_le_bytes())?;
+        output.write_all(&self.waveform.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut InputStream) -> Result<(), PluginError> {
+        let mut values = [0; 16];
+        input.read_exact(&mut values)?;
+        let (gain, waveform) = values.split_at(8);
+        self.gain = f64::from_le_bytes(gain.try_into().unwrap());
+        self.waveform = f64::from_le_bytes(waveform.try_into().unwrap());
+        Ok(())
+    }
+}
+
+impl PluginLatencyImpl for TestMainThread {
+    fn get(&mut self) -> u32 {
+        LATENCY
+    }
+}
+
+pub struct TestAudioProcessor;
+
+impl<'a> PluginAudioProcessor<'a, (), TestMainThread> for TestAudioProcessor {
+    fn activate(
+        _host: HostAudioProcessorHandle<'a>,
+        _main_thread: &mut TestMainThread,
+        _shared: &'a (),
+        _audio_config: PluginAudioConfiguration,
+    ) -> Result<Self, PluginError> {
+        ACTIVATIONS.with(|count| count.set(count.get() + 1));
+        Ok(Self)
+    }
+
+    fn process(&mut self, _process: Process, _audio: Audio, _events: Events) -> Result<ProcessStatus, PluginError> {
+        Ok(ProcessStatus::Sleep)
+    }
+
+    fn deactivate(self, _main_thread: &mut TestMainThread) {
+        DEACTIVATIONS.with(|count| count.set(count.get() + 1));
+    }
+}
+
+impl PluginAudioProcessorParams for TestAudioProcessor {
+    fn flush(&mut self, _input_parameter_changes: &InputEvents, _output_parameter_changes: &mut OutputEvents) {}
+}
+
+clack_export_entry!(SinglePluginEntry<TestPlugin>);
+
+/// The bundle the test plugin is loaded from.
+pub fn bundle() -> PluginBundle {
+    unsafe { PluginBundle::load_from_raw(&clap_entry, "") }.expect("the test plugin's entry loads")
+}
+
+/// A host instance of the test plugin, not yet activated. Main-thread
+/// messages from its host callbacks go to `main_sender`.
+pub fn instance(main_sender: Sender<MainThreadMessage>) -> PluginInstance<OscClapHost> {
+    let host_info = HostInfo::new("OSC CLAP Host", "OSC CLAP Host", "https://example.com", "0.1.0").unwrap();
+    PluginInstance::<OscClapHost>::new(
+        |_| OscClapHostShared::new(main_sender.clone(), 0),
+        |shared| OscClapHostMainThread::new(shared),
+        &bundle(),
+        PLUGIN_ID,
+        &host_info,
+    )
+    .expect("the test plugin instantiates")
+}
@@ -0,0 +1,228 @@
+//! Test-signal generator for checking a rig's signal flow without anything
+//! musical loaded. Runs allocation-free inside the audio callback; the OSC
+//! thread steers it through `ToneControl`.
+
+use clap::ValueEnum;
+use std::f64::consts::TAU;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TestSignal {
+    Sine,
+    White,
+    Pink,
+    /// One full-scale sample per period of the tone frequency.
+    Impulse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TestToneTarget {
+    /// Straight to the device, replacing the plugin's output.
+    Output,
+    /// Into the plugin's input port, replacing any captured input.
+    Plugin,
+}
+
+/// Settings shared between the OSC thread and the generator.
+pub struct ToneControl {
+    frequency: AtomicU32,
+    gain: AtomicU32,
+    on: AtomicBool,
+}
+
+pub type SharedToneControl = Arc<ToneControl>;
+
+impl ToneControl {
+    pub fn new(frequency: f32, gain: f32, on: bool) -> SharedToneControl {
+        Arc::new(Self {
+            frequency: AtomicU32::new(frequency.to_bits()),
+            gain: AtomicU32::new(gain.to_bits()),
+            on: AtomicBool::new(on),
+        })
+    }
+
+    pub fn set(&self, frequency: f32, gain: f32, on: bool) {
+        self.frequency.store(frequency.to_bits(), Ordering::Relaxed);
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+        self.on.store(on, Ordering::Relaxed);
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on.load(Ordering::Relaxed)
+    }
+
+    fn frequency(&self) -> f32 {
+        f32::from_bits(self.frequency.load(Ordering::Relaxed))
+    }
+
+    fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+}
+
+/// Checks settings from `--test-tone` or `/host/testTone`: the gain must be
+/// finite and not negative, and the frequency above 0 and below Nyquist.
+pub fn check_tone(frequency: f32, gain: f32, sample_rate: u32) -> Result<(), String> {
+    if !gain.is_finite() || gain < 0.0 {
+        return Err(format!("gain {} is not a number of 0 or more", gain));
+    }
+    let nyquist = sample_rate as f32 / 2.0;
+    if !frequency.is_finite() || frequency <= 0.0 || frequency >= nyquist {
+        return Err(format!("{} Hz is outside 0-{} Hz", frequency, nyquist));
+    }
+    Ok(())
+}
+
+/// Audio-thread side of the generator.
+pub struct TestTone {
+    control: SharedToneControl,
+    signal: TestSignal,
+    target: TestToneTarget,
+    sample_rate: f64,
+    /// Frames after which the tone switches itself off; `None` runs until stopped.
+    max_frames: Option<u64>,
+    frames_played: u64,
+    was_on: bool,
+    phase: f64,
+    rng: u32,
+    pink: [f32; 3],
+}
+
+impl TestTone {
+    pub fn new(
+        control: SharedToneControl,
+        signal: TestSignal,
+        target: TestToneTarget,
+        sample_rate: u32,
+        duration_secs: Option<f64>,
+    ) -> Self {
+        Self {
+            control,
+            signal,
+            target,
+            sample_rate: sample_rate as f64,
+            max_frames: duration_secs.map(|secs| (secs * sample_rate as f64) as u64),
+            frames_played: 0,
+            was_on: false,
+            phase: 0.0,
+            rng: 0x1234_5678,
+            pink: [0.0; 3],
+        }
+    }
+
+    /// Overwrites `planar` (`channel_count` planes of `frame_count`) with the
+    /// signal if the tone is on and aimed at `target`. Returns whether it did.
+    pub fn render(&mut self, target: TestToneTarget, planar: &mut [f32], frame_count: usize, channel_count: usize) -> bool {
+        if target != self.target || channel_count == 0 {
+            return false;
+        }
+        let on = self.control.is_on();
+        if on && !self.was_on {
+            self.frames_played = 0;
+        }
+        self.was_on = on;
+        if !on {
+            return false;
+        }
+
+        let gain = self.control.gain();
+        let increment = self.control.frequency() as f64 / self.sample_rate;
+        let (first, rest) = planar.split_at_mut(frame_count);
+        for sample in first.iter_mut() {
+            *sample = self.next_sample(increment) * gain;
+        }
+        for channel in rest.chunks_exact_mut(frame_count).take(channel_count - 1) {
+            channel.copy_from_slice(first);
+        }
+
+        self.frames_played += frame_count as u64;
+        if self.max_frames.is_some_and(|max| self.frames_played >= max) {
+            self.control.on.store(false, Ordering::Relaxed);
+        }
+        true
+    }
+
+    fn next_sample(&mut self, increment: f64) -> f32 {
+        match self.signal {
+            TestSignal::Sine => {
+                let sample = (self.phase * TAU).sin() as f32;
+                self.advance_phase(increment);
+                sample
+            }
+            TestSignal::White => self.white(),
+            TestSignal::Pink => {
+                // Paul Kellett's economy pink filter over white noise.
+                let white = self.white();
+                self.pink[0] = 0.99765 * self.pink[0] + white * 0.0990460;
+                self.pink[1] = 0.96300 * self.pink[1] + white * 0.2965164;
+                self.pink[2] = 0.57000 * self.pink[2] + white * 1.0526913;
+                (self.pink[0] + self.pink[1] + self.pink[2] + white * 0.1848) * 0.25
+            }
+            TestSignal::Impulse => {
+                // The phase has just wrapped (or the tone just started).
+                let sample = if self.phase < increment { 1.0 } else { 0.0 };
+                self.advance_phase(increment);
+                sample
+            }
+        }
+    }
+
+    fn advance_phase(&mut self, increment: f64) {
+        self.phase = (self.phase + increment).fract();
+    }
+
+    /// Xorshift32 mapped to -1..1.
+    fn white(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tone_settings_are_checked() {
+        assert!(check_tone(440.0, 0.1, 48000).is_ok());
+        assert!(check_tone(440.0, 0.0, 48000).is_ok());
+        assert!(check_tone(23999.0, 1.0, 48000).is_ok());
+        assert!(check_tone(24000.0, 0.1, 48000).is_err());
+        assert!(check_tone(0.0, 0.1, 48000).is_err());
+        assert!(check_tone(-440.0, 0.1, 48000).is_err());
+        assert!(check_tone(f32::NAN, 0.1, 48000).is_err());
+        assert!(check_tone(440.0, -0.1, 48000).is_err());
+        assert!(check_tone(440.0, f32::INFINITY, 48000).is_err());
+        assert!(check_tone(440.0, f32::NAN, 48000).is_err());
+    }
+
+    #[test]
+    fn renders_only_when_on_and_aimed_at_the_target() {
+        let control = ToneControl::new(1000.0, 0.5, false);
+        let mut tone = TestTone::new(control.clone(), TestSignal::Sine, TestToneTarget::Output, 48000, None);
+        let mut planar = vec![0.0f32; 2 * 48];
+        assert!(!tone.render(TestToneTarget::Output, &mut planar, 48, 2));
+
+        control.set(1000.0, 0.5, true);
+        assert!(!tone.render(TestToneTarget::Plugin, &mut planar, 48, 2));
+        assert!(tone.render(TestToneTarget::Output, &mut planar, 48, 2));
+        // One full period at 1 kHz, peaking at the gain, on both channels.
+        assert!((planar[12] - 0.5).abs() < 1e-6);
+        assert!((planar[36] + 0.5).abs() < 1e-6);
+        assert_eq!(planar[..48], planar[48..]);
+    }
+
+    #[test]
+    fn duration_switches_the_tone_off() {
+        let control = ToneControl::new(440.0, 0.1, true);
+        let mut tone = TestTone::new(control.clone(), TestSignal::White, TestToneTarget::Output, 1000, Some(0.1));
+        let mut planar = vec![0.0f32; 50];
+        assert!(tone.render(TestToneTarget::Output, &mut planar, 50, 1));
+        assert!(control.is_on());
+        assert!(tone.render(TestToneTarget::Output, &mut planar, 50, 1));
+        assert!(!control.is_on());
+    }
+}
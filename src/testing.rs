@@ -0,0 +1,384 @@
+//! Harness pieces for exercising the OSC → command queue path without a
+//! plugin or audio device: a virtual block clock, a loopback OSC sender and
+//! load generators that tag each message so it can be traced through the queue.
+
+use crate::osc::Command;
+use crate::plugin::ParamInfo;
+#[cfg(test)]
+use crate::osc::{create_command_queue, OscReceiverState, OscSend, ScheduledCommand};
+#[cfg(test)]
+use crate::queue::SheddingQueue;
+#[cfg(test)]
+use rtrb::Consumer;
+use anyhow::{Context, Result};
+use rosc::{OscMessage, OscPacket, OscType};
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+#[cfg(test)]
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Block clock driven by sample position rather than an audio device. Each
+/// block boundary maps to a wall-clock instant so a loop can be paced like a
+/// real audio callback, or stepped as fast as possible when only the
+/// sample position matters.
+pub struct VirtualClock {
+    start: Instant,
+    sample_rate: u32,
+    block_size: u32,
+    position: u64,
+}
+
+impl VirtualClock {
+    pub fn new(sample_rate: u32, block_size: u32) -> Self {
+        Self {
+            start: Instant::now(),
+            sample_rate,
+            block_size,
+            position: 0,
+        }
+    }
+
+    pub fn block_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.block_size as f64 / self.sample_rate as f64)
+    }
+
+    /// Wall-clock instant at which the current block is due.
+    pub fn block_start(&self) -> Instant {
+        self.start + Duration::from_secs_f64(self.position as f64 / self.sample_rate as f64)
+    }
+
+    pub fn advance(&mut self) {
+        self.position += self.block_size as u64;
+    }
+
+    /// Sleeps until the current block is due. Returns immediately if it is late.
+    pub fn wait(&self) {
+        let due = self.block_start();
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+    }
+}
+
+/// Sends OSC messages to a host over the loopback interface.
+pub struct LoopbackSender {
+    socket: UdpSocket,
+    target: SocketAddr,
+}
+
+impl LoopbackSender {
+    pub fn new(target: SocketAddr) -> Result<Self> {
+        let socket = UdpSocket::bind("127.0.0.1:0").context("Failed to bind loopback sender")?;
+        Ok(Self { socket, target })
+    }
+
+    pub fn send(&self, msg: OscMessage) -> Result<()> {
+        let bytes = rosc::encoder::encode(&OscPacket::Message(msg))
+            .map_err(|e| anyhow::anyhow!("Failed to encode OSC message: {:?}", e))?;
+        self.socket
+            .send_to(&bytes, self.target)
+            .with_context(|| format!("Failed to send to {}", self.target))?;
+        Ok(())
+    }
+}
+
+/// Traffic shapes used by the latency benchmark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadProfile {
+    /// A note on/off pair every 50ms.
+    SparseNotes,
+    /// `/param/set` every 0.5ms, round-robin over 8 parameters.
+    DenseAutomation,
+    /// Notes every 20ms on top of automation every 2ms.
+    Mixed,
+}
+
+impl LoadProfile {
+    pub const ALL: [LoadProfile; 3] = [LoadProfile::SparseNotes, LoadProfile::DenseAutomation, LoadProfile::Mixed];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LoadProfile::SparseNotes => "sparse-notes",
+            LoadProfile::DenseAutomation => "dense-automation",
+            LoadProfile::Mixed => "mixed",
+        }
+    }
+
+    /// Builds the messages to send over `duration`, ordered by send offset.
+    /// Tags are unique within one schedule and run from 0 to `len - 1`.
+    pub fn schedule(&self, duration: Duration) -> Vec<ScheduledMessage> {
+        let mut schedule = Vec::new();
+        let mut tag = 0u32;
+
+        let add_notes = |schedule: &mut Vec<ScheduledMessage>, tag: &mut u32, every: Duration| {
+            let mut at = Duration::ZERO;
+            while at < duration {
+                schedule.push(ScheduledMessage { at, tag: *tag, msg: note_message("/note/on", *tag) });
+                *tag += 1;
+                let off_at = at + every / 2;
+                schedule.push(ScheduledMessage { at: off_at, tag: *tag, msg: note_message("/note/off", *tag) });
+                *tag += 1;
+                at += every;
+            }
+        };
+        let add_automation = |schedule: &mut Vec<ScheduledMessage>, tag: &mut u32, every: Duration| {
+            let mut at = Duration::ZERO;
+            while at < duration {
+                schedule.push(ScheduledMessage { at, tag: *tag, msg: param_message(*tag % 8, *tag) });
+                *tag += 1;
+                at += every;
+            }
+        };
+
+        match self {
+            LoadProfile::SparseNotes => add_notes(&mut schedule, &mut tag, Duration::from_millis(50)),
+            LoadProfile::DenseAutomation => add_automation(&mut schedule, &mut tag, Duration::from_micros(500)),
+            LoadProfile::Mixed => {
+                add_notes(&mut schedule, &mut tag, Duration::from_millis(20));
+                add_automation(&mut schedule, &mut tag, Duration::from_millis(2));
+            }
+        }
+
+        schedule.sort_by_key(|m| m.at);
+        schedule
+    }
+}
+
+/// One generated message and when to send it, relative to the start of the run.
+pub struct ScheduledMessage {
+    pub at: Duration,
+    /// Recoverable from the parsed command with [`command_tag`].
+    pub tag: u32,
+    pub msg: OscMessage,
+}
+
+// Tags ride in fields that survive parsing unchanged: the note id for notes
+// and the value for param sets.
+fn note_message(addr: &str, tag: u32) -> OscMessage {
+    OscMessage {
+        addr: addr.to_string(),
+        args: vec![OscType::Int(tag as i32), OscType::Int(60), OscType::Float(0.8)],
+    }
+}
+
+fn param_message(param_id: u32, tag: u32) -> OscMessage {
+    OscMessage {
+        addr: "/param/set".to_string(),
+        args: vec![OscType::Int(param_id as i32), OscType::Double(tag as f64)],
+    }
+}
+
+/// Stand-ins for the parameters the automation profiles write, so the
+/// receiver accepts the sets. The range is wide enough that no tag is clamped.
+pub fn automated_params() -> HashMap<u32, ParamInfo> {
+    (0..8)
+        .map(|id| (id, param_info(id, &format!("Bench {}", id), 0.0, u32::MAX as f64)))
+        .collect()
+}
+
+/// A plain automatable parameter with no module, defaulting to `min_value`.
+pub fn param_info(id: u32, name: &str, min_value: f64, max_value: f64) -> ParamInfo {
+    ParamInfo {
+        id,
+        name: name.to_string(),
+        module: String::new(),
+        min_value,
+        max_value,
+        default_value: min_value,
+        is_modulatable: false,
+        is_modulatable_per_note_id: false,
+        is_automatable: true,
+        is_stepped: false,
+        default_text: None,
+        min_text: None,
+        max_text: None,
+        step_texts: Vec::new(),
+    }
+}
+
+/// Recovers the tag a load generator put on a message.
+pub fn command_tag(cmd: &Command) -> Option<u32> {
+    match cmd {
+        Command::NoteOn { note_id, .. } | Command::NoteOff { note_id, .. } => Some(*note_id as u32),
+        Command::ParamSet { value, .. } => Some(*value as u32),
+        _ => None,
+    }
+}
+
+/// Summary of a latency distribution.
+pub struct LatencyStats {
+    pub count: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    pub fn from_samples(mut samples: Vec<Duration>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort();
+        let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p).round() as usize];
+        Some(Self {
+            count: samples.len(),
+            p50: percentile(0.50),
+            p90: percentile(0.90),
+            p99: percentile(0.99),
+            max: samples[samples.len() - 1],
+        })
+    }
+}
+
+/// A receiver with nothing attached, and the audio thread's end of its
+/// command queue.
+#[cfg(test)]
+pub fn detached_receiver(capacity: usize) -> (OscReceiverState, Consumer<ScheduledCommand>) {
+    let (producer, consumer) = create_command_queue(capacity);
+    let (main_sender, _) = crossbeam_channel::unbounded();
+    (OscReceiverState::detached(SheddingQueue::new(producer, false), main_sender), consumer)
+}
+
+/// The next queued command, waiting up to `timeout` for one to arrive.
+#[cfg(test)]
+pub fn next_command(consumer: &mut Consumer<ScheduledCommand>, timeout: Duration) -> Option<ScheduledCommand> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Ok(scheduled) = consumer.pop() {
+            return Some(scheduled);
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// Encodes one message as a UDP payload.
+#[cfg(test)]
+pub fn encode_message(addr: &str, args: Vec<OscType>) -> Vec<u8> {
+    rosc::encoder::encode(&OscPacket::Message(OscMessage {
+        addr: addr.to_string(),
+        args,
+    }))
+    .expect("OSC message encodes")
+}
+
+/// Keeps the replies sent through it, and when each was sent, instead of
+/// putting them on the network. Paced like a UDP socket unless made with
+/// `stream`.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingSocket {
+    sent: Mutex<Vec<(Instant, OscMessage)>>,
+    stream: bool,
+}
+
+#[cfg(test)]
+impl RecordingSocket {
+    /// Stands in for a TCP connection, which needs no pacing.
+    pub fn stream() -> Self {
+        Self {
+            stream: true,
+            ..Self::default()
+        }
+    }
+
+    /// The replies so far, in the order they were sent.
+    pub fn messages(&self) -> Vec<OscMessage> {
+        self.sent.lock().unwrap().iter().map(|(_, msg)| msg.clone()).collect()
+    }
+
+    /// When each reply was sent.
+    pub fn send_times(&self) -> Vec<Instant> {
+        self.sent.lock().unwrap().iter().map(|(at, _)| *at).collect()
+    }
+}
+
+#[cfg(test)]
+impl OscSend for RecordingSocket {
+    fn send_to(&self, buf: &[u8], _addr: SocketAddr) -> std::io::Result<usize> {
+        match rosc::decoder::decode_udp(buf).expect("replies decode").1 {
+            OscPacket::Message(msg) => self.sent.lock().unwrap().push((Instant::now(), msg)),
+            OscPacket::Bundle(bundle) => panic!("replies are single messages, got {:?}", bundle),
+        }
+        Ok(buf.len())
+    }
+
+    fn needs_pacing(&self) -> bool {
+        !self.stream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc::{run_receiver, DEFAULT_RECV_BUFFER_SIZE};
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn virtual_clock_steps_by_blocks() {
+        let mut clock = VirtualClock::new(48000, 480);
+        assert_eq!(clock.block_duration(), Duration::from_millis(10));
+        let first = clock.block_start();
+        clock.advance();
+        clock.advance();
+        assert_eq!(clock.block_start() - first, Duration::from_millis(20));
+    }
+
+    #[test]
+    fn schedules_are_ordered_with_unique_tags() {
+        for profile in LoadProfile::ALL {
+            let schedule = profile.schedule(Duration::from_millis(100));
+            assert!(schedule.windows(2).all(|pair| pair[0].at <= pair[1].at), "{}", profile.name());
+            let tags: BTreeSet<u32> = schedule.iter().map(|m| m.tag).collect();
+            assert_eq!(tags, (0..schedule.len() as u32).collect(), "{}", profile.name());
+        }
+    }
+
+    #[test]
+    fn latency_percentiles() {
+        assert!(LatencyStats::from_samples(Vec::new()).is_none());
+        let samples = (1..=100).rev().map(Duration::from_millis).collect();
+        let stats = LatencyStats::from_samples(samples).unwrap();
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.p50, Duration::from_millis(51));
+        assert_eq!(stats.p99, Duration::from_millis(99));
+        assert_eq!(stats.max, Duration::from_millis(100));
+    }
+
+    /// Every tagged message a profile sends over loopback comes out of the
+    /// command queue. The queue is sized so sets are never coalesced.
+    #[test]
+    fn loopback_messages_reach_the_queue() {
+        for profile in LoadProfile::ALL {
+            let schedule = profile.schedule(Duration::from_millis(50));
+            let (state, mut consumer) = detached_receiver(schedule.len() * 4);
+            let state = OscReceiverState {
+                params: automated_params(),
+                ..state
+            };
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let sender = LoopbackSender::new(socket.local_addr().unwrap()).unwrap();
+            thread::spawn(move || {
+                run_receiver(socket, || UdpSocket::bind("127.0.0.1:0"), None, DEFAULT_RECV_BUFFER_SIZE, state)
+            });
+
+            for message in &schedule {
+                sender.send(message.msg.clone()).unwrap();
+            }
+            let mut tags = BTreeSet::new();
+            while tags.len() < schedule.len() {
+                let Some(scheduled) = next_command(&mut consumer, Duration::from_secs(5)) else {
+                    break;
+                };
+                tags.extend(command_tag(&scheduled.command));
+            }
+            assert_eq!(tags, schedule.iter().map(|m| m.tag).collect(), "{}", profile.name());
+        }
+    }
+}
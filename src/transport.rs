@@ -0,0 +1,99 @@
+//! Host transport (`/transport/*`). The audio thread owns the clock: OSC
+//! changes arrive through the command queue like notes do, and every block
+//! hands the plugins a CLAP transport event describing where the clock is.
+
+use clack_host::events::event_types::{TransportEvent, TransportFlags};
+use clack_host::events::{EventFlags, EventHeader};
+use clack_host::utils::{BeatTime, SecondsTime};
+
+pub const DEFAULT_TEMPO: f64 = 120.0;
+
+/// Tempos outside this range are rejected by `/transport/tempo`.
+pub const MIN_TEMPO: f64 = 1.0;
+pub const MAX_TEMPO: f64 = 999.0;
+
+#[derive(Debug, Clone)]
+pub enum TransportChange {
+    Tempo(f64),
+    Play,
+    Stop,
+    /// Move the song position to this many quarter-note beats.
+    Seek(f64),
+}
+
+/// Tempo, play state and song position as the audio thread sees them.
+pub struct Transport {
+    tempo: f64,
+    playing: bool,
+    /// Song position in quarter-note beats.
+    beats: f64,
+    seconds: f64,
+    time_signature: (u16, u16),
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Self {
+            tempo: DEFAULT_TEMPO,
+            playing: false,
+            beats: 0.0,
+            seconds: 0.0,
+            time_signature: (4, 4),
+        }
+    }
+
+    pub fn apply(&mut self, change: &TransportChange) {
+        match *change {
+            TransportChange::Tempo(tempo) => self.tempo = tempo,
+            TransportChange::Play => self.playing = true,
+            TransportChange::Stop => self.playing = false,
+            TransportChange::Seek(beats) => {
+                self.beats = beats;
+                // Exact for a constant tempo, which is all the host knows about.
+                self.seconds = beats * 60.0 / self.tempo;
+            }
+        }
+    }
+
+    /// Moves the song position past a block, if playing.
+    pub fn advance(&mut self, frame_count: usize, sample_rate: u32) {
+        if !self.playing || sample_rate == 0 {
+            return;
+        }
+        let elapsed = frame_count as f64 / sample_rate as f64;
+        self.seconds += elapsed;
+        self.beats += elapsed * self.tempo / 60.0;
+    }
+
+    /// The transport at the start of the current block.
+    pub fn event(&self) -> TransportEvent {
+        let (numerator, denominator) = self.time_signature;
+        let beats_per_bar = numerator as f64 * 4.0 / denominator as f64;
+        let bar_number = (self.beats / beats_per_bar).floor();
+
+        let mut flags = TransportFlags::HAS_TEMPO
+            | TransportFlags::HAS_BEATS_TIMELINE
+            | TransportFlags::HAS_SECONDS_TIMELINE
+            | TransportFlags::HAS_TIME_SIGNATURE;
+        if self.playing {
+            flags |= TransportFlags::IS_PLAYING;
+        }
+
+        TransportEvent {
+            header: EventHeader::new_core(0, EventFlags::empty()),
+            flags,
+            song_pos_beats: BeatTime::from_float(self.beats),
+            song_pos_seconds: SecondsTime::from_float(self.seconds),
+            tempo: self.tempo,
+            tempo_inc: 0.0,
+            loop_start_beats: BeatTime::from_float(0.0),
+            loop_end_beats: BeatTime::from_float(0.0),
+            loop_start_seconds: SecondsTime::from_float(0.0),
+            loop_end_seconds: SecondsTime::from_float(0.0),
+            bar_start: BeatTime::from_float(bar_number * beats_per_bar),
+            bar_number: bar_number as i32,
+            time_signature_numerator: numerator,
+            time_signature_denominator: denominator,
+        }
+    }
+}
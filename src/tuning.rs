@@ -0,0 +1,273 @@
+//! Microtonal tuning: a Scala scale (`--tuning file.scl`) or an equal
+//! division of the octave (`--edo N`) that retunes every `/note/on`, and
+//! fractional keys like `/note/on 1 60.33 0.8`. CLAP keys are whole 12-TET
+//! semitones, so a note is sent on the nearest key and a tuning expression
+//! right after its note-on covers the difference.
+//!
+//! Scales are mapped linearly onto keys: key 60 is the scale's 1/1 at middle
+//! C, each key above it is the next degree, and the last degree is the
+//! period the scale repeats at.
+
+use crate::osc::{Command, MAX_TUNING};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// The key that plays the scale's 1/1, in tune with 12-TET.
+const REFERENCE_KEY: i32 = 60;
+
+#[derive(Debug, Clone)]
+pub struct Scale {
+    pub description: String,
+    /// Degrees 1..=n in cents above the 1/1; the last one is the period.
+    degrees: Vec<f64>,
+}
+
+impl Scale {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read scale {}", path.display()))?;
+        Self::parse(&text).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Parses the Scala `.scl` format: `!` comment lines, a description
+    /// line, the number of degrees, then one degree per line. A degree with
+    /// a period is in cents (`386.3`); anything else is a ratio (`5/4`) or a
+    /// whole number (`2`, meaning 2/1). Text after the value is ignored.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim_end_matches('\r')))
+            .filter(|(_, line)| !line.starts_with('!'));
+
+        let (_, description) = lines.next().ok_or("missing description line")?;
+        let (line, count) = lines.next().ok_or("missing number of notes")?;
+        let count: usize = count
+            .split_whitespace()
+            .next()
+            .and_then(|count| count.parse().ok())
+            .ok_or_else(|| format!("line {}: expected the number of notes, got \"{}\"", line, count.trim()))?;
+        if count == 0 {
+            return Err("the scale has no notes".to_string());
+        }
+
+        let mut degrees = Vec::with_capacity(count);
+        for (line, text) in lines.take(count) {
+            let cents = parse_degree(text).map_err(|e| format!("line {}: {}", line, e))?;
+            degrees.push(cents);
+        }
+        if degrees.len() < count {
+            return Err(format!("expected {} notes, found {}", count, degrees.len()));
+        }
+        if degrees[count - 1] <= 0.0 {
+            return Err(format!(
+                "the last note, {} cents, is not above the 1/1",
+                degrees[count - 1]
+            ));
+        }
+
+        Ok(Self {
+            description: description.trim().to_string(),
+            degrees,
+        })
+    }
+
+    /// `steps` equal divisions of the octave.
+    pub fn edo(steps: u32) -> Self {
+        Self {
+            description: format!("{} equal divisions of the octave", steps),
+            degrees: (1..=steps).map(|step| 1200.0 * step as f64 / steps as f64).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.degrees.len()
+    }
+
+    /// Semitones `key` sounds away from its 12-TET pitch, within what a
+    /// tuning expression can carry.
+    pub fn offset(&self, key: i32) -> f64 {
+        let steps = self.degrees.len() as i32;
+        let from_reference = key - REFERENCE_KEY;
+        let periods = from_reference.div_euclid(steps);
+        let degree = from_reference.rem_euclid(steps);
+        let period = self.degrees[self.degrees.len() - 1];
+        let within = if degree == 0 {
+            0.0
+        } else {
+            self.degrees[degree as usize - 1]
+        };
+        let cents = periods as f64 * period + within;
+        (cents / 100.0 - from_reference as f64).clamp(-MAX_TUNING, MAX_TUNING)
+    }
+}
+
+fn parse_degree(text: &str) -> Result<f64, String> {
+    let value = text.split_whitespace().next().ok_or("empty note line")?;
+    if value.contains('.') {
+        return value
+            .parse::<f64>()
+            .ok()
+            .filter(|cents| cents.is_finite())
+            .ok_or_else(|| format!("\"{}\" is not a cents value", value));
+    }
+    let (numerator, denominator) = value.split_once('/').unwrap_or((value, "1"));
+    let ratio = |part: &str| part.parse::<u64>().ok().filter(|&n| n > 0);
+    match (ratio(numerator), ratio(denominator)) {
+        (Some(numerator), Some(denominator)) => Ok(1200.0 * (numerator as f64 / denominator as f64).log2()),
+        _ => Err(format!("\"{}\" is not a ratio or cents value", value)),
+    }
+}
+
+/// Splits a possibly fractional key into the nearest key and the semitones
+/// the note sits away from it.
+pub fn split_key(key: f64) -> (i32, f64) {
+    let nearest = key.round();
+    (nearest as i32, key - nearest)
+}
+
+/// Retunes note-ons on the OSC thread before they are queued.
+pub struct Tuner {
+    scale: Option<Scale>,
+    /// Whether the plugin takes CLAP note events, and with them tuning
+    /// expressions. Without them notes play on the nearest key.
+    note_expressions: bool,
+    warned: bool,
+}
+
+impl Tuner {
+    pub fn new(scale: Option<Scale>, note_expressions: bool) -> Self {
+        Self {
+            scale,
+            note_expressions,
+            warned: false,
+        }
+    }
+
+    /// Adds the scale's offset to a note-on's tuning. If the plugin can't be
+    /// retuned, the tuning is dropped instead, with a warning the first time.
+    pub fn apply(&mut self, cmd: &mut Command) {
+        let Command::NoteOn { key, tuning, .. } = cmd else {
+            return;
+        };
+        if !self.note_expressions {
+            if *tuning != 0.0 && !self.warned {
+                log::warn!("The plugin doesn't support note expressions; fractional keys play the nearest key instead");
+                self.warned = true;
+            }
+            *tuning = 0.0;
+            return;
+        }
+        if let Some(scale) = &self.scale {
+            *tuning = (*tuning + scale.offset(*key)).clamp(-MAX_TUNING, MAX_TUNING);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    fn note_on(key: i32, tuning: f64) -> Command {
+        Command::NoteOn {
+            note_id: -1,
+            key,
+            velocity: 0.8,
+            channel: 0,
+            port: 0,
+            tuning,
+        }
+    }
+
+    #[test]
+    fn degrees_are_read_as_cents_or_ratios() {
+        let scale = Scale::parse("Just major triad\n 3\n386.3 cents\n3/2 fifth\n2\n").unwrap();
+        assert_eq!(scale.description, "Just major triad");
+        assert_eq!(scale.len(), 3);
+        assert_close(scale.degrees[0], 386.3);
+        assert_close(scale.degrees[1], 1200.0 * 1.5f64.log2());
+        assert_close(scale.degrees[2], 1200.0);
+    }
+
+    #[test]
+    fn comment_lines_are_skipped_anywhere() {
+        let text = "! triad.scl\r\n!\r\nJust major triad\r\n! degrees\r\n3\r\n5/4\r\n! the fifth\r\n3/2\r\n2/1\r\n";
+        let scale = Scale::parse(text).unwrap();
+        assert_eq!(scale.description, "Just major triad");
+        assert_eq!(scale.len(), 3);
+        assert_close(scale.degrees[0], 1200.0 * 1.25f64.log2());
+    }
+
+    #[test]
+    fn malformed_scales_are_rejected() {
+        let cases = [
+            ("", "missing description line"),
+            ("Triad\n", "missing number of notes"),
+            ("Triad\nthree\n", "line 2: expected the number of notes, got \"three\""),
+            ("Triad\n0\n", "the scale has no notes"),
+            ("Triad\n3\n5/4\n3/2\n", "expected 3 notes, found 2"),
+            ("Triad\n2\n5/0\n2\n", "line 3: \"5/0\" is not a ratio or cents value"),
+            ("Triad\n2\nfifth\n2\n", "line 3: \"fifth\" is not a ratio or cents value"),
+            ("Triad\n2\n1.2.3\n2\n", "line 3: \"1.2.3\" is not a cents value"),
+            ("Triad\n1\n-100.0\n", "the last note, -100 cents, is not above the 1/1"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(Scale::parse(text).unwrap_err(), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn scales_repeat_around_middle_c() {
+        let scale = Scale::parse("Triad\n3\n386.3\n700.0\n1200.0\n").unwrap();
+        assert_close(scale.offset(60), 0.0);
+        assert_close(scale.offset(61), 3.863 - 1.0);
+        assert_close(scale.offset(62), 7.0 - 2.0);
+        assert_close(scale.offset(63), 12.0 - 3.0);
+        assert_close(scale.offset(64), 15.863 - 4.0);
+        assert_close(scale.offset(59), -5.0 + 1.0);
+    }
+
+    #[test]
+    fn edo_steps_map_onto_consecutive_keys() {
+        let quarter_tones = Scale::edo(24);
+        assert_eq!(quarter_tones.len(), 24);
+        assert_close(quarter_tones.offset(61), 0.5 - 1.0);
+        assert_close(quarter_tones.offset(84), 12.0 - 24.0);
+        assert_close(quarter_tones.offset(36), -12.0 + 24.0);
+
+        let twelve = Scale::edo(12);
+        for key in [0, 59, 60, 61, 127] {
+            assert_close(twelve.offset(key), 0.0);
+        }
+
+        // Far from middle C the offset is clamped to what CLAP accepts.
+        assert_close(Scale::edo(5).offset(60 + 5 * 30), MAX_TUNING);
+    }
+
+    #[test]
+    fn fractional_keys_split_to_the_nearest_key() {
+        let (key, tuning) = split_key(60.25);
+        assert_eq!(key, 60);
+        assert_close(tuning, 0.25);
+        let (key, tuning) = split_key(60.75);
+        assert_eq!(key, 61);
+        assert_close(tuning, -0.25);
+    }
+
+    #[test]
+    fn tuners_add_the_scale_offset_or_drop_the_tuning() {
+        let mut tuner = Tuner::new(Some(Scale::edo(24)), true);
+        let mut cmd = note_on(61, 0.25);
+        tuner.apply(&mut cmd);
+        assert!(matches!(cmd, Command::NoteOn { key: 61, tuning, .. } if tuning == -0.25), "{:?}", cmd);
+
+        let mut tuner = Tuner::new(Some(Scale::edo(24)), false);
+        let mut cmd = note_on(61, 0.25);
+        tuner.apply(&mut cmd);
+        assert!(matches!(cmd, Command::NoteOn { key: 61, tuning, .. } if tuning == 0.0), "{:?}", cmd);
+    }
+}
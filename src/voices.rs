@@ -0,0 +1,120 @@
+//! Host-managed note ids for `/note/play`, `/note/release` and
+//! `/note/all-off`. The OSC thread remembers which ids it handed out per key,
+//! so clients that only think in keys never have to track ids themselves.
+
+use crate::osc::Command;
+use clap::ValueEnum;
+use std::collections::HashMap;
+
+/// Auto-assigned ids count up from here, clear of the small ids clients
+/// usually pick for `/note/on`.
+const FIRST_NOTE_ID: i32 = 0x4000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RetriggerMode {
+    /// Choke the voice already holding the key before starting the new one.
+    Choke,
+    /// Let the old voice ring; releases end the held voices oldest first.
+    Stack,
+}
+
+/// (plugin, port, channel, key) of a voice started by `/note/play`.
+type VoiceKey = (Option<usize>, i32, i32, i32);
+
+pub struct VoiceMap {
+    mode: RetriggerMode,
+    next_note_id: i32,
+    /// Held note ids per key, oldest first.
+    active: HashMap<VoiceKey, Vec<i32>>,
+}
+
+impl VoiceMap {
+    pub fn new(mode: RetriggerMode) -> Self {
+        Self {
+            mode,
+            next_note_id: FIRST_NOTE_ID,
+            active: HashMap::new(),
+        }
+    }
+
+    /// Commands for `/note/play`: a note on with a fresh id, preceded by a
+    /// choke of the held voice when retriggering in choke mode.
+    pub fn play(&mut self, plugin: Option<usize>, key: i32, velocity: f32, channel: i32, port: i32) -> Vec<Command> {
+        let note_id = self.next_note_id;
+        self.next_note_id = self.next_note_id.checked_add(1).unwrap_or(FIRST_NOTE_ID);
+
+        let held = self.active.entry((plugin, port, channel, key)).or_default();
+        let mut commands = Vec::with_capacity(2);
+        if self.mode == RetriggerMode::Choke {
+            commands.extend(held.drain(..).map(|note_id| Command::NoteChoke {
+                note_id,
+                key,
+                channel,
+                port,
+            }));
+        }
+        held.push(note_id);
+        commands.push(Command::NoteOn {
+            note_id,
+            key,
+            velocity,
+            channel,
+            port,
+            tuning: 0.0,
+        });
+        commands
+    }
+
+    /// Command for `/note/release`. A key with no tracked voice is released
+    /// by key alone, so notes started some other way can still be ended.
+    pub fn release(&mut self, plugin: Option<usize>, key: i32, velocity: f32, channel: i32, port: i32) -> Command {
+        let voice = (plugin, port, channel, key);
+        let note_id = match self.active.get_mut(&voice) {
+            Some(held) if !held.is_empty() => {
+                let note_id = held.remove(0);
+                if held.is_empty() {
+                    self.active.remove(&voice);
+                }
+                note_id
+            }
+            _ => -1,
+        };
+        Command::NoteOff {
+            note_id,
+            key,
+            velocity,
+            channel,
+            port,
+        }
+    }
+
+    /// Forgets every tracked voice, e.g. after a panic ended them all.
+    pub fn clear(&mut self) {
+        self.active.clear();
+    }
+
+    /// Note offs for every tracked voice, with the plugin each was sent to.
+    /// `plugin` limits them to one plugin's voices; `None` releases them all.
+    pub fn all_off(&mut self, plugin: Option<usize>) -> Vec<(Option<usize>, Command)> {
+        let mut released = Vec::new();
+        self.active.retain(|&(voice_plugin, port, channel, key), held| {
+            if plugin.is_some() && voice_plugin != plugin {
+                return true;
+            }
+            released.extend(held.drain(..).map(|note_id| {
+                (
+                    voice_plugin,
+                    Command::NoteOff {
+                        note_id,
+                        key,
+                        velocity: 0.0,
+                        channel,
+                        port,
+                    },
+                )
+            }));
+            false
+        });
+        released
+    }
+}